@@ -0,0 +1,319 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Expr, Fields, GenericArgument, Lifetime, LifetimeParam, LitStr, Path,
+    PathArguments, Type, parse_macro_input, spanned::Spanned,
+};
+
+/// Derives `kitchen_sink::config::AppConfig` and a `Debug` impl that redacts
+/// fields marked `secret`.
+///
+/// Field attributes, all optional:
+/// - `name = "..."`: the key used in config files and to derive env/CLI names
+/// - `env = "..."`: exact environment variable name
+/// - `cli = "..."`: exact CLI flag name, without the leading `--`
+/// - `default = "..."`: raw value used when no source provides one
+/// - `with = "path::to::parser"`: a `fn(&str) -> Result<T, anyhow::Error>`
+/// - `validate = "expr"`: a `Fn(&T) -> Result<(), anyhow::Error>`, e.g. one
+///   of `kitchen_sink::config::validate`'s
+/// - `secret`: printed as `<redacted>` by `Debug` and in error reports
+///
+/// `Option<T>` fields are optional; every other field must resolve to a value.
+/// `bool` fields are CLI flags, set by a bare `--name`.
+///
+/// `#[config(validate)]` on the struct also runs its
+/// `kitchen_sink::config::Validate` impl once every field is valid.
+#[proc_macro_derive(AppConfig, attributes(config))]
+pub fn derive_app_config(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct FieldAttrs {
+    name: Option<LitStr>,
+    env: Option<LitStr>,
+    cli: Option<LitStr>,
+    default: Option<LitStr>,
+    with: Option<Path>,
+    validate: Option<Expr>,
+    secret: bool,
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs {
+        name: None,
+        env: None,
+        cli: None,
+        default: None,
+        with: None,
+        validate: None,
+        secret: false,
+    };
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("config")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                attrs.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("env") {
+                attrs.env = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("cli") {
+                attrs.cli = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("default") {
+                attrs.default = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("with") {
+                let lit: LitStr = meta.value()?.parse()?;
+                attrs.with = Some(lit.parse()?);
+            } else if meta.path.is_ident("validate") {
+                let lit: LitStr = meta.value()?.parse()?;
+                attrs.validate = Some(lit.parse()?);
+            } else if meta.path.is_ident("secret") {
+                attrs.secret = true;
+            } else {
+                return Err(meta.error("unsupported config attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+/// Whether the struct carries `#[config(validate)]`.
+fn struct_validates(input: &DeriveInput) -> syn::Result<bool> {
+    let mut validate = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("config")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("validate") {
+                validate = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported config attribute"))
+            }
+        })?;
+    }
+    Ok(validate)
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    match &last.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.qself.is_none() && path.path.is_ident("bool"))
+}
+
+fn opt_lit(lit: &Option<LitStr>) -> TokenStream2 {
+    match lit {
+        Some(lit) => quote!(::core::option::Option::Some(#lit)),
+        None => quote!(::core::option::Option::None),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "AppConfig requires a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "AppConfig can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut specs = Vec::new();
+    let mut loads = Vec::new();
+    let mut inits = Vec::new();
+    let mut debug_fields = Vec::new();
+    for field in fields {
+        let attrs = field_attrs(field)?;
+        let name = field.ident.as_ref().expect("named field");
+        let key = attrs
+            .name
+            .clone()
+            .unwrap_or_else(|| LitStr::new(&name.to_string(), name.span()));
+        let env = opt_lit(&attrs.env);
+        let cli = opt_lit(&attrs.cli);
+        let default = opt_lit(&attrs.default);
+        let (method, value_ty) = match option_inner(&field.ty) {
+            Some(inner) => (quote!(optional), inner),
+            None => (quote!(required), &field.ty),
+        };
+        let flag = is_bool(value_ty);
+        let secret = attrs.secret;
+        let spec = quote! {
+            ::kitchen_sink::config::FieldSpec {
+                key: #key,
+                env: #env,
+                cli: #cli,
+                default: #default,
+                flag: #flag,
+                secret: #secret,
+            }
+        };
+        specs.push(spec.clone());
+
+        let parser = match &attrs.with {
+            Some(path) => quote!(#path),
+            None => quote!(::kitchen_sink::config::parse_str::<#value_ty>),
+        };
+        loads.push(quote! {
+            let #name = loader.#method::<#value_ty, _>(#spec, #parser);
+        });
+        if let Some(validator) = &attrs.validate {
+            loads.push(quote! {
+                loader.validate::<#value_ty, _>(#spec, #name.as_ref(), #validator);
+            });
+        }
+        inits.push(match option_inner(&field.ty) {
+            Some(_) => quote!(#name),
+            None => quote!(#name: #name.expect("missing fields are reported by finish")),
+        });
+
+        let label = name.to_string();
+        debug_fields.push(if attrs.secret {
+            quote!(.field(#label, &"<redacted>"))
+        } else {
+            quote!(.field(#label, &self.#name))
+        });
+    }
+
+    let build = if struct_validates(&input)? {
+        quote! {
+            let config = Self { #(#inits),* };
+            ::kitchen_sink::config::Validate::validate(&config)?;
+            ::core::result::Result::Ok(config)
+        }
+    } else {
+        quote!(::core::result::Result::Ok(Self { #(#inits),* }))
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let label = ident.to_string();
+    Ok(quote! {
+        impl #impl_generics ::kitchen_sink::config::AppConfig for #ident #ty_generics #where_clause {
+            fn fields() -> ::std::vec::Vec<::kitchen_sink::config::FieldSpec> {
+                ::std::vec![#(#specs),*]
+            }
+
+            fn from_sources(
+                sources: &::kitchen_sink::config::ConfigSources,
+            ) -> ::core::result::Result<Self, ::kitchen_sink::config::Error> {
+                let mut loader = ::kitchen_sink::config::FieldLoader::new(sources);
+                #(#loads)*
+                loader.finish()?;
+                #build
+            }
+        }
+
+        impl #impl_generics ::core::fmt::Debug for #ident #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.debug_struct(#label)
+                    #(#debug_fields)*
+                    .finish()
+            }
+        }
+    })
+}
+
+/// Derives the `TryFrom<Vec<u8>>` and `From<&T> for Vec<u8>` conversions a
+/// `kitchen_sink::simple_store::Store` needs, by way of a
+/// `kitchen_sink::simple_store::Codec<T>`, so one serde-backed codec can
+/// serve every stored type:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, StoreCodec)]
+/// #[store(codec = "crate::codecs::Json")]
+/// struct Catalog { items: Vec<Item> }
+/// ```
+///
+/// The codec is built with `Default` for each conversion. `From` can't
+/// report an encoding failure, so it panics; `Store::with_codec` returns it.
+#[proc_macro_derive(StoreCodec, attributes(store))]
+pub fn derive_store_codec(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_store_codec(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The codec named by the struct's `#[store(codec = "...")]`.
+fn store_codec(input: &DeriveInput) -> syn::Result<Path> {
+    let mut codec = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("store")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("codec") {
+                let lit: LitStr = meta.value()?.parse()?;
+                codec = Some(lit.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported store attribute"))
+            }
+        })?;
+    }
+    codec.ok_or_else(|| {
+        syn::Error::new(
+            input.span(),
+            "StoreCodec requires #[store(codec = \"path::to::Codec\")]",
+        )
+    })
+}
+
+fn expand_store_codec(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let codec = store_codec(&input)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut ref_generics = input.generics.clone();
+    let lifetime = Lifetime::new("'__store", ident.span());
+    ref_generics
+        .params
+        .insert(0, LifetimeParam::new(lifetime.clone()).into());
+    let (ref_impl_generics, _, _) = ref_generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::convert::TryFrom<::std::vec::Vec<u8>> for #ident #ty_generics
+        #where_clause
+        {
+            type Error = ::kitchen_sink::simple_store::Error;
+
+            fn try_from(bytes: ::std::vec::Vec<u8>) -> ::core::result::Result<Self, Self::Error> {
+                let codec = <#codec as ::core::default::Default>::default();
+                ::kitchen_sink::simple_store::Codec::<Self>::decode(&codec, bytes)
+            }
+        }
+
+        impl #ref_impl_generics ::core::convert::From<&#lifetime #ident #ty_generics>
+            for ::std::vec::Vec<u8>
+        #where_clause
+        {
+            fn from(value: &#lifetime #ident #ty_generics) -> Self {
+                let codec = <#codec as ::core::default::Default>::default();
+                ::kitchen_sink::simple_store::Codec::<#ident #ty_generics>::encode(&codec, value)
+                    .expect("codec failed to encode store data")
+            }
+        }
+    })
+}
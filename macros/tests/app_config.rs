@@ -0,0 +1,113 @@
+use kitchen_sink::config::validate::{one_of, range, url};
+use kitchen_sink::config::{AppConfig, ConfigSources, Error, Validate};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(AppConfig)]
+struct Settings {
+    #[config(default = "8080")]
+    port: u16,
+    #[config(env = "DATABASE_URL", secret)]
+    database_url: String,
+    #[config(default = "30s", with = "kitchen_sink::env::parse_duration")]
+    timeout: Duration,
+    #[config(cli = "log")]
+    log_dir: Option<String>,
+    #[config(default = "false")]
+    verbose: bool,
+}
+
+fn sources(vars: &[(&'static str, &'static str)]) -> ConfigSources {
+    let vars: HashMap<_, _> = vars.iter().copied().collect();
+    ConfigSources::new()
+        .with_env_prefix("APP")
+        .with_env_source(move |k| vars.get(k).map(|v| v.to_string()))
+}
+
+#[test]
+fn loads_layered_fields() {
+    let settings = Settings::load(
+        sources(&[("DATABASE_URL", "postgres://secret"), ("APP_PORT", "9000")]).with_args([
+            "--timeout",
+            "2m",
+            "--verbose",
+            "input.csv",
+            "--log=/var/log/app",
+        ]),
+    )
+    .unwrap();
+
+    assert_eq!(settings.port, 9000);
+    assert_eq!(settings.database_url, "postgres://secret");
+    assert_eq!(settings.timeout, Duration::from_secs(120));
+    assert_eq!(settings.log_dir.as_deref(), Some("/var/log/app"));
+    assert!(settings.verbose);
+    let debug = format!("{:?}", settings);
+    assert!(debug.contains("database_url: \"<redacted>\""));
+    assert!(!debug.contains("postgres"));
+}
+
+#[test]
+fn reports_every_bad_field() {
+    let err = Settings::load(sources(&[("APP_PORT", "http")])).unwrap_err();
+    let report = format!("{}", err);
+    assert!(report.starts_with("2 configuration value(s)"));
+    assert!(report.contains("port has invalid value"));
+    assert!(report.contains("database_url is not set (env DATABASE_URL"));
+
+    let unknown = Settings::load(sources(&[]).with_args(["--verbos"]));
+    assert!(unknown.is_err());
+}
+
+#[derive(AppConfig)]
+#[config(validate)]
+struct Upstream {
+    #[config(default = "8080", validate = "range(1024, 65535)")]
+    port: u16,
+    #[config(default = "info", validate = "one_of(&[\"debug\", \"info\"])")]
+    level: String,
+    #[config(validate = "url()")]
+    endpoint: Option<String>,
+    #[config(secret, validate = "url()")]
+    webhook: Option<String>,
+    #[config(default = "1")]
+    min_workers: u32,
+    #[config(default = "4")]
+    max_workers: u32,
+}
+
+impl Validate for Upstream {
+    fn validate(&self) -> Result<(), Error> {
+        if self.min_workers > self.max_workers {
+            return Err(Error::msg("min_workers must not exceed max_workers"));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn validates_fields_then_struct() {
+    let err = Upstream::load(sources(&[
+        ("APP_PORT", "80"),
+        ("APP_LEVEL", "trace"),
+        ("APP_ENDPOINT", "localhost"),
+        ("APP_WEBHOOK", "s3cr3t"),
+    ]))
+    .unwrap_err();
+    let report = format!("{}", err);
+    assert!(report.starts_with("4 configuration value(s)"));
+    assert!(report.contains("port has invalid value \"80\" (from env APP_PORT)"));
+    assert!(report.contains(
+        "level has invalid value \"trace\" (from env APP_LEVEL): must be one of debug, info"
+    ));
+    assert!(report.contains("endpoint has invalid value \"localhost\""));
+    assert!(report.contains("webhook has invalid value <redacted> (from env APP_WEBHOOK)"));
+    assert!(!report.contains("s3cr3t"));
+
+    let err = Upstream::load(sources(&[("APP_MIN_WORKERS", "8")])).unwrap_err();
+    assert_eq!(err.to_string(), "min_workers must not exceed max_workers");
+
+    let ok = Upstream::load(sources(&[("APP_ENDPOINT", "https://api.internal")])).unwrap();
+    assert_eq!(ok.endpoint.as_deref(), Some("https://api.internal"));
+    assert_eq!(ok.webhook, None);
+}
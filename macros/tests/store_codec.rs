@@ -0,0 +1,48 @@
+use kitchen_sink::simple_store::{Codec, Store, StoreCodec};
+
+/// `key=value` lines, standing in for a serde format.
+#[derive(Default)]
+struct Pairs;
+
+impl Codec<Settings> for Pairs {
+    fn encode(&self, value: &Settings) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(format!("name={}\nretries={}\n", value.name, value.retries).into_bytes())
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<Settings, anyhow::Error> {
+        let text = String::from_utf8(bytes)?;
+        let mut settings = Settings::default();
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("name", v)) => settings.name = v.to_string(),
+                Some(("retries", v)) => settings.retries = v.parse()?,
+                _ => anyhow::bail!("unexpected line {:?}", line),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Default, Debug, PartialEq, StoreCodec)]
+#[store(codec = "Pairs")]
+struct Settings {
+    name: String,
+    retries: u32,
+}
+
+#[test]
+fn stores_through_the_codec() {
+    let loc = std::env::temp_dir().join(format!("store-codec-{}", std::process::id()));
+    let store = Store::new_or_get(loc.clone(), || Ok(Settings::default())).unwrap();
+    store
+        .write(Settings {
+            name: "upstream".into(),
+            retries: 3,
+        })
+        .unwrap();
+    let bytes = std::fs::read(&loc).unwrap();
+    std::fs::remove_file(&loc).unwrap();
+    assert_eq!(bytes, b"name=upstream\nretries=3\n");
+    assert_eq!(Settings::try_from(bytes).unwrap(), *store.read());
+    assert!(Settings::try_from(b"port=1".to_vec()).is_err());
+}
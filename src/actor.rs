@@ -1,8 +1,19 @@
 use crate::shutdown::{ShutdownCoordinator, ShutdownHook};
 use async_trait::async_trait;
+use futures::{Stream, stream};
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 
+pub mod durable;
+pub mod envelope;
+pub mod recording;
+pub mod retry;
+pub mod router;
+pub mod sharded;
+pub mod supervise;
+
 // https://ryhl.io/blog/actors-with-tokio/
 #[async_trait]
 pub trait Actor<T: Send + Sync>: ShutdownHook {
@@ -20,13 +31,34 @@ impl<T: Clone + Send + Sync + 'static> ActorHandle<T> {
         mk_actor: impl FnOnce(Receiver<T>, ActorHandle<T>) -> Box<dyn Actor<T> + Send + Sync>,
         shutdown: &mut ShutdownCoordinator,
     ) -> Self {
+        Self::spawn_with_backlog(mk_actor, Vec::new(), shutdown)
+    }
+
+    /// Like `spawn`, but the actor handles `backlog` before anything sent
+    /// through the returned handle.
+    pub(crate) fn spawn_with_backlog(
+        mk_actor: impl FnOnce(Receiver<T>, ActorHandle<T>) -> Box<dyn Actor<T> + Send + Sync>,
+        backlog: Vec<T>,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Self {
+        let (handle, jhandle) = Self::spawn_task(mk_actor, backlog, shutdown.token());
+        shutdown.register_task(jhandle);
+        handle
+    }
+
+    /// Spawns the actor task without registering it anywhere; the actor shuts
+    /// down once `completion` is cancelled.
+    pub(crate) fn spawn_task(
+        mk_actor: impl FnOnce(Receiver<T>, ActorHandle<T>) -> Box<dyn Actor<T> + Send + Sync>,
+        backlog: Vec<T>,
+        completion: CancellationToken,
+    ) -> (Self, JoinHandle<()>) {
         let (sender, receiver) = mpsc::channel(8);
         let handle = Self { sender };
         let mut actor = mk_actor(receiver, handle.clone());
-        let completion = shutdown.token();
         let jhandle = tokio::spawn(async move {
             tokio::select! {
-              _ = run_actor(&mut actor) => {}
+              _ = run_actor(&mut actor, backlog) => {}
               _ = completion.cancelled() => {
                 if let Err(e) = actor.shutdown().await {
                   error!("Graceful shutdown failed for actor. {}", e);
@@ -34,17 +66,137 @@ impl<T: Clone + Send + Sync + 'static> ActorHandle<T> {
               }
             }
         });
-        shutdown.register_task(jhandle);
-        handle
+        (handle, jhandle)
     }
 
     pub async fn send(&self, msg: T) {
         let _ = self.sender.send(msg).await;
     }
+
+    /// Sends a request built around a bounded reply channel and returns the
+    /// replies as a stream. The actor streams results with
+    /// `reply.send(item).await`, which waits while the consumer is `buffer`
+    /// items behind and fails once the stream is dropped, so the actor can
+    /// stop producing.
+    ///
+    /// ```ignore
+    /// // in handle_msg
+    /// Msg::List { prefix, reply } => {
+    ///     for item in self.items.iter().filter(|i| i.starts_with(&prefix)) {
+    ///         if reply.send(item.clone()).await.is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let items = handle.ask_stream(16, |reply| Msg::List { prefix, reply }).await;
+    /// ```
+    pub async fn ask_stream<R, F>(
+        &self,
+        buffer: usize,
+        mk_msg: F,
+    ) -> impl Stream<Item = R> + use<T, R, F>
+    where
+        R: Send + 'static,
+        F: FnOnce(Sender<R>) -> T,
+    {
+        let (reply, mut replies) = mpsc::channel(buffer.max(1));
+        self.send(mk_msg(reply)).await;
+        stream::poll_fn(move |cx| replies.poll_recv(cx))
+    }
 }
 
-async fn run_actor<T: Send + Sync>(actor: &mut Box<dyn Actor<T> + Send + Sync>) {
+async fn run_actor<T: Send + Sync>(actor: &mut Box<dyn Actor<T> + Send + Sync>, backlog: Vec<T>) {
+    for msg in backlog {
+        actor.handle_msg(msg).await
+    }
     while let Some(msg) = actor.receiver().recv().await {
         actor.handle_msg(msg).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Clone)]
+    enum Msg {
+        Count { upto: u32, reply: Sender<u32> },
+    }
+
+    struct Counter {
+        receiver: Receiver<Msg>,
+        produced: Arc<AtomicU32>,
+    }
+
+    impl ShutdownHook for Counter {}
+
+    #[async_trait]
+    impl Actor<Msg> for Counter {
+        async fn handle_msg(&mut self, msg: Msg) {
+            match msg {
+                Msg::Count { upto, reply } => {
+                    for n in 0..upto {
+                        if reply.send(n).await.is_err() {
+                            break;
+                        }
+                        self.produced.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Msg> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_replies_and_stops_when_dropped() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let produced = Arc::new(AtomicU32::new(0));
+        let mvproduced = produced.clone();
+        let handle = ActorHandle::spawn(
+            move |receiver, _| {
+                Box::new(Counter {
+                    receiver,
+                    produced: mvproduced,
+                })
+            },
+            &mut shutdown,
+        );
+
+        let all: Vec<u32> = handle
+            .ask_stream(2, |reply| Msg::Count { upto: 5, reply })
+            .await
+            .collect()
+            .await;
+        assert_eq!(all, vec![0, 1, 2, 3, 4]);
+
+        produced.store(0, Ordering::Relaxed);
+        let first: Vec<u32> = handle
+            .ask_stream(1, |reply| Msg::Count {
+                upto: 1_000_000,
+                reply,
+            })
+            .await
+            .take(3)
+            .collect()
+            .await;
+        assert_eq!(first, vec![0, 1, 2]);
+        // Let the actor notice the closed stream, then prove it moved on
+        let after: Vec<u32> = handle
+            .ask_stream(1, |reply| Msg::Count { upto: 1, reply })
+            .await
+            .collect()
+            .await;
+        assert_eq!(after, vec![0]);
+        assert!(produced.load(Ordering::Relaxed) < 10);
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+    }
+}
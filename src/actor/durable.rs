@@ -0,0 +1,319 @@
+//! Mailboxes that survive restarts, for actors handling must-not-lose
+//! commands.
+//!
+//! Each message is appended to a log before it is queued for the actor, and
+//! acknowledged once `handle_msg` returns. On the next spawn with the same
+//! path, anything logged but not acknowledged is handled again, in order,
+//! before new messages. Delivery is therefore at-least-once: a crash between
+//! handling and acknowledging replays that message.
+
+use super::{Actor, ActorHandle};
+use crate::framing::{read_frames, write_frame};
+use crate::shutdown::{ShutdownCoordinator, ShutdownHook};
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::task::spawn_blocking;
+use tracing::{error, warn};
+
+struct MailboxLog {
+    file: File,
+    ack_path: PathBuf,
+    appended: u64,
+    acked: u64,
+}
+
+impl MailboxLog {
+    /// Opens `<path>` (messages) and `<path>.ack` (handled count), returning
+    /// the log and the frames still awaiting acknowledgement.
+    fn open(path: &Path) -> Result<(Self, Vec<Vec<u8>>), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open mailbox {}", path.display()))?;
+        let (frames, valid_len) = read_frames(&mut file)?;
+        if valid_len != file.metadata()?.len() {
+            warn!(
+                "Dropping partial trailing entry from mailbox {}",
+                path.display()
+            );
+            file.set_len(valid_len)?;
+        }
+
+        let mut ack_name = path.file_name().unwrap_or_default().to_os_string();
+        ack_name.push(".ack");
+        let ack_path = path.with_file_name(ack_name);
+        let acked = match std::fs::read(&ack_path) {
+            Ok(bytes) => bytes
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| anyhow!("Corrupt mailbox ack file {}", ack_path.display()))?,
+            Err(_) => 0,
+        };
+        // A crash right after clearing the log can leave a stale ack count
+        let acked = acked.min(frames.len() as u64);
+        let pending = frames[acked as usize..].to_vec();
+        let log = Self {
+            file,
+            ack_path,
+            appended: frames.len() as u64,
+            acked,
+        };
+        log.write_ack()?;
+        Ok((log, pending))
+    }
+
+    fn append(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(frame.len() + 4);
+        write_frame(&mut buf, frame)?;
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Records one more handled message, clearing the log once everything in
+    /// it has been handled.
+    fn ack(&mut self) -> Result<(), anyhow::Error> {
+        self.acked += 1;
+        if self.acked >= self.appended {
+            self.file.set_len(0)?;
+            self.appended = 0;
+            self.acked = 0;
+        }
+        self.write_ack()
+    }
+
+    fn write_ack(&self) -> Result<(), anyhow::Error> {
+        let tmp = self.ack_path.with_extension("ack.tmp");
+        std::fs::write(&tmp, self.acked.to_le_bytes())?;
+        std::fs::rename(&tmp, &self.ack_path)?;
+        Ok(())
+    }
+}
+
+/// Runs `f` on the log on the blocking pool, as it writes and syncs files,
+/// handing back the still-held lock with the result.
+async fn with_log<R: Send + 'static>(
+    log: &Arc<Mutex<MailboxLog>>,
+    f: impl FnOnce(&mut MailboxLog) -> R + Send + 'static,
+) -> Result<(OwnedMutexGuard<MailboxLog>, R), anyhow::Error> {
+    let mut log = log.clone().lock_owned().await;
+    Ok(spawn_blocking(move || {
+        let result = f(&mut log);
+        (log, result)
+    })
+    .await?)
+}
+
+/// Acknowledges each message once the wrapped actor has handled it.
+struct Durable<T> {
+    inner: Box<dyn Actor<T> + Send + Sync>,
+    log: Arc<Mutex<MailboxLog>>,
+}
+
+#[async_trait]
+impl<T: Send + Sync> ShutdownHook for Durable<T> {
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> Actor<T> for Durable<T> {
+    async fn handle_msg(&mut self, msg: T) {
+        self.inner.handle_msg(msg).await;
+        match with_log(&self.log, MailboxLog::ack).await {
+            Ok((_, Ok(()))) => {}
+            Ok((_, Err(e))) | Err(e) => {
+                error!("Failed to acknowledge mailbox message: {}", e)
+            }
+        }
+    }
+
+    fn receiver(&mut self) -> &mut Receiver<T> {
+        self.inner.receiver()
+    }
+}
+
+/// Handle to an actor whose mailbox is persisted at a path. Messages sent to
+/// the plain `ActorHandle` given to the actor itself bypass the log.
+#[derive(Clone)]
+pub struct DurableHandle<T: Clone> {
+    handle: ActorHandle<T>,
+    log: Arc<Mutex<MailboxLog>>,
+}
+
+impl<T> DurableHandle<T>
+where
+    T: Clone + Send + Sync + 'static + TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// Spawns the actor, first replaying any messages a previous run logged
+    /// but didn't finish handling.
+    pub fn spawn(
+        path: impl AsRef<Path>,
+        mk_actor: impl FnOnce(Receiver<T>, ActorHandle<T>) -> Box<dyn Actor<T> + Send + Sync>,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Result<Self, anyhow::Error> {
+        let (log, pending) = MailboxLog::open(path.as_ref())?;
+        let backlog = pending
+            .into_iter()
+            .map(T::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to decode pending mailbox message")?;
+        let log = Arc::new(Mutex::new(log));
+        let mvlog = log.clone();
+        let handle = ActorHandle::spawn_with_backlog(
+            move |receiver, handle| {
+                Box::new(Durable {
+                    inner: mk_actor(receiver, handle),
+                    log: mvlog,
+                })
+            },
+            backlog,
+            shutdown,
+        );
+        Ok(Self { handle, log })
+    }
+
+    /// Persists `msg` and queues it for the actor. Once this returns `Ok` the
+    /// message will be handled, in this run or after a restart.
+    pub async fn send(&self, msg: T) -> Result<(), anyhow::Error> {
+        let permit = self
+            .handle
+            .sender
+            .reserve()
+            .await
+            .map_err(|_| anyhow!("Actor has stopped"))?;
+        let frame: Vec<u8> = (&msg).into();
+        // Append and enqueue under one lock so log order matches delivery order
+        let (_log, appended) = with_log(&self.log, move |log| log.append(&frame)).await?;
+        appended?;
+        permit.send(msg);
+        Ok(())
+    }
+
+    /// Messages logged but not yet acknowledged.
+    pub async fn pending(&self) -> u64 {
+        let log = self.log.lock().await;
+        log.appended - log.acked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempDir;
+    use anyhow::bail;
+    use parking_lot::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Cmd(u8);
+
+    impl<'a> From<&'a Cmd> for Vec<u8> {
+        fn from(value: &'a Cmd) -> Self {
+            vec![value.0]
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Cmd {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            match value.as_slice() {
+                [n] => Ok(Cmd(*n)),
+                _ => bail!("bad command"),
+            }
+        }
+    }
+
+    /// Records commands, stalling on any command greater than `stall_above`.
+    struct Worker {
+        receiver: Receiver<Cmd>,
+        seen: Arc<Mutex<Vec<u8>>>,
+        stall_above: u8,
+        stalled: Arc<Notify>,
+    }
+
+    impl ShutdownHook for Worker {}
+
+    #[async_trait]
+    impl Actor<Cmd> for Worker {
+        async fn handle_msg(&mut self, msg: Cmd) {
+            if msg.0 > self.stall_above {
+                self.stalled.notify_one();
+                futures::future::pending::<()>().await;
+            }
+            self.seen.lock().push(msg.0);
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Cmd> {
+            &mut self.receiver
+        }
+    }
+
+    fn spawn(
+        path: &Path,
+        stall_above: u8,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> (DurableHandle<Cmd>, Arc<Mutex<Vec<u8>>>, Arc<Notify>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let stalled = Arc::new(Notify::new());
+        let (mvseen, mvstalled) = (seen.clone(), stalled.clone());
+        let handle = DurableHandle::spawn(
+            path,
+            move |receiver, _| {
+                Box::new(Worker {
+                    receiver,
+                    seen: mvseen,
+                    stall_above,
+                    stalled: mvstalled,
+                })
+            },
+            shutdown,
+        )
+        .unwrap();
+        (handle, seen, stalled)
+    }
+
+    #[tokio::test]
+    async fn replays_unacknowledged_messages_after_restart() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("mailbox");
+
+        // First run stalls on command 3, as if the process died mid-handle
+        let mut shutdown = ShutdownCoordinator::new();
+        let (handle, seen, stalled) = spawn(&path, 2, &mut shutdown);
+        for n in 1..=4 {
+            handle.send(Cmd(n)).await.unwrap();
+        }
+        stalled.notified().await;
+        assert_eq!(*seen.lock(), vec![1, 2]);
+        assert_eq!(handle.pending().await, 2);
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+
+        // Second run picks up 3 and 4 before anything new
+        let mut shutdown = ShutdownCoordinator::new();
+        let (handle, seen, _) = spawn(&path, u8::MAX, &mut shutdown);
+        handle.send(Cmd(5)).await.unwrap();
+        while seen.lock().len() < 3 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(*seen.lock(), vec![3, 4, 5]);
+        assert_eq!(handle.pending().await, 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+    }
+}
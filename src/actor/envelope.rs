@@ -0,0 +1,203 @@
+//! Versioned envelopes for messages that outlive a single build: recorded,
+//! journaled in a durable mailbox, or sent to another process.
+//!
+//! An envelope is `magic | tag length (u16) | tag | version (u32) | payload`,
+//! all little-endian. Decoding checks the tag, then walks the payload forward
+//! through `Versioned::upgrade` one version at a time until it matches the
+//! current `VERSION`.
+//!
+//! ```ignore
+//! impl Versioned for Command {
+//!     const TYPE_TAG: &'static str = "billing.Command";
+//!     const VERSION: u32 = 2;
+//!     fn encode_payload(&self) -> Vec<u8> { ... }
+//!     fn decode_payload(payload: &[u8]) -> Result<Self, anyhow::Error> { ... }
+//!     fn upgrade(from: u32, payload: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+//!         match from {
+//!             1 => Ok(v1_to_v2(payload)),
+//!             _ => bail!("no upgrade from v{}", from),
+//!         }
+//!     }
+//! }
+//!
+//! // Plug into Recorder / DurableHandle via the byte conversions
+//! impl<'a> From<&'a Command> for Vec<u8> {
+//!     fn from(cmd: &'a Command) -> Self { envelope::seal(cmd) }
+//! }
+//! impl TryFrom<Vec<u8>> for Command {
+//!     type Error = anyhow::Error;
+//!     fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> { envelope::open(&bytes) }
+//! }
+//! ```
+
+use anyhow::{anyhow, bail};
+
+const MAGIC: &[u8; 4] = b"KSE1";
+
+pub trait Versioned: Sized {
+    /// Stable name for the message type, checked on decode.
+    const TYPE_TAG: &'static str;
+    /// The version `encode_payload` produces and `decode_payload` accepts.
+    const VERSION: u32;
+
+    fn encode_payload(&self) -> Vec<u8>;
+    fn decode_payload(payload: &[u8]) -> Result<Self, anyhow::Error>;
+
+    /// Converts a payload written at version `from` into version `from + 1`.
+    /// The default rejects every old version.
+    fn upgrade(from: u32, payload: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+        let _ = payload;
+        bail!(
+            "{} has no upgrade from v{} to v{}",
+            Self::TYPE_TAG,
+            from,
+            from + 1
+        )
+    }
+}
+
+/// The parts of an envelope, without interpreting the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub type_tag: String,
+    pub version: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let tag = self.type_tag.as_bytes();
+        let mut out = Vec::with_capacity(MAGIC.len() + 2 + tag.len() + 4 + self.payload.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&(tag.len() as u16).to_le_bytes());
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let truncated = || anyhow!("Envelope is truncated");
+        let rest = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| anyhow!("Not a message envelope"))?;
+        let (len, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let len = u16::from_le_bytes(*len) as usize;
+        let tag = rest.get(..len).ok_or_else(truncated)?;
+        let (version, payload) = rest[len..].split_first_chunk::<4>().ok_or_else(truncated)?;
+        Ok(Self {
+            type_tag: String::from_utf8(tag.to_vec())?,
+            version: u32::from_le_bytes(*version),
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// Wraps `msg` in an envelope at its current version.
+pub fn seal<M: Versioned>(msg: &M) -> Vec<u8> {
+    Envelope {
+        type_tag: M::TYPE_TAG.to_string(),
+        version: M::VERSION,
+        payload: msg.encode_payload(),
+    }
+    .encode()
+}
+
+/// Decodes an envelope written by any version up to the current one,
+/// upgrading old payloads on the way.
+pub fn open<M: Versioned>(bytes: &[u8]) -> Result<M, anyhow::Error> {
+    let envelope = Envelope::decode(bytes)?;
+    if envelope.type_tag != M::TYPE_TAG {
+        bail!(
+            "Expected a {} envelope, found {}",
+            M::TYPE_TAG,
+            envelope.type_tag
+        );
+    }
+    if envelope.version > M::VERSION {
+        bail!(
+            "{} v{} is newer than this build understands (v{})",
+            M::TYPE_TAG,
+            envelope.version,
+            M::VERSION
+        );
+    }
+    let mut payload = envelope.payload;
+    for from in envelope.version..M::VERSION {
+        payload = M::upgrade(from, payload)?;
+    }
+    M::decode_payload(&payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// v1 carried only an amount; v2 added a currency code.
+    #[derive(Debug, PartialEq)]
+    struct Charge {
+        cents: u32,
+        currency: [u8; 3],
+    }
+
+    impl Versioned for Charge {
+        const TYPE_TAG: &'static str = "test.Charge";
+        const VERSION: u32 = 2;
+
+        fn encode_payload(&self) -> Vec<u8> {
+            let mut out = self.cents.to_le_bytes().to_vec();
+            out.extend_from_slice(&self.currency);
+            out
+        }
+
+        fn decode_payload(payload: &[u8]) -> Result<Self, anyhow::Error> {
+            let (cents, currency) = payload
+                .split_first_chunk::<4>()
+                .ok_or_else(|| anyhow!("short payload"))?;
+            Ok(Charge {
+                cents: u32::from_le_bytes(*cents),
+                currency: currency.try_into()?,
+            })
+        }
+
+        fn upgrade(from: u32, mut payload: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+            match from {
+                1 => {
+                    payload.extend_from_slice(b"USD");
+                    Ok(payload)
+                }
+                _ => bail!("unknown version {}", from),
+            }
+        }
+    }
+
+    #[test]
+    fn upgrades_old_payloads_and_rejects_mismatches() {
+        let current = Charge {
+            cents: 250,
+            currency: *b"EUR",
+        };
+        assert_eq!(open::<Charge>(&seal(&current)).unwrap(), current);
+
+        let v1 = Envelope {
+            type_tag: "test.Charge".into(),
+            version: 1,
+            payload: 99u32.to_le_bytes().to_vec(),
+        };
+        let upgraded: Charge = open(&v1.encode()).unwrap();
+        assert_eq!(upgraded.currency, *b"USD");
+        assert_eq!(upgraded.cents, 99);
+
+        let future = Envelope {
+            version: 3,
+            ..v1.clone()
+        };
+        assert!(open::<Charge>(&future.encode()).is_err());
+        let other = Envelope {
+            type_tag: "test.Refund".into(),
+            ..v1
+        };
+        assert!(open::<Charge>(&other.encode()).is_err());
+        assert!(open::<Charge>(b"KSE1\x05").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Record the messages an actor handles and replay them into a fresh instance,
+//! for golden tests of actors with complex state.
+//!
+//! Messages use the same byte conversions as `Store` (`From<&T> for Vec<u8>`
+//! to record, `TryFrom<Vec<u8>>` to replay) and are stored as length-prefixed
+//! frames.
+
+use super::Actor;
+use crate::framing::{read_frames, write_frame};
+use crate::shutdown::ShutdownHook;
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use tokio::sync::mpsc::Receiver;
+use tracing::error;
+
+/// Wraps an actor, appending every message to a file before handing it on.
+///
+/// ```ignore
+/// let handle = ActorHandle::spawn(
+///     |rx, h| Box::new(Recorder::new("counter.rec", Box::new(Counter::new(rx, h))).unwrap()),
+///     &mut shutdown,
+/// );
+/// ```
+pub struct Recorder<T> {
+    inner: Box<dyn Actor<T> + Send + Sync>,
+    file: File,
+}
+
+impl<T> Recorder<T> {
+    /// Starts a new recording at `path`, replacing any existing file.
+    pub fn new(
+        path: impl AsRef<Path>,
+        inner: Box<dyn Actor<T> + Send + Sync>,
+    ) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        Ok(Self { inner, file })
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> ShutdownHook for Recorder<T> {
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        self.file.sync_all()?;
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> Actor<T> for Recorder<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    async fn handle_msg(&mut self, msg: T) {
+        let frame: Vec<u8> = (&msg).into();
+        if let Err(e) = write_frame(&mut self.file, &frame) {
+            error!("Failed to record actor message: {}", e);
+        }
+        self.inner.handle_msg(msg).await
+    }
+
+    fn receiver(&mut self) -> &mut Receiver<T> {
+        self.inner.receiver()
+    }
+}
+
+/// Reads every message from a recording, in order.
+pub fn read_recording<T>(path: impl AsRef<Path>) -> Result<Vec<T>, anyhow::Error>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error>,
+{
+    let path = path.as_ref();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+    let (frames, len) = read_frames(&mut file)?;
+    if len != file.metadata()?.len() {
+        bail!("Recording {} ends with a partial frame", path.display());
+    }
+    let mut messages = Vec::with_capacity(frames.len());
+    for (idx, frame) in frames.into_iter().enumerate() {
+        messages
+            .push(T::try_from(frame).with_context(|| format!("Failed to decode frame {}", idx))?);
+    }
+    Ok(messages)
+}
+
+/// Feeds a recording into `actor` one message at a time, returning how many
+/// were replayed. The actor is driven directly, so its receiver is unused and
+/// its state can be asserted on afterwards.
+pub async fn replay<T, A>(path: impl AsRef<Path>, actor: &mut A) -> Result<usize, anyhow::Error>
+where
+    T: Send + Sync + TryFrom<Vec<u8>, Error = anyhow::Error>,
+    A: Actor<T> + ?Sized,
+{
+    let messages = read_recording::<T>(path)?;
+    let count = messages.len();
+    for msg in messages {
+        actor.handle_msg(msg).await;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::ActorHandle;
+    use crate::shutdown::ShutdownCoordinator;
+    use crate::simple_store::testing::TempDir;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Op {
+        Add(u8),
+        Reset,
+    }
+
+    impl<'a> From<&'a Op> for Vec<u8> {
+        fn from(op: &'a Op) -> Self {
+            match op {
+                Op::Add(n) => vec![0, *n],
+                Op::Reset => vec![1],
+            }
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Op {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            match value.as_slice() {
+                [0, n] => Ok(Op::Add(*n)),
+                [1] => Ok(Op::Reset),
+                other => bail!("unknown op {:?}", other),
+            }
+        }
+    }
+
+    struct Sum {
+        receiver: Receiver<Op>,
+        total: Arc<Mutex<u32>>,
+    }
+
+    impl ShutdownHook for Sum {}
+
+    #[async_trait]
+    impl Actor<Op> for Sum {
+        async fn handle_msg(&mut self, msg: Op) {
+            let mut total = self.total.lock();
+            match msg {
+                Op::Add(n) => *total += n as u32,
+                Op::Reset => *total = 0,
+            }
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Op> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_messages_into_fresh_actor() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sum.rec");
+        let live_total = Arc::new(Mutex::new(0));
+
+        let mut shutdown = ShutdownCoordinator::new();
+        let mvpath = path.clone();
+        let mvtotal = live_total.clone();
+        let handle = ActorHandle::spawn(
+            move |receiver, _| {
+                let inner = Box::new(Sum {
+                    receiver,
+                    total: mvtotal,
+                });
+                Box::new(Recorder::new(mvpath, inner).unwrap())
+            },
+            &mut shutdown,
+        );
+        for op in [Op::Add(3), Op::Reset, Op::Add(4), Op::Add(5)] {
+            handle.send(op).await;
+        }
+        while *live_total.lock() != 9 {
+            tokio::task::yield_now().await;
+        }
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+
+        let (_tx, receiver) = mpsc::channel(1);
+        let mut fresh = Sum {
+            receiver,
+            total: Arc::new(Mutex::new(0)),
+        };
+        assert_eq!(replay(&path, &mut fresh).await.unwrap(), 4);
+        assert_eq!(*fresh.total.lock(), *live_total.lock());
+    }
+}
@@ -0,0 +1,214 @@
+//! Retry a failing handler before giving up on a message, for actors that
+//! call flaky downstreams.
+//!
+//! `Actor::handle_msg` can't report failure, so handlers that want retries
+//! implement `TryActor` instead and are wrapped in `Retrying`, which is an
+//! `Actor` like any other. Messages that still fail once the policy is
+//! exhausted are handed to a dead-letter channel (or logged if there is none).
+
+use super::Actor;
+use crate::shutdown::ShutdownHook;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{error, warn};
+
+/// An actor whose handler can fail. The message is borrowed so it can be
+/// offered again on retry.
+#[async_trait]
+pub trait TryActor<T: Send + Sync>: ShutdownHook {
+    async fn try_handle(&mut self, msg: &T) -> Result<(), anyhow::Error>;
+    fn receiver(&mut self) -> &mut Receiver<T>;
+}
+
+/// How many times to attempt a message and how long to wait in between.
+/// The delay doubles after each failure, up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A message that failed every attempt allowed by the policy.
+#[derive(Debug)]
+pub struct DeadLetter<T> {
+    pub msg: T,
+    pub attempts: u32,
+    pub error: anyhow::Error,
+}
+
+/// Wraps a `TryActor`, retrying each message according to `policy`.
+///
+/// ```ignore
+/// let (dead_tx, dead_rx) = mpsc::channel(64);
+/// let handle = ActorHandle::spawn(
+///     |rx, _| Box::new(Retrying::new(Box::new(Uploader::new(rx)), RetryPolicy::default())
+///         .with_dead_letters(dead_tx)),
+///     &mut shutdown,
+/// );
+/// ```
+pub struct Retrying<T> {
+    inner: Box<dyn TryActor<T> + Send + Sync>,
+    policy: RetryPolicy,
+    dead_letters: Option<Sender<DeadLetter<T>>>,
+}
+
+impl<T> Retrying<T> {
+    pub fn new(inner: Box<dyn TryActor<T> + Send + Sync>, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            dead_letters: None,
+        }
+    }
+
+    /// Sends messages that exhaust their retries to `dead_letters`. The
+    /// actor waits for room in the channel, so a full queue slows it down
+    /// rather than losing messages.
+    pub fn with_dead_letters(mut self, dead_letters: Sender<DeadLetter<T>>) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> ShutdownHook for Retrying<T> {
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync> Actor<T> for Retrying<T> {
+    async fn handle_msg(&mut self, msg: T) {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            match self.inner.try_handle(&msg).await {
+                Ok(()) => return,
+                Err(e) if attempts >= self.policy.max_attempts.max(1) => break e,
+                Err(e) => {
+                    warn!(
+                        "Actor handler failed (attempt {}), retrying: {}",
+                        attempts, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.policy.max_backoff);
+                }
+            }
+        };
+        let letter = DeadLetter {
+            msg,
+            attempts,
+            error,
+        };
+        match &self.dead_letters {
+            Some(dead_letters) => {
+                if let Err(e) = dead_letters.send(letter).await {
+                    error!(
+                        "Dead-letter queue closed, dropping message: {:#}",
+                        e.0.error
+                    );
+                }
+            }
+            None => error!(
+                "Actor handler gave up after {} attempts: {:#}",
+                letter.attempts, letter.error
+            ),
+        }
+    }
+
+    fn receiver(&mut self) -> &mut Receiver<T> {
+        self.inner.receiver()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::ActorHandle;
+    use crate::shutdown::ShutdownCoordinator;
+    use anyhow::bail;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::sync::mpsc;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Job {
+        fails: u32,
+    }
+
+    struct Flaky {
+        receiver: Receiver<Job>,
+        calls: Arc<AtomicU32>,
+        done: Sender<Job>,
+        seen: u32,
+    }
+
+    impl ShutdownHook for Flaky {}
+
+    #[async_trait]
+    impl TryActor<Job> for Flaky {
+        async fn try_handle(&mut self, msg: &Job) -> Result<(), anyhow::Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self.seen < msg.fails {
+                self.seen += 1;
+                bail!("downstream unavailable");
+            }
+            self.seen = 0;
+            let _ = self.done.send(msg.clone()).await;
+            Ok(())
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Job> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_then_dead_letters() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let calls = Arc::new(AtomicU32::new(0));
+        let (done_tx, mut done_rx) = mpsc::channel(4);
+        let (dead_tx, mut dead_rx) = mpsc::channel(4);
+        let mvcalls = calls.clone();
+        let handle = ActorHandle::spawn(
+            move |receiver, _| {
+                let inner = Box::new(Flaky {
+                    receiver,
+                    calls: mvcalls,
+                    done: done_tx,
+                    seen: 0,
+                });
+                Box::new(Retrying::new(inner, RetryPolicy::default()).with_dead_letters(dead_tx))
+            },
+            &mut shutdown,
+        );
+
+        handle.send(Job { fails: 2 }).await;
+        assert_eq!(done_rx.recv().await, Some(Job { fails: 2 }));
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        handle.send(Job { fails: 5 }).await;
+        let letter = dead_rx.recv().await.unwrap();
+        assert_eq!(letter.msg, Job { fails: 5 });
+        assert_eq!(letter.attempts, 3);
+        assert_eq!(letter.error.to_string(), "downstream unavailable");
+        assert_eq!(calls.load(Ordering::Relaxed), 6);
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+    }
+}
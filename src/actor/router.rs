@@ -0,0 +1,259 @@
+//! Entity-per-actor routing: one child actor per key (a user, a device, an
+//! order), spawned on the first message for that key and reaped once idle.
+
+use super::{Actor, ActorHandle};
+use crate::shutdown::{ShutdownCoordinator, ShutdownHook};
+use async_trait::async_trait;
+use futures::future;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+type KeyOf<K, M> = dyn Fn(&M) -> K + Send + Sync;
+type MkChild<K, M> =
+    dyn Fn(&K, Receiver<M>, ActorHandle<M>) -> Box<dyn Actor<M> + Send + Sync> + Send + Sync;
+
+struct Child<M: Clone> {
+    handle: ActorHandle<M>,
+    token: CancellationToken,
+    task: JoinHandle<()>,
+    last_used: Instant,
+}
+
+/// Routes each message to the child actor owning its key.
+///
+/// Children are reaped once they've gone `idle_after` without a message; the
+/// sweep runs as messages arrive, and a reaped child gets its shutdown hook
+/// like any other actor. The next message for that key spawns a fresh child.
+/// A child that stops on its own is respawned the same way.
+///
+/// ```ignore
+/// let sessions = Router::spawn(
+///     |msg: &SessionMsg| msg.user_id,
+///     |user_id, rx, _| Box::new(Session::new(*user_id, rx)),
+///     Duration::from_secs(600),
+///     &mut shutdown,
+/// );
+/// sessions.send(SessionMsg { user_id: 7, .. }).await;
+/// ```
+pub struct Router<K, M: Clone> {
+    receiver: Receiver<M>,
+    key_of: Box<KeyOf<K, M>>,
+    mk_child: Box<MkChild<K, M>>,
+    idle_after: Duration,
+    token: CancellationToken,
+    children: Mutex<HashMap<K, Child<M>>>,
+    draining: Mutex<Vec<Child<M>>>,
+    last_sweep: Instant,
+}
+
+impl<K, M> Router<K, M>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    M: Clone + Send + Sync + 'static,
+{
+    /// Spawns the router and returns the handle messages are sent to.
+    pub fn spawn(
+        key_of: impl Fn(&M) -> K + Send + Sync + 'static,
+        mk_child: impl Fn(&K, Receiver<M>, ActorHandle<M>) -> Box<dyn Actor<M> + Send + Sync>
+        + Send
+        + Sync
+        + 'static,
+        idle_after: Duration,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> ActorHandle<M> {
+        let token = shutdown.token();
+        ActorHandle::spawn(
+            move |receiver, _| {
+                Box::new(Router {
+                    receiver,
+                    key_of: Box::new(key_of),
+                    mk_child: Box::new(mk_child),
+                    idle_after,
+                    token,
+                    children: Mutex::new(HashMap::new()),
+                    draining: Mutex::new(Vec::new()),
+                    last_sweep: Instant::now(),
+                })
+            },
+            shutdown,
+        )
+    }
+
+    fn reap_idle(&mut self, now: Instant) {
+        if now.duration_since(self.last_sweep) < self.idle_after / 2 {
+            return;
+        }
+        self.last_sweep = now;
+        let children = self.children.get_mut();
+        let idle: Vec<K> = children
+            .iter()
+            .filter(|(_, child)| now.duration_since(child.last_used) >= self.idle_after)
+            .map(|(key, _)| key.clone())
+            .collect();
+        // Hold on to reaped children until they exit: dropping the handle
+        // closes the mailbox, which would let the actor stop without its hook
+        let draining = self.draining.get_mut();
+        draining.retain(|child| !child.task.is_finished());
+        for key in idle {
+            if let Some(child) = children.remove(&key) {
+                child.token.cancel();
+                draining.push(child);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<K, M> ShutdownHook for Router<K, M>
+where
+    K: Send + Sync,
+    M: Clone + Send + Sync,
+{
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        // Child tokens hang off the coordinator's, so they're already
+        // cancelled; wait for the children to finish their own hooks.
+        let mut tasks: Vec<_> = self
+            .children
+            .lock()
+            .drain()
+            .map(|(_, child)| child.task)
+            .collect();
+        tasks.extend(self.draining.lock().drain(..).map(|child| child.task));
+        future::join_all(tasks).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<K, M> Actor<M> for Router<K, M>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    M: Clone + Send + Sync + 'static,
+{
+    async fn handle_msg(&mut self, msg: M) {
+        let now = Instant::now();
+        self.reap_idle(now);
+        let key = (self.key_of)(&msg);
+        let children = self.children.get_mut();
+        if children
+            .get(&key)
+            .is_some_and(|child| child.task.is_finished())
+        {
+            children.remove(&key);
+        }
+        let child = children.entry(key).or_insert_with_key(|key| {
+            let token = self.token.child_token();
+            let (handle, task) = ActorHandle::spawn_task(
+                |receiver, handle| (self.mk_child)(key, receiver, handle),
+                Vec::new(),
+                token.clone(),
+            );
+            Child {
+                handle,
+                token,
+                task,
+                last_used: now,
+            }
+        });
+        child.last_used = now;
+        let handle = child.handle.clone();
+        handle.send(msg).await;
+    }
+
+    fn receiver(&mut self) -> &mut Receiver<M> {
+        &mut self.receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::{self, Sender};
+
+    #[derive(Clone)]
+    struct Visit {
+        user: u32,
+    }
+
+    struct Session {
+        receiver: Receiver<Visit>,
+        user: u32,
+        visits: u32,
+        report: Sender<(u32, u32)>,
+        reaped: Arc<Mutex<Vec<u32>>>,
+    }
+
+    #[async_trait]
+    impl ShutdownHook for Session {
+        async fn shutdown(&self) -> anyhow::Result<()> {
+            self.reaped.lock().push(self.user);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Actor<Visit> for Session {
+        async fn handle_msg(&mut self, _msg: Visit) {
+            self.visits += 1;
+            let _ = self.report.send((self.user, self.visits)).await;
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Visit> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn routes_by_key_and_reaps_idle_children() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let (report, mut reports) = mpsc::channel(16);
+        let reaped = Arc::new(Mutex::new(Vec::new()));
+        let mvreaped = reaped.clone();
+        let router = Router::spawn(
+            |msg: &Visit| msg.user,
+            move |user, receiver, _| {
+                Box::new(Session {
+                    receiver,
+                    user: *user,
+                    visits: 0,
+                    report: report.clone(),
+                    reaped: mvreaped.clone(),
+                })
+            },
+            Duration::from_secs(60),
+            &mut shutdown,
+        );
+
+        for user in [1, 2, 1, 1] {
+            router.send(Visit { user }).await;
+        }
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            seen.push(reports.recv().await.unwrap());
+        }
+        seen.sort();
+        assert_eq!(seen, vec![(1, 1), (1, 2), (1, 3), (2, 1)]);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        router.send(Visit { user: 1 }).await;
+        // A fresh session for user 1, and the idle ones were shut down
+        assert_eq!(reports.recv().await, Some((1, 1)));
+        while reaped.lock().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let mut gone = reaped.lock().clone();
+        gone.sort();
+        assert_eq!(gone, vec![1, 2]);
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        assert_eq!(reaped.lock().len(), 3);
+    }
+}
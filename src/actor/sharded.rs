@@ -0,0 +1,204 @@
+//! A fixed pool of actors where every message for a key goes to the same
+//! shard, so per-key ordering holds while keys are processed in parallel.
+
+use super::{Actor, ActorHandle};
+use crate::shutdown::ShutdownCoordinator;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc::Receiver;
+
+/// FNV-1a. `DefaultHasher` may change between Rust releases, and shard
+/// assignment should stay put across builds.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Point-in-time counters for one shard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardStats {
+    pub shard: usize,
+    /// Messages routed to this shard since the pool started.
+    pub routed: u64,
+    /// Messages waiting in the shard's mailbox.
+    pub queued: usize,
+}
+
+struct Shard<M: Clone> {
+    handle: ActorHandle<M>,
+    routed: AtomicU64,
+}
+
+/// A single handle over `N` actors, routing each message by the hash of its
+/// key. Cheap to clone.
+///
+/// ```ignore
+/// let accounts = ShardedHandle::spawn(
+///     8,
+///     |msg: &Txn| msg.account_id,
+///     |shard, rx, _| Box::new(Ledger::new(shard, rx)),
+///     &mut shutdown,
+/// );
+/// accounts.send(txn).await;
+/// ```
+pub struct ShardedHandle<K, M: Clone> {
+    shards: Arc<[Shard<M>]>,
+    key_of: Arc<dyn Fn(&M) -> K + Send + Sync>,
+}
+
+impl<K, M: Clone> Clone for ShardedHandle<K, M> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+            key_of: self.key_of.clone(),
+        }
+    }
+}
+
+impl<K: Hash, M: Clone + Send + Sync + 'static> ShardedHandle<K, M> {
+    /// Spawns `shards` actors (at least one); `mk_actor` receives the shard
+    /// index alongside the usual receiver and handle.
+    pub fn spawn(
+        shards: usize,
+        key_of: impl Fn(&M) -> K + Send + Sync + 'static,
+        mk_actor: impl Fn(usize, Receiver<M>, ActorHandle<M>) -> Box<dyn Actor<M> + Send + Sync>,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|idx| Shard {
+                handle: ActorHandle::spawn(|rx, handle| mk_actor(idx, rx, handle), shutdown),
+                routed: AtomicU64::new(0),
+            })
+            .collect();
+        Self {
+            shards,
+            key_of: Arc::new(key_of),
+        }
+    }
+
+    /// The shard that owns `key`.
+    pub fn shard_for(&self, key: &K) -> usize {
+        let mut hasher = StableHasher::default();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub async fn send(&self, msg: M) {
+        let shard = &self.shards[self.shard_for(&(self.key_of)(&msg))];
+        shard.routed.fetch_add(1, Ordering::Relaxed);
+        shard.handle.send(msg).await
+    }
+
+    pub fn shard_stats(&self) -> Vec<ShardStats> {
+        self.shards
+            .iter()
+            .enumerate()
+            .map(|(idx, shard)| ShardStats {
+                shard: idx,
+                routed: shard.routed.load(Ordering::Relaxed),
+                queued: shard.handle.sender.max_capacity() - shard.handle.sender.capacity(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::ShutdownHook;
+    use async_trait::async_trait;
+    use tokio::sync::mpsc::{self, Sender};
+
+    #[derive(Clone)]
+    struct Event {
+        key: String,
+        seq: u32,
+    }
+
+    struct Worker {
+        receiver: Receiver<Event>,
+        shard: usize,
+        out: Sender<(usize, String, u32)>,
+    }
+
+    impl ShutdownHook for Worker {}
+
+    #[async_trait]
+    impl Actor<Event> for Worker {
+        async fn handle_msg(&mut self, msg: Event) {
+            let _ = self.out.send((self.shard, msg.key, msg.seq)).await;
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Event> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_keys_on_one_shard_in_order() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let (out, mut results) = mpsc::channel(64);
+        let pool = ShardedHandle::spawn(
+            4,
+            |e: &Event| e.key.clone(),
+            |shard, receiver, _| {
+                Box::new(Worker {
+                    receiver,
+                    shard,
+                    out: out.clone(),
+                })
+            },
+            &mut shutdown,
+        );
+        drop(out);
+
+        let keys = ["a", "b", "c", "d", "e", "f"];
+        for seq in 0..5 {
+            for key in keys {
+                pool.send(Event {
+                    key: key.to_string(),
+                    seq,
+                })
+                .await;
+            }
+        }
+
+        let mut by_key: std::collections::HashMap<String, Vec<u32>> = Default::default();
+        for _ in 0..30 {
+            let (shard, key, seq) = results.recv().await.unwrap();
+            assert_eq!(shard, pool.shard_for(&key));
+            by_key.entry(key).or_default().push(seq);
+        }
+        for seqs in by_key.values() {
+            assert_eq!(seqs, &vec![0, 1, 2, 3, 4]);
+        }
+        let stats = pool.shard_stats();
+        assert_eq!(stats.iter().map(|s| s.routed).sum::<u64>(), 30);
+        assert!(stats.iter().all(|s| s.queued == 0));
+
+        // Assignment must not drift between builds: pin the hash itself
+        let mut h = StableHasher::default();
+        h.write(b"a");
+        assert_eq!(h.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+    }
+}
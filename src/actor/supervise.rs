@@ -0,0 +1,167 @@
+//! Tell an owning actor when a child actor dies, instead of letting it find
+//! out through requests that never get answered.
+
+use super::{Actor, ActorHandle};
+use crate::shutdown::ShutdownCoordinator;
+use tokio::sync::mpsc::Receiver;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationReason {
+    /// `handle_msg` panicked; carries the panic message when it was a string.
+    Panicked(String),
+    /// Every handle was dropped and the mailbox drained before shutdown.
+    Stopped,
+}
+
+/// Sent to the parent when a watched actor ends outside of shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActorTerminated {
+    pub name: String,
+    pub reason: TerminationReason,
+}
+
+impl<T: Clone + Send + Sync + 'static> ActorHandle<T> {
+    /// Like `spawn`, but sends `ActorTerminated` to `parent` if the actor
+    /// panics or stops before shutdown. Ending because of shutdown is not
+    /// reported.
+    ///
+    /// ```ignore
+    /// enum ParentMsg { Child(ActorTerminated), .. }
+    /// impl From<ActorTerminated> for ParentMsg { .. }
+    ///
+    /// let worker = ActorHandle::spawn_watched("worker", mk_worker, parent.clone(), &mut shutdown);
+    /// ```
+    pub fn spawn_watched<P>(
+        name: impl Into<String>,
+        mk_actor: impl FnOnce(Receiver<T>, ActorHandle<T>) -> Box<dyn Actor<T> + Send + Sync>,
+        parent: ActorHandle<P>,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Self
+    where
+        P: From<ActorTerminated> + Clone + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let token = shutdown.token();
+        let (handle, task) = Self::spawn_task(mk_actor, Vec::new(), token.clone());
+        let watcher = tokio::spawn(async move {
+            let reason = match task.await {
+                Err(e) if e.is_panic() => {
+                    let panic = e.into_panic();
+                    let msg = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    TerminationReason::Panicked(msg)
+                }
+                _ if token.is_cancelled() => return,
+                _ => TerminationReason::Stopped,
+            };
+            parent.send(ActorTerminated { name, reason }.into()).await;
+        });
+        shutdown.register_task(watcher);
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::ShutdownHook;
+    use async_trait::async_trait;
+    use tokio::sync::mpsc::{self, Sender};
+
+    #[derive(Clone)]
+    struct Crash;
+
+    struct Fragile {
+        receiver: Receiver<Crash>,
+    }
+
+    impl ShutdownHook for Fragile {}
+
+    #[async_trait]
+    impl Actor<Crash> for Fragile {
+        async fn handle_msg(&mut self, _msg: Crash) {
+            panic!("bad input");
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Crash> {
+            &mut self.receiver
+        }
+    }
+
+    #[derive(Clone)]
+    struct Lost(ActorTerminated);
+
+    impl From<ActorTerminated> for Lost {
+        fn from(value: ActorTerminated) -> Self {
+            Lost(value)
+        }
+    }
+
+    struct Parent {
+        receiver: Receiver<Lost>,
+        out: Sender<ActorTerminated>,
+    }
+
+    impl ShutdownHook for Parent {}
+
+    #[async_trait]
+    impl Actor<Lost> for Parent {
+        async fn handle_msg(&mut self, msg: Lost) {
+            let _ = self.out.send(msg.0).await;
+        }
+
+        fn receiver(&mut self) -> &mut Receiver<Lost> {
+            &mut self.receiver
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_panics_and_early_stops_but_not_shutdown() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let (out, mut lost) = mpsc::channel(4);
+        let parent = ActorHandle::spawn(
+            |receiver, _| Box::new(Parent { receiver, out }),
+            &mut shutdown,
+        );
+
+        let fragile = ActorHandle::spawn_watched(
+            "fragile",
+            |receiver, _| Box::new(Fragile { receiver }),
+            parent.clone(),
+            &mut shutdown,
+        );
+        fragile.send(Crash).await;
+        assert_eq!(
+            lost.recv().await,
+            Some(ActorTerminated {
+                name: "fragile".into(),
+                reason: TerminationReason::Panicked("bad input".into()),
+            })
+        );
+
+        let dropped = ActorHandle::spawn_watched(
+            "dropped",
+            |receiver, _| Box::new(Fragile { receiver }),
+            parent.clone(),
+            &mut shutdown,
+        );
+        drop(dropped);
+        assert_eq!(
+            lost.recv().await.unwrap().reason,
+            TerminationReason::Stopped
+        );
+
+        let _idle = ActorHandle::spawn_watched(
+            "idle",
+            |receiver, _| Box::new(Fragile { receiver }),
+            parent,
+            &mut shutdown,
+        );
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        assert!(lost.try_recv().is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Fault-injection wrappers for exercising retry and supervision paths.
+//!
+//! Every wrapper draws from a seeded RNG shared between its clones, so a
+//! failing test can be replayed exactly by reusing the seed.
+
+use crate::actor::ActorHandle;
+use crate::rng::SeededRng;
+use crate::simple_store::{FetchResult, Fetcher, StorageBackend, Store};
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::debug;
+
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Delay uniformly drawn from this range before each call.
+    pub latency: Option<(Duration, Duration)>,
+    /// Probability that a call fails outright.
+    pub error_rate: f64,
+    /// Probability that a call is dropped: a send is discarded, a fetch never
+    /// completes (pair it with a timeout), a persist reports success without
+    /// storing anything and a load finds nothing stored.
+    pub drop_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    None,
+    Error,
+    Drop,
+}
+
+#[derive(Clone)]
+struct Chaos {
+    config: Arc<ChaosConfig>,
+    rng: Arc<Mutex<SeededRng>>,
+}
+
+impl Chaos {
+    fn new(config: ChaosConfig) -> Self {
+        Self {
+            rng: Arc::new(Mutex::new(SeededRng::new(config.seed))),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Draws the latency and fault for one call up front so the sequence of
+    /// draws doesn't depend on how long the calls take.
+    fn draw(&self) -> (Option<Duration>, Fault) {
+        let mut rng = self.rng.lock();
+        let delay = self
+            .config
+            .latency
+            .map(|(min, max)| rng.duration_between(min, max));
+        let fault = if rng.chance(self.config.drop_rate) {
+            Fault::Drop
+        } else if rng.chance(self.config.error_rate) {
+            Fault::Error
+        } else {
+            Fault::None
+        };
+        (delay, fault)
+    }
+
+    async fn roll(&self) -> Fault {
+        let (delay, fault) = self.draw();
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+        fault
+    }
+
+    /// `roll` for synchronous calls, blocking the thread for the latency.
+    fn roll_blocking(&self) -> Fault {
+        let (delay, fault) = self.draw();
+        if let Some(delay) = delay {
+            std::thread::sleep(delay);
+        }
+        fault
+    }
+}
+
+/// Wraps a `Fetcher`, injecting latency, errors, and hangs.
+#[derive(Clone)]
+pub struct ChaosFetcher<F> {
+    inner: F,
+    chaos: Chaos,
+}
+
+impl<F> ChaosFetcher<F> {
+    pub fn new(inner: F, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            chaos: Chaos::new(config),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for ChaosFetcher<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        match self.chaos.roll().await {
+            Fault::None => self.inner.fetch(store).await,
+            Fault::Error => bail!("chaos: injected fetch failure"),
+            Fault::Drop => {
+                debug!("chaos: dropping fetch");
+                futures::future::pending().await
+            }
+        }
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        match self.chaos.roll().await {
+            Fault::None => self.inner.fetch_update(store).await,
+            Fault::Error => bail!("chaos: injected fetch failure"),
+            Fault::Drop => {
+                debug!("chaos: dropping fetch");
+                futures::future::pending().await
+            }
+        }
+    }
+}
+
+/// Wraps an `ActorHandle`, delaying and discarding messages. Injected errors
+/// also discard, since sends are fire-and-forget.
+#[derive(Clone)]
+pub struct ChaosHandle<T: Clone> {
+    inner: ActorHandle<T>,
+    chaos: Chaos,
+}
+
+impl<T: Clone + Send + Sync + 'static> ChaosHandle<T> {
+    pub fn new(inner: ActorHandle<T>, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            chaos: Chaos::new(config),
+        }
+    }
+
+    pub async fn send(&self, msg: T) {
+        match self.chaos.roll().await {
+            Fault::None => self.inner.send(msg).await,
+            fault => debug!("chaos: discarding message ({:?})", fault),
+        }
+    }
+}
+
+/// Wraps a `StorageBackend`, injecting latency, errors, and lost reads and
+/// writes. Latency blocks the calling thread, as a slow disk or network
+/// would, since `Store` calls its backend synchronously.
+///
+/// ```ignore
+/// let backend = ChaosBackend::new(FileBackend::new(loc), ChaosConfig { error_rate: 0.1, ..Default::default() });
+/// let store = Store::with_backend(backend, Catalog::default)?;
+/// ```
+pub struct ChaosBackend<B> {
+    inner: B,
+    chaos: Chaos,
+}
+
+impl<B> ChaosBackend<B> {
+    pub fn new(inner: B, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            chaos: Chaos::new(config),
+        }
+    }
+}
+
+impl<B: StorageBackend> StorageBackend for ChaosBackend<B> {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match self.chaos.roll_blocking() {
+            Fault::None => self.inner.load(),
+            Fault::Error => Err(anyhow!("chaos: injected load failure")),
+            Fault::Drop => {
+                debug!("chaos: dropping load");
+                Ok(None)
+            }
+        }
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        match self.chaos.roll_blocking() {
+            Fault::None => self.inner.persist(bytes),
+            Fault::Error => Err(anyhow!("chaos: injected persist failure")),
+            Fault::Drop => {
+                debug!("chaos: dropping persist");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::MemoryBackend;
+    use crate::simple_store::testing::Count;
+
+    #[derive(Clone)]
+    struct Constant;
+
+    #[async_trait]
+    impl Fetcher<u32> for Constant {
+        async fn fetch(&self, _store: Option<Store<u32>>) -> Result<u32, anyhow::Error> {
+            Ok(1)
+        }
+    }
+
+    async fn outcomes(config: ChaosConfig) -> Vec<bool> {
+        let fetcher = ChaosFetcher::new(Constant, config);
+        let mut out = Vec::new();
+        for _ in 0..32 {
+            out.push(fetcher.fetch(None).await.is_ok());
+        }
+        out
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeded_faults_are_reproducible() {
+        let config = ChaosConfig {
+            seed: 42,
+            latency: Some((Duration::from_millis(5), Duration::from_millis(50))),
+            error_rate: 0.5,
+            drop_rate: 0.0,
+        };
+        let first = outcomes(config.clone()).await;
+        assert_eq!(first, outcomes(config.clone()).await);
+        assert!(first.contains(&true) && first.contains(&false));
+
+        let never = outcomes(ChaosConfig::default()).await;
+        assert!(never.iter().all(|ok| *ok));
+    }
+
+    #[test]
+    fn backend_faults_fail_or_lose_writes() {
+        let memory = MemoryBackend::new();
+        let failing = ChaosConfig {
+            error_rate: 1.0,
+            ..Default::default()
+        };
+        let backend = ChaosBackend::new(memory.clone(), failing);
+        assert!(Store::<Count>::with_backend(backend, || Ok(Count(1))).is_err());
+        assert_eq!(memory.bytes(), None);
+
+        let store = Store::with_backend(memory.clone(), || Ok(Count(1))).unwrap();
+        drop(store);
+        let dropping = ChaosConfig {
+            seed: 7,
+            drop_rate: 0.5,
+            ..Default::default()
+        };
+        let store =
+            Store::with_backend(ChaosBackend::new(memory.clone(), dropping), || Ok(Count(0)))
+                .unwrap();
+        let mut lost = 0;
+        for n in 2..34 {
+            store.write(Count(n)).unwrap();
+            if memory.bytes() != Some(vec![n]) {
+                lost += 1;
+            }
+        }
+        assert!(lost > 0 && lost < 32);
+    }
+}
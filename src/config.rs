@@ -0,0 +1,652 @@
+use crate::env;
+use anyhow::{Context, anyhow, bail};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub use anyhow::Error;
+#[cfg(feature = "derive")]
+pub use kitchen_sink_macros::AppConfig;
+
+/// A configuration struct that can be assembled from layered sources.
+///
+/// Usually implemented with `#[derive(AppConfig)]` (behind the `derive`
+/// feature), which wires each field to a `FieldLoader` lookup:
+///
+/// ```ignore
+/// #[derive(AppConfig)]
+/// struct Settings {
+///     #[config(default = "8080")]
+///     port: u16,
+///     #[config(env = "DATABASE_URL", secret)]
+///     database_url: String,
+///     #[config(default = "30s", with = "kitchen_sink::env::parse_duration")]
+///     timeout: Duration,
+///     log_dir: Option<PathBuf>,
+/// }
+///
+/// let settings = Settings::load(ConfigSources::new().with_env_prefix("APP").with_process_args())?;
+/// ```
+pub trait AppConfig: Sized {
+    /// Every field's spec, so CLI flags can be told apart from positional
+    /// arguments.
+    fn fields() -> Vec<FieldSpec>;
+
+    fn from_sources(sources: &ConfigSources) -> Result<Self, Error>;
+
+    /// Parses the sources' CLI arguments against `fields`, then loads.
+    fn load(mut sources: ConfigSources) -> Result<Self, Error> {
+        sources.parse_args(&Self::fields())?;
+        Self::from_sources(&sources)
+    }
+}
+
+/// Where a configuration value came from, in increasing order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli(String),
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provenance::Default => write!(f, "default"),
+            Provenance::File(path) => write!(f, "file {}", path.display()),
+            Provenance::Env(name) => write!(f, "env {}", name),
+            Provenance::Cli(flag) => write!(f, "flag --{}", flag),
+        }
+    }
+}
+
+/// Describes how a single field is looked up. `env` and `cli` override the
+/// names derived from `key`. A `flag` takes no value on the command line:
+/// `--name` alone reads as `true`. A `secret` value is never shown in
+/// error reports.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSpec {
+    pub key: &'static str,
+    pub env: Option<&'static str>,
+    pub cli: Option<&'static str>,
+    pub default: Option<&'static str>,
+    pub flag: bool,
+    pub secret: bool,
+}
+
+pub struct RawValue {
+    pub value: String,
+    pub provenance: Provenance,
+}
+
+type Lookup = dyn Fn(&str) -> Option<String>;
+
+/// The context for a value of `spec` that failed to parse or validate.
+fn invalid(spec: &FieldSpec, raw: &RawValue) -> String {
+    let value = if spec.secret {
+        "<redacted>".to_string()
+    } else {
+        format!("{:?}", raw.value)
+    };
+    format!(
+        "{} has invalid value {} (from {})",
+        spec.key, value, raw.provenance
+    )
+}
+
+/// The layered inputs a config is loaded from. Later layers win:
+/// defaults < file < environment < CLI flags.
+pub struct ConfigSources {
+    file: Option<(PathBuf, HashMap<String, String>)>,
+    env_prefix: Option<String>,
+    env: Box<Lookup>,
+    args: Vec<String>,
+    cli: HashMap<String, String>,
+}
+
+impl Default for ConfigSources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigSources {
+    pub fn new() -> Self {
+        Self {
+            file: None,
+            env_prefix: None,
+            env: Box::new(|name| std::env::var(name).ok()),
+            args: Vec::new(),
+            cli: HashMap::new(),
+        }
+    }
+
+    /// Loads a TOML file. Keys in tables are read as `table.key`, so a
+    /// field named `db.url` comes from `url` under `[db]`.
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let values = parse_file(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        self.file = Some((path.to_path_buf(), values));
+        Ok(self)
+    }
+
+    /// Prefixes derived env names, so field `port` reads `<PREFIX>_PORT`.
+    pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Replaces the process environment with a custom lookup, mostly for tests.
+    pub fn with_env_source(mut self, source: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        self.env = Box::new(source);
+        self
+    }
+
+    /// CLI arguments, without the program name, parsed by `parse_args`
+    /// once the fields are known (`AppConfig::load` does so). Fields take
+    /// `--key value` or `--key=value`, flags a bare `--flag`; positional
+    /// arguments are ignored.
+    pub fn with_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Reads flags from `std::env::args`, skipping the program name.
+    pub fn with_process_args(self) -> Self {
+        self.with_args(std::env::args().skip(1))
+    }
+
+    /// Parses the arguments given to `with_args` as the flags of `fields`,
+    /// failing on unknown flags and missing values. Behind the `clap`
+    /// feature, through clap, whose error for `--help` carries the usage.
+    pub fn parse_args(&mut self, fields: &[FieldSpec]) -> Result<(), Error> {
+        self.cli = parse_args(&self.args, fields)?;
+        Ok(())
+    }
+
+    pub fn env_name(&self, spec: &FieldSpec) -> String {
+        match (spec.env, &self.env_prefix) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(prefix)) => format!("{}_{}", prefix, spec.key).to_ascii_uppercase(),
+            (None, None) => spec.key.to_ascii_uppercase(),
+        }
+    }
+
+    pub fn cli_name(spec: &FieldSpec) -> String {
+        spec.cli
+            .map(str::to_string)
+            .unwrap_or_else(|| spec.key.replace('_', "-"))
+    }
+
+    /// Resolves the highest-precedence value for a field, if any.
+    pub fn lookup(&self, spec: &FieldSpec) -> Option<RawValue> {
+        let cli = Self::cli_name(spec);
+        if let Some(value) = self.cli.get(&cli) {
+            return Some(RawValue {
+                value: value.clone(),
+                provenance: Provenance::Cli(cli),
+            });
+        }
+        let env = self.env_name(spec);
+        if let Some(value) = (self.env)(&env) {
+            return Some(RawValue {
+                value,
+                provenance: Provenance::Env(env),
+            });
+        }
+        if let Some((path, values)) = &self.file
+            && let Some(value) = values.get(spec.key)
+        {
+            return Some(RawValue {
+                value: value.clone(),
+                provenance: Provenance::File(path.clone()),
+            });
+        }
+        spec.default.map(|value| RawValue {
+            value: value.to_string(),
+            provenance: Provenance::Default,
+        })
+    }
+}
+
+fn parse_file(contents: &str) -> Result<HashMap<String, String>, Error> {
+    fn flatten(
+        prefix: &str,
+        table: toml::Table,
+        values: &mut HashMap<String, String>,
+    ) -> Result<(), Error> {
+        for (key, value) in table {
+            let key = match prefix {
+                "" => key,
+                _ => format!("{}.{}", prefix, key),
+            };
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Table(table) => {
+                    flatten(&key, table, values)?;
+                    continue;
+                }
+                toml::Value::Array(_) => bail!("{}: arrays aren't supported", key),
+                other => other.to_string(),
+            };
+            values.insert(key, value);
+        }
+        Ok(())
+    }
+    let mut values = HashMap::new();
+    flatten("", contents.parse()?, &mut values)?;
+    Ok(values)
+}
+
+#[cfg(feature = "clap")]
+fn parse_args(args: &[String], fields: &[FieldSpec]) -> Result<HashMap<String, String>, Error> {
+    use clap::parser::ValueSource;
+    use clap::{Arg, ArgAction, Command};
+
+    let program = std::env::args()
+        .next()
+        .as_deref()
+        .and_then(|p| Path::new(p).file_name()?.to_str().map(str::to_string))
+        .unwrap_or_else(|| "app".to_string());
+    let names: Vec<String> = fields.iter().map(ConfigSources::cli_name).collect();
+    // Positional arguments are the application's own business
+    let mut command = Command::new(program)
+        .no_binary_name(true)
+        .arg(Arg::new("[args]").num_args(0..).hide(true));
+    for (spec, name) in fields.iter().zip(&names) {
+        let action = if spec.flag {
+            ArgAction::SetTrue
+        } else {
+            ArgAction::Set
+        };
+        command = command.arg(Arg::new(name.clone()).long(name.clone()).action(action));
+    }
+    let matches = command.try_get_matches_from(args)?;
+    let mut cli = HashMap::new();
+    for (spec, name) in fields.iter().zip(names) {
+        if matches.value_source(&name) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let value = if spec.flag {
+            "true".to_string()
+        } else {
+            matches
+                .get_one::<String>(&name)
+                .cloned()
+                .unwrap_or_default()
+        };
+        cli.insert(name, value);
+    }
+    Ok(cli)
+}
+
+#[cfg(not(feature = "clap"))]
+fn parse_args(args: &[String], fields: &[FieldSpec]) -> Result<HashMap<String, String>, Error> {
+    let mut cli = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            continue;
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let spec = fields
+            .iter()
+            .find(|spec| ConfigSources::cli_name(spec) == name)
+            .ok_or_else(|| anyhow!("unexpected argument --{}", name))?;
+        let value = match (value, spec.flag) {
+            (Some(value), _) => value,
+            (None, true) => "true".to_string(),
+            (None, false) => args
+                .next()
+                .cloned()
+                .ok_or_else(|| anyhow!("--{} needs a value", name))?,
+        };
+        cli.insert(name.to_string(), value);
+    }
+    Ok(cli)
+}
+
+/// Loads fields one at a time, collecting every failure so the caller sees a
+/// single report rather than the first bad key.
+pub struct FieldLoader<'a> {
+    sources: &'a ConfigSources,
+    errors: Vec<Error>,
+}
+
+impl<'a> FieldLoader<'a> {
+    pub fn new(sources: &'a ConfigSources) -> Self {
+        Self {
+            sources,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Loads a field that must resolve to a value.
+    pub fn required<T, F>(&mut self, spec: FieldSpec, parser: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, Error>,
+    {
+        match self.sources.lookup(&spec) {
+            Some(raw) => self.parse(&spec, raw, parser),
+            None => {
+                self.errors.push(anyhow!(
+                    "{} is not set (env {} or flag --{})",
+                    spec.key,
+                    self.sources.env_name(&spec),
+                    ConfigSources::cli_name(&spec)
+                ));
+                None
+            }
+        }
+    }
+
+    /// Loads a field that may be absent.
+    pub fn optional<T, F>(&mut self, spec: FieldSpec, parser: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, Error>,
+    {
+        let raw = self.sources.lookup(&spec)?;
+        self.parse(&spec, raw, parser)
+    }
+
+    /// Runs `validator` on a loaded value, reporting a failure against the
+    /// field and the source it came from. Absent values are skipped, since
+    /// `required` already reported them.
+    pub fn validate<T, F>(&mut self, spec: FieldSpec, value: Option<&T>, validator: F)
+    where
+        F: FnOnce(&T) -> Result<(), Error>,
+    {
+        let Some(value) = value else {
+            return;
+        };
+        if let Err(e) = validator(value) {
+            let context = match self.sources.lookup(&spec) {
+                Some(raw) => invalid(&spec, &raw),
+                None => format!("{} is invalid", spec.key),
+            };
+            self.errors.push(e.context(context));
+        }
+    }
+
+    fn parse<T, F>(&mut self, spec: &FieldSpec, raw: RawValue, parser: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, Error>,
+    {
+        parser(raw.value.trim())
+            .with_context(|| invalid(spec, &raw))
+            .map_err(|e| self.errors.push(e))
+            .ok()
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let report = self
+            .errors
+            .iter()
+            .map(|e| format!("  - {:#}", e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!(
+            "{} configuration value(s) missing or invalid:\n{}",
+            self.errors.len(),
+            report
+        )
+    }
+}
+
+/// Checks that span several fields, run by the derive after every field
+/// loaded and passed its own validators. Opt in with `#[config(validate)]` on
+/// the struct.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Error>;
+}
+
+/// Field validators for `#[config(validate = "...")]` and
+/// `FieldLoader::validate`.
+///
+/// ```ignore
+/// use kitchen_sink::config::validate::{one_of, range, url};
+///
+/// #[derive(AppConfig)]
+/// struct Settings {
+///     #[config(default = "8080", validate = "range(1, 65535)")]
+///     port: u16,
+///     #[config(default = "info", validate = "one_of(&[\"debug\", \"info\", \"warn\"])")]
+///     level: String,
+///     #[config(validate = "url()")]
+///     upstream: String,
+///     #[config(default = "30s", with = "kitchen_sink::env::parse_duration",
+///              validate = "range(Duration::from_secs(1), Duration::from_secs(300))")]
+///     timeout: Duration,
+/// }
+/// ```
+pub mod validate {
+    use super::Error;
+    use anyhow::bail;
+    use std::fmt::Debug;
+
+    /// Inclusive bounds; works for numbers and `Duration`s alike.
+    pub fn range<T: PartialOrd + Debug>(min: T, max: T) -> impl Fn(&T) -> Result<(), Error> {
+        move |value| {
+            if *value < min || *value > max {
+                bail!("must be between {:?} and {:?}", min, max);
+            }
+            Ok(())
+        }
+    }
+
+    pub fn one_of<T: AsRef<str>>(
+        allowed: &'static [&'static str],
+    ) -> impl Fn(&T) -> Result<(), Error> {
+        move |value| {
+            if !allowed.contains(&value.as_ref()) {
+                bail!("must be one of {}", allowed.join(", "));
+            }
+            Ok(())
+        }
+    }
+
+    /// A `scheme://host...` URL. Only the shape is checked.
+    pub fn url<T: AsRef<str>>() -> impl Fn(&T) -> Result<(), Error> {
+        |value| {
+            let value = value.as_ref();
+            let well_formed = value.split_once("://").is_some_and(|(scheme, rest)| {
+                !scheme.is_empty()
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                    && !rest.is_empty()
+                    && !rest.starts_with('/')
+            });
+            if !well_formed {
+                bail!("must be a URL like scheme://host");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Default field parser used by the derive, delegating to `FromStr`.
+pub fn parse_str<T>(raw: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    env::parse_from_str(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: FieldSpec = FieldSpec {
+        key: "port",
+        env: None,
+        cli: None,
+        default: Some("8080"),
+        flag: false,
+        secret: false,
+    };
+    const HOST: FieldSpec = FieldSpec {
+        key: "host",
+        env: Some("BIND_HOST"),
+        cli: None,
+        default: None,
+        flag: false,
+        secret: false,
+    };
+    const VERBOSE: FieldSpec = FieldSpec {
+        key: "verbose",
+        env: None,
+        cli: None,
+        default: None,
+        flag: true,
+        secret: false,
+    };
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let sources = ConfigSources::new().with_env_source(|_| None);
+        assert_eq!(sources.lookup(&PORT).unwrap().value, "8080");
+
+        let sources = sources
+            .with_env_prefix("app")
+            .with_env_source(|k| (k == "APP_PORT").then(|| "9000".to_string()));
+        let raw = sources.lookup(&PORT).unwrap();
+        assert_eq!(raw.value, "9000");
+        assert_eq!(raw.provenance, Provenance::Env("APP_PORT".into()));
+
+        let mut sources = sources.with_args(["--port=9100", "--verbose"]);
+        sources.parse_args(&[PORT, VERBOSE]).unwrap();
+        let raw = sources.lookup(&PORT).unwrap();
+        assert_eq!(raw.value, "9100");
+        assert_eq!(raw.provenance, Provenance::Cli("port".into()));
+    }
+
+    #[test]
+    fn loader_aggregates_errors() {
+        let mut sources = ConfigSources::new()
+            .with_env_source(|_| None)
+            .with_args(["--port", "eighty"]);
+        sources.parse_args(&[PORT, HOST]).unwrap();
+        let mut loader = FieldLoader::new(&sources);
+        let port: Option<u16> = loader.required(PORT, parse_str);
+        let host: Option<String> = loader.required(HOST, parse_str);
+        assert!(port.is_none() && host.is_none());
+
+        let report = format!("{}", loader.finish().unwrap_err());
+        assert!(report.contains("port has invalid value \"eighty\" (from flag --port)"));
+        assert!(report.contains("host is not set (env BIND_HOST or flag --host)"));
+    }
+
+    #[test]
+    fn validators_report_with_provenance() {
+        let mut sources = ConfigSources::new()
+            .with_env_source(|k| (k == "BIND_HOST").then(|| "localhost".to_string()))
+            .with_args(["--port", "80"]);
+        sources.parse_args(&[PORT, HOST]).unwrap();
+        let mut loader = FieldLoader::new(&sources);
+        let port: Option<u16> = loader.required(PORT, parse_str);
+        loader.validate(PORT, port.as_ref(), validate::range(1024, 65535));
+        let host: Option<String> = loader.required(HOST, parse_str);
+        loader.validate(HOST, host.as_ref(), validate::url());
+        loader.validate(HOST, host.as_ref(), validate::one_of(&["localhost"]));
+
+        let report = format!("{}", loader.finish().unwrap_err());
+        assert!(report.starts_with("2 configuration value(s)"));
+        assert!(report.contains(
+            "port has invalid value \"80\" (from flag --port): must be between 1024 and 65535"
+        ));
+        assert!(report.contains("host has invalid value \"localhost\" (from env BIND_HOST)"));
+
+        let url = validate::url::<&str>();
+        assert!(url(&"https://example.com/x").is_ok());
+        assert!(url(&"example.com").is_err());
+        assert!(url(&"http:///path").is_err());
+    }
+
+    #[test]
+    fn secret_values_stay_out_of_reports() {
+        const PIN: FieldSpec = FieldSpec {
+            key: "pin",
+            env: None,
+            cli: None,
+            default: None,
+            flag: false,
+            secret: true,
+        };
+        const TOKEN: FieldSpec = FieldSpec {
+            key: "token",
+            secret: true,
+            ..HOST
+        };
+        let mut sources = ConfigSources::new()
+            .with_env_source(|k| (k == "BIND_HOST").then(|| "hunter2".to_string()))
+            .with_args(["--pin", "hunter3"]);
+        sources.parse_args(&[PIN, TOKEN]).unwrap();
+        let mut loader = FieldLoader::new(&sources);
+        let pin: Option<u16> = loader.required(PIN, parse_str);
+        let token: Option<String> = loader.required(TOKEN, parse_str);
+        loader.validate(TOKEN, token.as_ref(), validate::url());
+        assert!(pin.is_none());
+
+        let report = format!("{}", loader.finish().unwrap_err());
+        assert!(report.contains("pin has invalid value <redacted> (from flag --pin)"));
+        assert!(report.contains("token has invalid value <redacted> (from env BIND_HOST)"));
+        assert!(!report.contains("hunter"));
+    }
+
+    #[test]
+    fn flags_leave_positional_arguments_alone() {
+        let mut sources = ConfigSources::new().with_env_source(|_| None).with_args([
+            "--verbose",
+            "input.txt",
+            "--host",
+            "example.com",
+            "out",
+        ]);
+        sources.parse_args(&[PORT, HOST, VERBOSE]).unwrap();
+        assert_eq!(sources.lookup(&VERBOSE).unwrap().value, "true");
+        assert_eq!(sources.lookup(&HOST).unwrap().value, "example.com");
+        assert_eq!(
+            sources.lookup(&PORT).unwrap().provenance,
+            Provenance::Default
+        );
+
+        let mut unknown = ConfigSources::new().with_args(["--prot", "80"]);
+        assert!(unknown.parse_args(&[PORT]).is_err());
+        let mut missing = ConfigSources::new().with_args(["--port"]);
+        assert!(missing.parse_args(&[PORT]).is_err());
+    }
+
+    #[test]
+    fn parses_toml_files() {
+        let values = parse_file(
+            "# comment\nport = 80\nname = \"svc\"\ndebug = true\n\n[db]\nurl = \"postgres://db\"\n",
+        )
+        .unwrap();
+        assert_eq!(values["port"], "80");
+        assert_eq!(values["name"], "svc");
+        assert_eq!(values["debug"], "true");
+        assert_eq!(values["db.url"], "postgres://db");
+        assert!(parse_file("nonsense").is_err());
+        assert!(parse_file("hosts = [\"a\", \"b\"]").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context, anyhow, bail};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Reads `name` from the environment and parses it with `FromStr`, failing
+/// when the variable is unset or does not parse.
+///
+/// # Example
+/// ```no_run
+/// use kitchen_sink::env;
+/// let port: u16 = env::required("PORT").unwrap();
+/// ```
+pub fn required<T>(name: &str) -> Result<T, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    required_with(name, parse_from_str)
+}
+
+/// Like `required`, but an unset variable yields `None`. A set but invalid
+/// value is still an error.
+pub fn optional<T>(name: &str) -> Result<Option<T>, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    optional_with(name, parse_from_str)
+}
+
+/// Like `optional`, falling back to `default` when the variable is unset.
+pub fn optional_with_default<T>(name: &str, default: T) -> Result<T, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    Ok(optional(name)?.unwrap_or(default))
+}
+
+/// Reads `name` and parses it with a custom parser, such as `parse_duration`.
+pub fn required_with<T, F>(name: &str, parser: F) -> Result<T, anyhow::Error>
+where
+    F: FnOnce(&str) -> Result<T, anyhow::Error>,
+{
+    match lookup(name) {
+        Some(raw) => parse_var(name, &raw, parser),
+        None => bail!("{} is not set", name),
+    }
+}
+
+/// Reads `name` with a custom parser, yielding `None` when unset.
+pub fn optional_with<T, F>(name: &str, parser: F) -> Result<Option<T>, anyhow::Error>
+where
+    F: FnOnce(&str) -> Result<T, anyhow::Error>,
+{
+    lookup(name)
+        .map(|raw| parse_var(name, &raw, parser))
+        .transpose()
+}
+
+fn lookup(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn parse_var<T, F>(name: &str, raw: &str, parser: F) -> Result<T, anyhow::Error>
+where
+    F: FnOnce(&str) -> Result<T, anyhow::Error>,
+{
+    parser(raw.trim()).with_context(|| format!("{} has invalid value {:?}", name, raw))
+}
+
+pub(crate) fn parse_from_str<T>(raw: &str) -> Result<T, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    raw.parse::<T>().map_err(|e| anyhow!("{}", e))
+}
+
+/// Parses a duration such as `250ms`, `30s`, `5m`, `1h30m`, or `2d`. A bare
+/// number is taken as seconds.
+pub fn parse_duration(raw: &str) -> Result<Duration, anyhow::Error> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        bail!("empty duration");
+    }
+    if let Ok(secs) = raw.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|e| anyhow!("{}", e));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let num_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(|| anyhow!("missing unit in {:?}", raw))?;
+        let (num, tail) = rest.split_at(num_end);
+        let unit_end = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+        let value: f64 = num
+            .parse()
+            .map_err(|_| anyhow!("invalid number {:?} in {:?}", num, raw))?;
+        let scale = match unit.trim().to_ascii_lowercase().as_str() {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            "d" | "day" | "days" => 86400.0,
+            other => bail!("unknown duration unit {:?} in {:?}", other, raw),
+        };
+        total += Duration::try_from_secs_f64(value * scale).map_err(|e| anyhow!("{}", e))?;
+        rest = tail.trim_start();
+    }
+    Ok(total)
+}
+
+/// Parses a byte size such as `512`, `64k`, `10MB`, or `1.5GiB`. Decimal units
+/// (`kb`, `mb`, ...) are powers of 1000, binary units (`kib`, `mib`, ...) and
+/// bare single letters (`k`, `m`, ...) are powers of 1024.
+pub fn parse_bytes(raw: &str) -> Result<u64, anyhow::Error> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(raw.len());
+    let (num, unit) = raw.split_at(split);
+    let value: f64 = num
+        .parse()
+        .map_err(|_| anyhow!("invalid byte size {:?}", raw))?;
+    let scale: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "k" | "kib" => 1024.0,
+        "m" | "mib" => 1024f64.powi(2),
+        "g" | "gib" => 1024f64.powi(3),
+        "t" | "tib" => 1024f64.powi(4),
+        other => bail!("unknown byte size unit {:?} in {:?}", other, raw),
+    };
+    Ok((value * scale).round() as u64)
+}
+
+/// Parses a boolean, accepting `true/false`, `yes/no`, `y/n`, `on/off`, and
+/// `1/0` in any case.
+pub fn parse_bool(raw: &str) -> Result<bool, anyhow::Error> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "y" | "on" | "1" => Ok(true),
+        "false" | "no" | "n" | "off" | "0" => Ok(false),
+        _ => bail!("invalid boolean {:?}", raw),
+    }
+}
+
+type Lookup = dyn Fn(&str) -> Option<String>;
+
+/// Collects every missing or invalid variable instead of failing on the first,
+/// so startup can report all configuration problems at once.
+///
+/// # Example
+/// ```no_run
+/// use kitchen_sink::env::{self, EnvCollector};
+/// use std::time::Duration;
+///
+/// let mut vars = EnvCollector::new();
+/// let port: Option<u16> = vars.required("PORT");
+/// let timeout = vars.optional_with("TIMEOUT", env::parse_duration, Duration::from_secs(5));
+/// vars.finish().unwrap();
+/// ```
+pub struct EnvCollector {
+    source: Box<Lookup>,
+    errors: Vec<anyhow::Error>,
+}
+
+impl Default for EnvCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnvCollector {
+    /// Reads from the process environment.
+    pub fn new() -> Self {
+        Self::with_source(lookup)
+    }
+
+    /// Reads from a custom lookup, which is mostly useful for tests.
+    pub fn with_source(source: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        Self {
+            source: Box::new(source),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn required<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.required_with(name, parse_from_str)
+    }
+
+    pub fn optional_with_default<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional_with(name, parse_from_str, default)
+    }
+
+    pub fn required_with<T, F>(&mut self, name: &str, parser: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, anyhow::Error>,
+    {
+        match (self.source)(name) {
+            Some(raw) => self.record(parse_var(name, &raw, parser)),
+            None => {
+                self.errors.push(anyhow!("{} is not set", name));
+                None
+            }
+        }
+    }
+
+    pub fn optional_with<T, F>(&mut self, name: &str, parser: F, default: T) -> T
+    where
+        F: FnOnce(&str) -> Result<T, anyhow::Error>,
+    {
+        match (self.source)(name) {
+            Some(raw) => self
+                .record(parse_var(name, &raw, parser))
+                .unwrap_or(default),
+            None => default,
+        }
+    }
+
+    fn record<T>(&mut self, res: Result<T, anyhow::Error>) -> Option<T> {
+        res.map_err(|e| self.errors.push(e)).ok()
+    }
+
+    /// Fails with a single report listing every problem seen so far.
+    pub fn finish(self) -> Result<(), anyhow::Error> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let report = self
+            .errors
+            .iter()
+            .map(|e| format!("  - {:#}", e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!(
+            "{} environment variable(s) missing or invalid:\n{}",
+            self.errors.len(),
+            report
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration("2 days").unwrap(),
+            Duration::from_secs(172800)
+        );
+        assert!(parse_duration("5 parsecs").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn parses_byte_sizes() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("64k").unwrap(), 65536);
+        assert_eq!(parse_bytes("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_bytes("1.5 GiB").unwrap(), 1_610_612_736);
+        assert!(parse_bytes("lots").is_err());
+    }
+
+    #[test]
+    fn parses_bools() {
+        assert!(parse_bool("Yes").unwrap());
+        assert!(!parse_bool("off").unwrap());
+        assert!(parse_bool("maybe").is_err());
+    }
+
+    #[test]
+    fn collector_reports_every_problem() {
+        let vars: HashMap<&str, &str> = [("PORT", "http"), ("WORKERS", "4")].into();
+        let mut env = EnvCollector::with_source(move |k| vars.get(k).map(|v| v.to_string()));
+        let port: Option<u16> = env.required("PORT");
+        let workers: Option<usize> = env.required("WORKERS");
+        let host: Option<String> = env.required("HOST");
+        let retries = env.optional_with_default("RETRIES", 3u32);
+
+        assert_eq!(port, None);
+        assert_eq!(workers, Some(4));
+        assert_eq!(host, None);
+        assert_eq!(retries, 3);
+        let report = format!("{}", env.finish().unwrap_err());
+        assert!(report.starts_with("2 environment variable(s)"));
+        assert!(report.contains("PORT has invalid value"));
+        assert!(report.contains("HOST is not set"));
+    }
+}
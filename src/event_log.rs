@@ -0,0 +1,274 @@
+//! In-process publish/subscribe where every event is journaled, so a named
+//! subscriber that goes away (or restarts with the process) picks up from the
+//! last event it acknowledged. Delivery is at-least-once: events after the
+//! last ack are delivered again.
+//!
+//! Events use the same byte conversions as `Store` and are stored as
+//! length-prefixed frames in a single file. Each subscriber's position lives
+//! next to it in `<path>.<name>.offset`.
+//!
+//! ```ignore
+//! let log: EventLog<OrderEvent> = EventLog::open("orders.events")?;
+//! log.publish(&OrderEvent::Placed(42))?;
+//!
+//! let mut sub = log.subscribe("billing")?;
+//! while let Ok((offset, event)) = sub.next().await {
+//!     bill(event).await?;
+//!     sub.ack(offset)?;
+//! }
+//! ```
+
+use crate::framing::{read_frames, write_frame};
+use anyhow::{Context, anyhow, bail};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::warn;
+
+struct Journal {
+    file: File,
+    // Byte position where each event's frame starts, plus the end of the file
+    positions: Vec<u64>,
+}
+
+struct Shared {
+    path: PathBuf,
+    journal: Mutex<Journal>,
+    published: watch::Sender<u64>,
+}
+
+/// A journaled event stream. Cheap to clone.
+pub struct EventLog<E> {
+    shared: Arc<Shared>,
+    _events: PhantomData<fn() -> E>,
+}
+
+impl<E> Clone for EventLog<E> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            _events: PhantomData,
+        }
+    }
+}
+
+impl<E> EventLog<E>
+where
+    E: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a E>,
+{
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open event log {}", path.display()))?;
+        let (frames, valid_len) = read_frames(&mut file)?;
+        if valid_len != file.metadata()?.len() {
+            warn!(
+                "Dropping partial trailing event from log {}",
+                path.display()
+            );
+            file.set_len(valid_len)?;
+        }
+        let mut positions = Vec::with_capacity(frames.len() + 1);
+        let mut pos = 0;
+        for frame in &frames {
+            positions.push(pos);
+            pos += 4 + frame.len() as u64;
+        }
+        positions.push(pos);
+        let (published, _) = watch::channel(frames.len() as u64);
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path: path.to_path_buf(),
+                journal: Mutex::new(Journal { file, positions }),
+                published,
+            }),
+            _events: PhantomData,
+        })
+    }
+
+    /// Journals `event` and wakes subscribers, returning its offset. Once this
+    /// returns the event survives a crash.
+    pub fn publish(&self, event: &E) -> Result<u64, anyhow::Error> {
+        let frame: Vec<u8> = event.into();
+        let mut buf = Vec::with_capacity(frame.len() + 4);
+        write_frame(&mut buf, &frame)?;
+        let mut journal = self.shared.journal.lock();
+        journal.file.write_all(&buf)?;
+        journal.file.sync_data()?;
+        let end = journal.positions.last().copied().unwrap_or_default() + buf.len() as u64;
+        journal.positions.push(end);
+        let offset = journal.positions.len() as u64 - 2;
+        self.shared.published.send_replace(offset + 1);
+        Ok(offset)
+    }
+
+    /// Number of events published so far.
+    pub fn len(&self) -> u64 {
+        *self.shared.published.borrow()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resumes the subscriber called `name` after its last acknowledged
+    /// event, or from the beginning if it has never acknowledged one.
+    pub fn subscribe(&self, name: &str) -> Result<Subscription<E>, anyhow::Error> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            bail!("Invalid subscriber name {:?}", name);
+        }
+        let mut offset_name = self
+            .shared
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_os_string();
+        offset_name.push(format!(".{}.offset", name));
+        let offset_path = self.shared.path.with_file_name(offset_name);
+        let acked = match std::fs::read(&offset_path) {
+            Ok(bytes) => bytes
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| anyhow!("Corrupt offset file {}", offset_path.display()))?,
+            Err(_) => 0,
+        };
+        Ok(Subscription {
+            log: self.clone(),
+            offset_path,
+            next: acked.min(self.len()),
+            buffered: VecDeque::new(),
+            published: self.shared.published.subscribe(),
+        })
+    }
+
+    /// Events from `from` up to the current end of the journal.
+    fn read_from(&self, from: u64) -> Result<Vec<E>, anyhow::Error> {
+        let start = {
+            let journal = self.shared.journal.lock();
+            match journal.positions.get(from as usize) {
+                Some(pos) => *pos,
+                None => return Ok(Vec::new()),
+            }
+        };
+        let mut file = File::open(&self.shared.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let (frames, _) = read_frames(&mut file)?;
+        frames
+            .into_iter()
+            .map(E::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to decode journaled event")
+    }
+}
+
+/// A named cursor over an `EventLog`.
+pub struct Subscription<E> {
+    log: EventLog<E>,
+    offset_path: PathBuf,
+    next: u64,
+    buffered: VecDeque<E>,
+    published: watch::Receiver<u64>,
+}
+
+impl<E> Subscription<E>
+where
+    E: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a E>,
+{
+    /// The next event and its offset, catching up on the journal first and
+    /// then waiting for new publishes.
+    pub async fn next(&mut self) -> Result<(u64, E), anyhow::Error> {
+        loop {
+            if let Some(event) = self.buffered.pop_front() {
+                let offset = self.next;
+                self.next += 1;
+                return Ok((offset, event));
+            }
+            if *self.published.borrow_and_update() > self.next {
+                self.buffered = self.log.read_from(self.next)?.into();
+                continue;
+            }
+            self.published
+                .changed()
+                .await
+                .map_err(|_| anyhow!("Event log closed"))?;
+        }
+    }
+
+    /// Records that everything up to and including `offset` was handled.
+    pub fn ack(&mut self, offset: u64) -> Result<(), anyhow::Error> {
+        let tmp = self.offset_path.with_extension("offset.tmp");
+        std::fs::write(&tmp, (offset + 1).to_le_bytes())?;
+        std::fs::rename(&tmp, &self.offset_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempDir;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq)]
+    struct Event(String);
+
+    impl TryFrom<Vec<u8>> for Event {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Event(String::from_utf8(value)?))
+        }
+    }
+
+    impl<'a> From<&'a Event> for Vec<u8> {
+        fn from(value: &'a Event) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    fn ev(s: &str) -> Event {
+        Event(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn subscribers_catch_up_from_last_ack() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events");
+        {
+            let log: EventLog<Event> = EventLog::open(&path).unwrap();
+            for name in ["a", "b", "c"] {
+                log.publish(&ev(name)).unwrap();
+            }
+            let mut sub = log.subscribe("billing").unwrap();
+            assert_eq!(sub.next().await.unwrap(), (0, ev("a")));
+            let (offset, event) = sub.next().await.unwrap();
+            assert_eq!(event, ev("b"));
+            sub.ack(offset).unwrap();
+        }
+
+        let log: EventLog<Event> = EventLog::open(&path).unwrap();
+        assert_eq!(log.len(), 3);
+        let mut billing = log.subscribe("billing").unwrap();
+        assert_eq!(billing.next().await.unwrap(), (2, ev("c")));
+        let mut audit = log.subscribe("audit").unwrap();
+        assert_eq!(audit.next().await.unwrap(), (0, ev("a")));
+
+        let waiting = tokio::spawn(async move { billing.next().await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        log.publish(&ev("d")).unwrap();
+        assert_eq!(waiting.await.unwrap(), (3, ev("d")));
+        assert!(log.subscribe("../escape").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+//! Length-prefixed frames (`u32` little-endian length, then the bytes) used by
+//! the append-only files in this crate.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+pub(crate) fn write_frame(w: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(frame.len()).map_err(|_| ErrorKind::InvalidInput)?;
+    let mut buf = Vec::with_capacity(frame.len() + 4);
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(frame);
+    w.write_all(&buf)
+}
+
+/// Every complete frame, plus the byte length they span. A trailing partial
+/// frame (from an interrupted append) is left out, so callers can tell by
+/// comparing the length against the file size.
+pub(crate) fn read_frames(r: &mut impl Read) -> io::Result<(Vec<Vec<u8>>, u64)> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    let mut frames = Vec::new();
+    let mut pos = 0usize;
+    while let Some(len) = bytes.get(pos..pos + 4) {
+        let len = u32::from_le_bytes(len.try_into().expect("4 byte slice")) as usize;
+        let Some(frame) = bytes.get(pos + 4..pos + 4 + len) else {
+            break;
+        };
+        frames.push(frame.to_vec());
+        pos += 4 + len;
+    }
+    Ok((frames, pos as u64))
+}
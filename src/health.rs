@@ -0,0 +1,293 @@
+//! Component health that rolls up through dependencies.
+//!
+//! Each component reports its own status; its effective status is the worst
+//! of that and what its dependencies pass on. A `Required` dependency passes
+//! its status on as-is, while an `Optional` one can at most make the
+//! dependent `Degraded`. The overall status is the worst effective status.
+//!
+//! ```ignore
+//! let health = HealthRegistry::new();
+//! health.depends_on("api", "users-store", Dependency::Required);
+//! health.depends_on("api", "recommendations", Dependency::Optional);
+//! health.set("recommendations", Status::unhealthy("timeouts"));
+//! assert_eq!(health.overall(), Health::Degraded);
+//! ```
+
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Health {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// What a component reports about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub health: Health,
+    pub reason: Option<String>,
+}
+
+impl Status {
+    pub fn healthy() -> Self {
+        Self {
+            health: Health::Healthy,
+            reason: None,
+        }
+    }
+
+    pub fn degraded(reason: impl Into<String>) -> Self {
+        Self {
+            health: Health::Degraded,
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub fn unhealthy(reason: impl Into<String>) -> Self {
+        Self {
+            health: Health::Unhealthy,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dependency {
+    /// The dependent can't work without it.
+    Required,
+    /// The dependent keeps working, degraded, without it.
+    Optional,
+}
+
+/// A component's rolled-up status, with the reasons behind it (its own and
+/// those inherited from dependencies, prefixed with their name).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub health: Health,
+    pub reasons: Vec<String>,
+}
+
+/// Sent whenever a component's effective health changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthTransition {
+    pub component: String,
+    pub from: Health,
+    pub to: Health,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    own: BTreeMap<String, Status>,
+    deps: BTreeMap<String, Vec<(String, Dependency)>>,
+    effective: BTreeMap<String, Health>,
+}
+
+impl State {
+    fn resolve(&self, name: &str, visiting: &mut HashSet<String>) -> ComponentHealth {
+        let mut health = Health::Healthy;
+        let mut reasons = Vec::new();
+        if let Some(status) = self.own.get(name) {
+            health = status.health;
+            reasons.extend(status.reason.clone());
+        }
+        // A dependency cycle is ignored at the edge that closes it
+        visiting.insert(name.to_string());
+        for (dep, kind) in self.deps.get(name).into_iter().flatten() {
+            if visiting.contains(dep) {
+                continue;
+            }
+            let resolved = self.resolve(dep, visiting);
+            let passed_on = match kind {
+                Dependency::Required => resolved.health,
+                Dependency::Optional => resolved.health.min(Health::Degraded),
+            };
+            if passed_on > Health::Healthy {
+                health = health.max(passed_on);
+                if resolved.reasons.is_empty() {
+                    reasons.push(format!("{}: {:?}", dep, resolved.health));
+                }
+                reasons.extend(resolved.reasons.iter().map(|r| format!("{}: {}", dep, r)));
+            }
+        }
+        visiting.remove(name);
+        ComponentHealth {
+            name: name.to_string(),
+            health,
+            reasons,
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.own.keys().cloned().collect();
+        for (name, deps) in &self.deps {
+            names.push(name.clone());
+            names.extend(deps.iter().map(|(d, _)| d.clone()));
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn report(&self) -> Vec<ComponentHealth> {
+        self.names()
+            .iter()
+            .map(|name| self.resolve(name, &mut HashSet::new()))
+            .collect()
+    }
+}
+
+/// Tracks component statuses and their dependency graph. Cheap to clone.
+#[derive(Clone)]
+pub struct HealthRegistry {
+    state: Arc<Mutex<State>>,
+    transitions: broadcast::Sender<HealthTransition>,
+    overall: Arc<watch::Sender<Health>>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        let (transitions, _) = broadcast::channel(64);
+        let (overall, _) = watch::channel(Health::Healthy);
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            transitions,
+            overall: Arc::new(overall),
+        }
+    }
+
+    /// Records a component's own status.
+    pub fn set(&self, name: impl Into<String>, status: Status) {
+        let mut state = self.state.lock();
+        state.own.insert(name.into(), status);
+        self.publish(&mut state);
+    }
+
+    /// Declares that `name` depends on `dependency`. Components don't need
+    /// to report a status before they're referenced; unknown ones count as
+    /// healthy.
+    pub fn depends_on(
+        &self,
+        name: impl Into<String>,
+        dependency: impl Into<String>,
+        kind: Dependency,
+    ) {
+        let mut state = self.state.lock();
+        let deps = state.deps.entry(name.into()).or_default();
+        let dependency = dependency.into();
+        deps.retain(|(d, _)| *d != dependency);
+        deps.push((dependency, kind));
+        self.publish(&mut state);
+    }
+
+    pub fn component(&self, name: &str) -> ComponentHealth {
+        self.state.lock().resolve(name, &mut HashSet::new())
+    }
+
+    /// Every known component, sorted by name.
+    pub fn report(&self) -> Vec<ComponentHealth> {
+        self.state.lock().report()
+    }
+
+    pub fn overall(&self) -> Health {
+        *self.overall.borrow()
+    }
+
+    /// Follows the overall status, e.g. to gate readiness.
+    pub fn watch_overall(&self) -> watch::Receiver<Health> {
+        self.overall.subscribe()
+    }
+
+    /// Receives a `HealthTransition` for every change in a component's
+    /// effective health. Slow receivers miss transitions rather than
+    /// holding up status updates.
+    pub fn subscribe(&self) -> broadcast::Receiver<HealthTransition> {
+        self.transitions.subscribe()
+    }
+
+    fn publish(&self, state: &mut State) {
+        let report = state.report();
+        let mut overall = Health::Healthy;
+        for component in report {
+            overall = overall.max(component.health);
+            let from = state
+                .effective
+                .insert(component.name.clone(), component.health)
+                .unwrap_or(Health::Healthy);
+            if from != component.health {
+                let _ = self.transitions.send(HealthTransition {
+                    component: component.name,
+                    from,
+                    to: component.health,
+                    reasons: component.reasons,
+                });
+            }
+        }
+        self.overall.send_if_modified(|current| {
+            let changed = *current != overall;
+            *current = overall;
+            changed
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_up_through_dependencies() {
+        let health = HealthRegistry::new();
+        let mut transitions = health.subscribe();
+        let overall = health.watch_overall();
+        health.depends_on("api", "store", Dependency::Required);
+        health.depends_on("api", "recs", Dependency::Optional);
+        health.depends_on("recs", "api", Dependency::Required);
+        health.set("api", Status::healthy());
+        assert_eq!(health.overall(), Health::Healthy);
+
+        health.set("recs", Status::unhealthy("timeouts"));
+        let api = health.component("api");
+        assert_eq!(api.health, Health::Degraded);
+        assert_eq!(api.reasons, vec!["recs: timeouts"]);
+        assert_eq!(health.overall(), Health::Unhealthy);
+        assert!(overall.has_changed().unwrap());
+
+        health.set("recs", Status::healthy());
+        health.set("store", Status::unhealthy("disk full"));
+        assert_eq!(health.component("api").health, Health::Unhealthy);
+        // recs depends on api, so the store outage reaches it too
+        assert_eq!(
+            health.component("recs").reasons,
+            vec!["api: store: disk full"]
+        );
+
+        let mut seen = Vec::new();
+        while let Ok(t) = transitions.try_recv() {
+            seen.push((t.component, t.from, t.to));
+        }
+        assert_eq!(
+            seen,
+            vec![
+                ("api".into(), Health::Healthy, Health::Degraded),
+                ("recs".into(), Health::Healthy, Health::Unhealthy),
+                ("api".into(), Health::Degraded, Health::Healthy),
+                ("recs".into(), Health::Unhealthy, Health::Healthy),
+                ("api".into(), Health::Healthy, Health::Unhealthy),
+                ("recs".into(), Health::Healthy, Health::Unhealthy),
+                ("store".into(), Health::Healthy, Health::Unhealthy),
+            ]
+        );
+    }
+}
@@ -1,4 +1,45 @@
+//! Subsystems sit behind cargo features so users only build what they use:
+//! `store` (the default), `actor`, `config`, `events`, `logging` and
+//! `metrics`, or `full` for all of them. Shutdown coordination, lifecycle
+//! timings, health, rate limiting, env helpers and `tail` are always
+//! available. Most users want `use kitchen_sink::prelude::*`.
+
+#[cfg(any(test, feature = "actor"))]
 pub mod actor;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+#[cfg(any(test, feature = "config"))]
+pub mod config;
+pub mod env;
+#[cfg(any(test, feature = "events"))]
+pub mod event_log;
+#[cfg(any(test, feature = "store", feature = "actor", feature = "events"))]
+mod framing;
+pub mod health;
+pub mod lifecycle;
+#[cfg(any(test, feature = "logging"))]
 pub mod logging;
+pub mod prelude;
+#[cfg(any(test, feature = "pushgateway"))]
+pub mod pushgateway;
+pub mod rate_limit;
+#[cfg(any(
+    test,
+    feature = "chaos",
+    feature = "sim",
+    feature = "store",
+    feature = "testing"
+))]
+mod rng;
 pub mod shutdown;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(any(test, feature = "store"))]
 pub mod simple_store;
+#[cfg(any(test, feature = "metrics"))]
+pub mod sink;
+#[cfg(any(test, feature = "metrics"))]
+pub mod statsd;
+#[cfg(any(test, feature = "systemd"))]
+pub mod systemd;
+pub mod tail;
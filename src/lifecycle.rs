@@ -0,0 +1,174 @@
+//! Process lifecycle timings: when the process started, how long each startup
+//! phase took, and why and how long shutdown took. `ShutdownCoordinator`
+//! records the shutdown side on its own; the rest is marked by the service.
+//!
+//! `snapshot()` is the single read point for exporting these to a metrics or
+//! diagnostics endpoint.
+//!
+//! ```ignore
+//! lifecycle::init();
+//! {
+//!     let _phase = lifecycle::startup_phase("load config");
+//!     config = Config::load()?;
+//! }
+//! lifecycle::startup_finished();
+//! ```
+
+use parking_lot::Mutex;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// What set shutdown in motion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownTrigger {
+    Sigint,
+    Sigterm,
+    CtrlC,
+    /// The coordinator's token was cancelled from code.
+    Requested,
+}
+
+#[derive(Debug, Clone)]
+pub struct LifecycleSnapshot {
+    pub started_at: SystemTime,
+    pub uptime: Duration,
+    /// Startup phases in the order they finished.
+    pub startup_phases: Vec<(String, Duration)>,
+    /// Time from start until `startup_finished`, once called.
+    pub startup_duration: Option<Duration>,
+    pub shutdown_trigger: Option<ShutdownTrigger>,
+    /// Time from the trigger until every registered task finished.
+    pub shutdown_duration: Option<Duration>,
+}
+
+struct Lifecycle {
+    started: Instant,
+    started_at: SystemTime,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    phases: Vec<(String, Duration)>,
+    startup_duration: Option<Duration>,
+    shutdown_trigger: Option<ShutdownTrigger>,
+    shutdown_started: Option<Instant>,
+    shutdown_duration: Option<Duration>,
+}
+
+impl Lifecycle {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn record_phase(&self, name: String, took: Duration) {
+        self.state.lock().phases.push((name, took));
+    }
+
+    fn startup_finished(&self) {
+        self.state
+            .lock()
+            .startup_duration
+            .get_or_insert(self.started.elapsed());
+    }
+
+    fn shutdown_started(&self, trigger: ShutdownTrigger) {
+        let mut state = self.state.lock();
+        state.shutdown_trigger = Some(trigger);
+        state.shutdown_started = Some(Instant::now());
+    }
+
+    fn shutdown_finished(&self) {
+        let mut state = self.state.lock();
+        state.shutdown_duration = state.shutdown_started.map(|s| s.elapsed());
+    }
+
+    fn snapshot(&self) -> LifecycleSnapshot {
+        let state = self.state.lock();
+        LifecycleSnapshot {
+            started_at: self.started_at,
+            uptime: self.started.elapsed(),
+            startup_phases: state.phases.clone(),
+            startup_duration: state.startup_duration,
+            shutdown_trigger: state.shutdown_trigger,
+            shutdown_duration: state.shutdown_duration,
+        }
+    }
+}
+
+static LIFECYCLE: LazyLock<Lifecycle> = LazyLock::new(Lifecycle::new);
+
+/// Pins the process start time. Call first thing in `main`; otherwise the
+/// clock starts on first use of this module.
+pub fn init() {
+    LazyLock::force(&LIFECYCLE);
+}
+
+/// Times a startup phase until the returned guard is dropped.
+pub fn startup_phase(name: impl Into<String>) -> PhaseTimer {
+    init();
+    PhaseTimer {
+        name: name.into(),
+        started: Instant::now(),
+    }
+}
+
+/// Marks startup as complete. Only the first call counts.
+pub fn startup_finished() {
+    LIFECYCLE.startup_finished()
+}
+
+pub fn snapshot() -> LifecycleSnapshot {
+    LIFECYCLE.snapshot()
+}
+
+pub(crate) fn shutdown_started(trigger: ShutdownTrigger) {
+    LIFECYCLE.shutdown_started(trigger)
+}
+
+pub(crate) fn shutdown_finished() {
+    LIFECYCLE.shutdown_finished()
+}
+
+pub struct PhaseTimer {
+    name: String,
+    started: Instant,
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        LIFECYCLE.record_phase(std::mem::take(&mut self.name), self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_startup_and_shutdown() {
+        let lifecycle = Lifecycle::new();
+        lifecycle.record_phase("config".into(), Duration::from_millis(5));
+        lifecycle.record_phase("stores".into(), Duration::from_millis(7));
+        lifecycle.startup_finished();
+        let first = lifecycle.snapshot().startup_duration;
+        lifecycle.startup_finished();
+
+        let before = lifecycle.snapshot();
+        assert_eq!(before.startup_phases.len(), 2);
+        assert_eq!(before.startup_phases[1].0, "stores");
+        assert_eq!(before.startup_duration, first);
+        assert!(before.shutdown_trigger.is_none());
+
+        lifecycle.shutdown_started(ShutdownTrigger::Sigterm);
+        lifecycle.shutdown_finished();
+        let after = lifecycle.snapshot();
+        assert_eq!(after.shutdown_trigger, Some(ShutdownTrigger::Sigterm));
+        assert!(after.shutdown_duration.is_some());
+        assert!(after.uptime >= before.uptime);
+    }
+}
@@ -6,16 +6,30 @@ use tracing_subscriber::{
     Registry,
     filter::LevelFilter,
     fmt::Layer,
-    layer::SubscriberExt,
+    layer::{Layered, SubscriberExt},
     prelude::*,
     registry::LookupSpan,
     reload::{self, Handle},
 };
 
+type Filtered = Layered<reload::Layer<LevelFilter, Registry>, Registry>;
+
+/// The subscriber that runtime-attached layers sit on, for naming the `S` in
+/// `tracing_subscriber::Layer<S>` when writing a custom layer.
+pub type BaseSubscriber = Layered<ErrorLayer<Filtered>, Filtered>;
+
+type DynLayer = Box<dyn tracing_subscriber::Layer<BaseSubscriber> + Send + Sync>;
+type LayersHandle = Handle<Vec<Option<DynLayer>>, BaseSubscriber>;
+
 // Global handle for runtime log level changes
 static LOG_RELOAD_HANDLE: LazyLock<Mutex<Option<Handle<LevelFilter, Registry>>>> =
     LazyLock::new(|| Mutex::new(None));
 
+// Global handle for attaching and detaching output layers. A detached layer
+// leaves `None` behind so the ids of the others stay valid.
+static LAYERS_RELOAD_HANDLE: LazyLock<Mutex<Option<LayersHandle>>> =
+    LazyLock::new(|| Mutex::new(None));
+
 pub fn set_log_level(level: Level) -> Result<(), anyhow::Error> {
     let handle_guard = LOG_RELOAD_HANDLE
         .lock()
@@ -30,6 +44,63 @@ pub fn set_log_level(level: Level) -> Result<(), anyhow::Error> {
     }
 }
 
+/// Identifies a layer added with `attach_layer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerId(usize);
+
+/// Adds an output layer to the running subscriber, e.g. a verbose file
+/// layer during an incident. Pair with `set_log_level` to let more through.
+///
+/// Layers with their own per-layer filter (`.with_filter(..)`) don't get
+/// that filter registered when attached this way; filter inside the layer
+/// instead.
+///
+/// # Example
+/// ```no_run
+/// use kitchen_sink::logging::{attach_layer, detach_layer};
+/// use tracing_subscriber::fmt::Layer;
+///
+/// let file = std::fs::File::create("/tmp/incident.log").unwrap();
+/// let id = attach_layer(Layer::default().with_writer(std::sync::Mutex::new(file))).unwrap();
+/// // ...
+/// detach_layer(id).unwrap();
+/// ```
+pub fn attach_layer<L>(layer: L) -> Result<LayerId, anyhow::Error>
+where
+    L: tracing_subscriber::Layer<BaseSubscriber> + Send + Sync + 'static,
+{
+    let handle_guard = LAYERS_RELOAD_HANDLE
+        .lock()
+        .map_err(|e| anyhow!("Lock error: {}", e))?;
+    let Some(handle) = handle_guard.as_ref() else {
+        bail!("Log reload handle not initialized")
+    };
+    let mut id = 0;
+    handle
+        .modify(|layers| {
+            id = layers.len();
+            layers.push(Some(Box::new(layer)));
+        })
+        .map_err(|e| anyhow!("Failed to attach log layer: {}", e))?;
+    Ok(LayerId(id))
+}
+
+/// Removes a layer added with `attach_layer`. Returns `false` if it was
+/// already detached.
+pub fn detach_layer(id: LayerId) -> Result<bool, anyhow::Error> {
+    let handle_guard = LAYERS_RELOAD_HANDLE
+        .lock()
+        .map_err(|e| anyhow!("Lock error: {}", e))?;
+    let Some(handle) = handle_guard.as_ref() else {
+        bail!("Log reload handle not initialized")
+    };
+    let mut removed = false;
+    handle
+        .modify(|layers| removed = layers.get_mut(id.0).and_then(Option::take).is_some())
+        .map_err(|e| anyhow!("Failed to detach log layer: {}", e))?;
+    Ok(removed)
+}
+
 /// Builds a logging subscriber with reload and error layers, but no output layer.
 ///
 /// This function allows callers to add their own output layers (such as file appenders,
@@ -38,6 +109,7 @@ pub fn set_log_level(level: Level) -> Result<(), anyhow::Error> {
 /// The subscriber includes:
 /// - A reload layer for runtime log level changes via `set_log_level()`
 /// - An error layer for error tracking
+/// - A slot for output layers added at runtime via `attach_layer()`
 /// - **No output layer** - callers must add their own
 ///
 /// # Example with stdout
@@ -74,9 +146,15 @@ pub fn build_logging_subscriber()
         let mut handle_guard = LOG_RELOAD_HANDLE.lock().unwrap();
         *handle_guard = Some(reload_handle);
     }
+    let (layers, layers_handle) = reload::Layer::new(Vec::new());
+    {
+        let mut handle_guard = LAYERS_RELOAD_HANDLE.lock().unwrap();
+        *handle_guard = Some(layers_handle);
+    }
     tracing_subscriber::Registry::default()
         .with(filter)
         .with(ErrorLayer::default())
+        .with(layers)
 }
 
 /// Initializes logging with default stdout output.
@@ -98,3 +176,35 @@ pub fn initialize_logging() {
         .with(Layer::default().with_target(false))
         .init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing::{Event, Subscriber, info};
+    use tracing_subscriber::layer::Context;
+
+    struct Count(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Count {
+        fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn attaches_and_detaches_layers_at_runtime() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        tracing::subscriber::with_default(build_logging_subscriber(), || {
+            info!("before");
+            let id = attach_layer(Count(seen.clone())).unwrap();
+            info!("during");
+            info!("during");
+            assert!(detach_layer(id).unwrap());
+            assert!(!detach_layer(id).unwrap());
+            info!("after");
+        });
+        assert_eq!(seen.load(Ordering::Relaxed), 2);
+    }
+}
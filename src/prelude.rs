@@ -0,0 +1,20 @@
+//! The traits and handles most applications touch, for glob import.
+
+pub use crate::health::{Dependency, Health, HealthRegistry, Status};
+pub use crate::rate_limit::{KeyedRateLimiter, RateLimit, RateLimiter};
+pub use crate::shutdown::{ShutdownCoordinator, ShutdownHook};
+
+#[cfg(any(test, feature = "actor"))]
+pub use crate::actor::{Actor, ActorHandle};
+#[cfg(any(test, feature = "config"))]
+pub use crate::config::{AppConfig, ConfigSources, Validate};
+#[cfg(any(test, feature = "events"))]
+pub use crate::event_log::{EventLog, Subscription};
+#[cfg(any(test, feature = "pushgateway"))]
+pub use crate::pushgateway::{PushGateway, PushGatewayConfig};
+#[cfg(any(test, feature = "store"))]
+pub use crate::simple_store::{FetchMeta, FetchResult, Fetcher, FetcherExt, Store};
+#[cfg(any(test, feature = "metrics"))]
+pub use crate::sink::{BufferedSink, SinkWriter};
+#[cfg(any(test, feature = "metrics"))]
+pub use crate::statsd::{Statsd, StatsdConfig};
@@ -0,0 +1,338 @@
+//! Push metrics to a Prometheus Pushgateway, for short-lived jobs and
+//! environments Prometheus can't scrape. Behind the `pushgateway` feature.
+//!
+//! Metrics are aggregated in memory and the whole group is PUT to the
+//! gateway every `interval`, replacing what it held, with a final push on
+//! shutdown. Only `http` gateways are reached unless a TLS feature of
+//! `reqwest` is enabled.
+//!
+//! ```ignore
+//! let gateway = PushGateway::spawn(
+//!     PushGatewayConfig::new("http://pushgateway:9091", "importer")
+//!         .with_grouping("instance", hostname)
+//!         .with_prefix("myapp"),
+//!     &mut shutdown,
+//! )?;
+//! gateway.count("rows", 500, &[("table", "users")]);
+//! gateway.timing("import", elapsed, &[]);
+//! ```
+
+use crate::shutdown::ShutdownCoordinator;
+use anyhow::{Context, anyhow};
+use parking_lot::Mutex;
+use reqwest::{Client, Url};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+#[derive(Debug, Clone)]
+pub struct PushGatewayConfig {
+    /// Gateway address, e.g. `http://pushgateway:9091`.
+    pub url: String,
+    pub job: String,
+    /// Labels after the job in the push URL, which together name the group
+    /// each push replaces.
+    pub grouping: Vec<(String, String)>,
+    /// Prepended to every metric name with a `_`.
+    pub prefix: Option<String>,
+    /// Labels added to every metric.
+    pub labels: Vec<(String, String)>,
+    pub interval: Duration,
+    /// How long the final push may take once shutdown begins.
+    pub shutdown_grace: Duration,
+}
+
+impl PushGatewayConfig {
+    pub fn new(url: impl Into<String>, job: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            job: job.into(),
+            grouping: Vec::new(),
+            prefix: None,
+            labels: Vec::new(),
+            interval: Duration::from_secs(15),
+            shutdown_grace: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_grouping(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.grouping.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// `{url}/metrics/job/{job}/{key}/{value}...`, with each part escaped.
+    fn push_url(&self) -> Result<Url, anyhow::Error> {
+        let mut url =
+            Url::parse(&self.url).with_context(|| format!("Invalid gateway URL {}", self.url))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Gateway URL {} can't take a path", self.url))?
+            .pop_if_empty()
+            .extend(["metrics", "job", &self.job])
+            .extend(self.grouping.iter().flat_map(|(k, v)| [k, v]));
+        Ok(url)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Counter(f64),
+    Gauge(f64),
+    /// Milliseconds recorded and how many times.
+    Timing(f64, u64),
+}
+
+/// A metric name and its rendered labels.
+type Series = (String, String);
+
+/// A cheap-to-clone Pushgateway client. Recording only updates the values
+/// held in memory; pushing happens in the background.
+#[derive(Clone)]
+pub struct PushGateway {
+    metrics: Arc<Mutex<BTreeMap<Series, Value>>>,
+    prefix: Option<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl PushGateway {
+    /// Must be called from within a tokio runtime.
+    pub fn spawn(
+        config: PushGatewayConfig,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Result<Self, anyhow::Error> {
+        let url = config.push_url()?;
+        let client = Client::builder().timeout(config.interval).build()?;
+        let gateway = Self {
+            metrics: Arc::new(Mutex::new(BTreeMap::new())),
+            prefix: config.prefix.clone(),
+            labels: config.labels.clone(),
+        };
+        let mvgateway = gateway.clone();
+        let completion = shutdown.token();
+        let jhandle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                  _ = sleep(config.interval) => {}
+                  _ = completion.cancelled() => break,
+                }
+                if let Err(e) = mvgateway.push(&client, &url).await {
+                    warn!("Failed to push metrics to {}: {:#}", url, e);
+                }
+            }
+            match tokio::time::timeout(config.shutdown_grace, mvgateway.push(&client, &url)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Final push to {} failed: {:#}", url, e),
+                Err(_) => error!("Final push to {} exceeded {:?}", url, config.shutdown_grace),
+            }
+        });
+        shutdown.register_task(jhandle);
+        Ok(gateway)
+    }
+
+    pub fn count(&self, name: &str, value: i64, labels: &[(&str, &str)]) {
+        self.record(name, labels, |current| match current {
+            Some(Value::Counter(total)) => Value::Counter(total + value as f64),
+            _ => Value::Counter(value as f64),
+        })
+    }
+
+    pub fn gauge(&self, name: &str, value: f64, labels: &[(&str, &str)]) {
+        self.record(name, labels, |_| Value::Gauge(value))
+    }
+
+    /// Pushed in milliseconds, as a summary's `_sum` and `_count`.
+    pub fn timing(&self, name: &str, value: Duration, labels: &[(&str, &str)]) {
+        let ms = value.as_secs_f64() * 1000.0;
+        self.record(name, labels, |current| match current {
+            Some(Value::Timing(sum, count)) => Value::Timing(sum + ms, count + 1),
+            _ => Value::Timing(ms, 1),
+        })
+    }
+
+    fn record(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        update: impl FnOnce(Option<Value>) -> Value,
+    ) {
+        let name = match &self.prefix {
+            Some(prefix) => metric_name(&format!("{}_{}", prefix, name)),
+            None => metric_name(name),
+        };
+        let labels = self
+            .labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .chain(labels.iter().copied());
+        let series = (name, render_labels(labels));
+        let mut metrics = self.metrics.lock();
+        let value = update(metrics.get(&series).copied());
+        metrics.insert(series, value);
+    }
+
+    async fn push(&self, client: &Client, url: &Url) -> Result<(), anyhow::Error> {
+        let body = render(&self.metrics.lock());
+        client
+            .put(url.clone())
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// `name` with anything Prometheus doesn't allow in a metric name replaced
+/// by `_`.
+fn metric_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':' => c,
+            _ => '_',
+        })
+        .collect();
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// `{k="v",...}`, or nothing without labels.
+fn render_labels<'a>(labels: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut out = String::new();
+    for (idx, (key, value)) in labels.enumerate() {
+        out.push(if idx == 0 { '{' } else { ',' });
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = write!(out, "{}=\"{}\"", metric_name(key), value);
+    }
+    if !out.is_empty() {
+        out.push('}');
+    }
+    out
+}
+
+/// The text exposition format, one `# TYPE` line per metric name.
+fn render(metrics: &BTreeMap<Series, Value>) -> String {
+    let mut out = String::new();
+    let mut typed: Option<&str> = None;
+    for ((name, labels), value) in metrics {
+        if typed != Some(name) {
+            let kind = match value {
+                Value::Counter(_) => "counter",
+                Value::Gauge(_) => "gauge",
+                Value::Timing(..) => "summary",
+            };
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            typed = Some(name);
+        }
+        let _ = match value {
+            Value::Counter(v) | Value::Gauge(v) => writeln!(out, "{}{} {}", name, labels, v),
+            Value::Timing(sum, count) => writeln!(
+                out,
+                "{name}_sum{labels} {sum}\n{name}_count{labels} {count}"
+            ),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn renders_the_exposition_format() {
+        let mut metrics = BTreeMap::new();
+        metrics.insert(
+            ("requests".to_string(), "{route=\"/\"}".to_string()),
+            Value::Counter(3.0),
+        );
+        metrics.insert(
+            ("requests".to_string(), "{route=\"/x\"}".to_string()),
+            Value::Counter(1.0),
+        );
+        metrics.insert(("fetch".to_string(), String::new()), Value::Timing(42.5, 2));
+        assert_eq!(
+            render(&metrics),
+            "# TYPE fetch summary\nfetch_sum 42.5\nfetch_count 2\n\
+             # TYPE requests counter\nrequests{route=\"/\"} 3\nrequests{route=\"/x\"} 1\n"
+        );
+        assert_eq!(metric_name("app.fetch-time"), "app_fetch_time");
+        assert_eq!(
+            render_labels([("path", "a\"b")].into_iter()),
+            "{path=\"a\\\"b\"}"
+        );
+    }
+
+    #[tokio::test]
+    async fn pushes_the_group_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let complete = |request: &[u8]| {
+                let text = String::from_utf8_lossy(request);
+                let (head, body) = text.split_once("\r\n\r\n")?;
+                let len = head.lines().find_map(|l| {
+                    l.to_ascii_lowercase()
+                        .strip_prefix("content-length: ")?
+                        .parse()
+                        .ok()
+                })?;
+                (body.len() >= len).then_some(())
+            };
+            while complete(&request).is_none() {
+                let n = conn.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed mid-request");
+                request.extend_from_slice(&buf[..n]);
+            }
+            conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let mut shutdown = ShutdownCoordinator::new();
+        let mut config = PushGatewayConfig::new(url, "import")
+            .with_grouping("instance", "a b")
+            .with_prefix("svc")
+            .with_label("env", "test");
+        config.interval = Duration::from_secs(3600);
+        let gateway = PushGateway::spawn(config, &mut shutdown).unwrap();
+        gateway.count("rows", 2, &[]);
+        gateway.count("rows", 3, &[]);
+        gateway.gauge("queue", 1.5, &[("q", "in")]);
+        gateway.timing("import", Duration::from_millis(40), &[]);
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+
+        let request = received.await.unwrap();
+        assert!(request.starts_with("PUT /metrics/job/import/instance/a%20b HTTP/1.1\r\n"));
+        assert!(request.ends_with(
+            "# TYPE svc_import summary\nsvc_import_sum{env=\"test\"} 40\nsvc_import_count{env=\"test\"} 1\n\
+             # TYPE svc_queue gauge\nsvc_queue{env=\"test\",q=\"in\"} 1.5\n\
+             # TYPE svc_rows counter\nsvc_rows{env=\"test\"} 5\n"
+        ));
+    }
+}
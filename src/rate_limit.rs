@@ -0,0 +1,210 @@
+//! Token bucket rate limiting, either shared or per key (tenant, client IP,
+//! ...).
+//!
+//! ```ignore
+//! let limiter = KeyedRateLimiter::new(RateLimit::per_second(10.0).with_burst(20))
+//!     .with_idle_ttl(Duration::from_secs(300))
+//!     .with_max_keys(10_000);
+//! if !limiter.check(peer.ip()) {
+//!     return Err(anyhow!("Too many requests"));
+//! }
+//! ```
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    pub rate: f64,
+    /// Most tokens a bucket holds, i.e. the largest burst allowed.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// `rate` requests per second, with a burst of one second's worth.
+    pub fn per_second(rate: f64) -> Self {
+        Self {
+            rate,
+            burst: rate.ceil().max(1.0) as u32,
+        }
+    }
+
+    /// One request per `interval`, with no burst.
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            rate: 1.0 / interval.as_secs_f64(),
+            burst: 1,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// How long an empty bucket takes to fill back up.
+    fn refill_time(&self) -> Duration {
+        Duration::from_secs_f64(self.burst as f64 / self.rate)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last: now,
+        }
+    }
+
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A single bucket shared by every caller.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket::full(&limit, Instant::now())),
+        }
+    }
+
+    /// Takes a token if one is available.
+    pub fn check(&self) -> bool {
+        self.bucket.lock().take(&self.limit, Instant::now())
+    }
+}
+
+/// An independent bucket per key. Buckets idle for longer than the TTL are
+/// evicted, and once `max_keys` is reached the least recently used bucket
+/// makes room for a new key.
+///
+/// The TTL defaults to the time a bucket takes to refill, so evicting one
+/// never gives a key more tokens than it would have had anyway.
+pub struct KeyedRateLimiter<K> {
+    limit: RateLimit,
+    idle_ttl: Duration,
+    max_keys: usize,
+    state: Mutex<Keyed<K>>,
+}
+
+struct Keyed<K> {
+    buckets: HashMap<K, Bucket>,
+    last_sweep: Instant,
+}
+
+impl<K: Hash + Eq + Clone> KeyedRateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            idle_ttl: limit.refill_time(),
+            max_keys: 100_000,
+            state: Mutex::new(Keyed {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn with_idle_ttl(mut self, idle_ttl: Duration) -> Self {
+        self.idle_ttl = idle_ttl;
+        self
+    }
+
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys.max(1);
+        self
+    }
+
+    /// Takes a token from `key`'s bucket if one is available.
+    pub fn check(&self, key: K) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        if now.saturating_duration_since(state.last_sweep) >= self.idle_ttl {
+            state.last_sweep = now;
+            self.evict_idle(&mut state.buckets, now);
+        }
+        if !state.buckets.contains_key(&key) && state.buckets.len() >= self.max_keys {
+            self.evict_idle(&mut state.buckets, now);
+            if state.buckets.len() >= self.max_keys
+                && let Some(lru) = state
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, b)| b.last)
+                    .map(|(k, _)| k.clone())
+            {
+                state.buckets.remove(&lru);
+            }
+        }
+        state
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(&self.limit, now))
+            .take(&self.limit, now)
+    }
+
+    /// Number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.state.lock().buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn evict_idle(&self, buckets: &mut HashMap<K, Bucket>, now: Instant) {
+        buckets.retain(|_, b| now.saturating_duration_since(b.last) < self.idle_ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_each_key_independently() {
+        let limiter = KeyedRateLimiter::new(RateLimit::per_second(2.0)).with_max_keys(2);
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+
+        // At capacity: "b" was used least recently and makes room for "c"
+        assert!(limiter.check("c"));
+        assert_eq!(limiter.len(), 2);
+
+        // Both refill within a second, so both are dropped after one idle
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.check("d"));
+        assert_eq!(limiter.len(), 1);
+    }
+}
@@ -0,0 +1,41 @@
+/// Small seeded PRNG (SplitMix64) for reproducible randomness in simulation,
+/// fault injection, and jitter. Not suitable for anything security related.
+#[derive(Debug, Clone)]
+pub(crate) struct SeededRng(u64);
+
+impl SeededRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, or 0 when `n` is 0.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next_u64() % n }
+    }
+
+    /// True with probability `p`, clamped to `0.0..=1.0`.
+    #[cfg(any(test, feature = "chaos"))]
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        p > 0.0 && unit < p
+    }
+
+    /// Uniform in `min..=max`.
+    #[cfg(any(test, feature = "chaos", feature = "store"))]
+    pub(crate) fn duration_between(
+        &mut self,
+        min: std::time::Duration,
+        max: std::time::Duration,
+    ) -> std::time::Duration {
+        let span = max.saturating_sub(min).as_nanos() as u64;
+        min + std::time::Duration::from_nanos(self.below(span.saturating_add(1)))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::lifecycle::{self, ShutdownTrigger};
 use anyhow::Result;
 use async_trait::async_trait;
 use futures::future;
@@ -23,6 +24,12 @@ pub struct ShutdownCoordinator {
     tasks: Vec<JoinHandle<()>>,
 }
 
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ShutdownCoordinator {
     pub fn new() -> Self {
         Self {
@@ -47,20 +54,26 @@ impl ShutdownCoordinator {
         let mut sigterm =
             unix::signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
 
-        tokio::select! {
-            _ = sigint.recv() => {info!("Received SIGINT")}
-            _ = sigterm.recv() => {info!("Received SIGTERM")}
-            _ = signal::ctrl_c() => {info!("Received Ctrl+C")}
-            _ = self.token.cancelled() => {info!("Shutdown requested programmatically")}
-        }
+        let trigger = tokio::select! {
+            _ = sigint.recv() => {info!("Received SIGINT"); ShutdownTrigger::Sigint}
+            _ = sigterm.recv() => {info!("Received SIGTERM"); ShutdownTrigger::Sigterm}
+            _ = signal::ctrl_c() => {info!("Received Ctrl+C"); ShutdownTrigger::CtrlC}
+            _ = self.token.cancelled() => {info!("Shutdown requested programmatically"); ShutdownTrigger::Requested}
+        };
+        lifecycle::shutdown_started(trigger);
 
         info!("Starting shutdown sequence");
+        #[cfg(feature = "systemd")]
+        if let Err(e) = crate::systemd::stopping() {
+            error!("Failed to notify systemd of shutdown: {:#}", e);
+        }
         self.token.cancel();
         for res in future::join_all(self.tasks).await {
             if let Err(e) = res {
                 error!("Shutdown hook failed: {}", e);
             }
         }
+        lifecycle::shutdown_finished();
         info!("Shutdown sequence complete");
     }
 }
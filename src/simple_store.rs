@@ -1,20 +1,226 @@
+use crate::rate_limit::RateLimiter;
+use anyhow::Context;
 use async_trait::async_trait;
-use parking_lot::{RwLock, lock_api::RwLockReadGuard};
+use codec::SharedCodec;
+use hooks::Hooks;
+use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use read_only::FileStamp;
+use refresh::{Flight, SharedFetcher};
+use replication::Replica;
+use std::fmt;
 use std::marker::{Send, Sync};
-use std::time::Duration;
-use std::{path::PathBuf, sync::Arc};
-use tokio::time::sleep;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::watch;
+use tokio::time::Instant;
 use tracing::error;
+use unchanged::ContentHash;
+use write_behind::Deferred;
+
+mod adaptive;
+mod append;
+mod async_io;
+mod backend;
+mod background;
+mod backup;
+mod blue_green;
+mod builder;
+mod cached;
+mod codec;
+mod cron;
+mod delta;
+mod diff;
+mod durability;
+mod handle;
+mod health;
+mod history;
+mod hooks;
+mod indexed;
+mod info;
+#[cfg(feature = "redb")]
+mod kv;
+mod lazy;
+mod lock;
+mod map;
+mod meta;
+mod metrics;
+mod middleware;
+#[cfg(any(test, feature = "mmap"))]
+mod mmap;
+#[cfg(feature = "object-store")]
+mod object;
+#[cfg(feature = "directories")]
+mod path;
+mod prefetch;
+mod read_only;
+#[cfg(feature = "redis")]
+mod redis;
+mod refresh;
+mod registry;
+mod replication;
+mod retry;
+mod schedule;
+mod size_limit;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stream;
+mod swr;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod throttle;
+mod timeout;
+mod transaction;
+mod ttl;
+mod unchanged;
+mod view;
+mod write_behind;
+
+pub use adaptive::{AdaptiveInterval, RefreshPolicy};
+pub use anyhow::Error;
+pub use append::{AppendStore, Records};
+pub use backend::{FileBackend, MemoryBackend, StorageBackend};
+pub use blue_green::BlueGreenBackend;
+pub use builder::StoreBuilder;
+pub use cached::{CachedStore, KeyedFetcher};
+#[cfg(feature = "aes-gcm")]
+pub use codec::AesGcm;
+#[cfg(feature = "bincode")]
+pub use codec::BincodeCodec;
+#[cfg(feature = "gzip")]
+pub use codec::Gzip;
+#[cfg(feature = "json")]
+pub use codec::JsonCodec;
+#[cfg(feature = "toml")]
+pub use codec::TomlCodec;
+#[cfg(feature = "zstd")]
+pub use codec::Zstd;
+pub use codec::{
+    Checksummed, Cipher, Codec, Compressed, Compression, Encoded, Encrypted, EnvKey, KeyProvider,
+    Migrated, Migrator, NewerSchema, Raw,
+};
+pub use cron::CronSchedule;
+pub use delta::{DeltaFetcher, DeltaStore, Patch};
+pub use diff::{Changes, Differ};
+pub use durability::{Durability, FileSync};
+pub use handle::RefreshHandle;
+pub use health::StoreHealth;
+pub use history::{History, HistoryEntry};
+pub use indexed::{Indexed, Matches};
+pub use info::{DataSource, StoreInfo};
+#[cfg(feature = "derive")]
+pub use kitchen_sink_macros::StoreCodec;
+#[cfg(feature = "redb")]
+pub use kv::KvStoreMap;
+pub use lazy::LazyStore;
+pub use lock::{LockPolicy, StoreLocked};
+pub use map::StoreMap;
+pub use meta::FetchMeta;
+pub use metrics::StoreMetrics;
+pub use middleware::{FetchThrottled, FetcherExt, RateLimitedFetcher, RetryFetcher, TracedFetcher};
+#[cfg(feature = "object-store")]
+pub use object::{ObjectChanged, ObjectStoreBackend};
+#[cfg(feature = "directories")]
+pub use path::StorePath;
+pub use read_only::ReadOnlyError;
+#[cfg(feature = "redis")]
+pub use redis::{Elected, RedisBackend};
+pub use registry::StoreRegistry;
+pub use replication::ReplicaStatus;
+pub use retry::InitialRetry;
+pub use schedule::{Backoff, ErrorPolicy, RefreshSchedule};
+pub use size_limit::{SizeExceeded, SizeLimit};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteBackend, StoredRow};
+pub use stream::{StreamCodec, Streamed};
+pub use throttle::RefreshThrottled;
+pub use timeout::{FetchTimeout, TimeoutFetcher};
+pub use transaction::Transaction;
+pub use ttl::Expired;
+pub use view::StoreView;
+pub use write_behind::WriteBehind;
 
 /// Exposes a thread-safe store that loads itself on initalization
 /// (if it exists) and can be refreshed on demand. When refreshed
 /// a working copy is stored on disk while the memory representation
 /// is updated.
-pub struct Store<T>(Arc<RwLock<T>>, PathBuf);
+pub struct Store<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    // Swapped rather than mutated in place while `read_owned` snapshots
+    // share it
+    data: RwLock<Arc<T>>,
+    // Held for the whole of a write, so writers persist one at a time while
+    // readers only wait for the swap
+    writer: Mutex<()>,
+    loc: PathBuf,
+    // Converts `data` to and from the bytes persisted
+    codec: SharedCodec<T>,
+    // Bumped on every successful write, so tests can observe refreshes
+    generation: AtomicU64,
+    changes: watch::Sender<u64>,
+    // False only while serving a placeholder; any write makes it true
+    ready: watch::Sender<bool>,
+    updated_at: Mutex<Instant>,
+    replicas: Mutex<Vec<Replica>>,
+    read_only: bool,
+    durability: Mutex<Durability>,
+    backups: AtomicUsize,
+    fetcher: Mutex<Option<SharedFetcher<T>>>,
+    metrics: Mutex<StoreMetrics>,
+    write_behind: Mutex<Option<Arc<Deferred>>>,
+    lock: Mutex<Option<LockPolicy>>,
+    // The file as our last write left it, so `watch_file` can skip it
+    written: Mutex<Option<FileStamp>>,
+    // Loaded from the sidecar on first use
+    meta: Mutex<Option<FetchMeta>>,
+    // Replaces the file at `loc` when set
+    backend: Option<Arc<dyn StorageBackend>>,
+    // The refresh in flight, for concurrent refreshes to join
+    refreshing: Mutex<Option<Flight>>,
+    hooks: Mutex<Hooks>,
+    size_limit: Mutex<Option<SizeLimit<T>>>,
+    content_hash: Mutex<Option<ContentHash>>,
+    refresh_limit: Mutex<Option<RateLimiter>>,
+    source: Mutex<DataSource>,
+    // The error of the latest refresh, cleared by a successful one
+    last_error: Mutex<Option<String>>,
+    max_age: Mutex<Option<Duration>>,
+    soft_ttl: Mutex<Option<Duration>>,
+    // Set while `get` has a background refresh running
+    revalidating: AtomicBool,
+    history: Mutex<Option<History>>,
+}
+
+impl<T> Inner<T> {
+    /// Records that `data` changed, returning the new generation. Called
+    /// with `data` still write-locked so the generation always matches it.
+    fn mark_updated(&self) -> u64 {
+        *self.updated_at.lock() = Instant::now();
+        self.set_source(DataSource::Written);
+        let generation = self.generation.fetch_add(1, Ordering::Release) + 1;
+        self.changes.send_replace(generation);
+        self.ready
+            .send_if_modified(|ready| !std::mem::replace(ready, true));
+        generation
+    }
+
+    /// Records that a fetch confirmed `data` is current without changing it.
+    fn mark_fresh(&self) {
+        *self.updated_at.lock() = Instant::now();
+    }
+}
 
 impl<T> Clone for Store<T> {
     fn clone(&self) -> Self {
-        Self(self.0.clone(), self.1.clone())
+        Self {
+            inner: self.inner.clone(),
+        }
     }
 }
 
@@ -38,17 +244,34 @@ where
     where
         F: FnOnce() -> Result<T, anyhow::Error>,
     {
-        let data = match std::fs::read(&loc) {
-            Err(_) => {
+        Store::load_or_get(loc, codec::raw(), Recovery::default(), getter)
+    }
+}
+
+impl<T> Store<T> {
+    /// Loads the file at `loc` through `codec`, or persists and serves
+    /// `getter`'s data when there is nothing usable to load.
+    fn load_or_get<F>(
+        loc: PathBuf,
+        codec: SharedCodec<T>,
+        recovery: Recovery,
+        getter: F,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        F: FnOnce() -> Result<T, anyhow::Error>,
+    {
+        let (data, decoded_in) = match load_or_recover(&loc, &*codec, &recovery)? {
+            None => {
                 // Assume store missing, let's run an update
                 let new_data = getter()?;
-                let serialized: Vec<u8> = (&new_data).into();
-                std::fs::write(&loc, serialized)?;
-                new_data
+                persist(&loc, &codec.encode(&new_data)?, Durability::NONE)?;
+                (new_data, None)
             }
-            Ok(v) => T::try_from(v)?,
+            Some((v, elapsed)) => (v, Some(elapsed)),
         };
-        Ok(Store(Arc::new(RwLock::new(data)), loc))
+        let store = Store::from_parts(data, loc, codec);
+        store.record_deserialize(decoded_in);
+        Ok(store)
     }
 }
 
@@ -60,72 +283,629 @@ where
     where
         F: Fetcher<T>,
     {
-        let data = match std::fs::read(&loc) {
-            Err(_) => {
+        let codec = codec::raw();
+        let (data, decoded_in) = match load_or_recover(&loc, &*codec, &Recovery::default())? {
+            None => {
                 // Assume store missing, let's run an update
                 let new_data = fetcher.fetch(None).await?;
-                let serialized: Vec<u8> = (&new_data).into();
-                std::fs::write(&loc, serialized)?;
-                new_data
+                persist(&loc, &codec.encode(&new_data)?, Durability::NONE)?;
+                (new_data, None)
             }
-            Ok(v) => T::try_from(v)?,
+            Some((v, elapsed)) => (v, Some(elapsed)),
         };
-        Ok(Store(Arc::new(RwLock::new(data)), loc))
+        let store = Store::from_parts(data, loc, codec);
+        store.record_deserialize(decoded_in);
+        Ok(store)
     }
 }
 
-impl<T> Store<T>
-where
-    for<'a> Vec<u8>: From<&'a T>,
-{
-    pub fn write(&self, new_data: T) -> Result<(), anyhow::Error> {
-        let serialized: Vec<u8> = (&new_data).into();
-        std::fs::write(&self.1, serialized)?;
-        {
-            let mut w = self.0.write();
-            *w = new_data;
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Replaces the file at `loc` with `bytes` by writing a temporary file next
+/// to it and renaming it into place, so a crash leaves either the old or the
+/// new contents but never a mix. `durability` says what is flushed to disk
+/// before returning.
+pub(crate) fn persist(
+    loc: &Path,
+    bytes: &[u8],
+    durability: Durability,
+) -> Result<(), anyhow::Error> {
+    persist_with(loc, durability, |out| Ok(out.write_all(bytes)?))
+}
+
+/// `persist` with the contents produced by `write` rather than held in
+/// memory. Nothing replaces `loc` if `write` fails.
+pub(crate) fn persist_with(
+    loc: &Path,
+    durability: Durability,
+    write: impl FnOnce(&mut dyn std::io::Write) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    // Unique per write so concurrent writers never share a temp file
+    let tmp = loc.with_file_name(format!(
+        ".{}.tmp-{}-{}",
+        base,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = (|| {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        durability.sync_file(&file)?;
+        Ok::<_, anyhow::Error>(std::fs::rename(&tmp, loc)?)
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to persist store {}", loc.display()));
+    }
+    durability.sync_parent(loc)?;
+    Ok(())
+}
+
+/// What opening a store does with a file that fails to deserialize, be it
+/// corrupt or written by an incompatible version. Whatever the choice, the
+/// failure is reported to the store's `StoreBuilder::on_corruption` hook and
+/// the store never opens with the bad data. Data from a newer schema
+/// (`NewerSchema`) is not corrupt and always fails the open instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoadFailure {
+    /// Move the file aside to `<name>.corrupt-<unix millis>` and fetch or
+    /// get fresh data. A `StorageBackend` has nowhere to move its data
+    /// aside, so for those this is `Overwrite`.
+    #[default]
+    Quarantine,
+    /// Fetch or get fresh data and write it over the file, keeping no copy.
+    /// The file is left alone if that fails.
+    Overwrite,
+    /// Fail to open with the deserialize error.
+    Fail,
+}
+
+/// How opening a store handles a file that fails to deserialize.
+#[derive(Clone, Default)]
+struct Recovery {
+    on_failure: LoadFailure,
+    /// Called for each file that fails to deserialize.
+    on_corruption: Option<CorruptionHook>,
+}
+
+impl From<LoadFailure> for Recovery {
+    fn from(on_failure: LoadFailure) -> Self {
+        Self {
+            on_failure,
+            on_corruption: None,
+        }
+    }
+}
+
+impl Recovery {
+    /// Reports `error`, from deserializing the data at `path`, to the hook,
+    /// handing it back.
+    fn report(
+        &self,
+        path: &Path,
+        quarantined: Option<PathBuf>,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        let Some(hook) = &self.on_corruption else {
+            return error;
+        };
+        let corruption = Corruption {
+            path: path.to_path_buf(),
+            quarantined,
+            error,
+        };
+        hook(&corruption);
+        corruption.error
+    }
+}
+
+/// Reads the file at `loc` and deserializes it through `codec`, returning
+/// `None` when there is nothing usable to load and the caller should fall
+/// back to fetching fresh data. A file that fails to deserialize is handled
+/// per `recovery`.
+fn load_or_recover<T>(
+    loc: &Path,
+    codec: &dyn Codec<T>,
+    recovery: &Recovery,
+) -> Result<Option<(T, Duration)>, anyhow::Error> {
+    let bytes = match std::fs::read(loc) {
+        Err(_) => return Ok(None),
+        Ok(v) => v,
+    };
+    let started = std::time::Instant::now();
+    let decoded = codec.decode(bytes).map(|data| (data, started.elapsed()));
+    recover(loc, recovery, decoded)
+}
+
+/// Handles the result of deserializing the file at `loc` per `recovery`,
+/// for loaders that don't go through `load_or_recover`.
+fn recover<T>(
+    loc: &Path,
+    recovery: &Recovery,
+    decoded: Result<(T, Duration), anyhow::Error>,
+) -> Result<Option<(T, Duration)>, anyhow::Error> {
+    let e = match decoded {
+        Ok(loaded) => return Ok(Some(loaded)),
+        Err(e) => e,
+    };
+    // Intact data from a newer build, which a rollback mustn't throw away
+    if e.is::<NewerSchema>() {
+        return Err(e).with_context(|| format!("Failed to load store {}", loc.display()));
+    }
+    match recovery.on_failure {
+        LoadFailure::Quarantine => {
+            let quarantined = quarantine(loc, &e)?;
+            recovery.report(loc, Some(quarantined), e);
+            Ok(None)
+        }
+        LoadFailure::Overwrite => {
+            error!(
+                path = %loc.display(),
+                error = %format!("{:#}", e),
+                "Store file failed to deserialize, refetching over it"
+            );
+            recovery.report(loc, None, e);
+            Ok(None)
+        }
+        LoadFailure::Fail => Err(recovery.report(loc, None, e))
+            .with_context(|| format!("Failed to load store {}", loc.display())),
+    }
+}
+
+/// Moves the file at `loc`, which failed to deserialize with `err`, aside to
+/// `<name>.corrupt-<unix millis>`, returning where it went.
+fn quarantine(loc: &Path, err: &anyhow::Error) -> Result<PathBuf, anyhow::Error> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    let mut quarantined = loc.with_file_name(format!("{}.corrupt-{}", base, millis));
+    let mut attempt = 1;
+    while quarantined.exists() {
+        quarantined = loc.with_file_name(format!("{}.corrupt-{}-{}", base, millis, attempt));
+        attempt += 1;
+    }
+    std::fs::rename(loc, &quarantined)
+        .with_context(|| format!("Failed to quarantine corrupt store {}", loc.display()))?;
+    error!(
+        path = %loc.display(),
+        quarantined = %quarantined.display(),
+        error = %format!("{:#}", err),
+        "Store file failed to deserialize, moved aside and refetching"
+    );
+    Ok(quarantined)
+}
+
+/// Stored data that failed to deserialize when a store was opened.
+#[derive(Debug)]
+pub struct Corruption {
+    /// The store's file, empty for a store kept in a `StorageBackend`.
+    pub path: PathBuf,
+    /// Where the file was moved aside to, under `LoadFailure::Quarantine`.
+    pub quarantined: Option<PathBuf>,
+    pub error: anyhow::Error,
+}
+
+type CorruptionHook = Arc<dyn Fn(&Corruption) + Send + Sync>;
+
+impl<T> Store<T> {
+    /// Replaces the data and persists it. Data identical to what the store
+    /// holds is neither rewritten nor signalled as a change.
+    ///
+    /// Writers take turns, each persisting its data before swapping it in,
+    /// so the file always holds what the store does or is about to. Readers
+    /// only wait for the swap, never for the file.
+    pub fn write(&self, mut new_data: T) -> Result<(), anyhow::Error> {
+        self.check_writable()?;
+        let serialized = self.serialize_within_limit(&mut new_data)?;
+        self.replace(new_data, serialized)
+    }
+
+    /// The rest of `write` once `new_data` is serialized: persists
+    /// `serialized` and swaps `new_data` in.
+    fn replace(&self, new_data: T, serialized: Vec<u8>) -> Result<(), anyhow::Error> {
+        let writer = self.inner.writer.lock();
+        let generation = self.replace_locked(new_data, serialized)?;
+        drop(writer);
+        if let Some(generation) = generation {
+            self.run_write_hooks(generation);
         }
         Ok(())
     }
+
+    /// `replace` for a caller holding the writer lock, leaving the write
+    /// hooks to it. Returns the new generation, or `None` when the data was
+    /// unchanged.
+    fn replace_locked(
+        &self,
+        new_data: T,
+        serialized: Vec<u8>,
+    ) -> Result<Option<u64>, anyhow::Error> {
+        if self.unchanged(&self.read_owned(), &serialized) {
+            return Ok(None);
+        }
+        self.save(&serialized)?;
+        let generation = self.swap(Arc::new(new_data));
+        self.written(generation, serialized);
+        Ok(Some(generation))
+    }
+
+    /// Swaps in `new_data`, returning its generation. The only part of a
+    /// write that holds the data lock.
+    fn swap(&self, new_data: Arc<T>) -> u64 {
+        let mut data = self.inner.data.write();
+        *data = new_data;
+        self.inner.mark_updated()
+    }
+
+    /// Notes that `serialized` was persisted as `generation` and passes it
+    /// on to replicas.
+    fn written(&self, generation: u64, serialized: Vec<u8>) {
+        self.remember_content(generation, &serialized);
+        replication::replicate(&self.inner.replicas.lock(), generation, serialized);
+    }
+
+    /// Writes `new_data` only if the store is still at version `expected`,
+    /// returning the new version. Otherwise nothing is written and the error
+    /// is a `VersionConflict`, so a writer that read an older version finds
+    /// out instead of clobbering a newer write.
+    pub fn write_if_version(&self, expected: u64, mut new_data: T) -> Result<u64, anyhow::Error> {
+        self.check_writable()?;
+        let writer = self.inner.writer.lock();
+        let actual = self.version();
+        if actual != expected {
+            return Err(VersionConflict { expected, actual }.into());
+        }
+        let serialized = self.serialize_within_limit(&mut new_data)?;
+        self.save(&serialized)?;
+        let generation = self.swap(Arc::new(new_data));
+        self.written(generation, serialized);
+        drop(writer);
+        self.run_write_hooks(generation);
+        Ok(generation)
+    }
+
+    fn check_writable(&self) -> Result<(), anyhow::Error> {
+        if self.inner.read_only {
+            return Err(ReadOnlyError {
+                path: self.inner.loc.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Mirrors every successful write to `path` from a background task.
+    /// Replicas are best-effort: a failing replica is logged and reported
+    /// through `replica_status` but never fails the primary write. Only the
+    /// latest write is copied if a replica falls behind.
+    ///
+    /// The current contents are copied immediately. Must be called from
+    /// within a tokio runtime.
+    pub fn add_replica(&self, path: PathBuf) {
+        let backend = Arc::new(FileBackend::new(path.clone()));
+        self.spawn_replica(path, backend);
+    }
+
+    /// `add_replica` to a backend, such as object storage, rather than a
+    /// file. Its status reports an empty path.
+    pub fn add_replica_backend(&self, backend: impl StorageBackend) {
+        self.spawn_replica(PathBuf::new(), Arc::new(backend));
+    }
+
+    fn spawn_replica(&self, path: PathBuf, backend: Arc<dyn StorageBackend>) {
+        let generation = self.inner.generation.load(Ordering::Acquire);
+        let serialized = match self.encode(&self.read()) {
+            Ok(serialized) => serialized,
+            Err(e) => {
+                // Caught up by the next write instead
+                error!("Failed to serialize store for a new replica: {:#}", e);
+                Vec::new()
+            }
+        };
+        let replica = Replica::spawn(path, backend, generation, serialized);
+        self.inner.replicas.lock().push(replica);
+    }
+    /// Applies `f` to a copy of the data and persists the result before
+    /// swapping it in, with other writers waiting, so concurrent updates
+    /// never lose each other's changes. If persisting fails the store keeps
+    /// its old data and the error is returned.
+    ///
+    /// Readers see the old data until the new data is on disk.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, anyhow::Error>
+    where
+        T: Clone,
+    {
+        self.check_writable()?;
+        let writer = self.inner.writer.lock();
+        // Shared with the store, so `make_mut` clones it
+        let mut next = self.read_owned();
+        let data = Arc::make_mut(&mut next);
+        let result = f(data);
+        let serialized = self.serialize_within_limit(data)?;
+        self.save(&serialized)?;
+        let generation = self.swap(next);
+        self.written(generation, serialized);
+        drop(writer);
+        self.run_write_hooks(generation);
+        Ok(result)
+    }
 }
 
 impl<T> Store<T> {
-    pub fn read(&self) -> RwLockReadGuard<'_, parking_lot::RawRwLock, T> {
-        self.0.read()
+    fn from_parts(data: T, loc: PathBuf, codec: SharedCodec<T>) -> Self {
+        Self::with_access(data, loc, codec, false, None)
+    }
+
+    fn with_access(
+        data: T,
+        loc: PathBuf,
+        codec: SharedCodec<T>,
+        read_only: bool,
+        backend: Option<Arc<dyn StorageBackend>>,
+    ) -> Self {
+        // Data loaded from disk is as old as the file it came from
+        let now = Instant::now();
+        let updated_at = std::fs::metadata(&loc)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .and_then(|age| now.checked_sub(age))
+            .unwrap_or(now);
+        Store {
+            inner: Arc::new(Inner {
+                data: RwLock::new(Arc::new(data)),
+                writer: Mutex::new(()),
+                loc,
+                codec,
+                generation: AtomicU64::new(0),
+                changes: watch::channel(0).0,
+                ready: watch::channel(true).0,
+                updated_at: Mutex::new(updated_at),
+                replicas: Mutex::new(Vec::new()),
+                read_only,
+                durability: Mutex::new(Durability::NONE),
+                backups: AtomicUsize::new(0),
+                fetcher: Mutex::new(None),
+                metrics: Mutex::new(StoreMetrics::default()),
+                write_behind: Mutex::new(None),
+                lock: Mutex::new(None),
+                written: Mutex::new(None),
+                meta: Mutex::new(None),
+                backend,
+                refreshing: Mutex::new(None),
+                hooks: Mutex::new(Hooks::default()),
+                size_limit: Mutex::new(None),
+                content_hash: Mutex::new(None),
+                refresh_limit: Mutex::new(None),
+                source: Mutex::new(DataSource::Initial),
+                last_error: Mutex::new(None),
+                max_age: Mutex::new(None),
+                soft_ttl: Mutex::new(None),
+                revalidating: AtomicBool::new(false),
+                history: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Persists `bytes`, or only notes that a flush is due in write-behind
+    /// mode.
+    fn save(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        if let Some(deferred) = &*self.inner.write_behind.lock() {
+            deferred.defer();
+            return Ok(());
+        }
+        self.save_now(bytes)
+    }
+
+    /// Persists `bytes` to the store's backend, or as its file honouring
+    /// `with_durability`, `with_backups` and `with_history`.
+    fn save_now(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        match &self.inner.backend {
+            Some(backend) => backend.persist(bytes)?,
+            None => {
+                let _lock = self
+                    .lock_policy()
+                    .map(|policy| lock::acquire(&self.inner.loc, policy))
+                    .transpose()?;
+                backup::save_file(&self.inner.loc, bytes, self.durability(), self.backups())?;
+                *self.inner.written.lock() = read_only::file_stamp(&self.inner.loc);
+                self.record_history(bytes);
+            }
+        }
+        self.record_written(bytes.len());
+        Ok(())
+    }
+
+    pub fn read(&self) -> MappedRwLockReadGuard<'_, T> {
+        RwLockReadGuard::map(self.inner.data.read(), |data| &**data)
+    }
+
+    /// `read`, but gives up with a `ReadTimeout` rather than blocking for
+    /// longer than `timeout` behind a writer.
+    pub fn try_read(
+        &self,
+        timeout: Duration,
+    ) -> Result<MappedRwLockReadGuard<'_, T>, anyhow::Error> {
+        match self.inner.data.try_read_for(timeout) {
+            Some(data) => Ok(RwLockReadGuard::map(data, |data| &**data)),
+            None => Err(ReadTimeout {
+                path: self.inner.loc.clone(),
+                waited: timeout,
+                version: self.version(),
+            }
+            .into()),
+        }
+    }
+
+    /// A snapshot of the data that doesn't hold the lock, so unlike `read`
+    /// it is safe to keep across awaits. Writes replace the data rather than
+    /// changing it, so a snapshot never sees them.
+    pub fn read_owned(&self) -> Arc<T> {
+        self.inner.data.read().clone()
+    }
+
+    /// A copy of the data, detached from the store, for callers that need
+    /// to own or mutate it. Copied from a `read_owned` snapshot, so writers
+    /// aren't held up by the clone.
+    pub fn snapshot(&self) -> T
+    where
+        T: Clone,
+    {
+        T::clone(&self.read_owned())
+    }
+
+    /// Follows the store's generation, which increases with every change to
+    /// its data, so consumers can react to refreshes instead of polling.
+    /// Call `read` after `changed()` resolves for the new data.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.inner.changes.subscribe()
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Acquire)
+    }
+
+    /// Increases with every change to the data; pass it to
+    /// `write_if_version` to detect concurrent writes.
+    pub fn version(&self) -> u64 {
+        self.generation()
+    }
+
+    /// Progress of each replica added with `add_replica`.
+    pub fn replica_status(&self) -> Vec<ReplicaStatus> {
+        let generation = self.generation();
+        self.inner
+            .replicas
+            .lock()
+            .iter()
+            .map(|r| r.status(generation))
+            .collect()
+    }
+}
+
+/// Returned by `write_if_version` when the store moved on from the version
+/// the writer expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConflict {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Store is at version {}, expected {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+/// Returned by `try_read` when the lock stayed held, almost always by a
+/// writer stuck persisting, for longer than the caller was willing to wait.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadTimeout {
+    pub path: PathBuf,
+    pub waited: Duration,
+    /// The store's version when the read gave up.
+    pub version: u64,
+}
+
+impl fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:?} waiting to read store {} at version {}; a writer is holding its lock",
+            self.waited,
+            self.path.display(),
+            self.version
+        )
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+/// What a refresh fetched: new data to write, or word that the upstream
+/// hasn't changed since the store's data was fetched.
+pub enum FetchResult<T> {
+    Updated(T),
+    /// New data along with validators to send on the next fetch; see
+    /// `Store::fetch_meta`.
+    Tagged(T, FetchMeta),
+    Unchanged,
+}
+
+impl<T> FetchResult<T> {
+    /// The new data and its validators, or `None` when unchanged.
+    fn into_parts(self) -> Option<(T, Option<FetchMeta>)> {
+        match self {
+            FetchResult::Updated(data) => Some((data, None)),
+            FetchResult::Tagged(data, meta) => Some((data, Some(meta))),
+            FetchResult::Unchanged => None,
+        }
     }
 }
 
 #[async_trait]
 pub trait Fetcher<T> {
     async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error>;
+
+    /// Fetches for a refresh of an existing `store`. Override to return
+    /// `Unchanged` when the upstream can say so (an ETag, a version number),
+    /// which skips serializing and rewriting the file; the data still counts
+    /// as fresh. Defaults to `fetch`.
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.fetch(Some(store)).await.map(FetchResult::Updated)
+    }
+}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for Arc<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync + ?Sized,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        (**self).fetch(store).await
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        (**self).fetch_update(store).await
+    }
 }
 impl<T: Send + Sync + 'static> Store<T> {
-    pub fn scheduled_updates<F>(&self, fetcher: F, between: Duration)
+    /// Fetches and writes new data on `schedule`, usually just a `Duration`
+    /// or a `CronSchedule`, until stopped through the returned handle.
+    /// Failures are logged and retried on the next interval; see
+    /// `scheduled_updates_with_policy` for other behaviour.
+    pub fn scheduled_updates<F>(
+        &self,
+        fetcher: F,
+        schedule: impl Into<RefreshSchedule>,
+    ) -> RefreshHandle
     where
-        F: Fetcher<T> + Send + Sync + 'static + Clone,
-        for<'a> Vec<u8>: From<&'a T>,
+        F: Fetcher<T> + Send + Sync + 'static,
     {
-        let mvfetch = fetcher.clone();
-        let mvstore = self.clone();
-        tokio::spawn(async move {
-            loop {
-                sleep(between).await;
-                if let Err(e) = mvfetch
-                    .fetch(Some(mvstore.clone()))
-                    .await
-                    .and_then(|v| mvstore.write(v))
-                {
-                    error!("Failed to update database: {}", e);
-                }
-            }
-        });
+        self.scheduled_updates_with_policy(fetcher, schedule, ErrorPolicy::default())
     }
 }
 
 #[cfg(test)]
+#[allow(unused)]
 mod tests {
     /// Example of how to use this module
     use super::*;
+    use crate::simple_store::testing::Text;
+    use std::time::Duration;
 
     #[derive(Default)]
     struct MyData {
@@ -173,4 +953,225 @@ mod tests {
         s.read(); // Grab a read lock
         Ok(())
     }
+
+    #[test]
+    fn writes_replace_the_file_atomically() {
+        let tmp = testing::TempStore::from_value(Text("old".into())).unwrap();
+        let store = tmp.store().clone().with_fsync();
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let store = store.clone();
+                s.spawn(move || store.write(Text(format!("value {}", i).repeat(1000))));
+            }
+        });
+        let on_disk = String::from_utf8(tmp.bytes().unwrap()).unwrap();
+        assert!((0..8).any(|i| on_disk == format!("value {}", i).repeat(1000)));
+        assert_eq!(on_disk, tmp.read().0);
+        // Only the store file itself is left behind
+        let files = std::fs::read_dir(tmp.dir()).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_writes_leave_file_and_memory_agreed() {
+        let tmp = testing::TempStore::from_value(Text(String::new())).unwrap();
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let store = tmp.store().clone();
+                s.spawn(move || {
+                    for n in 0..50 {
+                        store.write(Text(format!("{}-{}", i, n))).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(tmp.bytes().unwrap(), tmp.read().0.as_bytes());
+
+        let writes = (0..4).map(|i| {
+            let store = tmp.store().clone();
+            tokio::spawn(async move {
+                for n in 0..50 {
+                    store
+                        .write_async(Text(format!("{}+{}", i, n)))
+                        .await
+                        .unwrap();
+                }
+            })
+        });
+        futures::future::try_join_all(writes).await.unwrap();
+        assert_eq!(tmp.bytes().unwrap(), tmp.read().0.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn subscribers_see_each_write() {
+        let tmp = testing::TempStore::from_value(Text("a".into())).unwrap();
+        let mut changes = tmp.subscribe();
+        let mvstore = tmp.store().clone();
+        let watcher = tokio::spawn(async move {
+            changes.changed().await.unwrap();
+            (*changes.borrow_and_update(), mvstore.read().0.clone())
+        });
+        tmp.write(Text("b".into())).unwrap();
+        assert_eq!(watcher.await.unwrap(), (1, "b".to_string()));
+    }
+
+    #[test]
+    fn updates_are_not_lost() {
+        let tmp = testing::TempStore::from_value(Text(String::new())).unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..25 {
+                        tmp.update(|t| t.0.push('x')).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(tmp.read().0.len(), 100);
+        assert_eq!(tmp.bytes().unwrap().len(), 100);
+
+        std::fs::remove_dir_all(tmp.dir()).unwrap();
+        assert!(tmp.update(|t| t.0.clear()).is_err());
+        assert_eq!(tmp.read().0.len(), 100);
+    }
+
+    #[test]
+    fn stale_versioned_writes_are_rejected() {
+        let tmp = testing::TempStore::from_value(Text("a".into())).unwrap();
+        let seen = tmp.version();
+        let next = tmp.write_if_version(seen, Text("b".into())).unwrap();
+        assert_eq!(next, tmp.version());
+
+        let err = tmp.write_if_version(seen, Text("c".into())).unwrap_err();
+        let conflict = err.downcast_ref::<VersionConflict>().unwrap();
+        assert_eq!(conflict.actual, next);
+        assert_eq!(tmp.read().0, "b");
+        assert_eq!(tmp.bytes().unwrap(), b"b");
+    }
+
+    #[tokio::test]
+    async fn owned_snapshots_outlive_writes() {
+        let tmp = testing::TempStore::from_value(Text("a".into())).unwrap();
+        let snapshot = tmp.read_owned();
+        tokio::task::yield_now().await;
+        tmp.update(|t| t.0.push('b')).unwrap();
+        tmp.write(Text("c".into())).unwrap();
+        assert_eq!(snapshot.0, "a");
+        assert_eq!(tmp.read_owned().0, "c");
+    }
+
+    #[tokio::test]
+    async fn load_failures_follow_the_policy() {
+        let tmp = testing::TempStore::from_value(Text("a".into())).unwrap();
+        tmp.corrupt_with([0xff]).unwrap();
+        let failed = Store::<Text>::builder()
+            .path(tmp.path())
+            .on_load_failure(LoadFailure::Fail)
+            .build()
+            .await;
+        assert!(failed.is_err());
+
+        let store = Store::<Text>::builder()
+            .path(tmp.path())
+            .initial(|| Text("fresh".into()))
+            .on_load_failure(LoadFailure::Overwrite)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(store.read().0, "fresh");
+        assert_eq!(tmp.bytes().unwrap(), b"fresh");
+        assert!(tmp.quarantined().unwrap().is_empty());
+    }
+
+    /// Persists only once `release` is sent, reporting on `started` first.
+    struct Gated {
+        started: std::sync::mpsc::Sender<()>,
+        release: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl StorageBackend for Gated {
+        fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+            Ok(None)
+        }
+
+        fn persist(&self, _: &[u8]) -> Result<(), anyhow::Error> {
+            let _ = self.started.send(());
+            self.release.lock().recv()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn readers_dont_wait_for_a_persisting_writer() {
+        let (started, on_start) = std::sync::mpsc::channel();
+        let (release, on_release) = std::sync::mpsc::channel();
+        let backend: Arc<dyn StorageBackend> = Arc::new(Gated {
+            started,
+            release: Mutex::new(on_release),
+        });
+        let store = Store::with_access(
+            Text("a".into()),
+            PathBuf::new(),
+            codec::raw(),
+            false,
+            Some(backend),
+        );
+        let writing = std::thread::spawn({
+            let store = store.clone();
+            move || store.write_if_version(0, Text("b".into()))
+        });
+        on_start.recv().unwrap();
+        assert_eq!(store.try_read(Duration::from_millis(100)).unwrap().0, "a");
+        release.send(()).unwrap();
+        assert_eq!(writing.join().unwrap().unwrap(), 1);
+        assert_eq!(store.read().0, "b");
+
+        // Remembered as written, so the same data again is skipped
+        release.send(()).unwrap();
+        store.write(Text("b".into())).unwrap();
+        assert_eq!(store.version(), 1);
+    }
+
+    /// Fails every persist, like a full disk.
+    struct Unwritable;
+
+    impl StorageBackend for Unwritable {
+        fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+            Ok(None)
+        }
+
+        fn persist(&self, _: &[u8]) -> Result<(), anyhow::Error> {
+            Err(anyhow::anyhow!("disk full"))
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_persists_leave_the_store_unchanged() {
+        let store = Store::with_access(
+            Text("a".into()),
+            PathBuf::new(),
+            codec::raw(),
+            false,
+            Some(Arc::new(Unwritable)),
+        );
+        let changes = store.subscribe();
+        let err = store.write(Text("b".into())).unwrap_err();
+        assert!(format!("{:#}", err).contains("disk full"));
+        assert!(store.update(|t| t.0.push('!')).is_err());
+        assert!(store.write_async(Text("c".into())).await.is_err());
+        assert_eq!(store.read().0, "a");
+        assert_eq!(store.version(), 0);
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn reads_give_up_behind_a_stuck_writer() {
+        let tmp = testing::TempStore::from_value(Text("a".into())).unwrap();
+        let wait = Duration::from_millis(20);
+        let held = tmp.inner.data.write();
+        let err = tmp.try_read(wait).unwrap_err();
+        assert_eq!(err.downcast_ref::<ReadTimeout>().unwrap().waited, wait);
+        drop(held);
+        assert_eq!(tmp.try_read(wait).unwrap().0, "a");
+    }
 }
@@ -0,0 +1,98 @@
+use super::{Fetcher, Store};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::error;
+
+/// Decides how long to wait before the next scheduled fetch, given the
+/// interval just used and whether that fetch changed the stored data.
+/// Closures of the same shape work too.
+pub trait RefreshPolicy: Send + 'static {
+    fn next_interval(&mut self, current: Duration, changed: bool) -> Duration;
+}
+
+impl<F> RefreshPolicy for F
+where
+    F: FnMut(Duration, bool) -> Duration + Send + 'static,
+{
+    fn next_interval(&mut self, current: Duration, changed: bool) -> Duration {
+        self(current, changed)
+    }
+}
+
+/// Divides the interval by `factor` after a fetch that changed the data and
+/// multiplies it by `factor` after one that didn't, staying within
+/// `min..=max`.
+#[derive(Debug, Clone)]
+pub struct AdaptiveInterval {
+    pub min: Duration,
+    pub max: Duration,
+    pub factor: u32,
+}
+
+impl RefreshPolicy for AdaptiveInterval {
+    fn next_interval(&mut self, current: Duration, changed: bool) -> Duration {
+        let factor = self.factor.max(1);
+        let next = if changed {
+            current / factor
+        } else {
+            current.saturating_mul(factor)
+        };
+        next.clamp(self.min, self.max)
+    }
+}
+
+impl<T: Send + Sync + 'static> Store<T> {
+    /// Like `scheduled_updates`, but the wait between fetches is recomputed by
+    /// `policy` after each successful fetch, starting from `initial`. A fetch
+    /// counts as a change when its serialized form differs from what was
+    /// stored. Failed fetches are logged and retried after the same interval.
+    pub fn scheduled_updates_adaptive<F, P>(&self, fetcher: F, initial: Duration, mut policy: P)
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+        P: RefreshPolicy,
+    {
+        let mvstore = self.clone();
+        tokio::spawn(async move {
+            let mut between = initial;
+            loop {
+                sleep(between).await;
+                let changed = match mvstore.refresh(&fetcher).await {
+                    Ok(changed) => changed,
+                    Err(e) => {
+                        error!("Failed to update database: {}", e);
+                        continue;
+                    }
+                };
+                between = policy.next_interval(between, changed);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Climb, Count, TempStore, TimeHarness};
+
+    #[tokio::test]
+    async fn speeds_up_on_change_and_backs_off_when_static() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let policy = AdaptiveInterval {
+            min: Duration::from_secs(10),
+            max: Duration::from_secs(80),
+            factor: 2,
+        };
+        tmp.scheduled_updates_adaptive(Climb(3), Duration::from_secs(40), policy);
+
+        let mut refreshed_at = Vec::new();
+        for _ in 0..7 {
+            time.advance_until_refresh(&tmp).await.unwrap();
+            refreshed_at.push(time.elapsed().as_secs());
+        }
+        // Three changes halve the wait down to the floor, then it doubles
+        // back up to the cap once the data stops moving.
+        assert_eq!(refreshed_at, vec![40, 60, 70, 80, 100, 140, 220]);
+        assert_eq!(*tmp.read(), Count(3));
+    }
+}
@@ -0,0 +1,225 @@
+use super::{Codec, DeltaStore, Patch, Raw, Store};
+use crate::framing::{read_frames, write_frame};
+use anyhow::{Context, bail};
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// The records of an `AppendStore`, oldest first. Persisted as one
+/// length-prefixed frame per record, each encoded with `C`.
+pub struct Records<R, C = Raw> {
+    pub records: Vec<R>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<R, C> Records<R, C> {
+    pub fn new(records: Vec<R>) -> Self {
+        Self {
+            records,
+            _codec: PhantomData,
+        }
+    }
+}
+
+impl<R, C> Default for Records<R, C> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+// Derives would needlessly require the codec to implement these too
+impl<R: Clone, C> Clone for Records<R, C> {
+    fn clone(&self) -> Self {
+        Self::new(self.records.clone())
+    }
+}
+
+impl<R: fmt::Debug, C> fmt::Debug for Records<R, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Records").field(&self.records).finish()
+    }
+}
+
+impl<R: PartialEq, C> PartialEq for Records<R, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.records == other.records
+    }
+}
+
+impl<R, C: Codec<R> + Default> TryFrom<Vec<u8>> for Records<R, C> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        let (frames, valid_len) = read_frames(&mut value.as_slice())?;
+        if valid_len != value.len() as u64 {
+            bail!("Truncated record at byte {}", valid_len);
+        }
+        frames
+            .into_iter()
+            .enumerate()
+            .map(|(idx, frame)| {
+                C::default()
+                    .decode(frame)
+                    .with_context(|| format!("Failed to decode record {}", idx))
+            })
+            .collect::<Result<_, _>>()
+            .map(Records::new)
+    }
+}
+
+impl<'a, R, C: Codec<R> + Default> From<&'a Records<R, C>> for Vec<u8> {
+    fn from(value: &'a Records<R, C>) -> Self {
+        let codec = C::default();
+        let mut out = Vec::new();
+        for record in &value.records {
+            let frame = codec.encode(record).expect("codec failed to encode record");
+            write_frame(&mut out, &frame).expect("record exceeds 4GiB");
+        }
+        out
+    }
+}
+
+/// One record journaled by `AppendStore::append`.
+struct Append<R, C>(R, PhantomData<fn() -> C>);
+
+impl<R, C> Patch<Records<R, C>> for Append<R, C> {
+    fn apply(self, target: &mut Records<R, C>) {
+        target.records.push(self.0);
+    }
+}
+
+impl<R, C: Codec<R> + Default> TryFrom<Vec<u8>> for Append<R, C> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        C::default()
+            .decode(value)
+            .map(|record| Append(record, PhantomData))
+    }
+}
+
+impl<'a, R, C: Codec<R> + Default> From<&'a Append<R, C>> for Vec<u8> {
+    fn from(value: &'a Append<R, C>) -> Self {
+        C::default()
+            .encode(&value.0)
+            .expect("codec failed to encode record")
+    }
+}
+
+/// A `Store` of a growing collection, for log-like data where rewriting
+/// the whole file on every addition would be wasteful. Each `append` only
+/// writes the new record to the journal; on open the collection is rebuilt
+/// from the last compacted file plus the journal. Every `compact_after`
+/// records the file is rewritten and the journal cleared.
+///
+/// ```ignore
+/// let events: AppendStore<Event> = AppendStore::open(dir.join("events"), 1000)?;
+/// events.append(Event::started())?;
+/// let count = events.len();
+/// ```
+///
+/// Records are stored in their `TryFrom`/`From` byte form unless another
+/// `Codec` is named, as in `AppendStore<Event, EventCodec>`.
+pub struct AppendStore<R, C = Raw> {
+    delta: DeltaStore<Records<R, C>, Append<R, C>>,
+}
+
+impl<R, C> Clone for AppendStore<R, C> {
+    fn clone(&self) -> Self {
+        Self {
+            delta: self.delta.clone(),
+        }
+    }
+}
+
+impl<R: Clone, C: Codec<R> + Default> AppendStore<R, C> {
+    /// Opens the collection at `loc`, empty if there is none yet.
+    pub fn open(loc: PathBuf, compact_after: usize) -> Result<Self, anyhow::Error> {
+        let delta = DeltaStore::open(loc, || Ok(Records::default()), compact_after)?;
+        Ok(Self { delta })
+    }
+
+    pub fn append(&self, record: R) -> Result<(), anyhow::Error> {
+        self.delta.apply(Append(record, PhantomData))
+    }
+
+    /// Appends every record in one journal write.
+    pub fn append_all(&self, records: Vec<R>) -> Result<(), anyhow::Error> {
+        self.delta.apply_all(
+            records
+                .into_iter()
+                .map(|record| Append(record, PhantomData))
+                .collect(),
+        )
+    }
+
+    /// Folds the journal into the file now.
+    pub fn compact(&self) -> Result<(), anyhow::Error> {
+        self.delta.compact()
+    }
+
+    pub fn len(&self) -> usize {
+        self.store().read().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn store(&self) -> &Store<Records<R, C>> {
+        self.delta.store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempDir;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Event(String);
+
+    impl TryFrom<Vec<u8>> for Event {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Event(String::from_utf8(value)?))
+        }
+    }
+
+    impl<'a> From<&'a Event> for Vec<u8> {
+        fn from(value: &'a Event) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    fn event(name: &str) -> Event {
+        Event(name.to_string())
+    }
+
+    #[test]
+    fn appends_and_rebuilds_on_open() {
+        let dir = TempDir::new().unwrap();
+        let loc = dir.path().join("events");
+        let log: AppendStore<Event> = AppendStore::open(loc.clone(), 3).unwrap();
+        assert!(log.is_empty());
+        log.append(event("a")).unwrap();
+        log.append(event("b")).unwrap();
+        assert!(std::fs::read(&loc).unwrap().is_empty());
+
+        let reopened: AppendStore<Event> = AppendStore::open(loc.clone(), 3).unwrap();
+        assert_eq!(
+            reopened.store().read().records,
+            vec![event("a"), event("b")]
+        );
+        drop(reopened);
+
+        log.append_all(vec![event("c"), event("d")]).unwrap();
+        assert!(!std::fs::read(&loc).unwrap().is_empty());
+        log.append(event("e")).unwrap();
+
+        let reopened: AppendStore<Event> = AppendStore::open(loc, 3).unwrap();
+        assert_eq!(reopened.len(), 5);
+        assert_eq!(*reopened.store().read(), *log.store().read());
+    }
+}
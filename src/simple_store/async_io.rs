@@ -0,0 +1,115 @@
+//! Counterparts to the constructors and `write` that keep file IO, and
+//! serialization on write, off the async worker threads, for stores large
+//! enough that blocking on them would stall other tasks. The files written
+//! are identical.
+
+use super::codec::{self, SharedCodec};
+use super::{Durability, Fetcher, Recovery, Store, load_or_recover, persist};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::spawn_blocking;
+
+impl<T> Store<T>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error> + Send + 'static,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_with_default` without blocking the runtime.
+    pub async fn new_with_default_async(loc: PathBuf) -> Result<Store<T>, anyhow::Error>
+    where
+        T: Default,
+    {
+        let initial = async { Ok(T::default()) };
+        Self::load_async(loc, codec::raw(), Recovery::default(), initial).await
+    }
+
+    /// `new_with_fetcher` without blocking the runtime.
+    pub async fn new_with_fetcher_async<F>(
+        loc: PathBuf,
+        fetcher: F,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        F: Fetcher<T>,
+    {
+        Self::load_async(loc, codec::raw(), Recovery::default(), fetcher.fetch(None)).await
+    }
+}
+
+impl<T: Send + 'static> Store<T> {
+    pub(super) async fn load_async(
+        loc: PathBuf,
+        codec: SharedCodec<T>,
+        recovery: Recovery,
+        getter: impl Future<Output = Result<T, anyhow::Error>>,
+    ) -> Result<Store<T>, anyhow::Error> {
+        let (loc, loaded) = Self::load_file(loc, codec.clone(), recovery).await?;
+        let (data, decoded_in) = match loaded {
+            None => {
+                // Assume store missing, let's run an update
+                let new_data = getter.await?;
+                let serialized = codec.encode(&new_data)?;
+                let target = loc.clone();
+                spawn_blocking(move || persist(&target, &serialized, Durability::NONE)).await??;
+                (new_data, None)
+            }
+            Some((v, elapsed)) => (v, Some(elapsed)),
+        };
+        let store = Store::from_parts(data, loc, codec);
+        store.record_deserialize(decoded_in);
+        Ok(store)
+    }
+
+    /// `load_or_recover` on the blocking pool, handing `loc` back.
+    pub(super) async fn load_file(
+        loc: PathBuf,
+        codec: SharedCodec<T>,
+        recovery: Recovery,
+    ) -> Result<(PathBuf, Option<(T, Duration)>), anyhow::Error> {
+        let (loc, loaded) = spawn_blocking(move || {
+            let loaded = load_or_recover(&loc, &*codec, &recovery);
+            (loc, loaded)
+        })
+        .await?;
+        Ok((loc, loaded?))
+    }
+}
+
+impl<T: Send + Sync + 'static> Store<T> {
+    /// `write` with the data serialized and the file written on the blocking
+    /// pool, so a large value doesn't stall the runtime.
+    pub async fn write_async(&self, new_data: T) -> Result<(), anyhow::Error> {
+        self.check_writable()?;
+        if self.inner.write_behind.lock().is_some() {
+            // Nothing to keep off the runtime, the file is written later
+            return self.write(new_data);
+        }
+        let store = self.clone();
+        spawn_blocking(move || {
+            let mut new_data = new_data;
+            let serialized = store.serialize_within_limit(&mut new_data)?;
+            store.replace(new_data, serialized)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[tokio::test]
+    async fn persists_through_the_blocking_pool() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("text");
+        let store: Store<Text> = Store::new_with_default_async(path.clone()).await.unwrap();
+        assert!(path.exists());
+        store.write_async(Text("hello".into())).await.unwrap();
+        assert_eq!(store.version(), 1);
+        store.write_async(Text("hello".into())).await.unwrap();
+        assert_eq!(store.version(), 1);
+
+        let reopened: Store<Text> = Store::new_with_default_async(path).await.unwrap();
+        assert_eq!(*reopened.read(), Text("hello".into()));
+    }
+}
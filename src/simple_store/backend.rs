@@ -0,0 +1,315 @@
+use super::codec::{self, Codec, NewerSchema, SharedCodec};
+use super::{Durability, LoadFailure, Recovery, Store, persist};
+use anyhow::Context;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::error;
+
+/// Where a Store keeps its serialized data. The default is a file, written
+/// atomically; implement this to keep stores in sled, sqlite, S3 or memory
+/// without changing how they are read and written.
+///
+/// Only the bytes go through the backend. File-specific features (backups,
+/// `with_durability`, quarantining, delta journals, `read_only` and `StoreMap`)
+/// apply to file stores alone.
+pub trait StorageBackend: Any + Send + Sync {
+    /// The stored bytes, or `None` when nothing has been stored yet.
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error>;
+
+    /// Replaces the stored bytes. A failed persist must leave the previous
+    /// bytes in place.
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+/// The file storage every Store uses by default, as a backend for callers
+/// that want to wrap or swap it.
+pub struct FileBackend {
+    loc: PathBuf,
+    durability: Durability,
+}
+
+impl FileBackend {
+    pub fn new(loc: impl Into<PathBuf>) -> Self {
+        Self {
+            loc: loc.into(),
+            durability: Durability::NONE,
+        }
+    }
+
+    /// Flushes every write to disk before `persist` returns.
+    pub fn with_fsync(self) -> Self {
+        self.with_durability(Durability::FULL)
+    }
+
+    /// See `Store::with_durability`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match std::fs::read(&self.loc) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", self.loc.display())),
+        }
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        persist(&self.loc, bytes, self.durability)
+    }
+}
+
+/// Keeps the bytes in memory, for tests and short-lived stores that want the
+/// full Store API without touching disk. Clones share the same bytes, so a
+/// test can keep one to inspect or seed what the store sees.
+#[derive(Clone, Default)]
+pub struct MemoryBackend(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts out holding `bytes`, as if a previous store had written them.
+    pub fn with_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self(Arc::new(Mutex::new(Some(bytes.into()))))
+    }
+
+    /// The last bytes persisted, if any.
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        self.0.lock().clone()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        Ok(self.bytes())
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        *self.0.lock() = Some(bytes.to_vec());
+        Ok(())
+    }
+}
+
+impl<T> Store<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// A store that never touches disk, starting out as `data`. Writes,
+    /// refreshes and subscriptions behave as they do for a file store.
+    pub fn in_memory(data: T) -> Self {
+        let backend = MemoryBackend::with_bytes(&data);
+        let backend = Some(Arc::new(backend) as Arc<dyn StorageBackend>);
+        Store::with_access(data, PathBuf::new(), codec::encode_only(), false, backend)
+    }
+}
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> Store<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_or_get` against `backend` instead of a file. Data the backend
+    /// holds but that fails to deserialize is logged and replaced by
+    /// `getter`'s; see `StoreBuilder::on_load_failure` to fail instead.
+    pub fn with_backend<B, F>(backend: B, getter: F) -> Result<Store<T>, anyhow::Error>
+    where
+        B: StorageBackend,
+        F: FnOnce() -> Result<T, anyhow::Error>,
+    {
+        let codec = codec::raw();
+        let loaded = decode_loaded(&*codec, &Recovery::default(), backend.load()?)?;
+        let (data, decoded_in) = match loaded {
+            Some((data, elapsed)) => (data, Some(elapsed)),
+            None => {
+                let new_data = getter()?;
+                backend.persist(&codec.encode(&new_data)?)?;
+                (new_data, None)
+            }
+        };
+        Ok(Store::from_backend(
+            data,
+            decoded_in,
+            codec,
+            Arc::new(backend),
+        ))
+    }
+}
+
+impl<T> Store<T> {
+    /// `with_backend` through `codec`, with an async getter, awaited only
+    /// when the backend has nothing usable. Data that fails to deserialize
+    /// is handled per `recovery`.
+    pub(super) async fn with_backend_async(
+        backend: Arc<dyn StorageBackend>,
+        codec: SharedCodec<T>,
+        recovery: Recovery,
+        getter: impl Future<Output = Result<T, anyhow::Error>>,
+    ) -> Result<Store<T>, anyhow::Error> {
+        let loaded = decode_loaded(&*codec, &recovery, backend.load()?)?;
+        let (data, decoded_in) = match loaded {
+            Some((data, elapsed)) => (data, Some(elapsed)),
+            None => {
+                let new_data = getter.await?;
+                backend.persist(&codec.encode(&new_data)?)?;
+                (new_data, None)
+            }
+        };
+        Ok(Store::from_backend(data, decoded_in, codec, backend))
+    }
+
+    fn from_backend(
+        data: T,
+        decoded_in: Option<Duration>,
+        codec: SharedCodec<T>,
+        backend: Arc<dyn StorageBackend>,
+    ) -> Store<T> {
+        let store = Store::with_access(data, PathBuf::new(), codec, false, Some(backend));
+        store.record_deserialize(decoded_in);
+        store
+    }
+}
+
+/// Runs backend IO that blocks, handing a multi-threaded tokio runtime's
+/// other tasks off the worker first, as `Store` calls backends synchronously
+/// even from async code.
+#[cfg(any(feature = "object-store", feature = "redis"))]
+pub(super) fn blocking<R>(io: impl FnOnce() -> R) -> R {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    let multi_thread = Handle::try_current()
+        .is_ok_and(|current| current.runtime_flavor() == RuntimeFlavor::MultiThread);
+    if multi_thread {
+        tokio::task::block_in_place(io)
+    } else {
+        io()
+    }
+}
+
+/// Deserializes what a backend loaded through `codec`, with the time it
+/// took. Data that fails to deserialize is reported and, unless `recovery`
+/// says to fail, logged and treated as missing; there is nowhere to move it
+/// aside, so `Quarantine` overwrites it too. A `NewerSchema` always fails.
+fn decode_loaded<T>(
+    codec: &dyn Codec<T>,
+    recovery: &Recovery,
+    loaded: Option<Vec<u8>>,
+) -> Result<Option<(T, Duration)>, anyhow::Error> {
+    let Some(bytes) = loaded else {
+        return Ok(None);
+    };
+    let started = Instant::now();
+    let e = match codec.decode(bytes) {
+        Ok(data) => return Ok(Some((data, started.elapsed()))),
+        Err(e) => e,
+    };
+    if e.is::<NewerSchema>() {
+        return Err(e).context("Failed to load store backend data");
+    }
+    let e = recovery.report(Path::new(""), None, e);
+    if recovery.on_failure == LoadFailure::Fail {
+        return Err(e).context("Failed to load store backend data");
+    }
+    error!(
+        error = %format!("{:#}", e),
+        "Store backend data failed to deserialize, replacing it"
+    );
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Corruption;
+    use crate::simple_store::testing::{Count, Increment};
+
+    #[tokio::test]
+    async fn reads_and_writes_through_the_backend() {
+        let backend = MemoryBackend::with_bytes(*b"junk");
+        let store: Store<Count> = Store::with_backend(backend.clone(), || Ok(Count(1))).unwrap();
+        assert_eq!(*store.read(), Count(1));
+        assert_eq!(backend.bytes(), Some(vec![1]));
+
+        store.write(Count(2)).unwrap();
+        store.write_async(Count(3)).await.unwrap();
+        assert_eq!(backend.bytes(), Some(vec![3]));
+
+        let reopened: Store<Count> =
+            Store::with_backend(backend, || unreachable!("data is stored")).unwrap();
+        assert_eq!(*reopened.read(), Count(3));
+    }
+
+    #[tokio::test]
+    async fn in_memory_keeps_the_full_api() {
+        let store = Store::in_memory(Count(0)).with_fetcher(Increment);
+        let mut changes = store.subscribe();
+        assert!(store.refresh_now().await.unwrap());
+        changes.changed().await.unwrap();
+        assert_eq!(*store.read(), Count(1));
+        assert_eq!(store.write_if_version(1, Count(5)).unwrap(), 2);
+        assert!(store.write_if_version(1, Count(6)).is_err());
+        assert_eq!(*store.read(), Count(5));
+        let snapshot = store.read_owned();
+        store.update(|c| c.0 += 1).unwrap();
+        assert_eq!((snapshot.0, store.read().0), (5, 6));
+    }
+    /// Opens a store over junk bytes per `on_failure`, with what the hook
+    /// reported.
+    async fn open_junk(
+        on_failure: LoadFailure,
+    ) -> (
+        MemoryBackend,
+        Result<Store<Count>, anyhow::Error>,
+        Vec<Corruption>,
+    ) {
+        let backend = MemoryBackend::with_bytes(*b"junk");
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mvreported = reported.clone();
+        let store = Store::builder()
+            .backend(backend.clone())
+            .initial(|| Count(9))
+            .on_load_failure(on_failure)
+            .on_corruption(move |c| {
+                mvreported.lock().push(Corruption {
+                    path: c.path.clone(),
+                    quarantined: c.quarantined.clone(),
+                    error: anyhow::anyhow!("{:#}", c.error),
+                })
+            })
+            .build()
+            .await;
+        let reported = std::mem::take(&mut *reported.lock());
+        (backend, store, reported)
+    }
+
+    #[tokio::test]
+    async fn undecodable_backend_data_fails_the_open() {
+        let (backend, store, reported) = open_junk(LoadFailure::Fail).await;
+        assert!(store.is_err());
+        assert_eq!(backend.bytes(), Some(b"junk".to_vec()));
+        assert_eq!(reported.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn undecodable_backend_data_is_overwritten() {
+        let (backend, store, reported) = open_junk(LoadFailure::Overwrite).await;
+        assert_eq!(*store.unwrap().read(), Count(9));
+        assert_eq!(backend.bytes(), Some(vec![9]));
+        assert_eq!(reported.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn backends_have_nowhere_to_quarantine() {
+        let (backend, store, reported) = open_junk(LoadFailure::Quarantine).await;
+        assert_eq!(*store.unwrap().read(), Count(9));
+        assert_eq!(backend.bytes(), Some(vec![9]));
+        assert_eq!(reported[0].quarantined, None);
+        assert_eq!(reported[0].path, PathBuf::new());
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Backoff, DataSource, Fetcher, Recovery, Store, codec};
+use std::path::PathBuf;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::warn;
+
+impl<T> Store<T>
+where
+    T: Default + TryFrom<Vec<u8>, Error = anyhow::Error> + Send + Sync + 'static,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_with_fetcher` that doesn't hold up startup on the first fetch.
+    /// When there is no file to load, the store starts out as `T::default()`
+    /// and fetches in the background, retrying failures after `backoff`,
+    /// until the real data lands. Until then `is_ready` is false and nothing
+    /// is written to disk, so a restart never mistakes the placeholder for
+    /// data. Any write makes the store ready.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub async fn new_with_fetcher_in_background<F>(
+        loc: PathBuf,
+        fetcher: F,
+        backoff: Backoff,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        let codec = codec::raw();
+        let (loc, loaded) = Self::load_file(loc, codec.clone(), Recovery::default()).await?;
+        if let Some((data, elapsed)) = loaded {
+            let store = Store::from_parts(data, loc, codec);
+            store.record_deserialize(Some(elapsed));
+            return Ok(store);
+        }
+        let store = Store::from_parts(T::default(), loc, codec);
+        store.inner.ready.send_replace(false);
+        store.inner.set_source(DataSource::Placeholder);
+        let mvstore = store.clone();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            while !mvstore.is_ready() {
+                match fetcher.fetch(None).await {
+                    Ok(data) if !mvstore.is_ready() => match mvstore.write(data) {
+                        Ok(()) => {
+                            mvstore.inner.set_source(DataSource::Fetched);
+                            break;
+                        }
+                        Err(e) => warn!("Failed to store initial data: {:#}", e),
+                    },
+                    // Written by someone else while we fetched
+                    Ok(_) => break,
+                    Err(e) => warn!("Initial fetch failed, retrying: {:#}", e),
+                }
+                failures += 1;
+                sleep(backoff.delay(failures)).await;
+            }
+        });
+        Ok(store)
+    }
+}
+
+impl<T> Store<T> {
+    /// Whether the store holds real data rather than the placeholder of
+    /// `new_with_fetcher_in_background`. Always true for other stores.
+    pub fn is_ready(&self) -> bool {
+        *self.inner.ready.borrow()
+    }
+
+    /// Follows `is_ready`, for waiting on the initial fetch.
+    pub fn subscribe_ready(&self) -> watch::Receiver<bool> {
+        self.inner.ready.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempDir};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    /// Fails the first time, then returns 5.
+    struct SlowStart(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl Fetcher<Count> for SlowStart {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(anyhow!("not yet")),
+                _ => Ok(Count(5)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_a_placeholder_until_the_first_fetch() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("count");
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(10),
+        };
+        let calls = Arc::new(AtomicU32::new(0));
+        let store =
+            Store::new_with_fetcher_in_background(path.clone(), SlowStart(calls.clone()), backoff)
+                .await
+                .unwrap();
+        let mut ready = store.subscribe_ready();
+        assert!(!store.is_ready());
+        assert_eq!(*store.read(), Count(0));
+        assert!(!path.exists());
+
+        ready.wait_for(|r| *r).await.unwrap();
+        assert_eq!(*store.read(), Count(5));
+        assert_eq!(std::fs::read(&path).unwrap(), vec![5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let reopened = Store::new_with_fetcher_in_background(path, SlowStart(calls), backoff)
+            .await
+            .unwrap();
+        assert!(reopened.is_ready());
+    }
+}
@@ -0,0 +1,211 @@
+use super::{Durability, Store, TMP_COUNTER, persist, persist_with};
+use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tracing::warn;
+
+/// `<file>.bak.<n>`, where 1 is the most recent backup.
+fn backup_path(loc: &Path, n: usize) -> PathBuf {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    loc.with_file_name(format!("{}.bak.{}", base, n))
+}
+
+/// Keeps the current file at `loc`, if any, as a hard link (or, failing
+/// that, a copy) beside it, to become `.bak.1` once its replacement is in
+/// place.
+fn keep_current(loc: &Path) -> Result<Option<PathBuf>, anyhow::Error> {
+    if !loc.exists() {
+        return Ok(None);
+    }
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    let kept = loc.with_file_name(format!(
+        ".{}.bak-new-{}-{}",
+        base,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    if std::fs::hard_link(loc, &kept).is_err() {
+        std::fs::copy(loc, &kept)?;
+    }
+    Ok(Some(kept))
+}
+
+/// Shifts `.bak.1..keep` up by one, dropping the oldest, and moves `kept`
+/// in as `.bak.1`.
+fn rotate(loc: &Path, keep: usize, kept: &Path) -> Result<(), anyhow::Error> {
+    let _ = std::fs::remove_file(backup_path(loc, keep));
+    for n in (1..keep).rev() {
+        let from = backup_path(loc, n);
+        if from.exists() {
+            std::fs::rename(&from, backup_path(loc, n + 1))?;
+        }
+    }
+    std::fs::rename(kept, backup_path(loc, 1))?;
+    Ok(())
+}
+
+/// Runs `persist`, rotating backups when `backups` is non-zero only once it
+/// has succeeded, so a failed write leaves them as they were. The new
+/// contents are already in place by then, so a failed rotation is logged
+/// rather than failing the write.
+fn persist_rotating(
+    loc: &Path,
+    backups: usize,
+    persist: impl FnOnce() -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    if backups == 0 {
+        return persist();
+    }
+    let kept = keep_current(loc)
+        .with_context(|| format!("Failed to keep a backup of {}", loc.display()))?;
+    let persisted = persist();
+    let Some(kept) = kept else {
+        return persisted;
+    };
+    if persisted.is_err() {
+        let _ = std::fs::remove_file(&kept);
+        return persisted;
+    }
+    if let Err(e) = rotate(loc, backups, &kept) {
+        let _ = std::fs::remove_file(&kept);
+        warn!("Failed to rotate backups of {}: {:#}", loc.display(), e);
+    }
+    Ok(())
+}
+
+/// Persists atomically, then rotates backups when `backups` is non-zero.
+pub(super) fn save_file(
+    loc: &Path,
+    bytes: &[u8],
+    durability: Durability,
+    backups: usize,
+) -> Result<(), anyhow::Error> {
+    persist_rotating(loc, backups, || persist(loc, bytes, durability))
+}
+
+/// `save_file` with the contents produced by `write`; see `persist_with`.
+pub(super) fn save_file_with(
+    loc: &Path,
+    durability: Durability,
+    backups: usize,
+    write: impl FnOnce(&mut dyn std::io::Write) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    persist_rotating(loc, backups, || persist_with(loc, durability, write))
+}
+
+impl<T> Store<T> {
+    /// Keeps the last `keep` versions of the file as `<file>.bak.1` (newest)
+    /// through `<file>.bak.<keep>`, so a bad fetch can be rolled back with
+    /// `restore_backup`.
+    pub fn with_backups(self, keep: usize) -> Self {
+        self.inner.backups.store(keep, Ordering::Relaxed);
+        self
+    }
+
+    pub(super) fn backups(&self) -> usize {
+        self.inner.backups.load(Ordering::Relaxed)
+    }
+
+    /// Backups currently on disk, newest first.
+    pub fn backup_paths(&self) -> Vec<PathBuf> {
+        (1..=self.backups())
+            .map(|n| backup_path(&self.inner.loc, n))
+            .take_while(|p| p.exists())
+            .collect()
+    }
+}
+
+impl<T> Store<T> {
+    /// Persists the data as of now to `path`, outside the store's own file
+    /// and backups, returning the version written. Later writes don't touch
+    /// it; open it like any store file to inspect it.
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> Result<u64, anyhow::Error> {
+        let path = path.as_ref();
+        let (data, version) = {
+            let data = self.inner.data.read();
+            (data.clone(), self.version())
+        };
+        persist(path, &self.encode(&data)?, self.durability())
+            .with_context(|| format!("Failed to snapshot store to {}", path.display()))?;
+        Ok(version)
+    }
+}
+
+impl<T> Store<T> {
+    /// Writes the contents of backup `n` (1 is the newest) back as the
+    /// current data. This is an ordinary write, so the data being replaced
+    /// becomes backup 1 and older backups shift up.
+    pub fn restore_backup(&self, n: usize) -> Result<(), anyhow::Error> {
+        if n == 0 || n > self.backups() {
+            bail!("Store keeps {} backups, no backup {}", self.backups(), n);
+        }
+        let path = backup_path(&self.inner.loc, n);
+        let bytes =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        self.write(self.decode(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempStore, Text};
+
+    #[test]
+    fn snapshots_are_detached() {
+        let tmp = TempStore::from_value(Text("v1".into())).unwrap();
+        let mut copy = tmp.snapshot();
+        copy.0.push('!');
+        assert_eq!(*tmp.read(), Text("v1".into()));
+
+        let path = tmp.dir().join("snapshot");
+        let version = tmp.snapshot_to(&path).unwrap();
+        tmp.write(Text("v2".into())).unwrap();
+        assert_eq!(tmp.version(), version + 1);
+        assert_eq!(std::fs::read(&path).unwrap(), b"v1");
+    }
+
+    #[test]
+    fn rotates_and_restores_backups() {
+        let tmp = TempStore::from_value(Text("v0".into())).unwrap();
+        let store = tmp.store().clone().with_backups(2);
+        for v in ["v1", "v2", "v3"] {
+            store.write(Text(v.into())).unwrap();
+        }
+        let backups: Vec<String> = store
+            .backup_paths()
+            .iter()
+            .map(|p| std::fs::read_to_string(p).unwrap())
+            .collect();
+        assert_eq!(backups, vec!["v2", "v1"]);
+
+        store.restore_backup(2).unwrap();
+        assert_eq!(*store.read(), Text("v1".into()));
+        assert_eq!(tmp.bytes().unwrap(), b"v1");
+        assert_eq!(std::fs::read(&store.backup_paths()[0]).unwrap(), b"v3");
+        assert!(store.restore_backup(3).is_err());
+    }
+
+    #[test]
+    fn failed_writes_leave_backups_alone() {
+        let tmp = TempStore::from_value(Text("v0".into())).unwrap();
+        let store = tmp.store().clone().with_backups(2);
+        store.write(Text("v1".into())).unwrap();
+        let failed = save_file_with(tmp.path(), Durability::NONE, 2, |_| bail!("disk full"));
+        assert!(failed.is_err());
+        assert_eq!(tmp.bytes().unwrap(), b"v1");
+        assert_eq!(store.backup_paths().len(), 1);
+        assert_eq!(std::fs::read(&store.backup_paths()[0]).unwrap(), b"v0");
+        let strays = std::fs::read_dir(tmp.dir())
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with('.')
+            })
+            .count();
+        assert_eq!(strays, 0);
+    }
+}
@@ -0,0 +1,174 @@
+use super::{Durability, StorageBackend, persist};
+use anyhow::{Context, bail};
+use parking_lot::Mutex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Blue,
+    Green,
+}
+
+impl Side {
+    fn name(self) -> &'static str {
+        match self {
+            Side::Blue => "blue",
+            Side::Green => "green",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Side::Blue => Side::Green,
+            Side::Green => Side::Blue,
+        }
+    }
+}
+
+/// Keeps a store in two copies, `<file>.blue` and `<file>.green`, with a
+/// small `<file>.active` pointer naming the complete one. Each write goes
+/// in place to the inactive copy and only then flips the pointer, itself
+/// replaced atomically, so the active copy is never partly written however
+/// long a write takes, and the large file is never renamed. Suits stores
+/// too large to copy or rename cheaply, or kept on filesystems that can't
+/// rename atomically.
+///
+/// Use `with_fsync` to also survive power loss: the new copy is then on
+/// disk before the pointer moves to it.
+///
+/// ```ignore
+/// let store = Store::builder().backend(BlueGreenBackend::new(dir.join("index"))).build().await?;
+/// ```
+pub struct BlueGreenBackend {
+    loc: PathBuf,
+    durability: Durability,
+    // The active side, once known; also serializes writes
+    active: Mutex<Option<Side>>,
+}
+
+impl BlueGreenBackend {
+    pub fn new(loc: impl Into<PathBuf>) -> Self {
+        Self {
+            loc: loc.into(),
+            durability: Durability::NONE,
+            active: Mutex::new(None),
+        }
+    }
+
+    pub fn with_fsync(self) -> Self {
+        self.with_durability(Durability::FULL)
+    }
+
+    /// See `Store::with_durability`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    fn with_suffix(&self, suffix: &str) -> PathBuf {
+        let base = self.loc.file_name().unwrap_or_default().to_string_lossy();
+        self.loc.with_file_name(format!("{}.{}", base, suffix))
+    }
+
+    fn pointer_path(&self) -> PathBuf {
+        self.with_suffix("active")
+    }
+
+    fn side_path(&self, side: Side) -> PathBuf {
+        self.with_suffix(side.name())
+    }
+
+    /// The side the pointer names, or `None` before the first write.
+    fn read_pointer(&self) -> Result<Option<Side>, anyhow::Error> {
+        let path = self.pointer_path();
+        let pointer = match std::fs::read_to_string(&path) {
+            Ok(pointer) => pointer,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        match pointer.trim() {
+            "blue" => Ok(Some(Side::Blue)),
+            "green" => Ok(Some(Side::Green)),
+            other => bail!("{} names no copy: {:?}", path.display(), other),
+        }
+    }
+
+    /// The copy currently active, if any has been written.
+    pub fn active_path(&self) -> Result<Option<PathBuf>, anyhow::Error> {
+        Ok(self.read_pointer()?.map(|side| self.side_path(side)))
+    }
+}
+
+/// Overwrites `path` in place with `bytes`.
+fn write_copy(path: &Path, bytes: &[u8], durability: Durability) -> Result<(), anyhow::Error> {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(bytes)?;
+    durability.sync_file(&file)?;
+    Ok(())
+}
+
+impl StorageBackend for BlueGreenBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let mut active = self.active.lock();
+        let Some(side) = self.read_pointer()? else {
+            return Ok(None);
+        };
+        let path = self.side_path(side);
+        let bytes =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        *active = Some(side);
+        Ok(Some(bytes))
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        let mut active = self.active.lock();
+        let current = match *active {
+            Some(side) => Some(side),
+            None => self.read_pointer()?,
+        };
+        let next = current.map_or(Side::Blue, Side::other);
+        let path = self.side_path(next);
+        write_copy(&path, bytes, self.durability)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        persist(
+            &self.pointer_path(),
+            next.name().as_bytes(),
+            self.durability,
+        )?;
+        *active = Some(next);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Store;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[test]
+    fn alternates_copies_behind_the_pointer() {
+        let dir = TempDir::new().unwrap();
+        let loc = dir.path().join("index");
+        let backend = BlueGreenBackend::new(&loc).with_fsync();
+        assert_eq!(backend.active_path().unwrap(), None);
+        let store = Store::with_backend(backend, || Ok(Text("v1".into()))).unwrap();
+        store.write(Text("v2".into())).unwrap();
+
+        let (blue, green) = (
+            dir.path().join("index.blue"),
+            dir.path().join("index.green"),
+        );
+        let backend = BlueGreenBackend::new(&loc);
+        assert_eq!(backend.active_path().unwrap(), Some(green));
+        assert_eq!(std::fs::read(&blue).unwrap(), b"v1");
+
+        // A write cut short leaves the inactive copy torn, not the active one
+        std::fs::write(&blue, b"v3 but tor").unwrap();
+        let reopened = Store::with_backend(backend, || unreachable!("data is stored")).unwrap();
+        assert_eq!(*reopened.read(), Text("v2".into()));
+        reopened.write(Text("v3".into())).unwrap();
+        assert_eq!(std::fs::read(&blue).unwrap(), b"v3");
+    }
+}
@@ -0,0 +1,325 @@
+use super::codec::{self, SharedCodec};
+use super::refresh::SharedFetcher;
+use super::{
+    Codec, Corruption, Durability, ErrorPolicy, Fetcher, FileBackend, History, LoadFailure,
+    Recovery, RefreshSchedule, StorageBackend, Store, TimeoutFetcher,
+};
+use crate::rate_limit::RateLimit;
+use crate::shutdown::ShutdownCoordinator;
+use anyhow::{anyhow, bail};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Getter<T> = Box<dyn FnOnce() -> Result<T, anyhow::Error> + Send>;
+
+/// Every way to open a `Store`, in one chain:
+///
+/// ```ignore
+/// let store = Store::builder()
+///     .path(dir.join("users"))
+///     .fetcher(UsersFetcher::new(client))
+///     .refresh(RefreshSchedule::every(Duration::from_secs(60)).with_jitter(0.1))
+///     .error_policy(ErrorPolicy::default().stop_after(10))
+///     .shutdown(&mut shutdown)
+///     .build()
+///     .await?;
+/// ```
+///
+/// `Store::builder` converts the data through its own `TryFrom`/`From`
+/// impls; `Store::builder_with_codec` through a `Codec` instead.
+pub struct StoreBuilder<'a, T> {
+    codec: SharedCodec<T>,
+    loc: Option<PathBuf>,
+    backend: Option<Arc<dyn StorageBackend>>,
+    fetcher: Option<SharedFetcher<T>>,
+    getter: Option<Getter<T>>,
+    schedule: Option<RefreshSchedule>,
+    timeout: Option<Duration>,
+    refresh_limit: Option<RateLimit>,
+    max_age: Option<Duration>,
+    soft_ttl: Option<Duration>,
+    policy: ErrorPolicy,
+    shutdown: Option<&'a mut ShutdownCoordinator>,
+    durability: Durability,
+    recovery: Recovery,
+    backups: usize,
+    history: Option<History>,
+    replicas: Vec<(PathBuf, Arc<dyn StorageBackend>)>,
+}
+
+impl<T> Store<T> {
+    pub fn builder<'a>() -> StoreBuilder<'a, T>
+    where
+        T: TryFrom<Vec<u8>, Error = anyhow::Error>,
+        for<'b> Vec<u8>: From<&'b T>,
+    {
+        Self::builder_from(codec::raw())
+    }
+
+    /// `builder` for a store that persists its data through `codec`, as
+    /// `with_codec` does.
+    pub fn builder_with_codec<'a>(
+        codec: impl Codec<T> + Send + Sync + 'static,
+    ) -> StoreBuilder<'a, T> {
+        Self::builder_from(Arc::new(codec))
+    }
+
+    fn builder_from<'a>(codec: SharedCodec<T>) -> StoreBuilder<'a, T> {
+        StoreBuilder {
+            codec,
+            loc: None,
+            backend: None,
+            fetcher: None,
+            getter: None,
+            schedule: None,
+            timeout: None,
+            refresh_limit: None,
+            max_age: None,
+            soft_ttl: None,
+            policy: ErrorPolicy::default(),
+            shutdown: None,
+            durability: Durability::NONE,
+            recovery: Recovery::default(),
+            backups: 0,
+            history: None,
+            replicas: Vec::new(),
+        }
+    }
+}
+
+impl<'a, T: Send + Sync + 'static> StoreBuilder<'a, T> {
+    /// Keeps the store in the file at `loc`.
+    pub fn path(mut self, loc: impl Into<PathBuf>) -> Self {
+        self.loc = Some(loc.into());
+        self
+    }
+
+    /// Keeps the store in `backend` instead of a file.
+    pub fn backend(mut self, backend: impl StorageBackend) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Fetches the initial data when there is none stored, and backs
+    /// `refresh_now` and the `refresh` schedule.
+    pub fn fetcher(mut self, fetcher: impl Fetcher<T> + Send + Sync + 'static) -> Self {
+        self.fetcher = Some(Arc::new(fetcher));
+        self
+    }
+
+    /// The initial data when there is none stored. Takes precedence over
+    /// the fetcher for that.
+    pub fn initial(mut self, getter: impl FnOnce() -> T + Send + 'static) -> Self {
+        self.getter = Some(Box::new(move || Ok(getter())));
+        self
+    }
+
+    /// `initial` with `T::default()`.
+    pub fn or_default(self) -> Self
+    where
+        T: Default,
+    {
+        self.initial(T::default)
+    }
+
+    /// Runs the fetcher on `schedule`, usually just a `Duration`.
+    pub fn refresh(mut self, schedule: impl Into<RefreshSchedule>) -> Self {
+        self.schedule = Some(schedule.into());
+        self
+    }
+
+    /// Fails any fetch, initial or scheduled, that takes longer than
+    /// `limit`. See `TimeoutFetcher`.
+    pub fn fetch_timeout(mut self, limit: Duration) -> Self {
+        self.timeout = Some(limit);
+        self
+    }
+
+    /// See `Store::with_refresh_limit`.
+    pub fn refresh_limit(mut self, limit: RateLimit) -> Self {
+        self.refresh_limit = Some(limit);
+        self
+    }
+
+    /// See `Store::with_max_age`.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// See `Store::with_soft_ttl`.
+    pub fn soft_ttl(mut self, soft_ttl: Duration) -> Self {
+        self.soft_ttl = Some(soft_ttl);
+        self
+    }
+
+    /// How the `refresh` schedule handles failed fetches.
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Registers the `refresh` schedule with `shutdown`, so it stops when
+    /// shutdown begins.
+    pub fn shutdown(mut self, shutdown: &'a mut ShutdownCoordinator) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// See `Store::with_fsync`.
+    pub fn fsync(self) -> Self {
+        self.durability(Durability::FULL)
+    }
+
+    /// See `Store::with_durability`.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// What to do when the file or backend data fails to deserialize.
+    /// Quarantines it and fetches fresh data by default.
+    pub fn on_load_failure(mut self, on_failure: LoadFailure) -> Self {
+        self.recovery.on_failure = on_failure;
+        self
+    }
+
+    /// Calls `hook` when the file or backend data fails to deserialize
+    /// (including a checksum mismatch), whatever `on_load_failure` then
+    /// does with it.
+    pub fn on_corruption(mut self, hook: impl Fn(&Corruption) + Send + Sync + 'static) -> Self {
+        self.recovery.on_corruption = Some(Arc::new(hook));
+        self
+    }
+
+    /// See `Store::with_backups`.
+    pub fn backups(mut self, keep: usize) -> Self {
+        self.backups = keep;
+        self
+    }
+
+    /// See `Store::add_replica`. May be given more than once.
+    pub fn replica(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.replicas
+            .push((path.clone(), Arc::new(FileBackend::new(path))));
+        self
+    }
+
+    /// See `Store::add_replica_backend`. May be given more than once.
+    pub fn replica_backend(mut self, backend: impl StorageBackend) -> Self {
+        self.replicas.push((PathBuf::new(), Arc::new(backend)));
+        self
+    }
+
+    /// See `Store::with_history`.
+    pub fn history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Opens the store, loading it or fetching its initial data without
+    /// blocking the runtime, and starts its refresh schedule.
+    pub async fn build(self) -> Result<Store<T>, anyhow::Error> {
+        if self.schedule.is_some() && self.fetcher.is_none() {
+            bail!("A refresh schedule needs a fetcher");
+        }
+        let fetcher: Option<SharedFetcher<T>> = match (self.fetcher, self.timeout) {
+            (Some(fetcher), Some(limit)) => Some(Arc::new(TimeoutFetcher::new(fetcher, limit))),
+            (fetcher, _) => fetcher,
+        };
+        let initial_fetcher = fetcher.clone();
+        let getter = self.getter;
+        let initial = async move {
+            match (getter, initial_fetcher) {
+                (Some(getter), _) => getter(),
+                (None, Some(fetcher)) => fetcher.fetch(None).await,
+                (None, None) => Err(anyhow!("Store is empty and has no fetcher or initial data")),
+            }
+        };
+        let store = match (self.loc, self.backend) {
+            (Some(loc), None) => Store::load_async(loc, self.codec, self.recovery, initial).await?,
+            (None, Some(backend)) => {
+                Store::with_backend_async(backend, self.codec, self.recovery, initial).await?
+            }
+            (Some(_), Some(_)) => bail!("A store takes a path or a backend, not both"),
+            (None, None) => bail!("A store needs a path or a backend"),
+        };
+        let mut store = store
+            .with_backups(self.backups)
+            .with_durability(self.durability);
+        if let Some(limit) = self.refresh_limit {
+            store = store.with_refresh_limit(limit);
+        }
+        if let Some(history) = self.history {
+            store = store.with_history(history);
+        }
+        if let Some(max_age) = self.max_age {
+            store = store.with_max_age(max_age);
+        }
+        if let Some(soft_ttl) = self.soft_ttl {
+            store = store.with_soft_ttl(soft_ttl);
+        }
+        for (path, backend) in self.replicas {
+            store.spawn_replica(path, backend);
+        }
+        let Some(fetcher) = fetcher else {
+            return Ok(store);
+        };
+        let store = store.with_fetcher(fetcher.clone());
+        match (self.schedule, self.shutdown) {
+            (Some(schedule), Some(shutdown)) => {
+                store.scheduled_updates_with_shutdown(fetcher, schedule, self.policy, shutdown);
+            }
+            (Some(schedule), None) => {
+                store.scheduled_updates_with_policy(fetcher, schedule, self.policy);
+            }
+            (None, _) => {}
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::MemoryBackend;
+    use crate::simple_store::testing::{Count, Increment, TempDir, TimeHarness};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn builds_scheduled_stores() {
+        let dir = TempDir::new().unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let store = Store::builder()
+            .path(dir.path().join("count"))
+            .fetcher(Increment)
+            .refresh(Duration::from_secs(10))
+            .shutdown(&mut shutdown)
+            .backups(1)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(*store.read(), Count(1));
+
+        let time = TimeHarness::pause();
+        time.advance_until_refresh(&store).await.unwrap();
+        assert_eq!(*store.read(), Count(2));
+        assert_eq!(store.backup_paths().len(), 1);
+        assert!(store.refresh_now().await.unwrap());
+
+        let missing = Store::<Count>::builder()
+            .backend(MemoryBackend::new())
+            .build()
+            .await;
+        assert!(missing.is_err());
+        let defaulted = Store::<Count>::builder()
+            .backend(MemoryBackend::new())
+            .or_default()
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(*defaulted.read(), Count(0));
+    }
+}
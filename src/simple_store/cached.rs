@@ -0,0 +1,150 @@
+use super::StoreMap;
+use async_trait::async_trait;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Fetches the value for one key of a `CachedStore`.
+#[async_trait]
+pub trait KeyedFetcher<K, V> {
+    async fn fetch(&self, key: &K) -> Result<V, anyhow::Error>;
+}
+
+#[async_trait]
+impl<K, V, F> KeyedFetcher<K, V> for Arc<F>
+where
+    K: Sync,
+    F: KeyedFetcher<K, V> + Send + Sync + ?Sized,
+{
+    async fn fetch(&self, key: &K) -> Result<V, anyhow::Error> {
+        (**self).fetch(key).await
+    }
+}
+
+/// A `StoreMap` filled on demand: `get_or_fetch` serves a key from its
+/// store while it is younger than `ttl`, and otherwise fetches it, persists
+/// it and serves that. Entries survive restarts and keep their age, judged
+/// from their file's modification time. Cheap to clone.
+///
+/// Concurrent misses on the same key each fetch; the last write wins.
+///
+/// ```ignore
+/// let profiles = CachedStore::open(dir, ProfileFetcher::new(client), Duration::from_secs(600))?;
+/// let profile = profiles.get_or_fetch(user_id).await?;
+/// ```
+pub struct CachedStore<K, V> {
+    map: StoreMap<K, V>,
+    fetcher: Arc<dyn KeyedFetcher<K, V> + Send + Sync>,
+    ttl: Duration,
+}
+
+impl<K, V> Clone for CachedStore<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            fetcher: self.fetcher.clone(),
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl<K, V> CachedStore<K, V>
+where
+    K: Display + FromStr + Hash + Eq + Clone,
+    V: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a V>,
+{
+    /// Opens the entries already cached in `dir`, as `StoreMap::open` does.
+    pub fn open<F>(
+        dir: impl Into<PathBuf>,
+        fetcher: F,
+        ttl: Duration,
+    ) -> Result<Self, anyhow::Error>
+    where
+        F: KeyedFetcher<K, V> + Send + Sync + 'static,
+    {
+        Ok(Self {
+            map: StoreMap::open(dir)?,
+            fetcher: Arc::new(fetcher),
+            ttl,
+        })
+    }
+
+    /// The value for `key`, fetched only if it isn't cached or is older than
+    /// the TTL. A failed fetch returns the error and leaves any stale entry
+    /// in place.
+    pub async fn get_or_fetch(&self, key: K) -> Result<Arc<V>, anyhow::Error> {
+        if let Some(store) = self.map.get(&key)
+            && store.age() <= self.ttl
+        {
+            return Ok(store.read_owned());
+        }
+        let value = self.fetcher.fetch(&key).await?;
+        let store = self.map.insert(key, value)?;
+        // An unchanged value isn't rewritten, but is fresh all the same
+        store.inner.mark_fresh();
+        Ok(store.read_owned())
+    }
+
+    /// The cached value for `key`, however old, without fetching.
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.map.get(key).map(|store| store.read_owned())
+    }
+
+    /// Drops `key` from the cache, so the next `get_or_fetch` fetches it.
+    pub fn invalidate(&self, key: &K) -> Result<bool, anyhow::Error> {
+        self.map.remove(key)
+    }
+
+    /// The stores behind the cache, one per key.
+    pub fn map(&self) -> &StoreMap<K, V> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Greets each key, counting its calls.
+    #[derive(Default)]
+    struct Greeter(AtomicU32);
+
+    #[async_trait]
+    impl KeyedFetcher<String, Text> for Greeter {
+        async fn fetch(&self, key: &String) -> Result<Text, anyhow::Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Text(format!("hello {}", key)))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetches_missing_and_expired_keys() {
+        let dir = TempDir::new().unwrap();
+        let greeter = Arc::new(Greeter::default());
+        let ttl = Duration::from_secs(60);
+        let cache = CachedStore::open(dir.path(), greeter.clone(), ttl).unwrap();
+        assert_eq!(cache.get(&"a".into()), None);
+
+        let a = cache.get_or_fetch("a".into()).await.unwrap();
+        assert_eq!(*a, Text("hello a".into()));
+        cache.get_or_fetch("a".into()).await.unwrap();
+        cache.get_or_fetch("b".into()).await.unwrap();
+        assert_eq!(greeter.0.load(Ordering::SeqCst), 2);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        cache.get_or_fetch("a".into()).await.unwrap();
+        cache.get_or_fetch("a".into()).await.unwrap();
+        assert_eq!(greeter.0.load(Ordering::SeqCst), 3);
+
+        assert!(cache.invalidate(&"b".into()).unwrap());
+        let reopened = CachedStore::open(dir.path(), greeter.clone(), ttl).unwrap();
+        assert_eq!(reopened.get(&"a".into()), Some(a));
+        assert_eq!(reopened.get(&"b".into()), None);
+    }
+}
@@ -0,0 +1,1199 @@
+use super::{Durability, Recovery, Store, persist};
+use anyhow::{Context, bail};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Converts a value to and from the bytes a `Store` persists, so the
+/// conversion can live in one reusable type (e.g. a serde format) instead of
+/// a pair of `TryFrom`/`From` impls per stored type.
+///
+/// A store opened with `Store::with_codec` or `Store::builder_with_codec`
+/// keeps the codec it was given, so codecs can carry configuration such as
+/// a key. `JsonCodec`, `BincodeCodec` and `TomlCodec` cover the common serde
+/// formats behind the `json`, `bincode` and `toml` features.
+///
+/// `#[derive(StoreCodec)]` (behind the `derive` feature) implements a
+/// type's own conversions through a `Default` codec, for stores of the bare
+/// type.
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error>;
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error>;
+}
+
+/// The codec a store holds.
+pub(super) type SharedCodec<T> = Arc<dyn Codec<T> + Send + Sync>;
+
+/// `Raw`, for the stores whose type converts itself.
+pub(super) fn raw<T>() -> SharedCodec<T>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    Arc::new(Raw)
+}
+
+/// Encodes through `From` and refuses to decode, for stores that never
+/// load, such as `in_memory` ones.
+struct EncodeOnly;
+
+impl<T> Codec<T> for EncodeOnly
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(value.into())
+    }
+
+    fn decode(&self, _: Vec<u8>) -> Result<T, anyhow::Error> {
+        bail!("Store data has no TryFrom<Vec<u8>> to decode it")
+    }
+}
+
+pub(super) fn encode_only<T>() -> SharedCodec<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    Arc::new(EncodeOnly)
+}
+
+/// Decodes through `TryFrom` and refuses to encode, for stores that never
+/// write, such as read-only ones.
+struct DecodeOnly;
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> Codec<T> for DecodeOnly {
+    fn encode(&self, _: &T) -> Result<Vec<u8>, anyhow::Error> {
+        bail!("Store data has no From<&T> for Vec<u8> to encode it")
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        T::try_from(bytes)
+    }
+}
+
+pub(super) fn decode_only<T: TryFrom<Vec<u8>, Error = anyhow::Error>>() -> SharedCodec<T> {
+    Arc::new(DecodeOnly)
+}
+
+impl<T> Store<T> {
+    /// The data's bytes, through the store's codec.
+    pub(super) fn encode(&self, data: &T) -> Result<Vec<u8>, anyhow::Error> {
+        self.inner.codec.encode(data)
+    }
+
+    /// Data from `bytes`, through the store's codec.
+    pub(super) fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        self.inner.codec.decode(bytes)
+    }
+}
+
+/// The `TryFrom`/`From` conversions of a type that implements them, or the
+/// fallible ones of an `Encoded`, so such types can be wrapped in the layers
+/// below.
+#[derive(Default)]
+pub struct Raw;
+
+impl<T> Codec<T> for Raw
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(value.into())
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        T::try_from(bytes)
+    }
+}
+
+/// A `T` that converts itself through the codec type `C`, built with its
+/// `Default`, for stores opened with `Raw`. Derefs to the value. A failure
+/// to encode is returned from the write. `Store::with_codec(loc, C)` stores
+/// the bare `T` instead.
+///
+/// # Example
+/// ```ignore
+/// let store: Store<Encoded<Vec<String>, LinesCodec>> = Store::with_codec(loc, Raw)?;
+/// let lines: &Vec<String> = &store.read();
+/// store.write(Encoded::new(vec!["a".into()]))?;
+/// ```
+pub struct Encoded<T, C> {
+    value: T,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<T, C> Encoded<T, C> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _codec: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Default, C> Default for Encoded<T, C> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T, C> Deref for Encoded<T, C> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, C> DerefMut for Encoded<T, C> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T, C: Codec<T> + Default> TryFrom<Vec<u8>> for Encoded<T, C> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        C::default().decode(value).map(Self::new)
+    }
+}
+
+impl<'a, T, C: Codec<T> + Default> TryFrom<&'a Encoded<T, C>> for Vec<u8> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &'a Encoded<T, C>) -> Result<Self, Self::Error> {
+        C::default().encode(&value.value)
+    }
+}
+
+impl<T, C: Codec<T> + Default> Codec<Encoded<T, C>> for Raw {
+    fn encode(&self, value: &Encoded<T, C>) -> Result<Vec<u8>, anyhow::Error> {
+        value.try_into()
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<Encoded<T, C>, anyhow::Error> {
+        bytes.try_into()
+    }
+}
+
+/// A compression format for `Compressed`. `Gzip` and `Zstd` are behind
+/// the `gzip` and `zstd` features.
+pub trait Compression {
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+/// Compresses whatever the codec `C` produces with `Z`, so the file on disk
+/// is compressed while the value in memory is untouched.
+///
+/// # Example
+/// ```ignore
+/// let store = Store::with_codec(loc, Compressed::new(JsonCodec::default(), Gzip::level(9)))?;
+/// ```
+#[derive(Default)]
+pub struct Compressed<C, Z> {
+    codec: C,
+    compression: Z,
+}
+
+impl<C, Z> Compressed<C, Z> {
+    pub fn new(codec: C, compression: Z) -> Self {
+        Self { codec, compression }
+    }
+}
+
+impl<T, C: Codec<T>, Z: Compression> Codec<T> for Compressed<C, Z> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        self.compression.compress(&self.codec.encode(value)?)
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        let bytes = self
+            .compression
+            .decompress(&bytes)
+            .context("Failed to decompress store")?;
+        self.codec.decode(bytes)
+    }
+}
+
+/// Authenticated encryption for `Encrypted`. A cipher holds its key, read
+/// once when it is built, typically from a `KeyProvider`. `AesGcm` is
+/// behind the `aes-gcm` feature.
+pub trait Cipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+    /// Must fail, rather than return garbage, for tampered ciphertext or the
+    /// wrong key.
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+/// Encrypts whatever the codec `C` produces with `E`, so store files are
+/// encrypted at rest and decrypted transparently on load. Compose with
+/// `Compressed` inside, since ciphertext doesn't compress.
+///
+/// # Example
+/// ```ignore
+/// let cipher = AesGcm::new(EnvKey::new("STORE_KEY"))?;
+/// let codec = Encrypted::new(Compressed::new(JsonCodec::default(), Gzip::default()), cipher);
+/// let store = Store::with_codec(loc, codec)?;
+/// ```
+#[derive(Default)]
+pub struct Encrypted<C, E> {
+    codec: C,
+    cipher: E,
+}
+
+impl<C, E> Encrypted<C, E> {
+    pub fn new(codec: C, cipher: E) -> Self {
+        Self { codec, cipher }
+    }
+}
+
+impl<T, C: Codec<T>, E: Cipher> Codec<T> for Encrypted<C, E> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        self.cipher.encrypt(&self.codec.encode(value)?)
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        let bytes = self
+            .cipher
+            .decrypt(&bytes)
+            .context("Failed to decrypt store")?;
+        self.codec.decode(bytes)
+    }
+}
+
+/// Where a `Cipher` gets its key. Closures returning the key work too, for
+/// keys from a secrets manager.
+pub trait KeyProvider {
+    fn key(&self) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+impl<F: Fn() -> Result<Vec<u8>, anyhow::Error>> KeyProvider for F {
+    fn key(&self) -> Result<Vec<u8>, anyhow::Error> {
+        self()
+    }
+}
+
+/// A hex-encoded key in an environment variable.
+#[derive(Debug, Clone)]
+pub struct EnvKey {
+    var: String,
+}
+
+impl EnvKey {
+    pub fn new(var: impl Into<String>) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+impl KeyProvider for EnvKey {
+    fn key(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let hex = std::env::var(&self.var)
+            .with_context(|| format!("Key variable {} isn't set", self.var))?;
+        from_hex(hex.trim()).with_context(|| format!("Key in {} isn't hex", self.var))
+    }
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, anyhow::Error> {
+    if !hex.len().is_multiple_of(2) {
+        bail!("odd number of digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            Ok(u8::from_str_radix(
+                hex.get(i..i + 2).unwrap_or_default(),
+                16,
+            )?)
+        })
+        .collect()
+}
+
+/// Appends a CRC-32 of what the codec `C` produces and verifies it on
+/// load, so a torn or bit-rotted file is caught even when it would still
+/// deserialize. A mismatch is treated like any other corrupt file: it is
+/// quarantined, reported to the store's `on_corruption` hook, and the store
+/// falls back to its getter, fetcher or default.
+#[derive(Default)]
+pub struct Checksummed<C> {
+    codec: C,
+}
+
+impl<C> Checksummed<C> {
+    pub fn new(codec: C) -> Self {
+        Self { codec }
+    }
+}
+
+impl<T, C: Codec<T>> Codec<T> for Checksummed<C> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let mut bytes = self.codec.encode(value)?;
+        let crc = crc32(&bytes);
+        bytes.extend(crc.to_le_bytes());
+        Ok(bytes)
+    }
+
+    fn decode(&self, mut bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        let Some(body_len) = bytes.len().checked_sub(4) else {
+            bail!("Store file too short for a checksum");
+        };
+        let stored = u32::from_le_bytes(bytes[body_len..].try_into()?);
+        bytes.truncate(body_len);
+        let actual = crc32(&bytes);
+        if stored != actual {
+            bail!(
+                "Store checksum mismatch: file says {:08x}, contents hash to {:08x}",
+                stored,
+                actual
+            );
+        }
+        self.codec.decode(bytes)
+    }
+}
+
+/// CRC-32 (IEEE), as used by zlib and gzip.
+pub(super) fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!0u32, |crc, b| {
+        TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const SCHEMA_MAGIC: &[u8; 4] = b"KSV1";
+
+/// Upgrades persisted bytes from older schema versions for `Migrated`.
+pub trait Migrator {
+    /// The version the inner codec reads and writes.
+    const VERSION: u32;
+
+    /// Converts bytes written at version `from` into version `from + 1`.
+    /// Files written before versioning was adopted are version 0.
+    fn migrate(&self, from: u32, bytes: Vec<u8>) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+/// Prefixes what the codec `C` produces with a schema version header. On
+/// load, bytes from older versions are passed through `M::migrate` one
+/// version at a time (v1 → v2 → v3) before `C` decodes them. The file keeps
+/// its old version until the store is next written. Bytes from a newer
+/// version fail with `NewerSchema`.
+///
+/// # Example
+/// ```ignore
+/// #[derive(Default)]
+/// struct SettingsMigrations;
+///
+/// impl Migrator for SettingsMigrations {
+///     const VERSION: u32 = 2;
+///     fn migrate(&self, from: u32, bytes: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+///         match from {
+///             0 | 1 => add_field(bytes, "timeout", "30s"),
+///             _ => bail!("no migration from v{}", from),
+///         }
+///     }
+/// }
+///
+/// let store = Store::with_codec(loc, Migrated::new(JsonCodec::default(), SettingsMigrations))?;
+/// ```
+#[derive(Default)]
+pub struct Migrated<C, M> {
+    codec: C,
+    migrator: M,
+}
+
+impl<C, M> Migrated<C, M> {
+    pub fn new(codec: C, migrator: M) -> Self {
+        Self { codec, migrator }
+    }
+}
+
+impl<T, C: Codec<T>, M: Migrator> Codec<T> for Migrated<C, M> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let mut bytes = SCHEMA_MAGIC.to_vec();
+        bytes.extend(M::VERSION.to_le_bytes());
+        bytes.extend(self.codec.encode(value)?);
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        let (mut version, mut bytes) = match bytes.strip_prefix(SCHEMA_MAGIC.as_slice()) {
+            Some(rest) => {
+                let (version, payload) = rest
+                    .split_first_chunk::<4>()
+                    .ok_or_else(|| anyhow::anyhow!("Store schema header is truncated"))?;
+                (u32::from_le_bytes(*version), payload.to_vec())
+            }
+            None => (0, bytes),
+        };
+        if version > M::VERSION {
+            return Err(NewerSchema {
+                found: version,
+                supported: M::VERSION,
+            }
+            .into());
+        }
+        while version < M::VERSION {
+            bytes = self
+                .migrator
+                .migrate(version, bytes)
+                .with_context(|| format!("Failed to migrate store from schema v{}", version))?;
+            version += 1;
+        }
+        self.codec.decode(bytes)
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when `Migrated` reads bytes written at
+/// a newer schema than its `Migrator`, as after rolling a build back. The
+/// bytes are intact, so opening a store fails on this whatever its
+/// `LoadFailure`, leaving them for the newer build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewerSchema {
+    pub found: u32,
+    pub supported: u32,
+}
+
+impl fmt::Display for NewerSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Store schema v{} is newer than this build understands (v{})",
+            self.found, self.supported
+        )
+    }
+}
+
+impl std::error::Error for NewerSchema {}
+
+/// JSON through `serde_json`, compact unless built with `pretty`.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec {
+    pretty: bool,
+}
+
+#[cfg(feature = "json")]
+impl JsonCodec {
+    /// Indented output, for files people read or edit.
+    pub fn pretty() -> Self {
+        Self { pretty: true }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(if self.pretty {
+            serde_json::to_vec_pretty(value)?
+        } else {
+            serde_json::to_vec(value)?
+        })
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// `bincode`'s standard configuration, through its serde support.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(bincode::serde::encode_to_vec(
+            value,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        let (value, read) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard())?;
+        if read != bytes.len() {
+            bail!("{} trailing bytes after store data", bytes.len() - read);
+        }
+        Ok(value)
+    }
+}
+
+/// TOML through the `toml` crate. The stored type must serialize to a
+/// table, i.e. be a struct or map.
+#[cfg(feature = "toml")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TomlCodec;
+
+#[cfg(feature = "toml")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for TomlCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(toml::to_string(value)?.into_bytes())
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        Ok(toml::from_str(std::str::from_utf8(&bytes)?)?)
+    }
+}
+
+impl<T> Store<T> {
+    /// Like `new_with_default`, with `codec` handling (de)serialization
+    /// for as long as the store is open.
+    pub fn with_codec(
+        loc: PathBuf,
+        codec: impl Codec<T> + Send + Sync + 'static,
+    ) -> Result<Self, anyhow::Error>
+    where
+        T: Default,
+    {
+        Store::load_or_get(loc, Arc::new(codec), Recovery::default(), || {
+            Ok(T::default())
+        })
+    }
+
+    /// Writes the data to `path` through `codec` rather than the store's
+    /// own, e.g. to dump a binary store as something readable.
+    pub fn export<D: Codec<T>>(
+        &self,
+        path: impl AsRef<Path>,
+        codec: D,
+    ) -> Result<(), anyhow::Error> {
+        let path = path.as_ref();
+        let bytes = codec.encode(&self.read())?;
+        persist(path, &bytes, Durability::NONE)
+            .with_context(|| format!("Failed to export store to {}", path.display()))
+    }
+
+    /// Replaces the data with what `codec` decodes from `path`, typically an
+    /// edited `export`. The store persists it through its own codec as
+    /// usual.
+    pub fn import<D: Codec<T>>(
+        &self,
+        path: impl AsRef<Path>,
+        codec: D,
+    ) -> Result<(), anyhow::Error> {
+        let path = path.as_ref();
+        let value = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| codec.decode(bytes))
+            .with_context(|| format!("Failed to import store from {}", path.display()))?;
+        self.write(value)
+    }
+}
+
+/// gzip through `flate2`, at level 6 unless built with `level`.
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy)]
+pub struct Gzip {
+    level: u32,
+}
+
+#[cfg(feature = "gzip")]
+impl Gzip {
+    /// From 0 (no compression) to 9 (smallest).
+    pub fn level(level: u32) -> Self {
+        Self {
+            level: level.min(9),
+        }
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Default for Gzip {
+    fn default() -> Self {
+        Self::level(6)
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Compression for Gzip {
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        use std::io::Write;
+        let level = flate2::Compression::new(self.level);
+        let mut out = flate2::write::GzEncoder::new(Vec::new(), level);
+        out.write_all(bytes)?;
+        Ok(out.finish()?)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Zstandard through `zstd`, at its default level unless built with
+/// `level`.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+pub struct Zstd {
+    level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Zstd {
+    /// From 1 (fastest) to 22 (smallest).
+    pub fn level(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Default for Zstd {
+    fn default() -> Self {
+        Self::level(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Compression for Zstd {
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(zstd::encode_all(bytes, self.level)?)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(zstd::decode_all(bytes)?)
+    }
+}
+
+/// AES-256-GCM through `aes-gcm`, with a random nonce stored ahead of each
+/// ciphertext.
+#[cfg(feature = "aes-gcm")]
+pub struct AesGcm {
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "aes-gcm")]
+impl AesGcm {
+    const NONCE_LEN: usize = 12;
+
+    /// A cipher under the 32 byte key from `keys`, read now rather than on
+    /// every write.
+    pub fn new(keys: impl KeyProvider) -> Result<Self, anyhow::Error> {
+        use aes_gcm::KeyInit;
+        let key = keys.key().context("Failed to read encryption key")?;
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| anyhow::anyhow!("AES-256-GCM needs a 32 byte key, not {}", key.len()))?;
+        Ok(Self { cipher })
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl Cipher for AesGcm {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        use aes_gcm::aead::{Aead, AeadCore, OsRng};
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt"))?;
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        Ok(out)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        use aes_gcm::aead::Aead;
+        if ciphertext.len() < Self::NONCE_LEN {
+            bail!("Ciphertext is too short to hold a nonce");
+        }
+        let (nonce, sealed) = ciphertext.split_at(Self::NONCE_LEN);
+        self.cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), sealed)
+            .map_err(|_| anyhow::anyhow!("Ciphertext failed authentication"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, check_codec};
+    use crate::simple_store::{LoadFailure, MemoryBackend};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct LinesCodec;
+
+    impl Codec<Vec<String>> for LinesCodec {
+        fn encode(&self, value: &Vec<String>) -> Result<Vec<u8>, anyhow::Error> {
+            Ok(value.join("\n").into_bytes())
+        }
+
+        fn decode(&self, bytes: Vec<u8>) -> Result<Vec<String>, anyhow::Error> {
+            Ok(String::from_utf8(bytes)?
+                .lines()
+                .map(String::from)
+                .collect())
+        }
+    }
+
+    #[test]
+    fn stores_through_a_codec() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let store = Store::with_codec(path.clone(), LinesCodec).unwrap();
+        assert!(store.read().is_empty());
+        store.write(vec!["a".into(), "b".into()]).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\nb");
+
+        let reopened = Store::with_codec(path, LinesCodec).unwrap();
+        assert_eq!(*reopened.read(), vec!["a", "b"]);
+    }
+
+    /// Joins with a separator chosen at construction.
+    struct Separated(char);
+
+    impl Codec<Vec<String>> for Separated {
+        fn encode(&self, value: &Vec<String>) -> Result<Vec<u8>, anyhow::Error> {
+            Ok(value.join(&self.0.to_string()).into_bytes())
+        }
+
+        fn decode(&self, bytes: Vec<u8>) -> Result<Vec<String>, anyhow::Error> {
+            Ok(String::from_utf8(bytes)?
+                .split(self.0)
+                .map(String::from)
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_codec_it_was_opened_with() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let store = Store::builder_with_codec(Separated(';'))
+            .path(path.clone())
+            .initial(|| vec!["a".into(), "b".into()])
+            .backups(1)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"a;b");
+        store.write(vec!["c".into()]).unwrap();
+        store.restore_backup(1).unwrap();
+
+        let reopened = Store::with_codec(path, Separated(';')).unwrap();
+        assert_eq!(*reopened.read(), vec!["a", "b"]);
+    }
+
+    /// Comma separated, for readable exports.
+    #[derive(Default)]
+    struct CsvCodec;
+
+    impl Codec<Vec<String>> for CsvCodec {
+        fn encode(&self, value: &Vec<String>) -> Result<Vec<u8>, anyhow::Error> {
+            Ok(value.join(",").into_bytes())
+        }
+
+        fn decode(&self, bytes: Vec<u8>) -> Result<Vec<String>, anyhow::Error> {
+            Ok(String::from_utf8(bytes)?
+                .split(',')
+                .map(String::from)
+                .collect())
+        }
+    }
+
+    #[test]
+    fn exports_and_imports_through_another_codec() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let store = Store::with_codec(path.clone(), LinesCodec).unwrap();
+        store.write(vec!["a".into(), "b".into()]).unwrap();
+        let export = dir.path().join("lines.csv");
+        store.export(&export, CsvCodec).unwrap();
+        assert_eq!(std::fs::read(&export).unwrap(), b"a,b");
+
+        std::fs::write(&export, "a,b,c").unwrap();
+        store.import(&export, CsvCodec).unwrap();
+        assert_eq!(*store.read(), vec!["a", "b", "c"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\nb\nc");
+        assert!(store.import(dir.path().join("missing"), CsvCodec).is_err());
+    }
+
+    /// Run-length encoding, as (count, byte) pairs.
+    #[derive(Default)]
+    struct Rle;
+
+    impl Compression for Rle {
+        fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+            let mut out = Vec::new();
+            for chunk in bytes.chunk_by(|a, b| a == b) {
+                for run in chunk.chunks(255) {
+                    out.extend([run.len() as u8, run[0]]);
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+            if !bytes.len().is_multiple_of(2) {
+                bail!("odd length");
+            }
+            Ok(bytes
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn compresses_on_disk_only() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let codec = Compressed::<LinesCodec, Rle>::default();
+        let store = Store::with_codec(path.clone(), codec).unwrap();
+        store.write(vec!["a".repeat(300), "bb".into()]).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            [255, b'a', 45, b'a', 1, b'\n', 2, b'b']
+        );
+
+        let reopened = Store::with_codec(path.clone(), Compressed::<LinesCodec, Rle>::default());
+        assert_eq!(reopened.unwrap().read()[1], "bb");
+
+        // Undecodable files are quarantined like any other corrupt store
+        std::fs::write(&path, b"odd").unwrap();
+        let fresh = Store::with_codec(path, Compressed::<LinesCodec, Rle>::default()).unwrap();
+        assert!(fresh.read().is_empty());
+    }
+
+    /// XORs with a fixed key and appends a checksum. Only for exercising the
+    /// layering; not encryption.
+    #[derive(Default)]
+    struct Xor;
+
+    impl Cipher for Xor {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+            let mut out: Vec<u8> = plaintext.iter().map(|b| b ^ 0x5a).collect();
+            out.push(plaintext.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)));
+            Ok(out)
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+            let Some((sum, body)) = ciphertext.split_last() else {
+                bail!("empty ciphertext");
+            };
+            let plain: Vec<u8> = body.iter().map(|b| b ^ 0x5a).collect();
+            if plain.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != *sum {
+                bail!("checksum mismatch");
+            }
+            Ok(plain)
+        }
+    }
+
+    #[test]
+    fn encrypts_compressed_bytes() {
+        type Layered = Encrypted<Compressed<LinesCodec, Rle>, Xor>;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let store = Store::with_codec(path.clone(), Layered::default()).unwrap();
+        store.write(vec!["aaa".into()]).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            [3 ^ 0x5a, b'a' ^ 0x5a, 3 + b'a']
+        );
+
+        let reopened = Store::with_codec(path.clone(), Layered::default()).unwrap();
+        assert_eq!(*reopened.read(), vec!["aaa"]);
+
+        // Tampering is detected, and the file quarantined rather than used
+        std::fs::write(&path, [0, 0, 1]).unwrap();
+        let fresh = Store::with_codec(path, Layered::default()).unwrap();
+        assert!(fresh.read().is_empty());
+    }
+
+    /// Lines without separators or empty ones, which `LinesCodec` keeps.
+    fn lines() -> impl proptest::strategy::Strategy<Value = Vec<String>> {
+        proptest::collection::vec("[a-z ]{1,300}", 0..8)
+    }
+
+    #[test]
+    fn layers_round_trip_arbitrary_lines() {
+        check_codec(
+            lines(),
+            Encrypted::<Compressed<LinesCodec, Rle>, Xor>::default(),
+        )
+        .unwrap();
+        check_codec(lines(), Checksummed::new(Compressed::new(LinesCodec, Rle))).unwrap();
+        #[cfg(all(feature = "gzip", feature = "zstd", feature = "aes-gcm"))]
+        {
+            let cipher = AesGcm::new(|| Ok(vec![7; 32])).unwrap();
+            check_codec(lines(), Compressed::new(LinesCodec, Gzip::default())).unwrap();
+            check_codec(
+                lines(),
+                Encrypted::new(Compressed::new(LinesCodec, Zstd::default()), cipher),
+            )
+            .unwrap();
+        }
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn gzip_and_zstd_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let lines = vec!["x".repeat(1000), "y".into()];
+        let path = dir.path().join("lines.gz");
+        let store = Store::with_codec(path.clone(), Compressed::new(LinesCodec, Gzip::default()));
+        store.unwrap().write(lines.clone()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[..2], [0x1f, 0x8b]);
+        let reopened = Store::with_codec(path, Compressed::new(LinesCodec, Gzip::level(1)));
+        assert_eq!(*reopened.unwrap().read(), lines);
+
+        let path = dir.path().join("lines.zst");
+        let store = Store::with_codec(path.clone(), Compressed::new(LinesCodec, Zstd::level(19)));
+        store.unwrap().write(lines.clone()).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < 100);
+        let reopened = Store::with_codec(path, Compressed::new(LinesCodec, Zstd::default()));
+        assert_eq!(*reopened.unwrap().read(), lines);
+        assert!(Zstd::default().decompress(b"not zstd").is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn aes_gcm_reads_its_key_once() {
+        let reads = Arc::new(Mutex::new(0));
+        let mvreads = reads.clone();
+        let cipher = AesGcm::new(move || {
+            *mvreads.lock() += 1;
+            Ok(vec![7; 32])
+        })
+        .unwrap();
+        let sealed = cipher.encrypt(b"secret").unwrap();
+        assert_ne!(sealed, cipher.encrypt(b"secret").unwrap());
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), b"secret");
+        assert_eq!(*reads.lock(), 1);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.decrypt(&tampered).is_err());
+        let other = AesGcm::new(|| Ok(vec![8; 32])).unwrap();
+        assert!(other.decrypt(&sealed).is_err());
+        assert!(AesGcm::new(|| Ok(vec![7; 16])).is_err());
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines.enc");
+        let codec =
+            |key| Encrypted::new(LinesCodec, AesGcm::new(move || Ok(vec![key; 32])).unwrap());
+        let store = Store::with_codec(path.clone(), codec(7)).unwrap();
+        store.write(vec!["token".into()]).unwrap();
+        assert!(
+            !std::fs::read(&path)
+                .unwrap()
+                .windows(5)
+                .any(|w| w == b"token")
+        );
+        assert_eq!(
+            *Store::with_codec(path, codec(7)).unwrap().read(),
+            vec!["token"]
+        );
+    }
+
+    #[test]
+    fn env_keys_are_hex() {
+        assert_eq!(from_hex("00ff10").unwrap(), vec![0, 0xff, 0x10]);
+        assert!(from_hex("0g").is_err());
+        assert!(from_hex("abc").is_err());
+        assert!(EnvKey::new("KITCHEN_SINK_TEST_UNSET_KEY").key().is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines.json");
+        let store = Store::with_codec(path.clone(), JsonCodec::default()).unwrap();
+        store.write(vec!["a".to_string()]).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), br#"["a"]"#);
+        let reopened: Store<Vec<String>> = Store::with_codec(path, JsonCodec::pretty()).unwrap();
+        assert_eq!(*reopened.read(), vec!["a"]);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines.bin");
+        let store = Store::with_codec(path.clone(), BincodeCodec).unwrap();
+        store.write(vec!["a".to_string(), "bc".into()]).unwrap();
+        let reopened: Store<Vec<String>> = Store::with_codec(path, BincodeCodec).unwrap();
+        assert_eq!(*reopened.read(), vec!["a", "bc"]);
+        assert!(Codec::<u8>::decode(&BincodeCodec, vec![1, 2]).is_err());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_round_trips() {
+        #[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Settings {
+            name: String,
+            retries: u32,
+        }
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("settings.toml");
+        let store = Store::with_codec(path.clone(), TomlCodec).unwrap();
+        let settings = Settings {
+            name: "upstream".into(),
+            retries: 3,
+        };
+        store.write(settings).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "name = \"upstream\"\nretries = 3\n"
+        );
+        let reopened: Store<Settings> = Store::with_codec(path, TomlCodec).unwrap();
+        assert_eq!(reopened.read().retries, 3);
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_falls_back_and_reports() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mvreported = reported.clone();
+
+        let store = Store::with_codec(path.clone(), Checksummed::<LinesCodec>::default()).unwrap();
+        store.write(vec!["ok".into()]).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[0] = b'O';
+        std::fs::write(&path, bytes).unwrap();
+
+        let reopened = Store::builder_with_codec(Checksummed::<LinesCodec>::default())
+            .path(&path)
+            .initial(Vec::new)
+            .on_corruption(move |c| mvreported.lock().push(c.path.clone()))
+            .build()
+            .await
+            .unwrap();
+        assert!(reopened.read().is_empty());
+        assert_eq!(*reported.lock(), vec![path]);
+    }
+
+    /// `LinesCodec` that refuses to encode an empty list.
+    #[derive(Default)]
+    struct NonEmpty;
+
+    impl Codec<Vec<String>> for NonEmpty {
+        fn encode(&self, value: &Vec<String>) -> Result<Vec<u8>, anyhow::Error> {
+            if value.is_empty() {
+                bail!("nothing to encode");
+            }
+            LinesCodec.encode(value)
+        }
+
+        fn decode(&self, bytes: Vec<u8>) -> Result<Vec<String>, anyhow::Error> {
+            LinesCodec.decode(bytes)
+        }
+    }
+
+    #[test]
+    fn encoded_values_return_encode_failures() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        std::fs::write(&path, "a").unwrap();
+        let store: Store<Encoded<Vec<String>, NonEmpty>> =
+            Store::with_codec(path.clone(), Raw).unwrap();
+        assert_eq!(**store.read(), vec!["a"]);
+
+        assert!(store.write(Encoded::new(Vec::new())).is_err());
+        assert_eq!(**store.read(), vec!["a"]);
+        store.write(Encoded::new(vec!["b".into()])).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"b");
+    }
+
+    /// v0 had no header, v1 prefixed each line with "- ", v2 upper-cases.
+    #[derive(Default)]
+    struct ListMigrations;
+
+    impl Migrator for ListMigrations {
+        const VERSION: u32 = 2;
+
+        fn migrate(&self, from: u32, bytes: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+            let text = String::from_utf8(bytes)?;
+            Ok(match from {
+                0 => text
+                    .lines()
+                    .map(|l| format!("- {}", l))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                1 => text.to_uppercase(),
+                _ => bail!("no migration from v{}", from),
+            }
+            .into_bytes())
+        }
+    }
+
+    #[test]
+    fn migrates_old_schemas_on_load() {
+        type Versioned = Migrated<LinesCodec, ListMigrations>;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        std::fs::write(&path, "a\nb").unwrap();
+        let store = Store::with_codec(path.clone(), Versioned::default()).unwrap();
+        assert_eq!(*store.read(), vec!["- A", "- B"]);
+
+        store.write(vec!["c".into()]).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"KSV1\x02\0\0\0c");
+        std::fs::write(&path, b"KSV1\x01\0\0\0- d").unwrap();
+        let reopened = Store::with_codec(path.clone(), Versioned::default()).unwrap();
+        assert_eq!(*reopened.read(), vec!["- D"]);
+
+        let newer = Versioned::default().decode(b"KSV1\x03\0\0\0".to_vec());
+        assert_eq!(
+            newer.unwrap_err().downcast_ref(),
+            Some(&NewerSchema {
+                found: 3,
+                supported: 2
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn newer_schemas_fail_the_open_and_stay_put() {
+        type Versioned = Migrated<LinesCodec, ListMigrations>;
+        let newer = b"KSV1\x03\0\0\0a".to_vec();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lines");
+        std::fs::write(&path, &newer).unwrap();
+        let opened = Store::with_codec(path.clone(), Versioned::default());
+        assert!(opened.err().unwrap().is::<NewerSchema>());
+        assert_eq!(std::fs::read(&path).unwrap(), newer);
+
+        let backend = MemoryBackend::with_bytes(newer.clone());
+        let opened = Store::builder_with_codec(Versioned::default())
+            .backend(backend.clone())
+            .on_load_failure(LoadFailure::Overwrite)
+            .initial(Vec::new)
+            .build()
+            .await;
+        assert!(opened.err().unwrap().is::<NewerSchema>());
+        assert_eq!(backend.bytes(), Some(newer));
+    }
+}
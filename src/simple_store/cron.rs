@@ -0,0 +1,213 @@
+use anyhow::{Context, bail};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A five-field cron expression (`minute hour day-of-month month
+/// day-of-week`), evaluated in UTC. Converts into a `RefreshSchedule`, so
+/// it can be passed to `scheduled_updates` in place of a `Duration`:
+///
+/// ```ignore
+/// // Upstream publishes at 02:00 UTC, give it five minutes
+/// store.scheduled_updates(fetcher, "5 2 * * *".parse::<CronSchedule>()?);
+/// ```
+///
+/// Fields take `*`, values, ranges (`1-5`), steps (`*/15`, `0-30/10`) and
+/// comma-separated lists of those; days of the week run from 0 (Sunday) to
+/// 6, with 7 also Sunday. As in cron, when both day fields are restricted a
+/// day matching either one fires, and otherwise only a day matching both;
+/// a field starting with `*`, such as `*/2`, counts as unrestricted. `@hourly`, `@daily`, `@weekly`,
+/// `@monthly` and `@yearly` are accepted too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parses one field into a bitmask of the values in `min..=max`.
+fn field(spec: &str, min: u32, max: u32) -> Result<u64, anyhow::Error> {
+    let mut bits = 0;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("step of 0 in {:?}", part);
+        }
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (from.parse()?, to.parse()?),
+                // `n/step` runs from n to the end of the field
+                None if step > 1 => (range.parse()?, max),
+                None => {
+                    let n = range.parse()?;
+                    (n, n)
+                }
+            },
+        };
+        if from < min || to > max || from > to {
+            bail!("{:?} is outside {}-{}", part, min, max);
+        }
+        for n in (from..=to).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+/// (year, month, day) of the `days`th day since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl CronSchedule {
+    fn runs_on(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4).rem_euclid(7) as u32;
+        let day_matches = self.days & (1 << day) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        let on_day = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        on_day && self.months & (1 << month) != 0
+    }
+
+    /// The first time after `after` that the schedule fires, if it fires
+    /// within the next few years.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let minute = after.duration_since(UNIX_EPOCH).ok()?.as_secs() / 60 + 1;
+        let (first_day, first_minute) = ((minute / 1440) as i64, minute % 1440);
+        // Long enough to reach the next February 29th
+        for days in first_day..first_day + 366 * 8 {
+            if !self.runs_on(days) {
+                continue;
+            }
+            let from = if days == first_day { first_minute } else { 0 };
+            let at = (from..1440)
+                .find(|m| self.hours & (1 << (m / 60)) != 0 && self.minutes & (1 << (m % 60)) != 0);
+            if let Some(at) = at {
+                let secs = days as u64 * 86_400 + at * 60;
+                return Some(UNIX_EPOCH + Duration::from_secs(secs));
+            }
+        }
+        None
+    }
+
+    /// How long from `now` until the schedule next fires.
+    pub(super) fn until_next(&self, now: SystemTime) -> Duration {
+        self.next_after(now)
+            .and_then(|next| next.duration_since(now).ok())
+            .unwrap_or(Duration::from_secs(86_400))
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("Cron expression {:?} needs 5 fields", expr);
+        };
+        let parse = |spec, min, max, name| {
+            field(spec, min, max)
+                .with_context(|| format!("Invalid {} field in cron expression {:?}", name, expr))
+        };
+        let weekdays = parse(weekday, 0, 7, "day-of-week")?;
+        let schedule = Self {
+            minutes: parse(minute, 0, 59, "minute")?,
+            hours: parse(hour, 0, 23, "hour")? as u32,
+            days: parse(day, 1, 31, "day-of-month")? as u32,
+            months: parse(month, 1, 12, "month")? as u16,
+            weekdays: ((weekdays | weekdays >> 7) & 0x7f) as u8,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        };
+        if schedule.next_after(UNIX_EPOCH).is_none() {
+            bail!("Cron expression {:?} never fires", expr);
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `y-m-d h:mi` UTC.
+    fn at(y: i64, m: u32, d: u32, h: u64, mi: u64) -> SystemTime {
+        let days = (0..200_000)
+            .find(|&days| civil_from_days(days) == (y, m, d))
+            .unwrap();
+        UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400 + h * 3600 + mi * 60)
+    }
+
+    #[test]
+    fn finds_the_next_run() {
+        let daily: CronSchedule = "0 2 * * *".parse().unwrap();
+        let now = at(2024, 2, 28, 3, 0);
+        assert_eq!(daily.next_after(now), Some(at(2024, 2, 29, 2, 0)));
+        assert_eq!(daily.until_next(now), Duration::from_secs(23 * 3600));
+
+        let weekdays: CronSchedule = "*/20 9-10 * * 1-5".parse().unwrap();
+        // A Saturday, so Monday morning
+        assert_eq!(
+            weekdays.next_after(at(2024, 3, 2, 9, 30)),
+            Some(at(2024, 3, 4, 9, 0))
+        );
+        assert_eq!(
+            weekdays.next_after(at(2024, 3, 4, 9, 40)),
+            Some(at(2024, 3, 4, 10, 0))
+        );
+
+        // Either day field matches once both are restricted
+        let either: CronSchedule = "0 0 13 * 5".parse().unwrap();
+        assert_eq!(
+            either.next_after(at(2024, 9, 1, 0, 0)),
+            Some(at(2024, 9, 6, 0, 0))
+        );
+        // A `*` step leaves its field unrestricted, so both must match: odd
+        // days that are Mondays
+        let stepped: CronSchedule = "0 0 */2 * 1".parse().unwrap();
+        assert_eq!(
+            stepped.next_after(at(2024, 9, 1, 0, 0)),
+            Some(at(2024, 9, 9, 0, 0))
+        );
+        let ranged: CronSchedule = "0 0 1-31/2 * 1".parse().unwrap();
+        assert_eq!(
+            ranged.next_after(at(2024, 9, 1, 0, 0)),
+            Some(at(2024, 9, 2, 0, 0))
+        );
+        let yearly: CronSchedule = "@yearly".parse().unwrap();
+        assert_eq!(yearly, "0 0 1 1 *".parse().unwrap());
+
+        assert!("0 2 * *".parse::<CronSchedule>().is_err());
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("0 0 30 2 *".parse::<CronSchedule>().is_err());
+    }
+}
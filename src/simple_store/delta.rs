@@ -0,0 +1,286 @@
+use super::{Store, replication};
+use crate::framing::{read_frames, write_frame};
+use anyhow::Context;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::fs::{File, OpenOptions};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+/// An incremental change to a `T`, persisted via the same byte conversions
+/// as the store itself.
+///
+/// Patches should be idempotent (upserts and deletes by key rather than
+/// increments): a crash during compaction can replay journaled patches onto a
+/// snapshot that already contains them.
+pub trait Patch<T> {
+    fn apply(self, target: &mut T);
+}
+
+/// Produces the patches needed to bring a store up to date. An empty `Vec`
+/// means nothing changed.
+#[async_trait]
+pub trait DeltaFetcher<T, P> {
+    async fn fetch_delta(&self, store: Store<T>) -> Result<Vec<P>, anyhow::Error>;
+}
+
+/// A `Store` whose updates are appended to a journal (`<loc>.journal`)
+/// instead of rewriting the whole snapshot each time. On open the snapshot is
+/// loaded and the journal replayed over it. Once `compact_after` patches have
+/// accumulated the snapshot is rewritten and the journal cleared.
+pub struct DeltaStore<T, P> {
+    store: Store<T>,
+    journal: Arc<Mutex<Journal>>,
+    compact_after: usize,
+    _patch: PhantomData<fn(P)>,
+}
+
+struct Journal {
+    file: File,
+    entries: usize,
+}
+
+impl<T, P> Clone for DeltaStore<T, P> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            journal: self.journal.clone(),
+            compact_after: self.compact_after,
+            _patch: PhantomData,
+        }
+    }
+}
+
+impl<T, P> DeltaStore<T, P>
+where
+    T: Clone + TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T> + From<&'a P>,
+    P: Patch<T> + TryFrom<Vec<u8>, Error = anyhow::Error>,
+{
+    pub fn open<F>(loc: PathBuf, getter: F, compact_after: usize) -> Result<Self, anyhow::Error>
+    where
+        F: FnOnce() -> Result<T, anyhow::Error>,
+    {
+        let store = Store::new_or_get(loc, getter)?;
+        let path = journal_path(&store.inner.loc);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open journal {}", path.display()))?;
+        let (frames, valid_len) = read_frames(&mut file)?;
+        if valid_len != file.metadata()?.len() {
+            warn!(
+                "Dropping partial trailing entry from journal {}",
+                path.display()
+            );
+            file.set_len(valid_len)?;
+        }
+
+        let entries = frames.len();
+        {
+            let mut guard = store.inner.data.write();
+            let data = Arc::make_mut(&mut guard);
+            for (idx, frame) in frames.into_iter().enumerate() {
+                P::try_from(frame)
+                    .with_context(|| format!("Failed to decode journal entry {}", idx))?
+                    .apply(data);
+            }
+        }
+        Ok(Self {
+            store,
+            journal: Arc::new(Mutex::new(Journal { file, entries })),
+            compact_after,
+            _patch: PhantomData,
+        })
+    }
+
+    /// Journals `patch`, then applies it in memory.
+    pub fn apply(&self, patch: P) -> Result<(), anyhow::Error> {
+        self.apply_all(vec![patch])
+    }
+
+    /// Journals every patch before applying any, so a failed append leaves
+    /// memory untouched.
+    pub fn apply_all(&self, patches: Vec<P>) -> Result<(), anyhow::Error> {
+        if patches.is_empty() {
+            return Ok(());
+        }
+        let mut journal = self.journal.lock();
+        let mut buf = Vec::new();
+        for patch in &patches {
+            let frame: Vec<u8> = patch.into();
+            write_frame(&mut buf, &frame)?;
+        }
+        std::io::Write::write_all(&mut journal.file, &buf)?;
+        journal.entries += patches.len();
+        let writer = self.store.inner.writer.lock();
+        let generation;
+        {
+            let mut guard = self.store.inner.data.write();
+            let data = Arc::make_mut(&mut guard);
+            for patch in patches {
+                patch.apply(data);
+            }
+            generation = self.store.inner.mark_updated();
+        }
+        if journal.entries >= self.compact_after {
+            self.compact_locked(&mut journal)?;
+        }
+        drop(writer);
+        drop(journal);
+        self.store.run_write_hooks(generation);
+        Ok(())
+    }
+
+    /// Rewrites the snapshot from memory and clears the journal.
+    pub fn compact(&self) -> Result<(), anyhow::Error> {
+        let mut journal = self.journal.lock();
+        let _writer = self.store.inner.writer.lock();
+        self.compact_locked(&mut journal)
+    }
+
+    /// `compact` with the journal and the store's writer lock held.
+    fn compact_locked(&self, journal: &mut Journal) -> Result<(), anyhow::Error> {
+        let (serialized, shrunk) = {
+            let mut guard = self.store.inner.data.write();
+            self.store.shrink_to_limit(Arc::make_mut(&mut guard))?
+        };
+        if shrunk {
+            self.store.inner.mark_updated();
+        }
+        self.store.save_now(&serialized)?;
+        journal.file.set_len(0)?;
+        journal.entries = 0;
+        let generation = self.store.generation();
+        replication::replicate(&self.store.inner.replicas.lock(), generation, serialized);
+        Ok(())
+    }
+
+    /// Number of patches journaled since the last compaction.
+    pub fn journal_len(&self) -> usize {
+        self.journal.lock().entries
+    }
+
+    pub fn store(&self) -> &Store<T> {
+        &self.store
+    }
+}
+
+impl<T, P> DeltaStore<T, P>
+where
+    T: Clone + TryFrom<Vec<u8>, Error = anyhow::Error> + Send + Sync + 'static,
+    for<'a> Vec<u8>: From<&'a T> + From<&'a P>,
+    P: Patch<T> + TryFrom<Vec<u8>, Error = anyhow::Error> + Send + 'static,
+{
+    /// Polls `fetcher` every `between` and applies whatever patches it returns.
+    pub fn scheduled_deltas<F>(&self, fetcher: F, between: Duration)
+    where
+        F: DeltaFetcher<T, P> + Send + Sync + 'static,
+    {
+        let mvstore = self.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(between).await;
+                if let Err(e) = fetcher
+                    .fetch_delta(mvstore.store.clone())
+                    .await
+                    .and_then(|patches| mvstore.apply_all(patches))
+                {
+                    error!("Failed to apply delta update: {}", e);
+                }
+            }
+        });
+    }
+}
+
+fn journal_path(loc: &Path) -> PathBuf {
+    let mut name = loc.file_name().unwrap_or_default().to_os_string();
+    name.push(".journal");
+    loc.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempDir;
+    use anyhow::bail;
+    use std::collections::BTreeMap;
+
+    #[derive(Default, Debug, Clone, PartialEq)]
+    struct Prices(BTreeMap<u8, u8>);
+
+    impl TryFrom<Vec<u8>> for Prices {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Prices(value.chunks(2).map(|kv| (kv[0], kv[1])).collect()))
+        }
+    }
+
+    impl<'a> From<&'a Prices> for Vec<u8> {
+        fn from(value: &'a Prices) -> Self {
+            value.0.iter().flat_map(|(k, v)| [*k, *v]).collect()
+        }
+    }
+
+    struct Set(u8, u8);
+
+    impl Patch<Prices> for Set {
+        fn apply(self, target: &mut Prices) {
+            target.0.insert(self.0, self.1);
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Set {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            match value.as_slice() {
+                [k, v] => Ok(Set(*k, *v)),
+                _ => bail!("bad patch"),
+            }
+        }
+    }
+
+    impl<'a> From<&'a Set> for Vec<u8> {
+        fn from(value: &'a Set) -> Self {
+            vec![value.0, value.1]
+        }
+    }
+
+    fn open(loc: &Path) -> DeltaStore<Prices, Set> {
+        DeltaStore::open(loc.to_path_buf(), || Ok(Prices::default()), 3).unwrap()
+    }
+
+    #[test]
+    fn journals_patches_and_compacts() {
+        let dir = TempDir::new().unwrap();
+        let loc = dir.path().join("prices");
+        let store = open(&loc);
+        store.apply(Set(1, 10)).unwrap();
+        store.apply(Set(2, 20)).unwrap();
+        assert_eq!(store.journal_len(), 2);
+        assert!(std::fs::read(&loc).unwrap().is_empty());
+
+        // Reopening replays the journal over the (still empty) snapshot
+        let reopened = open(&loc);
+        assert_eq!(reopened.store().read().0.len(), 2);
+        drop(reopened);
+
+        store.apply_all(vec![Set(1, 11), Set(3, 30)]).unwrap();
+        assert_eq!(store.journal_len(), 0);
+        assert_eq!(std::fs::read(&loc).unwrap(), vec![1, 11, 2, 20, 3, 30]);
+
+        // A torn append is discarded on open
+        std::fs::write(journal_path(&loc), [2, 0, 0, 0, 4]).unwrap();
+        let reopened = open(&loc);
+        assert_eq!(reopened.journal_len(), 0);
+        assert_eq!(*reopened.store().read(), *store.store().read());
+    }
+}
@@ -0,0 +1,123 @@
+use super::Store;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Computes what changed between two values of a store, for
+/// `Changes::diff`. Any `Fn(&T, &T) -> D` is one.
+pub trait Differ<T> {
+    type Diff;
+
+    fn diff(&self, old: &T, new: &T) -> Self::Diff;
+}
+
+impl<T, D, F> Differ<T> for F
+where
+    F: Fn(&T, &T) -> D,
+{
+    type Diff = D;
+
+    fn diff(&self, old: &T, new: &T) -> D {
+        self(old, new)
+    }
+}
+
+/// Follows a store's changes as pairs of the value before and after, from
+/// `Store::subscribe_changes`. Like `subscribe`, changes that land faster
+/// than they are taken are coalesced: the pair then spans all of them, so
+/// `old` is always the value the previous call returned as `new`.
+pub struct Changes<T> {
+    store: Store<T>,
+    changes: watch::Receiver<u64>,
+    last: Arc<T>,
+}
+
+impl<T> Changes<T> {
+    /// Waits for the next change, returning the data as it was and as it is
+    /// now.
+    pub async fn changed(&mut self) -> (Arc<T>, Arc<T>) {
+        // The store holds the sender, so it can't close under us
+        let _ = self.changes.changed().await;
+        let new = {
+            let data = self.store.inner.data.read();
+            self.changes.mark_unchanged();
+            data.clone()
+        };
+        let old = std::mem::replace(&mut self.last, new.clone());
+        (old, new)
+    }
+
+    /// `changed`, reduced to what `differ` makes of the pair.
+    pub async fn diff<D: Differ<T>>(&mut self, differ: &D) -> D::Diff {
+        let (old, new) = self.changed().await;
+        differ.diff(&old, &new)
+    }
+}
+
+impl<T> Store<T> {
+    /// Follows changes to the data as `(old, new)` pairs, or diffs of them,
+    /// so subscribers can react to what changed instead of rescanning the
+    /// whole value. Starts from the data as of now.
+    ///
+    /// ```ignore
+    /// let mut changes = store.subscribe_changes();
+    /// loop {
+    ///     let added = changes.diff(&|old: &Users, new: &Users| new.ids_not_in(old)).await;
+    ///     welcome(added);
+    /// }
+    /// ```
+    pub fn subscribe_changes(&self) -> Changes<T> {
+        let data = self.inner.data.read();
+        let mut changes = self.subscribe();
+        changes.mark_unchanged();
+        Changes {
+            store: self.clone(),
+            changes,
+            last: data.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simple_store::testing::TempStore;
+
+    #[derive(Default, Debug, Clone, PartialEq)]
+    struct Words(Vec<String>);
+
+    impl TryFrom<Vec<u8>> for Words {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            let text = String::from_utf8(value)?;
+            Ok(Words(text.split_whitespace().map(String::from).collect()))
+        }
+    }
+
+    impl<'a> From<&'a Words> for Vec<u8> {
+        fn from(value: &'a Words) -> Self {
+            value.0.join(" ").into_bytes()
+        }
+    }
+
+    fn added(old: &Words, new: &Words) -> Vec<String> {
+        new.0
+            .iter()
+            .filter(|w| !old.0.contains(w))
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pairs_each_change_with_the_last() {
+        let tmp: TempStore<Words> = TempStore::from_value(Words(vec!["a".into()])).unwrap();
+        let mut changes = tmp.subscribe_changes();
+        tmp.update(|w| w.0.push("b".into())).unwrap();
+        let (old, new) = changes.changed().await;
+        assert_eq!(old.0, vec!["a"]);
+        assert_eq!(new.0, vec!["a", "b"]);
+
+        tmp.update(|w| w.0.push("c".into())).unwrap();
+        tmp.update(|w| w.0.push("d".into())).unwrap();
+        assert_eq!(changes.diff(&added).await, vec!["c", "d"]);
+    }
+}
@@ -0,0 +1,125 @@
+use super::Store;
+use std::fs::File;
+use std::path::Path;
+
+/// What a write flushes to disk before returning, trading throughput for
+/// surviving power loss. Writes are atomic whatever this says; without
+/// syncing, a power cut can just lose the latest ones.
+///
+/// ```ignore
+/// // The data, but not the rename, survives a power cut
+/// let store = store.with_durability(Durability::none().with_file_sync(FileSync::Data));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Durability {
+    /// How the new file is synced before it is renamed into place.
+    pub file: FileSync,
+    /// Whether the parent directory is synced after the rename, which makes
+    /// the rename itself durable. Unix only.
+    pub sync_dir: bool,
+}
+
+/// How a freshly written file is flushed to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileSync {
+    /// Left to the OS.
+    #[default]
+    None,
+    /// `sync_data`: the contents, but not necessarily metadata like the
+    /// modification time.
+    Data,
+    /// `sync_all`: contents and metadata.
+    All,
+}
+
+impl Durability {
+    /// Nothing is synced, the default.
+    pub const NONE: Durability = Durability {
+        file: FileSync::None,
+        sync_dir: false,
+    };
+
+    /// The file and its directory are both synced, as `with_fsync` does.
+    pub const FULL: Durability = Durability {
+        file: FileSync::All,
+        sync_dir: true,
+    };
+
+    pub fn with_file_sync(mut self, file: FileSync) -> Self {
+        self.file = file;
+        self
+    }
+
+    pub fn with_dir_sync(mut self, sync_dir: bool) -> Self {
+        self.sync_dir = sync_dir;
+        self
+    }
+
+    pub(super) fn sync_file(&self, file: &File) -> std::io::Result<()> {
+        match self.file {
+            FileSync::None => Ok(()),
+            FileSync::Data => file.sync_data(),
+            FileSync::All => file.sync_all(),
+        }
+    }
+
+    /// Syncs the directory holding `loc`, if asked to.
+    pub(super) fn sync_parent(&self, loc: &Path) -> std::io::Result<()> {
+        #[cfg(unix)]
+        if self.sync_dir
+            && let Some(dir) = loc.parent()
+        {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        #[cfg(not(unix))]
+        let _ = loc;
+        Ok(())
+    }
+}
+
+impl<T> Store<T> {
+    /// Flushes every write to disk before it returns, trading write latency
+    /// for durability across power loss. Writes are atomic either way.
+    /// Short for `with_durability(Durability::FULL)`.
+    pub fn with_fsync(self) -> Self {
+        self.with_durability(Durability::FULL)
+    }
+
+    /// Controls what each write of the store's file, and its backups and
+    /// snapshots, syncs to disk.
+    pub fn with_durability(self, durability: Durability) -> Self {
+        *self.inner.durability.lock() = durability;
+        self
+    }
+
+    pub fn durability(&self) -> Durability {
+        *self.inner.durability.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempStore, Text};
+
+    #[test]
+    fn writes_with_each_durability() {
+        let tmp: TempStore<Text> = TempStore::new().unwrap();
+        assert_eq!(tmp.durability(), Durability::NONE);
+        for durability in [
+            Durability::NONE.with_file_sync(FileSync::Data),
+            Durability::NONE.with_dir_sync(true),
+            Durability::FULL,
+        ] {
+            let store = tmp.store().clone().with_durability(durability);
+            store.write(Text(format!("{:?}", durability))).unwrap();
+            assert_eq!(tmp.bytes().unwrap(), format!("{:?}", durability).as_bytes());
+        }
+        assert_eq!(tmp.durability(), Durability::FULL);
+    }
+}
@@ -0,0 +1,126 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Controls a scheduled update loop at runtime. Returned by
+/// `scheduled_updates` and its variants; clones control the same loop, and
+/// dropping every handle leaves the loop running.
+///
+/// ```ignore
+/// let refresh = store.scheduled_updates(fetcher, Duration::from_secs(300));
+/// refresh.pause();        // upstream maintenance
+/// refresh.trigger_now();  // but pick up the hotfix
+/// refresh.resume();
+/// ```
+#[derive(Clone)]
+pub struct RefreshHandle {
+    control: Arc<Control>,
+}
+
+struct Control {
+    paused: AtomicBool,
+    trigger: Notify,
+    token: CancellationToken,
+    last: Mutex<Option<Result<bool, String>>>,
+}
+
+impl RefreshHandle {
+    pub(super) fn new(token: CancellationToken) -> Self {
+        Self {
+            control: Arc::new(Control {
+                paused: AtomicBool::new(false),
+                trigger: Notify::new(),
+                token,
+                last: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Skips scheduled fetches until `resume`. The schedule keeps ticking,
+    /// so the first fetch after resuming happens on its next tick.
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused.load(Ordering::Relaxed)
+    }
+
+    /// Fetches now rather than at the next tick, even while paused. The
+    /// schedule restarts from this fetch.
+    pub fn trigger_now(&self) {
+        self.control.trigger.notify_one();
+    }
+
+    /// Ends the loop. A fetch in flight is abandoned; a write is never
+    /// interrupted.
+    pub fn stop(&self) {
+        self.control.token.cancel();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.control.token.is_cancelled()
+    }
+
+    /// Whether the last scheduled fetch changed the data, or why it failed.
+    /// `None` until the first one finishes.
+    pub fn last_result(&self) -> Option<Result<bool, String>> {
+        self.control.last.lock().clone()
+    }
+
+    pub(super) fn token(&self) -> &CancellationToken {
+        &self.control.token
+    }
+
+    /// Waits out `tick`, or until `trigger_now`, returning false for a tick
+    /// that should be skipped because the loop is paused.
+    pub(super) async fn wait(&self, tick: impl Future<Output = ()>) -> bool {
+        tokio::select! {
+            _ = tick => !self.is_paused(),
+            _ = self.control.trigger.notified() => true,
+        }
+    }
+
+    pub(super) fn record(&self, result: &Result<bool, anyhow::Error>) {
+        let result = result.as_ref().copied().map_err(|e| format!("{:#}", e));
+        *self.control.last.lock() = Some(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simple_store::testing::{Count, Increment, TempStore, TimeHarness};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn pauses_triggers_and_stops_the_loop() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let refresh = tmp.scheduled_updates(Increment, Duration::from_secs(10));
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(*tmp.read(), Count(1));
+        assert_eq!(refresh.last_result(), Some(Ok(true)));
+
+        refresh.pause();
+        time.advance(Duration::from_secs(30)).await;
+        assert_eq!(*tmp.read(), Count(1));
+        refresh.trigger_now();
+        time.advance(Duration::ZERO).await;
+        assert_eq!(*tmp.read(), Count(2));
+
+        refresh.resume();
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(*tmp.read(), Count(3));
+
+        refresh.stop();
+        time.advance(Duration::from_secs(60)).await;
+        assert_eq!(*tmp.read(), Count(3));
+        assert!(refresh.is_stopped());
+    }
+}
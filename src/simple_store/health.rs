@@ -0,0 +1,89 @@
+use super::Store;
+use crate::health::Status;
+use std::time::Duration;
+
+/// How fresh a store's data is, from `Store::health`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreHealth {
+    /// Changed within the allowed age.
+    Healthy,
+    /// Older than allowed, but no refresh has failed since the last success.
+    /// Usually means nothing is refreshing the store.
+    Stale { age: Duration },
+    /// Older than allowed because its refreshes keep failing.
+    Failed { age: Duration, failures: u32 },
+}
+
+impl From<StoreHealth> for Status {
+    fn from(health: StoreHealth) -> Self {
+        match health {
+            StoreHealth::Healthy => Status::healthy(),
+            StoreHealth::Stale { age } => Status::degraded(format!("data is {:?} old", age)),
+            StoreHealth::Failed { age, failures } => Status::unhealthy(format!(
+                "data is {:?} old after {} failed refreshes",
+                age, failures
+            )),
+        }
+    }
+}
+
+impl<T> Store<T> {
+    /// Judges the data against `max_age`, for readiness probes and alerts.
+    /// Age counts from the last change to the data, as `age` does. Convert
+    /// the result into a `Status` to report it to a `HealthRegistry`.
+    pub fn health(&self, max_age: Duration) -> StoreHealth {
+        let age = self.age();
+        if age <= max_age {
+            return StoreHealth::Healthy;
+        }
+        match self.metrics().consecutive_failures {
+            0 => StoreHealth::Stale { age },
+            failures => StoreHealth::Failed { age, failures },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Health;
+    use crate::simple_store::Fetcher;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    #[derive(Debug, PartialEq)]
+    struct Flag(bool);
+
+    impl<'a> From<&'a Flag> for Vec<u8> {
+        fn from(value: &'a Flag) -> Self {
+            vec![value.0 as u8]
+        }
+    }
+
+    struct Down;
+
+    #[async_trait]
+    impl Fetcher<Flag> for Down {
+        async fn fetch(&self, _: Option<Store<Flag>>) -> Result<Flag, anyhow::Error> {
+            Err(anyhow!("upstream unavailable"))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn goes_stale_then_failed() {
+        let store = Store::in_memory(Flag(true));
+        let max_age = Duration::from_secs(60);
+        assert_eq!(store.health(max_age), StoreHealth::Healthy);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(matches!(store.health(max_age), StoreHealth::Stale { .. }));
+
+        assert!(store.refresh(&Down).await.is_err());
+        let health = store.health(max_age);
+        assert!(matches!(health, StoreHealth::Failed { failures: 1, .. }));
+        assert_eq!(Status::from(health).health, Health::Unhealthy);
+
+        store.write(Flag(false)).unwrap();
+        assert_eq!(store.health(max_age), StoreHealth::Healthy);
+    }
+}
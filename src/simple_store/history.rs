@@ -0,0 +1,178 @@
+use super::{Durability, Store, persist};
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// How many past versions of a store's file `with_history` keeps. The
+/// oldest are dropped first once either limit is passed, but the newest is
+/// always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct History {
+    pub max_versions: usize,
+    /// Total size of the versions kept, if limited.
+    pub max_bytes: Option<u64>,
+}
+
+impl History {
+    pub fn versions(max_versions: usize) -> Self {
+        Self {
+            max_versions: max_versions.max(1),
+            max_bytes: None,
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+/// One past version of a store, from `Store::history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// When the version was persisted.
+    pub at: SystemTime,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// `.<file>.history`, beside `loc`.
+fn history_dir(loc: &Path) -> PathBuf {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    loc.with_file_name(format!(".{}.history", base))
+}
+
+/// Versions in the history of `loc`, newest first. Entries are named
+/// `<unix millis>.<n>`, `n` telling apart versions written the same
+/// millisecond.
+fn entries(loc: &Path) -> Vec<HistoryEntry> {
+    let Ok(dir) = std::fs::read_dir(history_dir(loc)) else {
+        return Vec::new();
+    };
+    let mut found: Vec<(u128, u64, HistoryEntry)> = dir
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name();
+            let (millis, n) = name.to_str()?.split_once('.')?;
+            let (millis, n): (u128, u64) = (millis.parse().ok()?, n.parse().ok()?);
+            let entry = HistoryEntry {
+                at: UNIX_EPOCH + Duration::from_millis(millis as u64),
+                bytes: entry.metadata().ok()?.len(),
+                path: entry.path(),
+            };
+            Some((millis, n, entry))
+        })
+        .collect();
+    found.sort_by_key(|(millis, n, _)| std::cmp::Reverse((*millis, *n)));
+    found.into_iter().map(|(_, _, entry)| entry).collect()
+}
+
+/// Adds `bytes` to the history of `loc` as of now, then drops whatever
+/// `history` no longer allows.
+fn record(loc: &Path, history: History, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    let dir = history_dir(loc);
+    std::fs::create_dir_all(&dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let mut n = 0;
+    while dir.join(format!("{}.{}", millis, n)).exists() {
+        n += 1;
+    }
+    persist(
+        &dir.join(format!("{}.{}", millis, n)),
+        bytes,
+        Durability::NONE,
+    )?;
+    let mut kept = 0;
+    let mut total = 0;
+    for entry in entries(loc) {
+        kept += 1;
+        total += entry.bytes;
+        let over = kept > history.max_versions || history.max_bytes.is_some_and(|max| total > max);
+        if kept > 1 && over {
+            std::fs::remove_file(&entry.path)?;
+        }
+    }
+    Ok(())
+}
+
+impl<T> Store<T> {
+    /// Keeps each version of the file persisted from now on, with the time
+    /// it was written, in a `.<file>.history` directory beside it, for
+    /// `read_at` and `history`. File stores only; a failure to keep a
+    /// version is logged rather than failing the write.
+    pub fn with_history(self, history: History) -> Self {
+        *self.inner.history.lock() = Some(history);
+        self
+    }
+
+    pub(super) fn history_policy(&self) -> Option<History> {
+        *self.inner.history.lock()
+    }
+
+    /// `record`, if the store keeps history, logging failures.
+    pub(super) fn record_history(&self, bytes: &[u8]) {
+        if let Some(history) = self.history_policy() {
+            log_failure(&self.inner.loc, record(&self.inner.loc, history, bytes));
+        }
+    }
+
+    /// The versions kept by `with_history`, newest first.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        entries(&self.inner.loc)
+    }
+}
+
+fn log_failure(loc: &Path, recorded: Result<(), anyhow::Error>) {
+    if let Err(e) = recorded {
+        warn!("Failed to keep history of {}: {:#}", loc.display(), e);
+    }
+}
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> Store<T> {
+    /// The data as it was at `at`: the newest kept version persisted no
+    /// later than that, or `None` if history doesn't reach back that far.
+    pub fn read_at(&self, at: SystemTime) -> Result<Option<T>, anyhow::Error> {
+        let Some(entry) = self.history().into_iter().find(|entry| entry.at <= at) else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(&entry.path)
+            .with_context(|| format!("Failed to read {}", entry.path.display()))?;
+        T::try_from(bytes).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempStore, Text};
+
+    #[test]
+    fn reads_past_versions() {
+        let tmp: TempStore<Text> = TempStore::new().unwrap();
+        let store = tmp.store().clone().with_history(History::versions(3));
+        let before = SystemTime::now() - Duration::from_secs(1);
+        for v in ["v1", "v2", "v3", "v4"] {
+            store.write(Text(v.into())).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let history = store.history();
+        assert_eq!(history.len(), 3);
+        let oldest = &history[2];
+        assert_eq!(std::fs::read(&oldest.path).unwrap(), b"v2");
+
+        assert_eq!(store.read_at(before).unwrap(), None);
+        assert_eq!(store.read_at(oldest.at).unwrap(), Some(Text("v2".into())));
+        assert_eq!(
+            store.read_at(SystemTime::now()).unwrap(),
+            Some(Text("v4".into()))
+        );
+
+        let store = store.with_history(History::versions(10).with_max_bytes(5));
+        store.write(Text("v5".into())).unwrap();
+        assert_eq!(store.history().len(), 2);
+    }
+}
@@ -0,0 +1,121 @@
+use super::Store;
+use std::sync::Arc;
+
+type WriteHook = Arc<dyn Fn(u64) + Send + Sync>;
+type SuccessHook = Arc<dyn Fn(bool) + Send + Sync>;
+type FailureHook = Arc<dyn Fn(&anyhow::Error) + Send + Sync>;
+
+/// Callbacks registered with `on_write`, `on_refresh_success` and
+/// `on_refresh_failure`.
+#[derive(Default)]
+pub(super) struct Hooks {
+    write: Vec<WriteHook>,
+    success: Vec<SuccessHook>,
+    failure: Vec<FailureHook>,
+}
+
+impl<T> Store<T> {
+    /// Calls `hook` with the new version after every write, once it has been
+    /// persisted (or queued, under write-behind). Reloads by `watch_file`
+    /// aren't writes and don't call it.
+    ///
+    /// Hooks run on the writing task once the store's locks are released,
+    /// so they may read the store, but should be quick: the write doesn't
+    /// return until they do.
+    pub fn on_write(&self, hook: impl Fn(u64) + Send + Sync + 'static) {
+        self.inner.hooks.lock().write.push(Arc::new(hook));
+    }
+
+    /// Calls `hook` with whether the data changed after every successful
+    /// refresh, scheduled or not. Refreshes that joined another in flight
+    /// don't call it again.
+    pub fn on_refresh_success(&self, hook: impl Fn(bool) + Send + Sync + 'static) {
+        self.inner.hooks.lock().success.push(Arc::new(hook));
+    }
+
+    /// Calls `hook` with the error after every failed refresh.
+    pub fn on_refresh_failure(&self, hook: impl Fn(&anyhow::Error) + Send + Sync + 'static) {
+        self.inner.hooks.lock().failure.push(Arc::new(hook));
+    }
+
+    pub(super) fn run_write_hooks(&self, generation: u64) {
+        let hooks = self.inner.hooks.lock().write.clone();
+        for hook in hooks {
+            hook(generation);
+        }
+    }
+
+    pub(super) fn run_refresh_hooks(&self, result: &Result<bool, anyhow::Error>) {
+        match result {
+            Ok(changed) => {
+                let hooks = self.inner.hooks.lock().success.clone();
+                for hook in hooks {
+                    hook(*changed);
+                }
+            }
+            Err(e) => {
+                let hooks = self.inner.hooks.lock().failure.clone();
+                for hook in hooks {
+                    hook(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Fetcher;
+    use crate::simple_store::testing::{Count, TempStore};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+
+    /// Fetches 3 until the upstream goes away.
+    struct Upstream(Mutex<bool>);
+
+    #[async_trait]
+    impl Fetcher<Count> for Upstream {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            match *self.0.lock() {
+                true => Ok(Count(3)),
+                false => Err(anyhow!("upstream gone")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_see_writes_and_refreshes() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let reader = tmp.store().clone();
+        let log = seen.clone();
+        tmp.on_write(move |version| {
+            log.lock()
+                .push(format!("write {} {}", version, reader.read().0))
+        });
+        let log = seen.clone();
+        tmp.on_refresh_success(move |changed| log.lock().push(format!("refreshed {}", changed)));
+        let log = seen.clone();
+        tmp.on_refresh_failure(move |e| log.lock().push(format!("failed: {}", e)));
+
+        tmp.write(Count(1)).unwrap();
+        tmp.update(|c| c.0 += 1).unwrap();
+        let upstream = Upstream(Mutex::new(true));
+        tmp.refresh(&upstream).await.unwrap();
+        *upstream.0.lock() = false;
+        assert!(tmp.refresh(&upstream).await.is_err());
+
+        assert_eq!(
+            *seen.lock(),
+            vec![
+                "write 1 1",
+                "write 2 2",
+                "write 3 3",
+                "refreshed true",
+                "failed: upstream gone",
+            ]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use super::Store;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+type Extractor<I, K> = Box<dyn Fn(&I) -> K + Send + Sync>;
+
+/// Lookup maps over the items of a Store holding a collection, so reads
+/// find items by key instead of scanning. Each index is named and built
+/// from an extractor giving an item's key; all of them are rebuilt together
+/// on the first lookup after the store is written or refreshed.
+///
+/// Keys share one type per `Indexed`; use an enum or `String` to index by
+/// differently typed fields.
+///
+/// ```ignore
+/// let users = Indexed::new(&store)
+///     .with_index("id", |u: &User| u.id.to_string())
+///     .with_index("team", |u: &User| u.team.clone());
+/// let alice = users.get("id", &"42".to_string()).first().cloned();
+/// let admins = users.get("team", &"admin".to_string()).len();
+/// ```
+pub struct Indexed<T, I, K> {
+    store: Store<T>,
+    extractors: BTreeMap<String, Extractor<I, K>>,
+    built: Mutex<Option<Arc<Built<T, K>>>>,
+}
+
+/// The indexes built over one version of the data.
+struct Built<T, K> {
+    generation: u64,
+    data: Arc<T>,
+    maps: BTreeMap<String, HashMap<K, Vec<usize>>>,
+}
+
+impl<T, I, K> Indexed<T, I, K>
+where
+    T: AsRef<[I]>,
+    K: Eq + Hash,
+{
+    pub fn new(store: &Store<T>) -> Self {
+        Self {
+            store: store.clone(),
+            extractors: BTreeMap::new(),
+            built: Mutex::new(None),
+        }
+    }
+
+    /// Index items under `name` by the key `extract` gives them. Several
+    /// items may share a key.
+    pub fn with_index(
+        mut self,
+        name: impl Into<String>,
+        extract: impl Fn(&I) -> K + Send + Sync + 'static,
+    ) -> Self {
+        self.extractors.insert(name.into(), Box::new(extract));
+        *self.built.get_mut() = None;
+        self
+    }
+
+    pub fn store(&self) -> &Store<T> {
+        &self.store
+    }
+
+    /// The items the index `name` holds under `key`, in collection order,
+    /// from the data as of this call. Empty if there are none, or no index
+    /// is named `name`.
+    pub fn get(&self, name: &str, key: &K) -> Matches<T, I> {
+        let built = self.current();
+        let positions = built
+            .maps
+            .get(name)
+            .and_then(|map| map.get(key))
+            .cloned()
+            .unwrap_or_default();
+        Matches {
+            data: built.data.clone(),
+            positions,
+            _item: PhantomData,
+        }
+    }
+
+    /// Whether the next lookup will rebuild the indexes.
+    pub fn is_stale(&self) -> bool {
+        match self.built.lock().as_ref() {
+            Some(built) => built.generation != self.store.generation(),
+            None => true,
+        }
+    }
+
+    /// The indexes over the store's current data, rebuilt first if it
+    /// changed since they were last built.
+    fn current(&self) -> Arc<Built<T, K>> {
+        let (generation, data) = {
+            // The generation only moves with the data write-locked
+            let data = self.store.inner.data.read();
+            (self.store.generation(), data.clone())
+        };
+        let mut built = self.built.lock();
+        if let Some(current) = built.as_ref()
+            && current.generation == generation
+        {
+            return current.clone();
+        }
+        let maps = self
+            .extractors
+            .iter()
+            .map(|(name, extract)| {
+                let mut map: HashMap<K, Vec<usize>> = HashMap::new();
+                for (position, item) in (*data).as_ref().iter().enumerate() {
+                    map.entry(extract(item)).or_default().push(position);
+                }
+                (name.clone(), map)
+            })
+            .collect();
+        let current = Arc::new(Built {
+            generation,
+            data,
+            maps,
+        });
+        *built = Some(current.clone());
+        current
+    }
+}
+
+/// The items found by `Indexed::get`. Holds the version of the data they
+/// were found in, so they stay valid across later writes.
+pub struct Matches<T, I> {
+    data: Arc<T>,
+    positions: Vec<usize>,
+    _item: PhantomData<fn() -> I>,
+}
+
+impl<T: AsRef<[I]>, I> Matches<T, I> {
+    pub fn iter(&self) -> impl Iterator<Item = &I> {
+        let items = (*self.data).as_ref();
+        self.positions.iter().map(move |&p| &items[p])
+    }
+
+    pub fn first(&self) -> Option<&I> {
+        self.iter().next()
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempStore;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Users(Vec<(u8, char)>);
+
+    impl AsRef<[(u8, char)]> for Users {
+        fn as_ref(&self) -> &[(u8, char)] {
+            &self.0
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Users {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Users(
+                value.chunks(2).map(|c| (c[0], c[1] as char)).collect(),
+            ))
+        }
+    }
+
+    impl<'a> From<&'a Users> for Vec<u8> {
+        fn from(value: &'a Users) -> Self {
+            value
+                .0
+                .iter()
+                .flat_map(|&(id, team)| [id, team as u8])
+                .collect()
+        }
+    }
+
+    #[test]
+    fn lookups_follow_writes() {
+        let tmp: TempStore<Users> =
+            TempStore::from_value(Users(vec![(1, 'a'), (2, 'b'), (3, 'a')])).unwrap();
+        let users = Indexed::new(&tmp)
+            .with_index("id", |u: &(u8, char)| u.0 as u32)
+            .with_index("team", |u: &(u8, char)| u.1 as u32);
+
+        assert!(users.is_stale());
+        assert_eq!(users.get("id", &2).first(), Some(&(2, 'b')));
+        let team_a: Vec<_> = users.get("team", &('a' as u32)).iter().copied().collect();
+        assert_eq!(team_a, vec![(1, 'a'), (3, 'a')]);
+        assert!(users.get("id", &9).is_empty());
+        assert!(users.get("name", &1).is_empty());
+        assert!(!users.is_stale());
+
+        let before = users.get("id", &1);
+        tmp.write(Users(vec![(4, 'b')])).unwrap();
+        assert!(users.is_stale());
+        assert!(users.get("id", &1).is_empty());
+        assert_eq!(users.get("team", &('b' as u32)).len(), 1);
+        assert_eq!(before.first(), Some(&(1, 'a')));
+    }
+}
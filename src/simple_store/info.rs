@@ -0,0 +1,110 @@
+use super::{Inner, Store};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Where the data a store currently holds came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSource {
+    /// Loaded from the store's file or backend when it was opened.
+    Loaded,
+    /// Fetched or produced by the getter when the store was opened with
+    /// nothing stored.
+    Initial,
+    /// The default served until a background or retried initial fetch
+    /// lands; see `is_ready`.
+    Placeholder,
+    /// Fetched by a refresh or the initial fetch that replaced a placeholder.
+    Fetched,
+    /// Written directly through `write`, `update` and friends.
+    Written,
+    /// Reloaded from the file after another process changed it.
+    Reloaded,
+}
+
+/// Where a store's data came from and how its refreshes are going, from
+/// `Store::info`, for debug endpoints and dashboards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreInfo {
+    /// The store's file, or empty for a store with a custom backend.
+    pub path: PathBuf,
+    pub source: DataSource,
+    /// See `Store::version`.
+    pub version: u64,
+    /// When a refresh last succeeded.
+    pub last_refresh: Option<SystemTime>,
+    /// Why the most recent refresh failed, if none has succeeded since.
+    pub last_error: Option<String>,
+}
+
+impl<T> Inner<T> {
+    pub(super) fn set_source(&self, source: DataSource) {
+        *self.source.lock() = source;
+    }
+}
+
+impl<T> Store<T> {
+    pub fn info(&self) -> StoreInfo {
+        StoreInfo {
+            path: self.inner.loc.clone(),
+            source: *self.inner.source.lock(),
+            version: self.version(),
+            last_refresh: self.metrics().last_refresh,
+            last_error: self.inner.last_error.lock().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Fetcher;
+    use crate::simple_store::testing::{Count, TempDir, TempStore};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Fetches 4 until the upstream goes away.
+    struct Upstream(Mutex<bool>);
+
+    #[async_trait]
+    impl Fetcher<Count> for Upstream {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            match *self.0.lock() {
+                true => Ok(Count(4)),
+                false => Err(anyhow!("upstream gone")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tracks_where_data_came_from() {
+        let dir = TempDir::new().unwrap();
+        let upstream = Arc::new(Upstream(Mutex::new(true)));
+        let fetched = Store::new_with_fetcher(dir.path().join("count"), upstream.clone())
+            .await
+            .unwrap();
+        assert_eq!(fetched.info().source, DataSource::Initial);
+
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let info = tmp.info();
+        assert_eq!(info.source, DataSource::Loaded);
+        assert_eq!(info.path, tmp.path());
+        assert_eq!(info.last_refresh, None);
+
+        tmp.write(Count(1)).unwrap();
+        assert_eq!(tmp.info().source, DataSource::Written);
+        tmp.refresh(&upstream).await.unwrap();
+        let info = tmp.info();
+        assert_eq!(info.source, DataSource::Fetched);
+        assert_eq!(info.version, 2);
+        assert!(info.last_refresh.is_some());
+
+        *upstream.0.lock() = false;
+        assert!(tmp.refresh(&upstream).await.is_err());
+        assert_eq!(tmp.info().last_error.as_deref(), Some("upstream gone"));
+        *upstream.0.lock() = true;
+        tmp.refresh(&upstream).await.unwrap();
+        assert_eq!(tmp.info().last_error, None);
+    }
+}
@@ -0,0 +1,296 @@
+//! `KvStoreMap`, a keyed map of stores kept in one redb database. Behind the
+//! `redb` feature.
+
+use super::{StorageBackend, Store, codec};
+use anyhow::{Context, anyhow};
+use parking_lot::RwLock;
+use redb::{Database, ReadableTable, TableDefinition};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const ENTRIES: TableDefinition<&str, &[u8]> = TableDefinition::new("entries");
+
+/// `StoreMap` with every entry in a single redb database file rather than a
+/// file per key, for maps of more than a few thousand keys. Adds what the
+/// directory layout can't do cheaply: scans over a range of keys, batches of
+/// inserts committed together, and compaction of the database file.
+///
+/// Keys round-trip through `Display`/`FromStr` as with `StoreMap`, and are
+/// ordered by that string form. Cheap to clone.
+///
+/// ```ignore
+/// let sessions: KvStoreMap<UserId, Session> = KvStoreMap::open(dir.join("sessions.redb"))?;
+/// sessions.insert_all(fresh_sessions)?;
+/// let recent = sessions.range(first_id..)?;
+/// ```
+pub struct KvStoreMap<K, V> {
+    db: Arc<RwLock<Database>>,
+    entries: Arc<RwLock<HashMap<K, Store<V>>>>,
+}
+
+impl<K, V> Clone for KvStoreMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+/// One key of a `KvStoreMap`'s database, as a store's backend.
+struct Entry {
+    db: Arc<RwLock<Database>>,
+    key: String,
+}
+
+impl StorageBackend for Entry {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let txn = self.db.read().begin_read()?;
+        let table = txn.open_table(ENTRIES)?;
+        Ok(table.get(self.key.as_str())?.map(|v| v.value().to_vec()))
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        let txn = self.db.read().begin_write()?;
+        txn.open_table(ENTRIES)?.insert(self.key.as_str(), bytes)?;
+        txn.commit()
+            .with_context(|| format!("Failed to persist entry {}", self.key))
+    }
+}
+
+impl<K, V> KvStoreMap<K, V>
+where
+    K: Display + FromStr + Hash + Eq + Clone,
+    V: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a V>,
+{
+    /// Loads every entry of the database at `path`, creating it if needed.
+    /// Entries whose key or value fail to decode are skipped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let db =
+            Database::create(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let txn = db.begin_write()?;
+        txn.open_table(ENTRIES)?;
+        txn.commit()?;
+        let db = Arc::new(RwLock::new(db));
+
+        let names = {
+            let txn = db.read().begin_read()?;
+            let table = txn.open_table(ENTRIES)?;
+            table
+                .iter()?
+                .map(|row| Ok(row?.0.value().to_string()))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?
+        };
+        let mut entries = HashMap::new();
+        for name in names {
+            let Ok(key) = name.parse::<K>() else {
+                continue;
+            };
+            let missing = || Err(anyhow!("entry failed to load"));
+            if let Ok(store) = Store::with_backend(entry(&db, name), missing) {
+                entries.insert(key, store);
+            }
+        }
+        Ok(Self {
+            db,
+            entries: Arc::new(RwLock::new(entries)),
+        })
+    }
+
+    /// The store for `key`, if present.
+    pub fn get(&self, key: &K) -> Option<Store<V>> {
+        self.entries.read().get(key).cloned()
+    }
+
+    /// Writes `value` under `key`, creating the entry if needed, and returns
+    /// its store.
+    pub fn insert(&self, key: K, value: V) -> Result<Store<V>, anyhow::Error> {
+        if let Some(store) = self.get(&key) {
+            store.write(value)?;
+            return Ok(store);
+        }
+        let mut entries = self.entries.write();
+        if let Some(store) = entries.get(&key) {
+            // Inserted while we waited for the lock
+            store.write(value)?;
+            return Ok(store.clone());
+        }
+        let mut value = Some(value);
+        let backend = entry(&self.db, key.to_string());
+        let store = Store::with_backend(backend, || Ok(value.take().expect("called once")))?;
+        if let Some(value) = value {
+            store.write(value)?;
+        }
+        entries.insert(key, store.clone());
+        Ok(store)
+    }
+
+    /// Writes every pair in one database transaction, so either all of
+    /// them are stored or, on error, none are.
+    pub fn insert_all(&self, items: impl IntoIterator<Item = (K, V)>) -> Result<(), anyhow::Error> {
+        let mut entries = self.entries.write();
+        let items: Vec<(K, V, Vec<u8>)> = items
+            .into_iter()
+            .map(|(key, value)| {
+                let bytes = Vec::from(&value);
+                (key, value, bytes)
+            })
+            .collect();
+        {
+            let db = self.db.read();
+            let txn = db.begin_write()?;
+            let mut table = txn.open_table(ENTRIES)?;
+            for (key, _, bytes) in &items {
+                table.insert(key.to_string().as_str(), bytes.as_slice())?;
+            }
+            drop(table);
+            txn.commit().context("Failed to persist entries")?;
+        }
+        for (key, value, bytes) in items {
+            match entries.get(&key) {
+                Some(store) => {
+                    let writer = store.inner.writer.lock();
+                    store.record_written(bytes.len());
+                    let generation = store.swap(Arc::new(value));
+                    store.written(generation, bytes);
+                    drop(writer);
+                    store.run_write_hooks(generation);
+                }
+                None => {
+                    let backend = Arc::new(entry(&self.db, key.to_string()));
+                    let store = Store::with_access(
+                        value,
+                        PathBuf::new(),
+                        codec::raw(),
+                        false,
+                        Some(backend),
+                    );
+                    entries.insert(key, store);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the entry. Returns whether the key was present.
+    ///
+    /// As with `StoreMap::remove`, existing handles to its store are not
+    /// invalidated: they still read the last value, and a write through
+    /// one, including a scheduled refresh, puts the entry back in the
+    /// database. It then isn't in this map but comes back the next time the
+    /// database is opened, so drop or stop every handle to an entry before
+    /// removing it.
+    pub fn remove(&self, key: &K) -> Result<bool, anyhow::Error> {
+        let mut entries = self.entries.write();
+        if !entries.contains_key(key) {
+            return Ok(false);
+        }
+        let txn = self.db.read().begin_write()?;
+        txn.open_table(ENTRIES)?.remove(key.to_string().as_str())?;
+        txn.commit()?;
+        entries.remove(key);
+        Ok(true)
+    }
+
+    /// The entries whose keys, as strings, fall in `range`, in key order.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<Vec<(K, Store<V>)>, anyhow::Error> {
+        let bound = |b: Bound<&K>| b.map(|k| k.to_string());
+        let range = (bound(range.start_bound()), bound(range.end_bound()));
+        let names = {
+            let txn = self.db.read().begin_read()?;
+            let table = txn.open_table(ENTRIES)?;
+            let bounds = (
+                range.0.as_ref().map(String::as_str),
+                range.1.as_ref().map(String::as_str),
+            );
+            table
+                .range::<&str>(bounds)?
+                .map(|row| Ok(row?.0.value().to_string()))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?
+        };
+        let entries = self.entries.read();
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let key = name.parse::<K>().ok()?;
+                let store = entries.get(&key)?.clone();
+                Some((key, store))
+            })
+            .collect())
+    }
+
+    /// Returns the space freed by removed and rewritten entries to the file
+    /// system, waiting for in-flight reads and writes. Returns whether the
+    /// file shrank.
+    pub fn compact(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.db.write().compact()?)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.read().contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        self.entries.read().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn entry(db: &Arc<RwLock<Database>>, key: String) -> Entry {
+    Entry {
+        db: db.clone(),
+        key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[test]
+    fn scans_batches_and_compacts() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("map.redb");
+        let map: KvStoreMap<String, Text> = KvStoreMap::open(&path).unwrap();
+        map.insert("a".into(), Text("1".into())).unwrap();
+        let a = map.get(&"a".into()).unwrap();
+        map.insert_all((b'b'..=b'e').map(|c| ((c as char).to_string(), Text("2".into()))))
+            .unwrap();
+        map.insert_all([("a".to_string(), Text("3".into()))])
+            .unwrap();
+        assert_eq!(*a.read(), Text("3".into()));
+        assert!(map.remove(&"c".into()).unwrap());
+        assert!(!map.remove(&"c".into()).unwrap());
+
+        let keys: Vec<String> = map
+            .range("b".to_string()..="d".to_string())
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["b", "d"]);
+        map.compact().unwrap();
+        drop((map, a));
+
+        let reopened: KvStoreMap<String, Text> = KvStoreMap::open(&path).unwrap();
+        let mut keys = reopened.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b", "d", "e"]);
+        assert_eq!(*reopened.get(&"a".into()).unwrap().read(), Text("3".into()));
+    }
+}
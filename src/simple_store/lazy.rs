@@ -0,0 +1,161 @@
+use super::refresh::SharedFetcher;
+use super::{Fetcher, Recovery, Store, codec};
+use parking_lot::MappedRwLockReadGuard;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+type Getter<T> = Arc<dyn Fn() -> T + Send + Sync>;
+
+/// A `Store` that isn't opened until it's first used, so constructing one
+/// is free. Nothing is read from disk, and no fetch made, until `read`,
+/// `refresh` or `ensure_loaded`; concurrent first uses share one load.
+/// Cheap to clone.
+///
+/// ```ignore
+/// let rarely_used: LazyStore<Report> = LazyStore::new(dir.join("report"), ReportFetcher);
+/// // ... much later
+/// let total = rarely_used.read().await?.total;
+/// ```
+pub struct LazyStore<T> {
+    inner: Arc<LazyInner<T>>,
+}
+
+struct LazyInner<T> {
+    loc: PathBuf,
+    fetcher: Option<SharedFetcher<T>>,
+    getter: Option<Getter<T>>,
+    store: OnceCell<Store<T>>,
+}
+
+impl<T> Clone for LazyStore<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> LazyStore<T>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error> + Send + Sync + 'static,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// Loads from `loc` on first use, running `fetcher` if the file is
+    /// missing. `refresh` uses the same fetcher.
+    pub fn new<F>(loc: PathBuf, fetcher: F) -> Self
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        Self::from_parts(loc, Some(Arc::new(fetcher)), None)
+    }
+
+    /// Loads from `loc` on first use, starting from `T::default()` if the
+    /// file is missing.
+    pub fn with_default(loc: PathBuf) -> Self
+    where
+        T: Default,
+    {
+        Self::from_parts(loc, None, Some(Arc::new(T::default)))
+    }
+
+    fn from_parts(
+        loc: PathBuf,
+        fetcher: Option<SharedFetcher<T>>,
+        getter: Option<Getter<T>>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(LazyInner {
+                loc,
+                fetcher,
+                getter,
+                store: OnceCell::new(),
+            }),
+        }
+    }
+
+    /// Opens the store if that hasn't happened yet. A failed load is
+    /// retried on the next use.
+    pub async fn ensure_loaded(&self) -> Result<&Store<T>, anyhow::Error> {
+        self.inner
+            .store
+            .get_or_try_init(|| async {
+                let getter = self.inner.getter.clone();
+                let fetcher = self.inner.fetcher.clone();
+                let initial = async move {
+                    match (getter, fetcher) {
+                        (Some(getter), _) => Ok(getter()),
+                        (None, Some(fetcher)) => fetcher.fetch(None).await,
+                        (None, None) => unreachable!("built with a fetcher or a getter"),
+                    }
+                };
+                let loc = self.inner.loc.clone();
+                let store =
+                    Store::load_async(loc, codec::raw(), Recovery::default(), initial).await?;
+                Ok(match self.inner.fetcher.clone() {
+                    Some(fetcher) => store.with_fetcher(fetcher),
+                    None => store,
+                })
+            })
+            .await
+    }
+
+    /// `Store::read`, loading first if needed.
+    pub async fn read(&self) -> Result<MappedRwLockReadGuard<'_, T>, anyhow::Error> {
+        Ok(self.ensure_loaded().await?.read())
+    }
+
+    /// `Store::refresh_now`, loading first if needed. Fails for stores
+    /// made `with_default`, which have no fetcher.
+    pub async fn refresh(&self) -> Result<bool, anyhow::Error> {
+        self.ensure_loaded().await?.refresh_now().await
+    }
+
+    /// The store, if it has been loaded.
+    pub fn get(&self) -> Option<&Store<T>> {
+        self.inner.store.get()
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.inner.store.initialized()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempDir};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counted(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Fetcher<Count> for Counted {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Count(calls as u8))
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_once_on_first_use() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lazy");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lazy = LazyStore::new(path.clone(), Counted(calls.clone()));
+        assert!(!lazy.is_loaded());
+        assert!(!path.exists());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let other = lazy.clone();
+        let (a, b) = tokio::join!(lazy.read(), other.ensure_loaded());
+        assert_eq!(*a.unwrap(), Count(1));
+        assert_eq!(*b.unwrap().read(), Count(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(path.exists());
+
+        assert!(lazy.refresh().await.unwrap());
+        assert_eq!(*lazy.get().unwrap().read(), Count(2));
+    }
+}
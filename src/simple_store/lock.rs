@@ -0,0 +1,193 @@
+use super::Store;
+use anyhow::Context;
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// What to do when another process holds a store's file lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Block until the lock is released.
+    Wait,
+    /// Fail straight away with `StoreLocked`.
+    Fail,
+    /// Wait up to the given time, then fail with `StoreLocked`.
+    WaitFor(Duration),
+}
+
+/// Returned when a store's file lock is held elsewhere and the `LockPolicy`
+/// gave up waiting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreLocked {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for StoreLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Store {} is locked by another process",
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for StoreLocked {}
+
+/// `<file>.lock`. The store file itself is replaced on every write, so the
+/// lock lives on a sibling that stays put.
+fn lock_path(loc: &Path) -> PathBuf {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    loc.with_file_name(format!("{}.lock", base))
+}
+
+/// Takes the exclusive advisory lock for the store at `loc`, held until the
+/// returned file is dropped.
+pub(super) fn acquire(loc: &Path, policy: LockPolicy) -> Result<File, anyhow::Error> {
+    let path = lock_path(loc);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+    let locked = || StoreLocked {
+        path: loc.to_path_buf(),
+    };
+    let wait = match policy {
+        LockPolicy::Wait => {
+            file.lock()
+                .with_context(|| format!("Failed to lock {}", path.display()))?;
+            return Ok(file);
+        }
+        LockPolicy::Fail => Duration::ZERO,
+        LockPolicy::WaitFor(wait) => wait,
+    };
+    let deadline = Instant::now() + wait;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(10))
+            }
+            Err(TryLockError::WouldBlock) => return Err(locked().into()),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+    }
+}
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> Store<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_or_get` for a file shared with other processes. The load, and
+    /// the initial write when there is nothing to load, happen under an
+    /// advisory lock on `<file>.lock`, as does every later write. Processes
+    /// that don't lock aren't kept out.
+    pub fn new_or_get_locked<F>(
+        loc: PathBuf,
+        policy: LockPolicy,
+        getter: F,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        F: FnOnce() -> Result<T, anyhow::Error>,
+    {
+        let lock = acquire(&loc, policy)?;
+        let store = Store::new_or_get(loc, getter)?;
+        drop(lock);
+        Ok(store.with_file_lock(policy))
+    }
+}
+
+impl<T> Store<T> {
+    /// Takes the advisory lock on `<file>.lock` around every later write.
+    /// Has no effect on stores with a custom backend.
+    pub fn with_file_lock(self, policy: LockPolicy) -> Self {
+        *self.inner.lock.lock() = Some(policy);
+        self
+    }
+
+    pub(super) fn lock_policy(&self) -> Option<LockPolicy> {
+        *self.inner.lock.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[test]
+    fn writes_respect_a_held_lock() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shared");
+        let store =
+            Store::new_or_get_locked(path.clone(), LockPolicy::Fail, || Ok(Text("a".into())))
+                .unwrap();
+
+        // Another process's lock; a separate open file description conflicts
+        // even within one process
+        let held = acquire(&path, LockPolicy::Wait).unwrap();
+        let err = store.write(Text("b".into())).unwrap_err();
+        assert!(err.downcast_ref::<StoreLocked>().is_some());
+        assert_eq!(store.read().0, "a");
+
+        let waiting = Store::new_or_get_locked(
+            path.clone(),
+            LockPolicy::WaitFor(Duration::from_millis(30)),
+            || Ok(Text("c".into())),
+        );
+        assert!(waiting.is_err());
+
+        drop(held);
+        store.write(Text("b".into())).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"b");
+    }
+    #[test]
+    fn waiting_writers_go_once_the_lock_is_released() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shared");
+        let policy = LockPolicy::WaitFor(Duration::from_secs(5));
+        let store =
+            Store::new_or_get_locked(path.clone(), policy, || Ok(Text("a".into()))).unwrap();
+
+        let held = acquire(&path, LockPolicy::Wait).unwrap();
+        let releasing = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(held);
+        });
+        store.write(Text("b".into())).unwrap();
+        releasing.join().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"b");
+    }
+
+    #[test]
+    fn separately_opened_writers_never_tear_the_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shared");
+        // A store each, as separate processes sharing the file would have
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let path = path.clone();
+                s.spawn(move || {
+                    let store =
+                        Store::new_or_get_locked(path, LockPolicy::Wait, || Ok(Text::default()))
+                            .unwrap();
+                    for n in 0..25 {
+                        store
+                            .write(Text(format!("{}-{}", i, n).repeat(100)))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        // The last write overall is one writer's last
+        let on_disk = String::from_utf8(std::fs::read(&path).unwrap()).unwrap();
+        assert!((0..4).any(|i| on_disk == format!("{}-24", i).repeat(100)));
+        // Only the store and its lock file are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
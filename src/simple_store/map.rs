@@ -0,0 +1,204 @@
+use super::Store;
+use anyhow::{Context, anyhow};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A directory of Stores, one file per key, for data that is naturally a
+/// dictionary rather than one blob. Each entry is a full `Store<V>`, so the
+/// usual refresh, subscribe and write APIs work per key.
+///
+/// Keys round-trip through `Display`/`FromStr` and are hex-encoded into file
+/// names, two characters per byte plus an `.entry` suffix, so any key is
+/// safe to use as long as that fits the file system's name limit: 255 bytes
+/// on most, or keys of up to 124 bytes. The temp, backup and quarantine
+/// files written beside an entry add up to about 30 more characters, so
+/// keep keys under about 110 bytes to be safe. Cheap to clone.
+///
+/// Handles returned by `get` and `insert` outlive `remove`: see there.
+///
+/// ```ignore
+/// let sessions: StoreMap<UserId, Session> = StoreMap::open(dir)?;
+/// sessions.insert(id, session)?;
+/// sessions.get(&id).unwrap().scheduled_updates(SessionFetcher(id), Duration::from_secs(60));
+/// ```
+pub struct StoreMap<K, V> {
+    dir: PathBuf,
+    entries: Arc<RwLock<HashMap<K, Store<V>>>>,
+}
+
+impl<K, V> Clone for StoreMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            dir: self.dir.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+const SUFFIX: &str = ".entry";
+
+fn encode_key(key: &str) -> String {
+    let mut name: String = key.bytes().map(|b| format!("{:02x}", b)).collect();
+    name.push_str(SUFFIX);
+    name
+}
+
+fn decode_key(name: &str) -> Option<String> {
+    let hex = name.strip_suffix(SUFFIX)?;
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+impl<K, V> StoreMap<K, V>
+where
+    K: Display + FromStr + Hash + Eq + Clone,
+    V: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a V>,
+{
+    /// Loads every entry in `dir`, creating it if needed. Files that aren't
+    /// entries are ignored; entries that fail to load are quarantined like
+    /// any other store and skipped.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut entries = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(key) = name
+                .to_str()
+                .and_then(decode_key)
+                .and_then(|k| k.parse::<K>().ok())
+            else {
+                continue;
+            };
+            let missing = || Err(anyhow!("entry failed to load"));
+            if let Ok(store) = Store::new_or_get(entry.path(), missing) {
+                entries.insert(key, store);
+            }
+        }
+        Ok(Self {
+            dir,
+            entries: Arc::new(RwLock::new(entries)),
+        })
+    }
+
+    /// The store for `key`, if present.
+    pub fn get(&self, key: &K) -> Option<Store<V>> {
+        self.entries.read().get(key).cloned()
+    }
+
+    /// Writes `value` under `key`, creating the entry if needed, and returns
+    /// its store.
+    pub fn insert(&self, key: K, value: V) -> Result<Store<V>, anyhow::Error> {
+        if let Some(store) = self.get(&key) {
+            store.write(value)?;
+            return Ok(store);
+        }
+        let mut entries = self.entries.write();
+        if let Some(store) = entries.get(&key) {
+            // Inserted while we waited for the lock
+            store.write(value)?;
+            return Ok(store.clone());
+        }
+        let path = self.dir.join(encode_key(&key.to_string()));
+        let mut value = Some(value);
+        let store = Store::new_or_get(path, || Ok(value.take().expect("called once")))?;
+        if let Some(value) = value {
+            // Another process left a file behind; ours wins
+            store.write(value)?;
+        }
+        entries.insert(key, store.clone());
+        Ok(store)
+    }
+
+    /// Deletes the entry's file. Returns whether the key was present.
+    ///
+    /// Existing handles to its store are not invalidated: they still read
+    /// the last value, and a write through one, including a scheduled
+    /// refresh, writes the file again. The entry then isn't in this map but
+    /// comes back the next time the directory is opened, so drop or stop
+    /// every handle to an entry before removing it.
+    pub fn remove(&self, key: &K) -> Result<bool, anyhow::Error> {
+        let mut entries = self.entries.write();
+        let Some(store) = entries.remove(key) else {
+            return Ok(false);
+        };
+        match std::fs::remove_file(&store.inner.loc) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(e) => {
+                entries.insert(key.clone(), store);
+                Err(e.into())
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.read().contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        self.entries.read().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[test]
+    fn persists_each_key_separately() {
+        let dir = TempDir::new().unwrap();
+        let map: StoreMap<String, Text> = StoreMap::open(dir.path()).unwrap();
+        map.insert("../a".into(), Text("1".into())).unwrap();
+        map.insert("b".into(), Text("2".into())).unwrap();
+        map.insert("b".into(), Text("3".into())).unwrap();
+        assert!(map.remove(&"b".into()).unwrap());
+        map.insert("c".into(), Text("4".into())).unwrap();
+        std::fs::write(dir.path().join("unrelated"), "x").unwrap();
+
+        let reopened: StoreMap<String, Text> = StoreMap::open(dir.path()).unwrap();
+        let mut keys = reopened.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["../a", "c"]);
+        assert_eq!(
+            *reopened.get(&"../a".into()).unwrap().read(),
+            Text("1".into())
+        );
+        assert!(!reopened.contains_key(&"b".into()));
+    }
+    #[test]
+    fn stale_handles_write_removed_entries_back() {
+        let dir = TempDir::new().unwrap();
+        let map: StoreMap<String, Text> = StoreMap::open(dir.path()).unwrap();
+        let stale = map.insert("a".into(), Text("1".into())).unwrap();
+        assert!(map.remove(&"a".into()).unwrap());
+        stale.write(Text("2".into())).unwrap();
+        assert!(!map.contains_key(&"a".into()));
+
+        let reopened: StoreMap<String, Text> = StoreMap::open(dir.path()).unwrap();
+        assert_eq!(*reopened.get(&"a".into()).unwrap().read(), Text("2".into()));
+    }
+}
@@ -0,0 +1,199 @@
+use super::{DataSource, Durability, Store, persist};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// What the upstream said about the data last fetched, kept so fetchers can
+/// make conditional requests (`If-None-Match`, `If-Modified-Since`) and
+/// answer `FetchResult::Unchanged` instead of downloading everything again.
+///
+/// File stores keep it in a `<file>.meta` sidecar; stores with a custom
+/// backend keep it in memory only.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchMeta {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// When a fetch last returned data or confirmed it unchanged.
+    pub fetched_at: Option<SystemTime>,
+}
+
+impl FetchMeta {
+    pub fn with_etag(mut self, etag: impl Into<String>) -> Self {
+        self.etag = Some(etag.into());
+        self
+    }
+
+    pub fn with_last_modified(mut self, last_modified: impl Into<String>) -> Self {
+        self.last_modified = Some(last_modified.into());
+        self
+    }
+
+    /// One `key=value` per line. Header values can't contain newlines, so
+    /// there is nothing to escape.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = String::new();
+        if let Some(etag) = &self.etag {
+            out.push_str(&format!("etag={}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            out.push_str(&format!("last_modified={}\n", last_modified));
+        }
+        if let Some(millis) = self
+            .fetched_at
+            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+        {
+            out.push_str(&format!("fetched_at={}\n", millis.as_millis()));
+        }
+        out.into_bytes()
+    }
+
+    /// Unknown keys and malformed lines are skipped.
+    fn decode(bytes: &[u8]) -> Self {
+        let mut meta = Self::default();
+        for line in String::from_utf8_lossy(bytes).lines() {
+            match line.split_once('=') {
+                Some(("etag", v)) => meta.etag = Some(v.to_string()),
+                Some(("last_modified", v)) => meta.last_modified = Some(v.to_string()),
+                Some(("fetched_at", v)) => {
+                    meta.fetched_at = v
+                        .parse()
+                        .ok()
+                        .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+                }
+                _ => {}
+            }
+        }
+        meta
+    }
+}
+
+/// `<file>.meta`.
+fn meta_path(loc: &Path) -> PathBuf {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    loc.with_file_name(format!("{}.meta", base))
+}
+
+impl<T> Store<T> {
+    /// Validators from the last fetch, for the fetcher's next request.
+    pub fn fetch_meta(&self) -> FetchMeta {
+        let mut meta = self.inner.meta.lock();
+        if meta.is_none() && self.inner.backend.is_none() {
+            *meta = std::fs::read(meta_path(&self.inner.loc))
+                .ok()
+                .map(|bytes| FetchMeta::decode(&bytes));
+        }
+        meta.get_or_insert_with(FetchMeta::default).clone()
+    }
+
+    /// Records `meta` as of a fetch that just happened. Losing it only costs
+    /// a full download next time, so failing to persist it is logged rather
+    /// than failing the refresh.
+    fn save_meta(&self, meta: FetchMeta) {
+        let meta = FetchMeta {
+            fetched_at: Some(SystemTime::now()),
+            ..meta
+        };
+        if self.inner.backend.is_none() {
+            let path = meta_path(&self.inner.loc);
+            if let Err(e) = persist(&path, &meta.encode(), Durability::NONE) {
+                warn!("Failed to save fetch metadata {}: {:#}", path.display(), e);
+            }
+        }
+        *self.inner.meta.lock() = Some(meta);
+    }
+
+    /// Notes that the upstream confirmed the data is current.
+    pub(super) fn confirm_unchanged(&self) {
+        self.inner.mark_fresh();
+        self.save_meta(self.fetch_meta());
+    }
+}
+
+impl<T> Store<T> {
+    /// Writes fetched data along with the validators that describe it,
+    /// returning whether the data changed. Data fetched without any
+    /// validators describes nothing the old ones did, so they are cleared.
+    pub(super) fn write_fetched(
+        &self,
+        mut new_data: T,
+        meta: Option<FetchMeta>,
+    ) -> Result<bool, anyhow::Error> {
+        self.check_writable()?;
+        let serialized = self.serialize_within_limit(&mut new_data)?;
+        let writer = self.inner.writer.lock();
+        let generation = self.replace_locked(new_data, serialized)?;
+        self.inner.set_source(DataSource::Fetched);
+        self.save_meta(meta.unwrap_or_default());
+        drop(writer);
+        if let Some(generation) = generation {
+            self.run_write_hooks(generation);
+        }
+        Ok(generation.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempStore;
+    use crate::simple_store::{FetchResult, Fetcher};
+    use async_trait::async_trait;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Body(String);
+
+    impl TryFrom<Vec<u8>> for Body {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Body(String::from_utf8(value)?))
+        }
+    }
+
+    impl<'a> From<&'a Body> for Vec<u8> {
+        fn from(value: &'a Body) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    /// Serves "v1" under ETag "1", answering 304 to a matching request.
+    struct Http;
+
+    #[async_trait]
+    impl Fetcher<Body> for Http {
+        async fn fetch(&self, _: Option<Store<Body>>) -> Result<Body, anyhow::Error> {
+            Ok(Body("v1".into()))
+        }
+
+        async fn fetch_update(
+            &self,
+            store: Store<Body>,
+        ) -> Result<FetchResult<Body>, anyhow::Error> {
+            if store.fetch_meta().etag.as_deref() == Some("1") {
+                return Ok(FetchResult::Unchanged);
+            }
+            Ok(FetchResult::Tagged(
+                Body("v1".into()),
+                FetchMeta::default().with_etag("1"),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn conditional_fetches_use_persisted_validators() {
+        let tmp: TempStore<Body> = TempStore::new().unwrap();
+        assert_eq!(tmp.fetch_meta(), FetchMeta::default());
+        assert!(tmp.refresh(&Http).await.unwrap());
+        let version = tmp.version();
+
+        let reopened = tmp.reopen().unwrap();
+        let meta = reopened.fetch_meta();
+        assert_eq!(meta.etag.as_deref(), Some("1"));
+        assert!(meta.fetched_at.is_some());
+        assert!(!reopened.refresh(&Http).await.unwrap());
+        assert_eq!(tmp.version(), version);
+
+        tmp.write_fetched(Body("v2".into()), None).unwrap();
+        assert_eq!(tmp.fetch_meta().etag, None);
+    }
+}
@@ -0,0 +1,160 @@
+use super::{DataSource, Store};
+#[cfg(any(test, feature = "metrics"))]
+use crate::statsd::Statsd;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A point-in-time view of how a store has been doing, from `Store::metrics`.
+/// Refreshes are fetches from `refresh`, `refresh_now` and the scheduled,
+/// adaptive and prefetching updaters; plain writes don't count as one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreMetrics {
+    /// When a refresh last succeeded.
+    pub last_refresh: Option<SystemTime>,
+    /// Refreshes failed since the last success.
+    pub consecutive_failures: u32,
+    /// Time taken to serialize the most recent write.
+    pub last_serialize: Option<Duration>,
+    /// Time taken to deserialize the data the store was loaded from, if it
+    /// was loaded rather than fetched.
+    pub last_deserialize: Option<Duration>,
+    /// Total bytes persisted by this store since it was opened.
+    pub bytes_written: u64,
+}
+
+impl StoreMetrics {
+    /// How long ago the last successful refresh was, if there was one.
+    pub fn staleness(&self) -> Option<Duration> {
+        self.last_refresh.map(|at| at.elapsed().unwrap_or_default())
+    }
+
+    /// Emits these metrics through `statsd`, with every name prefixed by
+    /// `store.` and tagged with the store's `name`.
+    #[cfg(any(test, feature = "metrics"))]
+    pub fn report(&self, statsd: &Statsd, name: &str) {
+        let tags = [("store", name)];
+        statsd.gauge(
+            "store.consecutive_failures",
+            self.consecutive_failures as f64,
+            &tags,
+        );
+        statsd.gauge("store.bytes_written", self.bytes_written as f64, &tags);
+        if let Some(staleness) = self.staleness() {
+            statsd.timing("store.staleness", staleness, &tags);
+        }
+        if let Some(elapsed) = self.last_serialize {
+            statsd.timing("store.serialize", elapsed, &tags);
+        }
+        if let Some(elapsed) = self.last_deserialize {
+            statsd.timing("store.deserialize", elapsed, &tags);
+        }
+    }
+}
+
+impl<T> Store<T> {
+    pub fn metrics(&self) -> StoreMetrics {
+        self.inner.metrics.lock().clone()
+    }
+
+    pub(super) fn record_refresh<R>(&self, result: &Result<R, anyhow::Error>) {
+        let mut metrics = self.inner.metrics.lock();
+        match result {
+            Ok(_) => {
+                metrics.last_refresh = Some(SystemTime::now());
+                metrics.consecutive_failures = 0;
+                *self.inner.last_error.lock() = None;
+            }
+            Err(e) => {
+                metrics.consecutive_failures += 1;
+                *self.inner.last_error.lock() = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// Records how long loading took, or `None` when there was nothing to
+    /// load and the data was produced instead.
+    pub(super) fn record_deserialize(&self, elapsed: Option<Duration>) {
+        self.inner.metrics.lock().last_deserialize = elapsed;
+        if elapsed.is_some() {
+            self.inner.set_source(DataSource::Loaded);
+        }
+    }
+
+    pub(super) fn record_written(&self, bytes: usize) {
+        self.inner.metrics.lock().bytes_written += bytes as u64;
+    }
+}
+
+impl<T> Store<T> {
+    /// Serializes `data`, recording how long it took.
+    pub(super) fn serialize(&self, data: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let started = Instant::now();
+        let serialized = self.encode(data)?;
+        self.inner.metrics.lock().last_serialize = Some(started.elapsed());
+        Ok(serialized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Fetcher;
+    use crate::simple_store::testing::TempStore;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Word(String);
+
+    impl TryFrom<Vec<u8>> for Word {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Word(String::from_utf8(value)?))
+        }
+    }
+
+    impl<'a> From<&'a Word> for Vec<u8> {
+        fn from(value: &'a Word) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    struct Down;
+
+    #[async_trait]
+    impl Fetcher<Word> for Down {
+        async fn fetch(&self, _: Option<Store<Word>>) -> Result<Word, anyhow::Error> {
+            Err(anyhow!("upstream unavailable"))
+        }
+    }
+
+    struct Hello;
+
+    #[async_trait]
+    impl Fetcher<Word> for Hello {
+        async fn fetch(&self, _: Option<Store<Word>>) -> Result<Word, anyhow::Error> {
+            Ok(Word("hello".into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn tracks_refreshes_and_writes() {
+        let tmp: TempStore<Word> = TempStore::new().unwrap();
+        assert_eq!(tmp.metrics().last_refresh, None);
+
+        assert!(tmp.refresh(&Down).await.is_err());
+        assert!(tmp.refresh(&Down).await.is_err());
+        assert_eq!(tmp.metrics().consecutive_failures, 2);
+
+        tmp.refresh(&Hello).await.unwrap();
+        tmp.write(Word("hi".into())).unwrap();
+        let metrics = tmp.metrics();
+        assert_eq!(metrics.consecutive_failures, 0);
+        assert!(metrics.staleness().is_some());
+        assert!(metrics.last_serialize.is_some());
+        assert_eq!(metrics.bytes_written, 7);
+
+        let reopened = tmp.reopen().unwrap();
+        assert!(reopened.metrics().last_deserialize.is_some());
+    }
+}
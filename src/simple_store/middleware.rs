@@ -0,0 +1,233 @@
+use super::{Backoff, FetchResult, Fetcher, Store, TimeoutFetcher};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use anyhow::Context;
+use async_trait::async_trait;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{Instrument, debug, info_span, warn};
+
+/// Wraps any fetcher in the cross-cutting behaviour every fetcher would
+/// otherwise reimplement. Wrappers compose, outermost last:
+///
+/// ```ignore
+/// let fetcher = UsersFetcher::new(client)
+///     .with_timeout(Duration::from_secs(10))
+///     .with_retry(3, Backoff { initial: Duration::from_secs(1), max: Duration::from_secs(5) })
+///     .with_rate_limit(RateLimit::every(Duration::from_secs(30)))
+///     .with_tracing("users");
+/// store.scheduled_updates(fetcher, Duration::from_secs(60));
+/// ```
+pub trait FetcherExt<T>: Fetcher<T> + Sized {
+    /// Retries a failed fetch until `attempts` have been made in total,
+    /// waiting `backoff` between them, and returns the last error.
+    fn with_retry(self, attempts: u32, backoff: Backoff) -> RetryFetcher<Self> {
+        RetryFetcher {
+            inner: self,
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// See `TimeoutFetcher`.
+    fn with_timeout(self, limit: Duration) -> TimeoutFetcher<Self> {
+        TimeoutFetcher::new(self, limit)
+    }
+
+    /// Runs each fetch in a `fetch` span tagged with `name`, logging how
+    /// long it took and whether it failed.
+    fn with_tracing(self, name: impl Into<String>) -> TracedFetcher<Self> {
+        TracedFetcher {
+            inner: self,
+            name: name.into(),
+        }
+    }
+
+    /// Fails fetches over `limit` with `FetchThrottled` rather than calling
+    /// the upstream. Unlike `Store::with_refresh_limit`, the limit travels
+    /// with the fetcher, so every store it serves shares it.
+    fn with_rate_limit(self, limit: RateLimit) -> RateLimitedFetcher<Self> {
+        RateLimitedFetcher {
+            inner: self,
+            limiter: RateLimiter::new(limit),
+        }
+    }
+}
+
+impl<T, F: Fetcher<T>> FetcherExt<T> for F {}
+
+/// A fetcher retried with backoff; see `FetcherExt::with_retry`.
+#[derive(Clone)]
+pub struct RetryFetcher<F> {
+    inner: F,
+    attempts: u32,
+    backoff: Backoff,
+}
+
+impl<F> RetryFetcher<F> {
+    /// Runs `attempt` until it succeeds or the attempts run out.
+    async fn retry<R, Fut>(&self, mut attempt: impl FnMut() -> Fut) -> Result<R, anyhow::Error>
+    where
+        Fut: Future<Output = Result<R, anyhow::Error>>,
+    {
+        let mut failures = 0;
+        loop {
+            match attempt().await {
+                Ok(fetched) => return Ok(fetched),
+                Err(e) => {
+                    failures += 1;
+                    if failures >= self.attempts {
+                        return Err(e).with_context(|| format!("Fetch failed {} times", failures));
+                    }
+                    warn!("Fetch failed ({}/{}): {:#}", failures, self.attempts, e);
+                    sleep(self.backoff.delay(failures)).await;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for RetryFetcher<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        self.retry(|| self.inner.fetch(store.clone())).await
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        self.retry(|| self.inner.fetch_update(store.clone())).await
+    }
+}
+
+/// A fetcher that traces each fetch; see `FetcherExt::with_tracing`.
+#[derive(Clone)]
+pub struct TracedFetcher<F> {
+    inner: F,
+    name: String,
+}
+
+impl<F> TracedFetcher<F> {
+    async fn traced<R>(
+        &self,
+        fetch: impl Future<Output = Result<R, anyhow::Error>>,
+    ) -> Result<R, anyhow::Error> {
+        let span = info_span!("fetch", store = %self.name);
+        async {
+            let started = Instant::now();
+            let result = fetch.await;
+            match &result {
+                Ok(_) => debug!(elapsed = ?started.elapsed(), "Fetch succeeded"),
+                Err(e) => warn!(elapsed = ?started.elapsed(), "Fetch failed: {:#}", e),
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for TracedFetcher<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        self.traced(self.inner.fetch(store)).await
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        self.traced(self.inner.fetch_update(store)).await
+    }
+}
+
+/// A fetcher limited to a rate; see `FetcherExt::with_rate_limit`.
+pub struct RateLimitedFetcher<F> {
+    inner: F,
+    limiter: RateLimiter,
+}
+
+/// Returned by a `RateLimitedFetcher` that refused to fetch because its
+/// limit was used up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchThrottled;
+
+impl fmt::Display for FetchThrottled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fetch refused by its rate limit")
+    }
+}
+
+impl std::error::Error for FetchThrottled {}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for RateLimitedFetcher<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        if !self.limiter.check() {
+            return Err(FetchThrottled.into());
+        }
+        self.inner.fetch(store).await
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        if !self.limiter.check() {
+            return Err(FetchThrottled.into());
+        }
+        self.inner.fetch_update(store).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempStore};
+    use anyhow::anyhow;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails every other call, returning the call count otherwise.
+    struct Flaky(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl Fetcher<Count> for Flaky {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            let call = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            match call % 2 {
+                1 => Err(anyhow!("flaked")),
+                _ => Ok(Count(call as u8)),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wrappers_compose() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let calls = Arc::new(AtomicU32::new(0));
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(1),
+        };
+        let fetcher = Flaky(calls.clone())
+            .with_timeout(Duration::from_secs(5))
+            .with_retry(2, backoff)
+            .with_rate_limit(RateLimit::every(Duration::from_secs(60)))
+            .with_tracing("count");
+
+        assert!(tmp.refresh(&fetcher).await.unwrap());
+        assert_eq!(*tmp.read(), Count(2));
+        let err = tmp.refresh(&fetcher).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<FetchThrottled>(), Some(&FetchThrottled));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let once = Flaky(calls.clone()).with_retry(1, backoff);
+        assert!(tmp.refresh(&once).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
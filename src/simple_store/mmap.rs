@@ -0,0 +1,144 @@
+//! Constructors that map the store's file into memory and decode straight
+//! from it, rather than reading it into a `Vec` first, so opening a
+//! multi-GB store doesn't need a second copy of it on the heap. Behind the
+//! `mmap` feature.
+//!
+//! The type decodes from a borrowed slice, through `TryFrom<&[u8]>`:
+//!
+//! ```ignore
+//! impl TryFrom<&[u8]> for Catalog {
+//!     type Error = anyhow::Error;
+//!
+//!     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+//!         Ok(bincode::deserialize(bytes)?)
+//!     }
+//! }
+//!
+//! let store: Store<Catalog> = Store::new_with_default_mapped(loc)?;
+//! ```
+//!
+//! Only map files that nothing truncates in place while they're being
+//! decoded; see `map`.
+
+use super::{Codec, Durability, LoadFailure, Store, persist, recover};
+use anyhow::Context;
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+impl<T> Store<T>
+where
+    T: for<'a> TryFrom<&'a [u8], Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_with_default`, decoding the file in place.
+    pub fn new_with_default_mapped(loc: PathBuf) -> Result<Store<T>, anyhow::Error>
+    where
+        T: Default,
+    {
+        Store::new_or_get_mapped(loc, || Ok(T::default()))
+    }
+
+    /// `new_or_get`, decoding the file in place.
+    pub fn new_or_get_mapped<F>(loc: PathBuf, getter: F) -> Result<Store<T>, anyhow::Error>
+    where
+        F: FnOnce() -> Result<T, anyhow::Error>,
+    {
+        let (data, decoded_in) = match load_mapped(&loc, LoadFailure::default())? {
+            None => {
+                // Assume store missing, let's run an update
+                let new_data = getter()?;
+                let serialized: Vec<u8> = (&new_data).into();
+                persist(&loc, &serialized, Durability::NONE)?;
+                (new_data, None)
+            }
+            Some((v, elapsed)) => (v, Some(elapsed)),
+        };
+        let store = Store::from_parts(data, loc, Arc::new(Mapped));
+        store.record_deserialize(decoded_in);
+        Ok(store)
+    }
+}
+
+/// The conversions of a mapped store's type, for the files it reads after
+/// opening, e.g. backups.
+struct Mapped;
+
+impl<T> Codec<T> for Mapped
+where
+    T: for<'a> TryFrom<&'a [u8], Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    fn encode(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(value.into())
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<T, anyhow::Error> {
+        T::try_from(bytes.as_slice())
+    }
+}
+
+/// `load_or_recover` decoding from a mapping of the file. The mapping is
+/// released once decoded, so the store holds no reference to the file.
+fn load_mapped<T>(
+    loc: &Path,
+    on_failure: LoadFailure,
+) -> Result<Option<(T, Duration)>, anyhow::Error>
+where
+    T: for<'a> TryFrom<&'a [u8], Error = anyhow::Error>,
+{
+    let file = match File::open(loc) {
+        Err(_) => return Ok(None),
+        Ok(f) => f,
+    };
+    let mapped = map(&file).with_context(|| format!("Failed to map {}", loc.display()))?;
+    let started = std::time::Instant::now();
+    let decoded = T::try_from(&mapped[..]).map(|data| (data, started.elapsed()));
+    drop(mapped);
+    recover(loc, &on_failure.into(), decoded)
+}
+
+/// Maps the whole of `file` read-only.
+///
+/// Writes replace a store's file by renaming a new one over it, which leaves
+/// a mapping of the old one intact. Anything truncating the file in place
+/// while it is mapped, such as another program rewriting it with
+/// `O_TRUNC`, would make the reads past the new end fault with `SIGBUS` and
+/// kill the process. Only map files nothing else rewrites in place; the
+/// mapping is held only for the length of the decode.
+fn map(file: &File) -> Result<Mmap, anyhow::Error> {
+    // SAFETY: the mapping is read-only and private, and dropped before
+    // `load_mapped` returns. The store itself only ever renames over the
+    // file, never truncates it, which leaves this mapping valid; outside
+    // truncation is the documented hazard above.
+    Ok(unsafe { Mmap::map(file)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[test]
+    fn decodes_from_the_mapped_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("text");
+        let empty: Store<Text> = Store::new_with_default_mapped(path.clone()).unwrap();
+        assert_eq!(*empty.read(), Text::default());
+        let reopened: Store<Text> = Store::new_with_default_mapped(path.clone()).unwrap();
+        assert_eq!(*reopened.read(), Text::default());
+
+        std::fs::write(&path, "hello").unwrap();
+        let loaded: Store<Text> = Store::new_with_default_mapped(path.clone()).unwrap();
+        assert_eq!(*loaded.read(), Text("hello".into()));
+        assert!(loaded.metrics().last_deserialize.is_some());
+
+        // Fails to decode, so is quarantined and replaced by the default
+        std::fs::write(&path, [0xff, 0xfe]).unwrap();
+        let recovered: Store<Text> = Store::new_with_default_mapped(path.clone()).unwrap();
+        assert_eq!(*recovered.read(), Text::default());
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+    }
+}
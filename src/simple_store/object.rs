@@ -0,0 +1,249 @@
+//! A `StorageBackend` keeping a store as one object in S3, GCS, Azure or any
+//! other `object_store` implementation. Behind the `object-store` feature.
+
+use super::StorageBackend;
+use super::backend::blocking;
+use anyhow::Context;
+use object_store::path::Path;
+use object_store::{GetOptions, ObjectStore, PutMode, UpdateVersion};
+use parking_lot::Mutex;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tracing::warn;
+
+/// Returned (inside `anyhow::Error`) when the object was changed by another
+/// writer since this backend last loaded or wrote it. The backend then
+/// takes the other writer's version as the one it last saw, so the next
+/// write goes over it; reopen the store first to pick up its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectChanged {
+    pub path: String,
+}
+
+impl fmt::Display for ObjectChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Object {} was changed by another writer", self.path)
+    }
+}
+
+impl std::error::Error for ObjectChanged {}
+
+/// Keeps a store as the object at `path`, so stateless containers can share
+/// persisted state. Loads are conditional on the ETag last seen, so an
+/// unchanged object isn't downloaded again, and writes only succeed over
+/// the version last loaded or written, failing with `ObjectChanged`
+/// otherwise instead of silently overwriting another instance's write.
+///
+/// Requests run on a small runtime owned by the backend, as `Store` calls
+/// its backend synchronously. Called from a multi-threaded tokio runtime,
+/// the waiting worker hands its other tasks off first.
+///
+/// `from_url` picks the service from the URL's scheme. The `s3`, `gcs` and
+/// `azure` features enable the matching clients, configured from options
+/// or the usual environment variables.
+///
+/// ```ignore
+/// let backend = ObjectStoreBackend::from_url("s3://state/catalog.bin")?;
+/// let store = Store::builder().backend(backend).fetcher(CatalogFetcher).build().await?;
+/// ```
+pub struct ObjectStoreBackend {
+    objects: Arc<dyn ObjectStore>,
+    path: Path,
+    // `None` only while dropping, as a runtime can't be dropped from async
+    // code
+    runtime: Option<Runtime>,
+    // The version last loaded or written, with its bytes
+    last: Mutex<Option<(UpdateVersion, Vec<u8>)>>,
+}
+
+impl ObjectStoreBackend {
+    pub fn new(
+        objects: Arc<dyn ObjectStore>,
+        path: impl Into<Path>,
+    ) -> Result<Self, anyhow::Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("object-store-backend")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            objects,
+            path: path.into(),
+            runtime: Some(runtime),
+            last: Mutex::new(None),
+        })
+    }
+
+    /// The object at `url`, such as `s3://bucket/key`, `gs://bucket/key` or
+    /// `az://container/key`.
+    pub fn from_url(url: &str) -> Result<Self, anyhow::Error> {
+        Self::from_url_opts(url, std::iter::empty::<(&str, &str)>())
+    }
+
+    /// `from_url` with client options such as `aws_region`, which
+    /// otherwise come from the environment.
+    pub fn from_url_opts<K, V>(
+        url: &str,
+        options: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, anyhow::Error>
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let parsed = url::Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+        // As the clients' own `from_env` would read them; unknown keys are
+        // ignored
+        let options = std::env::vars()
+            .filter(|(key, _)| {
+                ["AWS_", "GOOGLE_", "AZURE_"]
+                    .iter()
+                    .any(|p| key.starts_with(p))
+            })
+            .map(|(key, value)| (key.to_ascii_lowercase(), value))
+            .chain(
+                options
+                    .into_iter()
+                    .map(|(key, value)| (key.as_ref().to_string(), value.into())),
+            );
+        let (objects, path) = object_store::parse_url_opts(&parsed, options)
+            .with_context(|| format!("Unsupported object store URL {}", url))?;
+        Self::new(objects.into(), path)
+    }
+
+    /// Runs `request` on the backend's runtime, blocking until it's done.
+    fn block_on<R: Send + 'static>(
+        &self,
+        request: impl Future<Output = R> + Send + 'static,
+    ) -> Result<R, anyhow::Error> {
+        let runtime = self.runtime.as_ref().expect("runtime lives until drop");
+        let request = runtime.spawn(request);
+        Ok(blocking(|| futures::executor::block_on(request))?)
+    }
+
+    /// Loads the object, unless it is unchanged since `last`, and records
+    /// its version in `last`.
+    fn fetch(
+        &self,
+        last: &mut Option<(UpdateVersion, Vec<u8>)>,
+    ) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let options = GetOptions {
+            if_none_match: last.as_ref().and_then(|(version, _)| version.e_tag.clone()),
+            ..Default::default()
+        };
+        let (objects, path) = (self.objects.clone(), self.path.clone());
+        let loaded = self.block_on(async move {
+            let result = objects.get_opts(&path, options).await?;
+            let version = UpdateVersion {
+                e_tag: result.meta.e_tag.clone(),
+                version: result.meta.version.clone(),
+            };
+            Ok((version, result.bytes().await?.to_vec()))
+        })?;
+        match loaded {
+            Ok((version, bytes)) => {
+                *last = Some((version, bytes.clone()));
+                Ok(Some(bytes))
+            }
+            Err(object_store::Error::NotModified { .. }) => {
+                Ok(last.as_ref().map(|(_, bytes)| bytes.clone()))
+            }
+            Err(object_store::Error::NotFound { .. }) => {
+                *last = None;
+                Ok(None)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to load {}", self.path)),
+        }
+    }
+}
+
+impl Drop for ObjectStoreBackend {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl StorageBackend for ObjectStoreBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        self.fetch(&mut self.last.lock())
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        let mut last = self.last.lock();
+        let mode = match &*last {
+            Some((version, _)) => PutMode::Update(version.clone()),
+            None => PutMode::Create,
+        };
+        let (objects, path) = (self.objects.clone(), self.path.clone());
+        let payload = bytes.to_vec();
+        let put = self
+            .block_on(async move { objects.put_opts(&path, payload.into(), mode.into()).await })?;
+        match put {
+            Ok(put) => {
+                let version = UpdateVersion {
+                    e_tag: put.e_tag,
+                    version: put.version,
+                };
+                *last = Some((version, bytes.to_vec()));
+                Ok(())
+            }
+            Err(object_store::Error::Precondition { .. })
+            | Err(object_store::Error::AlreadyExists { .. }) => {
+                // Catch up, or every later write would conflict too
+                if let Err(e) = self.fetch(&mut last) {
+                    warn!("Failed to reload {} after a conflict: {:#}", self.path, e);
+                }
+                Err(ObjectChanged {
+                    path: self.path.to_string(),
+                }
+                .into())
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to persist {}", self.path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Store;
+    use crate::simple_store::testing::Count;
+    use object_store::memory::InMemory;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn writes_only_over_the_version_it_saw() {
+        let objects: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let backend = || ObjectStoreBackend::new(objects.clone(), "count").unwrap();
+        let first: Store<Count> = Store::with_backend(backend(), || Ok(Count(1))).unwrap();
+        let second: Store<Count> =
+            Store::with_backend(backend(), || unreachable!("data is stored")).unwrap();
+        assert_eq!(*second.read(), Count(1));
+
+        first.write(Count(2)).unwrap();
+        let err = second.write(Count(3)).unwrap_err();
+        assert!(err.downcast_ref::<ObjectChanged>().is_some());
+        assert_eq!(*second.read(), Count(1));
+
+        let reopened = backend();
+        assert_eq!(reopened.load().unwrap(), Some(vec![2]));
+        // Unchanged since, so served from the cached copy
+        assert_eq!(reopened.load().unwrap(), Some(vec![2]));
+
+        // The conflict caught the backend up, so it can write again
+        second.write(Count(3)).unwrap();
+        assert_eq!(reopened.load().unwrap(), Some(vec![3]));
+        assert!(first.write(Count(4)).is_err());
+    }
+
+    #[test]
+    fn opens_from_a_url() {
+        let backend = ObjectStoreBackend::from_url("memory:///state/count").unwrap();
+        assert_eq!(backend.load().unwrap(), None);
+        backend.persist(&[1]).unwrap();
+        assert_eq!(backend.load().unwrap(), Some(vec![1]));
+        assert!(ObjectStoreBackend::from_url("nope://bucket/key").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! `StorePath`, platform locations for stores. Behind the `directories`
+//! feature.
+
+use anyhow::{Context, anyhow};
+use directories::ProjectDirs;
+use std::path::PathBuf;
+
+/// Where an app's stores live, following each platform's conventions as
+/// the `directories` crate knows them: the XDG base directories on Linux
+/// and other unixes, `~/Library` on macOS, and `%APPDATA%`/`%LOCALAPPDATA%`
+/// on Windows. Every path is scoped to the app and has its parent
+/// directories created, ready to hand to a `Store` constructor.
+///
+/// ```ignore
+/// let paths = StorePath::new("my-app");
+/// let store = Store::new_with_fetcher(paths.cache("users")?, UsersFetcher).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct StorePath {
+    app: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Data,
+    Cache,
+    Config,
+}
+
+impl StorePath {
+    pub fn new(app: impl Into<String>) -> Self {
+        Self { app: app.into() }
+    }
+
+    /// `file` among the app's data, for stores that can't be rebuilt.
+    pub fn data(&self, file: &str) -> Result<PathBuf, anyhow::Error> {
+        self.resolve(Kind::Data, file)
+    }
+
+    /// `file` among the app's caches, for stores a fetcher can repopulate.
+    pub fn cache(&self, file: &str) -> Result<PathBuf, anyhow::Error> {
+        self.resolve(Kind::Cache, file)
+    }
+
+    /// `file` among the app's configuration.
+    pub fn config(&self, file: &str) -> Result<PathBuf, anyhow::Error> {
+        self.resolve(Kind::Config, file)
+    }
+
+    fn resolve(&self, kind: Kind, file: &str) -> Result<PathBuf, anyhow::Error> {
+        let dir = self.dir(kind)?;
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(dir.join(file))
+    }
+
+    /// The app's directory for `kind`, not yet created.
+    fn dir(&self, kind: Kind) -> Result<PathBuf, anyhow::Error> {
+        let dirs = ProjectDirs::from("", "", &self.app)
+            .ok_or_else(|| anyhow!("No home directory to put {:?} stores in", kind))?;
+        Ok(match kind {
+            Kind::Data => dirs.data_dir(),
+            Kind::Cache => dirs.cache_dir(),
+            Kind::Config => dirs.config_dir(),
+        }
+        .to_path_buf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn scopes_each_kind_to_the_app() {
+        let paths = StorePath::new("kitchen-sink-test");
+        let (Ok(data), Ok(cache), Ok(config)) = (
+            paths.dir(Kind::Data),
+            paths.dir(Kind::Cache),
+            paths.dir(Kind::Config),
+        ) else {
+            // No home directory to resolve against
+            return;
+        };
+        for dir in [&data, &cache, &config] {
+            assert!(dir.is_absolute());
+            assert!(dir.ends_with("kitchen-sink-test"));
+        }
+        assert_ne!(data, cache);
+        assert_ne!(cache, config);
+    }
+}
@@ -0,0 +1,92 @@
+use super::{Fetcher, Store};
+use std::time::Duration;
+use tokio::time::{sleep, sleep_until};
+use tracing::error;
+
+impl<T> Store<T> {
+    /// Time since the data last changed, or a fetch reported it `Unchanged`.
+    /// Data loaded from disk starts out as old as the file's modification
+    /// time.
+    pub fn age(&self) -> Duration {
+        self.inner.updated_at.lock().elapsed()
+    }
+}
+
+impl<T: Send + Sync + 'static> Store<T> {
+    /// Keeps data younger than `ttl` by refreshing it in the background once
+    /// it reaches `prefetch_at` (a fraction of `ttl`, e.g. `0.8`), so readers
+    /// never wait on a refresh. Writes from elsewhere reset the clock. A
+    /// failed fetch is logged and retried after a tenth of `ttl`.
+    pub fn prefetch_before_expiry<F>(&self, fetcher: F, ttl: Duration, prefetch_at: f64)
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        let refresh_after = ttl.mul_f64(prefetch_at.clamp(0.0, 1.0));
+        let mvstore = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let updated_at = *mvstore.inner.updated_at.lock();
+                sleep_until(updated_at + refresh_after).await;
+                if *mvstore.inner.updated_at.lock() != updated_at {
+                    // Refreshed by someone else while we slept
+                    continue;
+                }
+                if let Err(e) = mvstore.refresh(&fetcher).await {
+                    error!("Failed to prefetch store before expiry: {}", e);
+                    sleep(ttl / 10).await;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempStore, TimeHarness};
+    use async_trait::async_trait;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Version(u8);
+
+    impl TryFrom<Vec<u8>> for Version {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Version(value.first().copied().unwrap_or_default()))
+        }
+    }
+
+    impl<'a> From<&'a Version> for Vec<u8> {
+        fn from(value: &'a Version) -> Self {
+            vec![value.0]
+        }
+    }
+
+    struct Bump;
+
+    #[async_trait]
+    impl Fetcher<Version> for Bump {
+        async fn fetch(&self, store: Option<Store<Version>>) -> Result<Version, anyhow::Error> {
+            Ok(Version(store.map(|s| s.read().0 + 1).unwrap_or_default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn refreshes_ahead_of_ttl_and_defers_after_outside_writes() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Version> = TempStore::new().unwrap();
+        tmp.prefetch_before_expiry(Bump, Duration::from_secs(100), 0.8);
+
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(time.elapsed().as_secs(), 80);
+        assert_eq!(*tmp.read(), Version(1));
+
+        time.advance(Duration::from_secs(40)).await;
+        tmp.write(Version(10)).unwrap();
+        assert!(tmp.age() < Duration::from_secs(1));
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(time.elapsed().as_secs(), 200);
+        assert_eq!(*tmp.read(), Version(11));
+    }
+}
@@ -0,0 +1,247 @@
+use super::codec::{self, Codec};
+use super::{DataSource, Inner, Store};
+use anyhow::Context;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+/// Returned (inside `anyhow::Error`) when writing to a store opened with
+/// `Store::open_read_only`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadOnlyError {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Store {} is read-only", self.path.display())
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> Store<T> {
+    /// Opens a store maintained by another process. The file must already
+    /// exist; it is only ever opened for reading, and `write` fails with
+    /// `ReadOnlyError`. Pair with `watch_file` to follow the other process's
+    /// updates.
+    pub fn open_read_only(loc: PathBuf) -> Result<Store<T>, anyhow::Error> {
+        let codec = codec::decode_only();
+        let data = read_file(&loc, codec.as_ref())?;
+        Ok(Store::with_access(data, loc, codec, true, None))
+    }
+
+    /// Swaps in the file's contents when something else changes it, such
+    /// as another process or a hand edit. Each reload is a change to
+    /// `subscribe`rs like any write; the store's own writes aren't reloaded.
+    /// A file that can't be read or decoded is logged and the current value
+    /// kept.
+    ///
+    /// With the `notify` feature the file is followed through the OS's file
+    /// events, falling back to polling every `poll_interval` only if they
+    /// can't be watched. Without it the file is always polled. The task
+    /// stops at the first check after every handle to the store is dropped.
+    pub fn watch_file(&self, poll_interval: Duration)
+    where
+        T: Send + Sync + 'static,
+    {
+        let weak = Arc::downgrade(&self.inner);
+        let mut last = file_stamp(&self.inner.loc);
+        let mut changes = Changes::watch(&self.inner.loc, poll_interval);
+        tokio::spawn(async move {
+            while changes.next().await {
+                let Some(inner) = weak.upgrade() else {
+                    break;
+                };
+                // Reads the file and may wait out a write, so off the runtime
+                let reloaded = spawn_blocking(move || {
+                    reload_if_changed(&inner, &mut last);
+                    last
+                });
+                match reloaded.await {
+                    Ok(checked) => last = checked,
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+}
+
+/// When `watch_file` should look at the file again.
+enum Changes {
+    Polling(Duration),
+    #[cfg(feature = "notify")]
+    Watching {
+        // Stops watching when dropped
+        _watcher: notify::RecommendedWatcher,
+        events: tokio::sync::mpsc::UnboundedReceiver<()>,
+    },
+}
+
+impl Changes {
+    #[cfg(not(feature = "notify"))]
+    fn watch(_: &Path, poll_interval: Duration) -> Self {
+        Changes::Polling(poll_interval)
+    }
+
+    /// Watches the directory rather than the file, as writes rename a new
+    /// file over it.
+    #[cfg(feature = "notify")]
+    fn watch(loc: &Path, poll_interval: Duration) -> Self {
+        use notify::{EventKind, RecursiveMode, Watcher};
+        let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+        let name = loc.file_name().map(|n| n.to_os_string());
+        let watched = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let relevant = match event {
+                Ok(event) => {
+                    !matches!(event.kind, EventKind::Access(_))
+                        && event.paths.iter().any(|p| p.file_name() == name.as_deref())
+                }
+                // Look anyway, in case the event was for the file
+                Err(_) => true,
+            };
+            if relevant {
+                let _ = tx.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            let dir = match loc.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        match watched {
+            Ok(watcher) => Changes::Watching {
+                _watcher: watcher,
+                events,
+            },
+            Err(e) => {
+                warn!("Failed to watch {}, polling instead: {}", loc.display(), e);
+                Changes::Polling(poll_interval)
+            }
+        }
+    }
+
+    /// Waits until the file may have changed. `false` once it can't tell
+    /// any more.
+    async fn next(&mut self) -> bool {
+        match self {
+            Changes::Polling(interval) => {
+                sleep(*interval).await;
+                true
+            }
+            #[cfg(feature = "notify")]
+            Changes::Watching { events, .. } => events.recv().await.is_some(),
+        }
+    }
+}
+
+/// Reloads the file at `inner.loc` unless it is as it was at `last` or as
+/// the store's own write left it.
+fn reload_if_changed<T>(inner: &Inner<T>, last: &mut Option<FileStamp>) {
+    let stamp = file_stamp(&inner.loc);
+    if stamp.is_none() || stamp == *last {
+        return;
+    }
+    *last = stamp;
+    // Waits out a write in progress, whose stamp is only known after
+    let _writer = inner.writer.lock();
+    if stamp == *inner.written.lock() {
+        // Our own write
+        return;
+    }
+    match read_file(&inner.loc, inner.codec.as_ref()) {
+        Ok(data) => {
+            debug!(
+                "Reloaded store {} after external change",
+                inner.loc.display()
+            );
+            let mut current = inner.data.write();
+            *current = Arc::new(data);
+            inner.mark_updated();
+            inner.set_source(DataSource::Reloaded);
+        }
+        Err(e) => warn!("Failed to reload {}: {:#}", inner.loc.display(), e),
+    }
+}
+
+impl<T> Store<T> {
+    pub fn is_read_only(&self) -> bool {
+        self.inner.read_only
+    }
+}
+
+fn read_file<T>(loc: &Path, codec: &dyn Codec<T>) -> Result<T, anyhow::Error> {
+    let mut bytes = Vec::new();
+    File::open(loc)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .with_context(|| format!("Failed to read store {}", loc.display()))?;
+    codec
+        .decode(bytes)
+        .with_context(|| format!("Failed to decode store {}", loc.display()))
+}
+
+pub(super) type FileStamp = (SystemTime, u64, u64);
+
+/// Enough metadata to notice in-place edits as well as replacement by
+/// rename. Off unix there's no inode, so a replacement is only noticed by
+/// its modification time and length.
+pub(super) fn file_stamp(loc: &Path) -> Option<FileStamp> {
+    let meta = std::fs::metadata(loc).ok()?;
+    #[cfg(unix)]
+    let inode = meta.ino();
+    #[cfg(not(unix))]
+    let inode = 0;
+    Some((meta.modified().ok()?, meta.len(), inode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempDir, Text};
+
+    #[tokio::test]
+    async fn rejects_writes_and_follows_external_changes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shared");
+        assert!(Store::<Text>::open_read_only(path.clone()).is_err());
+
+        std::fs::write(&path, "first").unwrap();
+        let store = Store::<Text>::open_read_only(path.clone()).unwrap();
+        assert!(store.is_read_only());
+        let err = store.write(Text("mine".into())).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReadOnlyError>(),
+            Some(&ReadOnlyError { path: path.clone() })
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        store.watch_file(Duration::from_millis(5));
+        std::fs::write(&path, "second, longer").unwrap();
+        while store.read().0 != "second, longer" {
+            sleep(Duration::from_millis(5)).await;
+        }
+
+        // A writable store skips its own writes but not anyone else's
+        let writable = Store::new_or_get(path.clone(), || unreachable!()).unwrap();
+        writable.watch_file(Duration::from_millis(5));
+        writable.write(Text("own".into())).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(writable.version(), 1);
+        std::fs::write(&path, "third").unwrap();
+        while writable.read().0 != "third" {
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(writable.version(), 2);
+    }
+}
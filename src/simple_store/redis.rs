@@ -0,0 +1,288 @@
+//! Sharing one store between service instances through Redis. Behind the
+//! `redis` feature.
+
+use super::backend::blocking;
+use super::{DataSource, FetchResult, Fetcher, StorageBackend, Store};
+use ::redis::{AsyncCommands, Client, Commands, Connection, Script};
+use anyhow::Context;
+use async_trait::async_trait;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Takes the leader key if it's free, or extends it if it's already ours.
+const LEAD: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) and 1 or 0
+";
+
+/// Keeps a store's bytes under one Redis key, so every instance of a
+/// service shares a single copy. Each write also publishes on
+/// `<key>:changed`; `watch` follows those to reload the store when another
+/// instance writes, and `elected` wraps the fetcher so only one instance at
+/// a time refreshes from upstream. Clones share the connection.
+///
+/// ```ignore
+/// let backend = RedisBackend::open("redis://cache:6379", "catalog")?;
+/// let store = Store::with_backend(backend.clone(), Catalog::default)?;
+/// backend.watch(&store);
+/// store.scheduled_updates(backend.elected(CatalogFetcher, Duration::from_secs(30)), Duration::from_secs(10));
+/// ```
+#[derive(Clone)]
+pub struct RedisBackend {
+    client: Client,
+    key: String,
+    // Published with each write, so `watch` skips our own
+    instance: String,
+    conn: Arc<Mutex<Option<Connection>>>,
+}
+
+impl RedisBackend {
+    /// The store under `key` on the server at `url`. Connects lazily.
+    pub fn open(url: &str, key: impl Into<String>) -> Result<Self, anyhow::Error> {
+        let client = Client::open(url).with_context(|| format!("Invalid Redis URL {}", url))?;
+        Ok(Self {
+            client,
+            key: key.into(),
+            instance: format!(
+                "{}-{:x}",
+                std::process::id(),
+                RandomState::new().hash_one(0u8)
+            ),
+            conn: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn channel(&self) -> String {
+        format!("{}:changed", self.key)
+    }
+
+    fn leader_key(&self) -> String {
+        format!("{}:leader", self.key)
+    }
+
+    /// Runs `f` on the cached connection, reconnecting once if it fails.
+    fn with_conn<R>(
+        &self,
+        f: impl Fn(&mut Connection) -> ::redis::RedisResult<R>,
+    ) -> Result<R, anyhow::Error> {
+        blocking(|| {
+            let mut conn = self.conn.lock();
+            if let Some(c) = conn.as_mut()
+                && let Ok(result) = f(c)
+            {
+                return Ok(result);
+            }
+            let mut fresh = self.client.get_connection()?;
+            let result = f(&mut fresh)?;
+            *conn = Some(fresh);
+            Ok(result)
+        })
+    }
+
+    /// Reloads `store` whenever another instance writes, until every handle
+    /// to the store is dropped. A reload goes through like any write: it
+    /// waits for a write in progress, reaches `subscribe`rs, write hooks and
+    /// replicas. Data that fails to load or decode is logged and the
+    /// current value kept. Must be called from within a tokio runtime.
+    pub fn watch<T: Send + Sync + 'static>(&self, store: &Store<T>) {
+        let weak = Arc::downgrade(&store.inner);
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let subscribed = async {
+                let mut pubsub = backend.client.get_async_pubsub().await?;
+                pubsub.subscribe(backend.channel()).await?;
+                Ok::<_, ::redis::RedisError>(pubsub.into_on_message())
+            };
+            let mut messages = match subscribed.await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!("Failed to watch Redis key {}: {}", backend.key, e);
+                    return;
+                }
+            };
+            while let Some(msg) = messages.next().await {
+                let Some(inner) = weak.upgrade() else {
+                    break;
+                };
+                let store = Store { inner };
+                if msg.get_payload::<String>().ok().as_ref() == Some(&backend.instance) {
+                    continue;
+                }
+                let loaded = async {
+                    let mut conn = backend.client.get_multiplexed_async_connection().await?;
+                    let bytes: Option<Vec<u8>> = conn.get(&backend.key).await?;
+                    let bytes = bytes.context("Key was deleted")?;
+                    Ok::<_, anyhow::Error>((store.decode(bytes.clone())?, bytes))
+                };
+                match loaded.await {
+                    Ok((data, bytes)) => {
+                        debug!("Reloaded store from Redis key {}", backend.key);
+                        let writer = store.inner.writer.lock();
+                        let generation = store.swap(Arc::new(data));
+                        store.inner.set_source(DataSource::Reloaded);
+                        store.written(generation, bytes);
+                        drop(writer);
+                        store.run_write_hooks(generation);
+                    }
+                    Err(e) => warn!("Failed to reload Redis key {}: {:#}", backend.key, e),
+                }
+            }
+        });
+    }
+
+    /// Wraps `fetcher` so that refreshes only fetch on the instance holding
+    /// the `<key>:leader` lease, taken or renewed for `lease` on each
+    /// refresh. Elsewhere a refresh reports `Unchanged` and the store waits
+    /// for the leader's write to reach it through `watch`. Refresh at least
+    /// as often as `lease` so the leader keeps it.
+    pub fn elected<F>(&self, fetcher: F, lease: Duration) -> Elected<F> {
+        Elected {
+            leader: Box::new(self.clone()),
+            fetcher,
+            lease,
+        }
+    }
+}
+
+/// Decides which instance fetches for an `Elected` fetcher.
+#[async_trait]
+trait Leader: Send + Sync {
+    /// Takes or renews the lease for `lease`, returning whether it's ours.
+    async fn lead(&self, lease: Duration) -> Result<bool, anyhow::Error>;
+}
+
+#[async_trait]
+impl Leader for RedisBackend {
+    async fn lead(&self, lease: Duration) -> Result<bool, anyhow::Error> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let led: i64 = Script::new(LEAD)
+            .key(self.leader_key())
+            .arg(&self.instance)
+            .arg(lease.as_millis().max(1) as u64)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(led == 1)
+    }
+}
+
+impl StorageBackend for RedisBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        self.with_conn(|c| c.get(&self.key))
+            .with_context(|| format!("Failed to load Redis key {}", self.key))
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        self.with_conn(|c| {
+            ::redis::pipe()
+                .atomic()
+                .set(&self.key, bytes)
+                .ignore()
+                .publish(self.channel(), &self.instance)
+                .ignore()
+                .query::<()>(c)
+        })
+        .with_context(|| format!("Failed to persist Redis key {}", self.key))
+    }
+}
+
+/// A fetcher that only fetches on the elected instance; see
+/// `RedisBackend::elected`.
+pub struct Elected<F> {
+    leader: Box<dyn Leader>,
+    fetcher: F,
+    lease: Duration,
+}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for Elected<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    /// Only called when Redis has nothing stored, so any instance may.
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        self.fetcher.fetch(store).await
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        if !self.leader.lead(self.lease).await? {
+            return Ok(FetchResult::Unchanged);
+        }
+        self.fetcher.fetch_update(store).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::MemoryBackend;
+    use crate::simple_store::testing::{Count, Increment};
+
+    /// A lease that is always, or never, ours.
+    struct Fixed(bool);
+
+    #[async_trait]
+    impl Leader for Fixed {
+        async fn lead(&self, _: Duration) -> Result<bool, anyhow::Error> {
+            Ok(self.0)
+        }
+    }
+
+    fn elected(leads: bool) -> Elected<Increment> {
+        Elected {
+            leader: Box::new(Fixed(leads)),
+            fetcher: Increment,
+            lease: Duration::from_secs(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_leader_fetches_updates() {
+        let store = Store::in_memory(Count(1));
+        assert!(!store.refresh(&elected(false)).await.unwrap());
+        assert_eq!(*store.read(), Count(1));
+        assert!(store.refresh(&elected(true)).await.unwrap());
+        assert_eq!(*store.read(), Count(2));
+    }
+
+    #[tokio::test]
+    async fn any_instance_fetches_initial_data() {
+        let store: Store<Count> = Store::builder()
+            .backend(MemoryBackend::default())
+            .fetcher(elected(false))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(*store.read(), Count(1));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn instances_share_one_copy() {
+        let url = std::env::var("REDIS_URL").unwrap();
+        let key = format!("kitchen-sink-test-{}", std::process::id());
+        let (a, b) = (
+            RedisBackend::open(&url, &key).unwrap(),
+            RedisBackend::open(&url, &key).unwrap(),
+        );
+        let first: Store<Count> = Store::with_backend(a.clone(), || Ok(Count(1))).unwrap();
+        let second: Store<Count> =
+            Store::with_backend(b.clone(), || unreachable!("data is stored")).unwrap();
+        b.watch(&second);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut changes = second.subscribe();
+        let lease = Duration::from_secs(5);
+        assert!(first.refresh(&a.elected(Increment, lease)).await.unwrap());
+        assert!(!second.refresh(&b.elected(Increment, lease)).await.unwrap());
+        changes.changed().await.unwrap();
+        assert_eq!(*second.read(), Count(2));
+    }
+}
@@ -0,0 +1,185 @@
+use super::{Fetcher, Inner, Store};
+use anyhow::anyhow;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+pub(super) type SharedFetcher<T> = Arc<dyn Fetcher<T> + Send + Sync>;
+
+/// The outcome of the refresh in flight, once it lands, for refreshes that
+/// joined it. Errors are shared as their message.
+pub(super) type Flight = watch::Receiver<Option<Result<bool, String>>>;
+
+/// Clears the store's flight when the refresh leading it finishes or is
+/// dropped, so the next refresh fetches again.
+struct Landing<'a, T>(&'a Inner<T>);
+
+impl<T> Drop for Landing<'_, T> {
+    fn drop(&mut self) {
+        *self.0.refreshing.lock() = None;
+    }
+}
+
+impl<T> Store<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Fetches and writes new data right away, outside any schedule.
+    /// Returns whether the data changed, judged by its serialized form, or
+    /// `false` without a write when the fetcher reports `Unchanged`.
+    ///
+    /// A refresh started while another is in flight, scheduled or not,
+    /// doesn't fetch again: it waits for that one and returns its result.
+    pub async fn refresh<F>(&self, fetcher: &F) -> Result<bool, anyhow::Error>
+    where
+        F: Fetcher<T> + Sync + ?Sized,
+    {
+        loop {
+            let (mut joined, leading) = {
+                let mut flight = self.inner.refreshing.lock();
+                match flight.clone() {
+                    Some(joined) => (joined, None),
+                    None => {
+                        let (tx, rx) = watch::channel(None);
+                        *flight = Some(rx.clone());
+                        (rx, Some(tx))
+                    }
+                }
+            };
+            if let Some(tx) = leading {
+                let _landing = Landing(&self.inner);
+                let result = self.refresh_once(fetcher).await;
+                tx.send_replace(Some(match &result {
+                    Ok(changed) => Ok(*changed),
+                    Err(e) => Err(format!("{:#}", e)),
+                }));
+                return result;
+            }
+            let landed = joined
+                .wait_for(Option::is_some)
+                .await
+                .map(|result| result.clone());
+            match landed {
+                Ok(Some(Ok(changed))) => return Ok(changed),
+                Ok(Some(Err(e))) => return Err(anyhow!(e)),
+                // The refresh we joined was dropped before landing
+                _ => continue,
+            }
+        }
+    }
+
+    async fn refresh_once<F>(&self, fetcher: &F) -> Result<bool, anyhow::Error>
+    where
+        F: Fetcher<T> + Sync + ?Sized,
+    {
+        // Refused before fetching, so it isn't a failed refresh
+        self.check_refresh_limit()?;
+        let result = async {
+            let fetched = fetcher.fetch_update(self.clone()).await?;
+            let Some((new_data, meta)) = fetched.into_parts() else {
+                self.confirm_unchanged();
+                return Ok(false);
+            };
+            self.write_fetched(new_data, meta)
+        }
+        .await;
+        self.record_refresh(&result);
+        self.run_refresh_hooks(&result);
+        result
+    }
+
+    /// Keeps `fetcher` with the store so any holder of a clone can call
+    /// `refresh_now` without access to it.
+    pub fn with_fetcher<F>(self, fetcher: F) -> Self
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        *self.inner.fetcher.lock() = Some(Arc::new(fetcher));
+        self
+    }
+
+    /// `refresh` using the fetcher given to `with_fetcher`.
+    pub async fn refresh_now(&self) -> Result<bool, anyhow::Error> {
+        let fetcher = self
+            .inner
+            .fetcher
+            .lock()
+            .clone()
+            .ok_or_else(|| anyhow!("Store has no fetcher to refresh with"))?;
+        self.refresh(&*fetcher).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::FetchResult;
+    use crate::simple_store::testing::{Climb, Count, TempStore};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::time::Duration;
+
+    /// Knows the upstream is still at 7 without fetching it again.
+    struct Conditional;
+
+    #[async_trait]
+    impl Fetcher<Count> for Conditional {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            Ok(Count(7))
+        }
+
+        async fn fetch_update(
+            &self,
+            store: Store<Count>,
+        ) -> Result<FetchResult<Count>, anyhow::Error> {
+            match store.read().0 {
+                7 => Ok(FetchResult::Unchanged),
+                _ => Ok(FetchResult::Updated(Count(7))),
+            }
+        }
+    }
+
+    /// Takes a while to count its calls.
+    struct Slow(AtomicU8);
+
+    #[async_trait]
+    impl Fetcher<Count> for Slow {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(Count(calls))
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_refreshes_share_a_fetch() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let fetcher = Slow(AtomicU8::new(0));
+        let other = tmp.store().clone();
+        let (a, b) = tokio::join!(tmp.refresh(&fetcher), other.refresh(&fetcher));
+        assert!(a.unwrap() && b.unwrap());
+        assert_eq!(*tmp.read(), Count(1));
+        assert_eq!(tmp.metrics().consecutive_failures, 0);
+
+        assert!(tmp.refresh(&fetcher).await.unwrap());
+        assert_eq!(*tmp.read(), Count(2));
+    }
+
+    #[tokio::test]
+    async fn refreshes_on_demand() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        assert!(tmp.refresh_now().await.is_err());
+        assert!(tmp.refresh(&Climb(2)).await.unwrap());
+        assert_eq!(*tmp.read(), Count(1));
+
+        let store = tmp.store().clone().with_fetcher(Climb(2));
+        assert!(store.refresh_now().await.unwrap());
+        assert!(!tmp.refresh_now().await.unwrap());
+        assert_eq!(tmp.bytes().unwrap(), vec![2]);
+
+        assert!(tmp.refresh(&Conditional).await.unwrap());
+        let version = tmp.version();
+        assert!(!tmp.refresh(&Conditional).await.unwrap());
+        assert_eq!(tmp.version(), version);
+        assert!(tmp.metrics().last_refresh.is_some());
+    }
+}
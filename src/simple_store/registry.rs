@@ -0,0 +1,219 @@
+use super::{Store, StoreHealth, StoreInfo};
+use crate::health::{Health, HealthRegistry, Status};
+use crate::shutdown::ShutdownCoordinator;
+use anyhow::anyhow;
+use futures::future::{BoxFuture, join_all};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::error;
+
+/// What the registry needs of a `Store<T>`, whatever its `T`.
+trait Registered: Send + Sync {
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, anyhow::Error>>;
+    fn flush(&self) -> Result<(), anyhow::Error>;
+    fn health(&self, max_age: Duration) -> StoreHealth;
+    fn info(&self) -> StoreInfo;
+}
+
+impl<T> Registered for Store<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, anyhow::Error>> {
+        Box::pin(self.refresh_now())
+    }
+
+    fn flush(&self) -> Result<(), anyhow::Error> {
+        Store::flush(self)
+    }
+
+    fn health(&self, max_age: Duration) -> StoreHealth {
+        Store::health(self, max_age)
+    }
+
+    fn info(&self) -> StoreInfo {
+        Store::info(self)
+    }
+}
+
+struct Entry {
+    store: Arc<dyn Registered>,
+    max_age: Duration,
+}
+
+/// Stores of any type tracked by name, for operations over all of them at
+/// once. Clones share the same stores.
+///
+/// ```ignore
+/// let stores = StoreRegistry::new();
+/// stores.register("users", &users, Duration::from_secs(300));
+/// stores.register("flags", &flags, Duration::from_secs(60));
+/// stores.flush_on_shutdown(&mut shutdown);
+/// stores.report_health(&health);
+/// ```
+#[derive(Clone, Default)]
+pub struct StoreRegistry {
+    stores: Arc<Mutex<BTreeMap<String, Entry>>>,
+}
+
+impl StoreRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks `store` as `name`, judging its health against `max_age`.
+    /// Replaces any store already registered as `name`.
+    pub fn register<T>(&self, name: impl Into<String>, store: &Store<T>, max_age: Duration)
+    where
+        T: Send + Sync + 'static,
+    {
+        let entry = Entry {
+            store: Arc::new(store.clone()),
+            max_age,
+        };
+        self.stores.lock().insert(name.into(), entry);
+    }
+
+    /// Stops tracking `name`, returning whether it was registered.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.stores.lock().remove(name).is_some()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.stores.lock().keys().cloned().collect()
+    }
+
+    fn entries(&self) -> Vec<(String, Arc<dyn Registered>, Duration)> {
+        self.stores
+            .lock()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.store.clone(), entry.max_age))
+            .collect()
+    }
+
+    /// Refreshes every store concurrently through the fetcher given to its
+    /// `with_fetcher`, returning each result by name. Stores without one
+    /// fail.
+    pub async fn refresh_all(&self) -> BTreeMap<String, Result<bool, anyhow::Error>> {
+        let entries = self.entries();
+        let results = join_all(entries.iter().map(|(_, store, _)| store.refresh())).await;
+        entries
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .zip(results)
+            .collect()
+    }
+
+    /// Flushes every write-behind store. All are flushed even if some fail,
+    /// which are logged and named in the error.
+    pub fn flush_all(&self) -> Result<(), anyhow::Error> {
+        let mut failed = Vec::new();
+        for (name, store, _) in self.entries() {
+            if let Err(e) = store.flush() {
+                error!("Failed to flush store {}: {:#}", name, e);
+                failed.push(name);
+            }
+        }
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!("Failed to flush stores: {}", failed.join(", "))),
+        }
+    }
+
+    /// Runs `flush_all` once shutdown begins, including for stores
+    /// registered after this call. Must be called from within a tokio
+    /// runtime.
+    pub fn flush_on_shutdown(&self, shutdown: &mut ShutdownCoordinator) {
+        let token = shutdown.token();
+        let registry = self.clone();
+        shutdown.register_task(tokio::spawn(async move {
+            token.cancelled().await;
+            // Failures are logged by flush_all
+            let _ = registry.flush_all();
+        }));
+    }
+
+    /// Each store's health against its `max_age`, by name.
+    pub fn health(&self) -> BTreeMap<String, StoreHealth> {
+        self.entries()
+            .into_iter()
+            .map(|(name, store, max_age)| (name, store.health(max_age)))
+            .collect()
+    }
+
+    /// The worst health of any store, or healthy when there are none.
+    pub fn overall(&self) -> Health {
+        self.health()
+            .into_values()
+            .map(|health| Status::from(health).health)
+            .max()
+            .unwrap_or(Health::Healthy)
+    }
+
+    /// Sets each store's status in `health`, under its name.
+    pub fn report_health(&self, health: &HealthRegistry) {
+        for (name, store_health) in self.health() {
+            health.set(name, store_health.into());
+        }
+    }
+
+    /// Each store's `Store::info`, by name.
+    pub fn info(&self) -> BTreeMap<String, StoreInfo> {
+        self.entries()
+            .into_iter()
+            .map(|(name, store, _)| (name, store.info()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempStore, Text};
+    use crate::simple_store::{Fetcher, WriteBehind};
+    use async_trait::async_trait;
+
+    struct Seven;
+
+    #[async_trait]
+    impl Fetcher<Count> for Seven {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            Ok(Count(7))
+        }
+    }
+
+    #[tokio::test]
+    async fn operates_on_every_store() {
+        let counts: TempStore<Count> = TempStore::new().unwrap();
+        let texts: TempStore<Text> = TempStore::new().unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let store = texts
+            .store()
+            .clone()
+            .with_write_behind(WriteBehind::every(Duration::from_secs(3600)), &mut shutdown);
+        let registry = StoreRegistry::new();
+        registry.register(
+            "counts",
+            &counts.store().clone().with_fetcher(Seven),
+            Duration::MAX,
+        );
+        registry.register("texts", &store, Duration::MAX);
+        registry.flush_on_shutdown(&mut shutdown);
+        assert_eq!(registry.names(), vec!["counts", "texts"]);
+
+        let refreshed = registry.refresh_all().await;
+        assert!(refreshed["counts"].as_ref().unwrap());
+        assert!(refreshed["texts"].is_err());
+        assert_eq!(*counts.read(), Count(7));
+        assert_eq!(registry.overall(), Health::Healthy);
+        assert_eq!(registry.info()["texts"].last_error, None);
+
+        store.write(Text("pending".into())).unwrap();
+        assert_eq!(texts.bytes().unwrap(), b"");
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        assert_eq!(texts.bytes().unwrap(), b"pending");
+    }
+}
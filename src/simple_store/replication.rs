@@ -0,0 +1,153 @@
+use super::StorageBackend;
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::watch;
+use tracing::warn;
+
+/// Point-in-time view of a replica's progress. `lag` counts the store writes
+/// that haven't reached the replica yet.
+#[derive(Debug, Clone)]
+pub struct ReplicaStatus {
+    /// The replica's file, or empty for a replica backend.
+    pub path: PathBuf,
+    pub replicated_generation: u64,
+    pub lag: u64,
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct ReplicaState {
+    replicated_generation: u64,
+    last_success: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+pub(super) struct Replica {
+    path: PathBuf,
+    sender: watch::Sender<(u64, Arc<Vec<u8>>)>,
+    state: Arc<Mutex<ReplicaState>>,
+}
+
+impl Replica {
+    /// Starts a task that copies the latest bytes to `backend`, described by
+    /// `path`, whenever they change. The task ends once the store (and so
+    /// the sender) is dropped.
+    pub(super) fn spawn(
+        path: PathBuf,
+        backend: Arc<dyn StorageBackend>,
+        generation: u64,
+        bytes: Vec<u8>,
+    ) -> Self {
+        let (sender, mut receiver) = watch::channel((generation, Arc::new(bytes)));
+        receiver.mark_changed();
+        let state = Arc::new(Mutex::new(ReplicaState::default()));
+        let mvstate = state.clone();
+        let label = match path.as_os_str().is_empty() {
+            true => "replica backend".to_string(),
+            false => path.display().to_string(),
+        };
+        tokio::spawn(async move {
+            while receiver.changed().await.is_ok() {
+                let (generation, bytes) = receiver.borrow_and_update().clone();
+                let target = backend.clone();
+                let res = tokio::task::spawn_blocking(move || target.persist(&bytes))
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|res| res);
+                let mut state = mvstate.lock();
+                match res {
+                    Ok(()) => {
+                        state.replicated_generation = generation;
+                        state.last_success = Some(SystemTime::now());
+                        state.last_error = None;
+                    }
+                    Err(e) => {
+                        warn!("Failed to replicate store to {}: {:#}", label, e);
+                        state.last_error = Some(format!("{:#}", e));
+                    }
+                }
+            }
+        });
+        Self {
+            path,
+            sender,
+            state,
+        }
+    }
+
+    pub(super) fn status(&self, current_generation: u64) -> ReplicaStatus {
+        let state = self.state.lock();
+        ReplicaStatus {
+            path: self.path.clone(),
+            replicated_generation: state.replicated_generation,
+            lag: current_generation.saturating_sub(state.replicated_generation),
+            last_success: state.last_success,
+            last_error: state.last_error.clone(),
+        }
+    }
+}
+
+/// Hands freshly persisted bytes to every replica without blocking.
+pub(super) fn replicate(replicas: &[Replica], generation: u64, bytes: Vec<u8>) {
+    if replicas.is_empty() {
+        return;
+    }
+    let bytes = Arc::new(bytes);
+    for replica in replicas {
+        replica.sender.send_replace((generation, bytes.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simple_store::MemoryBackend;
+    use crate::simple_store::testing::TempStore;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Raw(Vec<u8>);
+
+    impl TryFrom<Vec<u8>> for Raw {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Raw(value))
+        }
+    }
+
+    impl<'a> From<&'a Raw> for Vec<u8> {
+        fn from(value: &'a Raw) -> Self {
+            value.0.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn mirrors_writes_and_isolates_failures() {
+        let tmp: TempStore<Raw> = TempStore::from_value(Raw(b"v0".to_vec())).unwrap();
+        let good = tmp.dir().join("mirror");
+        tmp.add_replica(good.clone());
+        tmp.add_replica(tmp.dir().join("missing/dir/mirror"));
+        let remote = MemoryBackend::new();
+        tmp.add_replica_backend(remote.clone());
+
+        tmp.write(Raw(b"v1".to_vec())).unwrap();
+        tmp.write(Raw(b"v2".to_vec())).unwrap();
+        while tmp.replica_status()[0].lag > 0
+            || tmp.replica_status()[1].last_error.is_none()
+            || tmp.replica_status()[2].lag > 0
+        {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(std::fs::read(&good).unwrap(), b"v2");
+        let status = tmp.replica_status();
+        assert_eq!(status[0].replicated_generation, 2);
+        assert!(status[0].last_error.is_none());
+        assert_eq!(status[1].lag, 2);
+        assert_eq!(remote.bytes().unwrap(), b"v2");
+        assert_eq!(status[2].path, std::path::PathBuf::new());
+        assert_eq!(tmp.bytes().unwrap(), b"v2");
+    }
+}
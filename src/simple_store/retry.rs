@@ -0,0 +1,177 @@
+use super::{Backoff, DataSource, Fetcher, Recovery, Store, codec};
+use anyhow::Context;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// How many times to try the fetch that populates a new store, waiting
+/// `backoff` between attempts.
+///
+/// ```ignore
+/// let retry = InitialRetry::attempts(5).with_backoff(Backoff {
+///     initial: Duration::from_millis(200),
+///     max: Duration::from_secs(5),
+/// });
+/// let store = Store::new_with_fetcher_retrying(loc, fetcher, retry).await?;
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct InitialRetry {
+    /// Total fetches made, including the first. At least one is always made.
+    pub attempts: u32,
+    pub backoff: Backoff,
+}
+
+impl InitialRetry {
+    /// `attempts` tries, a second apart doubling up to thirty.
+    pub fn attempts(attempts: u32) -> Self {
+        Self {
+            attempts,
+            backoff: Backoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(30),
+            },
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Runs `fetcher` until it succeeds or the attempts run out, returning
+    /// the last error.
+    async fn fetch<T, F>(&self, fetcher: &F) -> Result<T, anyhow::Error>
+    where
+        F: Fetcher<T> + ?Sized,
+    {
+        let attempts = self.attempts.max(1);
+        let mut failures = 0;
+        loop {
+            match fetcher.fetch(None).await {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    failures += 1;
+                    if failures >= attempts {
+                        return Err(e)
+                            .with_context(|| format!("Initial fetch failed {} times", failures));
+                    }
+                    warn!("Initial fetch failed ({}/{}): {:#}", failures, attempts, e);
+                    sleep(self.backoff.delay(failures)).await;
+                }
+            }
+        }
+    }
+}
+
+impl<T> Store<T>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error> + Send + Sync + 'static,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// `new_with_fetcher`, retrying the initial fetch per `retry` before
+    /// giving up.
+    pub async fn new_with_fetcher_retrying<F>(
+        loc: PathBuf,
+        fetcher: F,
+        retry: InitialRetry,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        F: Fetcher<T>,
+    {
+        let initial = retry.fetch(&fetcher);
+        Self::load_async(loc, codec::raw(), Recovery::default(), initial).await
+    }
+
+    /// `new_with_fetcher_retrying` that starts out as `T::default()` instead
+    /// of failing once the attempts run out. The default isn't written to
+    /// disk and `is_ready` stays false until the first write, so a scheduled
+    /// update or `refresh` can still fill the store in.
+    pub async fn new_with_fetcher_or_default<F>(
+        loc: PathBuf,
+        fetcher: F,
+        retry: InitialRetry,
+    ) -> Result<Store<T>, anyhow::Error>
+    where
+        T: Default,
+        F: Fetcher<T>,
+    {
+        let codec = codec::raw();
+        let (loc, loaded) = Self::load_file(loc, codec.clone(), Recovery::default()).await?;
+        if let Some((data, elapsed)) = loaded {
+            let store = Store::from_parts(data, loc, codec);
+            store.record_deserialize(Some(elapsed));
+            return Ok(store);
+        }
+        match retry.fetch(&fetcher).await {
+            Ok(data) => {
+                let store = Store::from_parts(T::default(), loc, codec);
+                store.write_async(data).await?;
+                store.inner.set_source(DataSource::Initial);
+                Ok(store)
+            }
+            Err(e) => {
+                warn!("Starting {} from its default: {:#}", loc.display(), e);
+                let store = Store::from_parts(T::default(), loc, codec);
+                store.inner.ready.send_replace(false);
+                store.inner.set_source(DataSource::Placeholder);
+                Ok(store)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempDir};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails `.1` times, then returns 5.
+    struct Flaky(Arc<AtomicU32>, u32);
+
+    #[async_trait]
+    impl Fetcher<Count> for Flaky {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            match self.0.fetch_add(1, Ordering::SeqCst) < self.1 {
+                true => Err(anyhow!("not yet")),
+                false => Ok(Count(5)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_the_initial_fetch() {
+        let dir = TempDir::new().unwrap();
+        let retry = InitialRetry::attempts(3).with_backoff(Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        });
+        let calls = Arc::new(AtomicU32::new(0));
+        let store =
+            Store::new_with_fetcher_retrying(dir.path().join("a"), Flaky(calls.clone(), 2), retry)
+                .await
+                .unwrap();
+        assert_eq!(*store.read(), Count(5));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let failed: Result<Store<Count>, _> =
+            Store::new_with_fetcher_retrying(dir.path().join("b"), Flaky(calls.clone(), 3), retry)
+                .await;
+        assert!(failed.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let path = dir.path().join("c");
+        let defaulted =
+            Store::new_with_fetcher_or_default(path.clone(), Flaky(calls, u32::MAX), retry)
+                .await
+                .unwrap();
+        assert_eq!(*defaulted.read(), Count(0));
+        assert!(!defaulted.is_ready());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,352 @@
+use super::{CronSchedule, Fetcher, RefreshHandle, Store};
+use crate::rng::SeededRng;
+use crate::shutdown::ShutdownCoordinator;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+/// Exponential delay between retries: `initial`, doubling after every
+/// further failure, never more than `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// The wait after the `failures`th consecutive failure (counting from 1).
+    pub fn delay(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.initial.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+/// When scheduled updates run: every `interval`, or at the times of a
+/// `cron` expression, optionally spread out by `jitter` so stores sharing
+/// an upstream don't all fetch at once, and optionally retried sooner with
+/// `backoff` after a failure. A plain `Duration` converts into a fixed
+/// schedule and a `CronSchedule` into a cron one.
+///
+/// # Example
+/// ```ignore
+/// let schedule = RefreshSchedule::every(Duration::from_secs(300))
+///     .with_jitter(0.1)
+///     .with_backoff(Backoff { initial: Duration::from_secs(1), max: Duration::from_secs(60) });
+/// store.scheduled_updates(fetcher, schedule);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshSchedule {
+    pub interval: Duration,
+    /// Each wait is drawn uniformly from `wait * (1 ± jitter)`, with
+    /// `jitter` clamped to `0.0..=1.0`.
+    pub jitter: f64,
+    /// Used in place of `interval` after consecutive failures.
+    pub backoff: Option<Backoff>,
+    /// Used in place of `interval` when set.
+    pub cron: Option<CronSchedule>,
+}
+
+impl RefreshSchedule {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval,
+            jitter: 0.0,
+            backoff: None,
+            cron: None,
+        }
+    }
+
+    /// Runs whenever `cron` fires, rather than at a fixed interval.
+    pub fn cron(cron: CronSchedule) -> Self {
+        Self {
+            cron: Some(cron),
+            ..Self::every(Duration::ZERO)
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// How long to wait given the number of consecutive failures so far.
+    fn next_wait(&self, failures: u32, rng: &mut SeededRng) -> Duration {
+        let wait = match self.backoff {
+            Some(backoff) if failures > 0 => backoff.delay(failures),
+            _ => match self.cron {
+                Some(cron) => cron.until_next(SystemTime::now()),
+                None => self.interval,
+            },
+        };
+        let spread = wait.mul_f64(self.jitter.clamp(0.0, 1.0));
+        rng.duration_between(wait - spread, wait + spread)
+    }
+}
+
+impl From<Duration> for RefreshSchedule {
+    fn from(interval: Duration) -> Self {
+        Self::every(interval)
+    }
+}
+
+impl From<CronSchedule> for RefreshSchedule {
+    fn from(cron: CronSchedule) -> Self {
+        Self::cron(cron)
+    }
+}
+
+type ErrorCallback = Arc<dyn Fn(&anyhow::Error, u32) + Send + Sync>;
+
+/// What a scheduled update loop does when a fetch or write fails. Failures
+/// are always logged; the loop then waits as its `RefreshSchedule` says.
+///
+/// # Example
+/// ```ignore
+/// let policy = ErrorPolicy::default()
+///     .on_error(|e, failures| alert(e, failures))
+///     .stop_after(10);
+/// store.scheduled_updates_with_policy(fetcher, Duration::from_secs(300), policy);
+/// ```
+#[derive(Clone, Default)]
+pub struct ErrorPolicy {
+    stop_after: Option<u32>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl ErrorPolicy {
+    /// Ends the update loop after `failures` consecutive failures.
+    pub fn stop_after(mut self, failures: u32) -> Self {
+        self.stop_after = Some(failures.max(1));
+        self
+    }
+
+    /// Called with each error and the number of consecutive failures so far.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&anyhow::Error, u32) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Handles the `failures`th consecutive failure, returning whether to
+    /// keep going.
+    fn failed(&self, err: &anyhow::Error, failures: u32) -> bool {
+        error!("Failed to update database: {:#}", err);
+        if let Some(callback) = &self.on_error {
+            callback(err, failures);
+        }
+        if self.stop_after.is_some_and(|limit| failures >= limit) {
+            error!(
+                "Stopping scheduled updates after {} consecutive failures",
+                failures
+            );
+            return false;
+        }
+        true
+    }
+}
+
+impl<T: Send + Sync + 'static> Store<T> {
+    /// Like `scheduled_updates`, with `policy` deciding what happens after a
+    /// failed update.
+    pub fn scheduled_updates_with_policy<F>(
+        &self,
+        fetcher: F,
+        schedule: impl Into<RefreshSchedule>,
+        policy: ErrorPolicy,
+    ) -> RefreshHandle
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        let handle = RefreshHandle::new(CancellationToken::new());
+        self.spawn_updates(fetcher, schedule.into(), policy, handle.clone());
+        handle
+    }
+
+    /// Like `scheduled_updates_with_policy`, but the loop is registered with
+    /// `shutdown` and stops once shutdown begins. A fetch in flight at that
+    /// point is abandoned; a write is never interrupted.
+    pub fn scheduled_updates_with_shutdown<F>(
+        &self,
+        fetcher: F,
+        schedule: impl Into<RefreshSchedule>,
+        policy: ErrorPolicy,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> RefreshHandle
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        let handle = RefreshHandle::new(shutdown.token().child_token());
+        let task = self.spawn_updates(fetcher, schedule.into(), policy, handle.clone());
+        shutdown.register_task(task);
+        handle
+    }
+
+    fn spawn_updates<F>(
+        &self,
+        fetcher: F,
+        schedule: RefreshSchedule,
+        policy: ErrorPolicy,
+        handle: RefreshHandle,
+    ) -> JoinHandle<()>
+    where
+        F: Fetcher<T> + Send + Sync + 'static,
+    {
+        let mvstore = self.clone();
+        let mut rng = SeededRng::new(RandomState::new().build_hasher().finish());
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let wait = schedule.next_wait(failures, &mut rng);
+                let update = async {
+                    match handle.wait(sleep(wait)).await {
+                        true => Some(mvstore.refresh(&fetcher).await),
+                        false => None,
+                    }
+                };
+                let res = tokio::select! {
+                    _ = handle.token().cancelled() => break,
+                    res = update => res,
+                };
+                // A tick skipped while paused
+                let Some(res) = res else {
+                    continue;
+                };
+                handle.record(&res);
+                match res {
+                    Ok(_) => failures = 0,
+                    Err(e) => {
+                        failures += 1;
+                        if !policy.failed(&e, failures) {
+                            handle.stop();
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempStore, TimeHarness};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the first `failing` fetches.
+    struct Flaky {
+        failing: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Fetcher<Count> for Flaky {
+        async fn fetch(&self, _store: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed);
+            if call < self.failing {
+                Err(anyhow!("upstream down"))
+            } else {
+                Ok(Count(call as u8))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn backs_off_then_stops_after_repeated_failures() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(4),
+        };
+        let flaky = Flaky {
+            failing: 3,
+            calls: AtomicU32::new(0),
+        };
+        tmp.scheduled_updates_with_policy(
+            flaky,
+            RefreshSchedule::every(Duration::from_secs(60)).with_backoff(backoff),
+            ErrorPolicy::default(),
+        );
+        // Fails at 60, 61 and 63 before succeeding
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(time.elapsed().as_secs(), 67);
+        assert_eq!(*tmp.read(), Count(3));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mvseen = seen.clone();
+        let policy = ErrorPolicy::default()
+            .on_error(move |_, failures| mvseen.lock().push(failures))
+            .stop_after(3);
+        let down = Flaky {
+            failing: u32::MAX,
+            calls: AtomicU32::new(0),
+        };
+        tmp.scheduled_updates_with_policy(down, Duration::from_secs(10), policy);
+        for _ in 0..10 {
+            time.advance(Duration::from_secs(10)).await;
+        }
+        assert_eq!(*seen.lock(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stops_refreshing_on_shutdown() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let fetcher = Flaky {
+            failing: 0,
+            calls: AtomicU32::new(0),
+        };
+        tmp.scheduled_updates_with_shutdown(
+            fetcher,
+            Duration::from_secs(10),
+            ErrorPolicy::default(),
+            &mut shutdown,
+        );
+        time.advance_until_refresh(&tmp).await.unwrap();
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        let generation = tmp.generation();
+        time.advance(Duration::from_secs(60)).await;
+        assert_eq!(tmp.generation(), generation);
+    }
+
+    #[tokio::test]
+    async fn jitter_spreads_refreshes_around_the_interval() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let fetcher = Flaky {
+            failing: 0,
+            calls: AtomicU32::new(0),
+        };
+        let schedule = RefreshSchedule::every(Duration::from_secs(100)).with_jitter(0.5);
+        tmp.scheduled_updates(fetcher, schedule);
+
+        let mut gaps = Vec::new();
+        let mut last = Duration::ZERO;
+        for _ in 0..5 {
+            time.advance_until_refresh(&tmp).await.unwrap();
+            gaps.push(time.elapsed() - last);
+            last = time.elapsed();
+        }
+        assert!(gaps.iter().all(|g| g.as_secs() >= 50 && g.as_secs() <= 151));
+        assert!(gaps.iter().any(|g| *g != gaps[0]));
+    }
+}
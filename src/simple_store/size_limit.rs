@@ -0,0 +1,192 @@
+use super::{Records, Store};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+type Shrink<T> = Arc<dyn Fn(&mut T) -> bool + Send + Sync>;
+
+/// The most a store may persist, and what to do about a write that would
+/// go over, so a runaway fetcher can't fill the disk. Set with
+/// `Store::with_size_limit`.
+///
+/// ```ignore
+/// // Keep the newest events, up to 10MB of them
+/// let events = AppendStore::<Event>::open(loc, 1000)?;
+/// events.store().set_size_limit(SizeLimit::drop_oldest(10 << 20));
+/// ```
+pub struct SizeLimit<T> {
+    pub max_bytes: usize,
+    shrink: Option<Shrink<T>>,
+}
+
+impl<T> Clone for SizeLimit<T> {
+    fn clone(&self) -> Self {
+        Self {
+            max_bytes: self.max_bytes,
+            shrink: self.shrink.clone(),
+        }
+    }
+}
+
+impl<T> SizeLimit<T> {
+    /// Fails oversized writes with `SizeExceeded`, leaving the store as it
+    /// was.
+    pub fn reject(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            shrink: None,
+        }
+    }
+
+    /// Calls `shrink` on oversized data until it fits, then writes it.
+    /// `shrink` returns false once there is nothing left to drop, and the
+    /// write fails with `SizeExceeded` instead.
+    pub fn shrink(
+        max_bytes: usize,
+        shrink: impl Fn(&mut T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            max_bytes,
+            shrink: Some(Arc::new(shrink)),
+        }
+    }
+}
+
+impl<R: 'static, C: 'static> SizeLimit<Records<R, C>> {
+    /// Drops the oldest records until the rest fit.
+    pub fn drop_oldest(max_bytes: usize) -> Self {
+        Self::shrink(max_bytes, |records: &mut Records<R, C>| {
+            // An eighth at a time, so a large overflow doesn't take a
+            // reserialization per record
+            let len = records.records.len();
+            records.records.drain(..(len / 8).max(1).min(len));
+            len > 0
+        })
+    }
+}
+
+/// Returned when a write is over the store's `SizeLimit` and couldn't be
+/// shrunk to fit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeExceeded {
+    pub path: PathBuf,
+    pub size: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for SizeExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refusing to write {} bytes to store {}, over its {} byte limit",
+            self.size,
+            self.path.display(),
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for SizeExceeded {}
+
+impl<T> Store<T> {
+    /// Caps how much every later write may persist. Patches journaled by a
+    /// `DeltaStore` or `AppendStore` are only held to it when compacted.
+    pub fn with_size_limit(self, limit: SizeLimit<T>) -> Self {
+        self.set_size_limit(limit);
+        self
+    }
+
+    /// `with_size_limit` for a store that's already shared.
+    pub fn set_size_limit(&self, limit: SizeLimit<T>) {
+        *self.inner.size_limit.lock() = Some(limit);
+    }
+}
+
+impl<T> Store<T> {
+    /// `serialize`, applying the store's `SizeLimit` to `data`.
+    pub(super) fn serialize_within_limit(&self, data: &mut T) -> Result<Vec<u8>, anyhow::Error> {
+        self.shrink_to_limit(data).map(|(serialized, _)| serialized)
+    }
+
+    /// `serialize_within_limit`, also returning whether `data` was shrunk.
+    pub(super) fn shrink_to_limit(&self, data: &mut T) -> Result<(Vec<u8>, bool), anyhow::Error> {
+        let mut serialized = self.serialize(data)?;
+        let Some(limit) = self.inner.size_limit.lock().clone() else {
+            return Ok((serialized, false));
+        };
+        let mut shrunk = false;
+        while serialized.len() > limit.max_bytes {
+            match &limit.shrink {
+                Some(shrink) if shrink(data) => {
+                    serialized = self.serialize(data)?;
+                    shrunk = true;
+                }
+                _ => {
+                    return Err(SizeExceeded {
+                        path: self.inner.loc.clone(),
+                        size: serialized.len(),
+                        limit: limit.max_bytes,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok((serialized, shrunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::AppendStore;
+    use crate::simple_store::testing::{TempDir, TempStore};
+
+    #[derive(Default, Debug, Clone, PartialEq)]
+    struct Bytes(Vec<u8>);
+
+    impl TryFrom<Vec<u8>> for Bytes {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Bytes(value))
+        }
+    }
+
+    impl<'a> From<&'a Bytes> for Vec<u8> {
+        fn from(value: &'a Bytes) -> Self {
+            value.0.clone()
+        }
+    }
+
+    #[test]
+    fn oversized_writes_are_rejected_or_shrunk() {
+        let tmp: TempStore<Bytes> = TempStore::new().unwrap();
+        tmp.set_size_limit(SizeLimit::reject(4));
+        tmp.write(Bytes(vec![1; 4])).unwrap();
+        let err = tmp.write(Bytes(vec![2; 5])).unwrap_err();
+        assert_eq!(err.downcast_ref::<SizeExceeded>().unwrap().size, 5);
+        assert!(tmp.update(|b| b.0.push(3)).is_err());
+        assert_eq!(tmp.bytes().unwrap(), vec![1; 4]);
+        assert_eq!(*tmp.read(), Bytes(vec![1; 4]));
+
+        tmp.set_size_limit(SizeLimit::shrink(4, |b: &mut Bytes| b.0.pop().is_some()));
+        tmp.write(Bytes(vec![1, 2, 3, 4, 5, 6])).unwrap();
+        assert_eq!(tmp.bytes().unwrap(), vec![1, 2, 3, 4]);
+
+        let dir = TempDir::new().unwrap();
+        let events: AppendStore<Bytes> = AppendStore::open(dir.path().join("events"), 1).unwrap();
+        // Each one-byte record takes five framed
+        events.store().set_size_limit(SizeLimit::drop_oldest(15));
+        for n in 0..5 {
+            events.append(Bytes(vec![n])).unwrap();
+        }
+        let kept: Vec<_> = events
+            .store()
+            .read()
+            .records
+            .iter()
+            .map(|b| b.0[0])
+            .collect();
+        assert_eq!(kept, vec![2, 3, 4]);
+    }
+}
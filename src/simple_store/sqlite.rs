@@ -0,0 +1,221 @@
+//! A `StorageBackend` keeping stores in a SQLite database. Behind the
+//! `sqlite` feature.
+
+use super::StorageBackend;
+use super::codec::crc32;
+use anyhow::{Context, bail};
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::any::Any;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS stores (
+    name TEXT PRIMARY KEY,
+    data BLOB NOT NULL,
+    version INTEGER NOT NULL,
+    checksum INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+)";
+
+/// What the `stores` table records about a store besides its bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredRow {
+    /// Bumped on every persist, starting at 1.
+    pub version: u64,
+    /// CRC-32 of the bytes, verified on load.
+    pub checksum: u32,
+    pub updated_at: SystemTime,
+}
+
+/// Keeps a store as one row of the `stores` table in a SQLite database,
+/// keyed by name, alongside a version, checksum and update time that can be
+/// queried with `row` or any SQLite client. Each persist is a single
+/// statement, so it lands whole or not at all.
+///
+/// Many stores can share one database. Those opened through `sibling`
+/// also share its connection, so a `Transaction` can write them together.
+///
+/// ```ignore
+/// let catalog = SqliteBackend::open(dir.join("stores.db"), "catalog")?;
+/// let index = catalog.sibling("index");
+/// let catalog = Store::builder().backend(catalog).fetcher(CatalogFetcher).build().await?;
+/// ```
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+    name: String,
+}
+
+impl SqliteBackend {
+    /// The row `name` of the database at `path`, created along with the
+    /// table if missing.
+    pub fn open(path: impl AsRef<Path>, name: impl Into<String>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let conn =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        conn.execute(SCHEMA, [])?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            name: name.into(),
+        })
+    }
+
+    /// Row `name` of the same database, over the same connection.
+    pub fn sibling(&self, name: impl Into<String>) -> Self {
+        Self {
+            conn: self.conn.clone(),
+            name: name.into(),
+        }
+    }
+
+    /// The store's metadata, or `None` when nothing has been stored yet.
+    pub fn row(&self) -> Result<Option<StoredRow>, anyhow::Error> {
+        let conn = self.conn.lock();
+        let row = conn
+            .query_row(
+                "SELECT version, checksum, updated_at FROM stores WHERE name = ?1",
+                params![self.name],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        Ok(row.map(|(version, checksum, updated_at)| StoredRow {
+            version: version as u64,
+            checksum: checksum as u32,
+            updated_at: UNIX_EPOCH + Duration::from_millis(updated_at),
+        }))
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn load(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let conn = self.conn.lock();
+        let row = conn
+            .query_row(
+                "SELECT data, checksum FROM stores WHERE name = ?1",
+                params![self.name],
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()
+            .with_context(|| format!("Failed to load store {}", self.name))?;
+        let Some((data, checksum)) = row else {
+            return Ok(None);
+        };
+        if crc32(&data) != checksum as u32 {
+            bail!("Store {} failed its checksum", self.name);
+        }
+        Ok(Some(data))
+    }
+
+    fn persist(&self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        upsert(&self.conn.lock(), &self.name, bytes)
+    }
+}
+
+fn upsert(conn: &Connection, name: &str, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    let updated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    conn.execute(
+        "INSERT INTO stores (name, data, version, checksum, updated_at)
+         VALUES (?1, ?2, 1, ?3, ?4)
+         ON CONFLICT (name) DO UPDATE SET
+            data = excluded.data,
+            version = version + 1,
+            checksum = excluded.checksum,
+            updated_at = excluded.updated_at",
+        params![name, bytes, crc32(bytes) as i64, updated_at],
+    )
+    .with_context(|| format!("Failed to persist store {}", name))?;
+    Ok(())
+}
+
+/// Persists every `(backend, bytes)` pair in one SQLite transaction, for
+/// `Transaction`. The backends must be `SqliteBackend`s sharing a
+/// connection.
+pub(super) fn persist_all(writes: &[(&dyn StorageBackend, &[u8])]) -> Result<(), anyhow::Error> {
+    let mut rows = Vec::with_capacity(writes.len());
+    for (backend, bytes) in writes {
+        let backend: &dyn Any = *backend;
+        let Some(backend) = backend.downcast_ref::<SqliteBackend>() else {
+            bail!("Transactions only support file and SQLite stores");
+        };
+        rows.push((backend, *bytes));
+    }
+    let Some((first, _)) = rows.first() else {
+        return Ok(());
+    };
+    if rows.iter().any(|(b, _)| !Arc::ptr_eq(&b.conn, &first.conn)) {
+        bail!("SQLite stores in one transaction must be opened as siblings");
+    }
+    let mut conn = first.conn.lock();
+    let txn = conn.transaction()?;
+    for (backend, bytes) in rows {
+        upsert(&txn, &backend.name, bytes)?;
+    }
+    txn.commit().context("Failed to commit SQLite transaction")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempDir};
+    use crate::simple_store::{Store, Transaction};
+
+    #[test]
+    fn keeps_a_row_per_store() {
+        let dir = TempDir::new().unwrap();
+        let db = dir.path().join("stores.db");
+        let backend = SqliteBackend::open(&db, "count").unwrap();
+        assert_eq!(backend.row().unwrap(), None);
+        let store: Store<Count> = Store::with_backend(backend, || Ok(Count(1))).unwrap();
+        store.write(Count(2)).unwrap();
+
+        let backend = SqliteBackend::open(&db, "count").unwrap();
+        let row = backend.row().unwrap().unwrap();
+        assert_eq!((row.version, row.checksum), (2, crc32(&[2])));
+        let reopened: Store<Count> =
+            Store::with_backend(backend, || unreachable!("data is stored")).unwrap();
+        assert_eq!(*reopened.read(), Count(2));
+
+        let other = SqliteBackend::open(&db, "other").unwrap();
+        assert_eq!(other.load().unwrap(), None);
+
+        Connection::open(&db)
+            .unwrap()
+            .execute("UPDATE stores SET data = x'07' WHERE name = 'count'", [])
+            .unwrap();
+        assert!(SqliteBackend::open(&db, "count").unwrap().load().is_err());
+    }
+
+    #[test]
+    fn siblings_commit_together() {
+        let dir = TempDir::new().unwrap();
+        let db = dir.path().join("stores.db");
+        let index = SqliteBackend::open(&db, "index").unwrap();
+        let data = index.sibling("data");
+        let index: Store<Count> = Store::with_backend(index, || Ok(Count(0))).unwrap();
+        let data: Store<Count> = Store::with_backend(data, || Ok(Count(0))).unwrap();
+        Transaction::new()
+            .stage(&index, Count(1))
+            .stage(&data, Count(1))
+            .commit_all()
+            .unwrap();
+        assert_eq!(*index.read(), Count(1));
+        assert_eq!(*data.read(), Count(1));
+        let row = SqliteBackend::open(&db, "data").unwrap().row().unwrap();
+        assert_eq!(row.unwrap().version, 2);
+
+        // Another connection to the same file can't join in
+        let apart = SqliteBackend::open(&db, "apart").unwrap();
+        let apart: Store<Count> = Store::with_backend(apart, || Ok(Count(0))).unwrap();
+        let err = Transaction::new()
+            .stage(&index, Count(2))
+            .stage(&apart, Count(2))
+            .commit_all();
+        assert!(err.is_err());
+        assert_eq!(*index.read(), Count(1));
+        assert_eq!(*apart.read(), Count(0));
+    }
+}
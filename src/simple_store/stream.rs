@@ -0,0 +1,269 @@
+use super::{LoadFailure, SizeExceeded, Store, backup, codec, lock, read_only, recover};
+use anyhow::Context;
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Like `Codec`, but reading and writing the store file incrementally, so a
+/// store of hundreds of megabytes doesn't need its whole serialized form in
+/// memory alongside the value. Wrappers over serde formats can usually pass
+/// the reader and writer straight to `from_reader`/`to_writer`.
+pub trait StreamCodec<T>: Default {
+    fn encode_to(&self, value: &T, out: &mut dyn Write) -> Result<(), anyhow::Error>;
+    fn decode_from(&self, input: &mut dyn Read) -> Result<T, anyhow::Error>;
+}
+
+/// A `T` stored through the streaming codec `S`. Derefs to the value.
+///
+/// Open with `Store::open_streamed` and write with `write_streamed` to keep
+/// peak memory down. Everything else works as for any store, going through
+/// an in-memory buffer: `write` and `update`, write-behind, custom backends
+/// and replicas.
+///
+/// ```ignore
+/// let store = Store::<Streamed<Catalog, CatalogCodec>>::open_streamed(loc, Catalog::default)?;
+/// store.write_streamed(fetch_catalog().await?)?;
+/// ```
+pub struct Streamed<T, S> {
+    value: T,
+    _codec: PhantomData<fn() -> S>,
+}
+
+impl<T, S> Streamed<T, S> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _codec: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Default, S> Default for Streamed<T, S> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T, S> Deref for Streamed<T, S> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, S> DerefMut for Streamed<T, S> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T, S: StreamCodec<T>> TryFrom<Vec<u8>> for Streamed<T, S> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        S::default()
+            .decode_from(&mut value.as_slice())
+            .map(Self::new)
+    }
+}
+
+impl<'a, T, S: StreamCodec<T>> From<&'a Streamed<T, S>> for Vec<u8> {
+    fn from(value: &'a Streamed<T, S>) -> Self {
+        let mut out = Vec::new();
+        S::default()
+            .encode_to(&value.value, &mut out)
+            .expect("writing to a Vec can't fail");
+        out
+    }
+}
+
+/// Fails writes past `limit` with `SizeExceeded`, so an oversized value is
+/// abandoned partway instead of written out in full first.
+struct Limited<'a> {
+    out: &'a mut dyn Write,
+    written: usize,
+    limit: Option<usize>,
+    loc: &'a PathBuf,
+}
+
+impl Write for Limited<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.written + buf.len();
+        if let Some(limit) = self.limit
+            && written > limit
+        {
+            let exceeded = SizeExceeded {
+                path: self.loc.clone(),
+                size: written,
+                limit,
+            };
+            return Err(std::io::Error::other(exceeded));
+        }
+        let n = self.out.write(buf)?;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<T, S: StreamCodec<T>> Store<Streamed<T, S>> {
+    /// Opens the store at `loc`, decoding the file as it is read. When there
+    /// is no file, or it fails to decode and is quarantined, the store
+    /// starts from `getter`'s data, which is written out the same way.
+    pub fn open_streamed(loc: PathBuf, getter: impl FnOnce() -> T) -> Result<Self, anyhow::Error> {
+        Self::open_streamed_with(loc, LoadFailure::default(), getter)
+    }
+
+    /// `open_streamed`, handling a file that fails to decode per
+    /// `on_failure`.
+    pub fn open_streamed_with(
+        loc: PathBuf,
+        on_failure: LoadFailure,
+        getter: impl FnOnce() -> T,
+    ) -> Result<Self, anyhow::Error> {
+        if let Ok(file) = std::fs::File::open(&loc) {
+            let started = std::time::Instant::now();
+            let decoded = S::default()
+                .decode_from(&mut BufReader::new(file))
+                .map(|value| (value, started.elapsed()));
+            if let Some((value, elapsed)) = recover(&loc, &on_failure.into(), decoded)? {
+                let store = Store::from_parts(Streamed::new(value), loc, codec::raw());
+                store.record_deserialize(Some(elapsed));
+                return Ok(store);
+            }
+        }
+        let store = Store::from_parts(Streamed::new(getter()), loc, codec::raw());
+        let data = store.read_owned();
+        store.persist_streamed(&data.value)?;
+        Ok(store)
+    }
+
+    /// `write` that encodes `value` straight to the file. A `SizeLimit`
+    /// rejects oversized values, but can't shrink them. Stores with a
+    /// backend, write-behind or replicas fall back to `write`, as they need
+    /// the serialized form in memory anyway.
+    pub fn write_streamed(&self, value: T) -> Result<(), anyhow::Error> {
+        if self.inner.backend.is_some()
+            || self.inner.write_behind.lock().is_some()
+            || !self.inner.replicas.lock().is_empty()
+        {
+            return self.write(Streamed::new(value));
+        }
+        self.check_writable()?;
+        let writer = self.inner.writer.lock();
+        self.persist_streamed(&value)?;
+        let generation = self.swap(Arc::new(Streamed::new(value)));
+        drop(writer);
+        self.run_write_hooks(generation);
+        Ok(())
+    }
+
+    fn persist_streamed(&self, value: &T) -> Result<(), anyhow::Error> {
+        let loc = &self.inner.loc;
+        let limit = self.inner.size_limit.lock().as_ref().map(|l| l.max_bytes);
+        let _lock = self
+            .lock_policy()
+            .map(|policy| lock::acquire(loc, policy))
+            .transpose()?;
+        let started = std::time::Instant::now();
+        let mut written = 0;
+        backup::save_file_with(loc, self.durability(), self.backups(), |out| {
+            let mut out = Limited {
+                out,
+                written: 0,
+                limit,
+                loc,
+            };
+            let encoded = S::default().encode_to(value, &mut out);
+            written = out.written;
+            // Surface a size limit as itself rather than as an I/O error
+            encoded.map_err(|e| match e.downcast::<std::io::Error>() {
+                Ok(io) => match io.downcast::<SizeExceeded>() {
+                    Ok(exceeded) => exceeded.into(),
+                    Err(io) => io.into(),
+                },
+                Err(e) => e,
+            })
+        })
+        .with_context(|| format!("Failed to stream store to {}", loc.display()))?;
+        self.inner.metrics.lock().last_serialize = Some(started.elapsed());
+        *self.inner.written.lock() = read_only::file_stamp(loc);
+        self.record_written(written);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::SizeLimit;
+    use crate::simple_store::testing::TempDir;
+
+    /// Numbers, one per line, read and written a line at a time.
+    #[derive(Default)]
+    struct LinesCodec;
+
+    impl StreamCodec<Vec<u32>> for LinesCodec {
+        fn encode_to(&self, value: &Vec<u32>, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+            for n in value {
+                writeln!(out, "{}", n)?;
+            }
+            Ok(())
+        }
+
+        fn decode_from(&self, input: &mut dyn Read) -> Result<Vec<u32>, anyhow::Error> {
+            let mut text = String::new();
+            input.read_to_string(&mut text)?;
+            Ok(text.lines().map(str::parse).collect::<Result<_, _>>()?)
+        }
+    }
+
+    type Numbers = Streamed<Vec<u32>, LinesCodec>;
+
+    #[test]
+    fn streams_to_and_from_disk() {
+        let dir = TempDir::new().unwrap();
+        let loc = dir.path().join("numbers");
+        let store = Store::<Numbers>::open_streamed(loc.clone(), || vec![1, 2]).unwrap();
+        assert_eq!(std::fs::read_to_string(&loc).unwrap(), "1\n2\n");
+
+        store.write_streamed(vec![3, 4, 5]).unwrap();
+        assert_eq!(store.metrics().bytes_written, 10);
+        let reopened = Store::<Numbers>::open_streamed(loc.clone(), Vec::new).unwrap();
+        assert_eq!(**reopened.read(), vec![3, 4, 5]);
+
+        store.set_size_limit(SizeLimit::reject(8));
+        let err = store.write_streamed(vec![10, 20, 30]).unwrap_err();
+        assert!(err.downcast_ref::<SizeExceeded>().is_some());
+        assert_eq!(std::fs::read_to_string(&loc).unwrap(), "3\n4\n5\n");
+
+        std::fs::write(&loc, "not a number").unwrap();
+        let failed = Store::<Numbers>::open_streamed_with(loc.clone(), LoadFailure::Fail, Vec::new);
+        assert!(failed.is_err());
+        let recovered = Store::<Numbers>::open_streamed(loc, || vec![7]).unwrap();
+        assert_eq!(**recovered.read(), vec![7]);
+    }
+
+    #[tokio::test]
+    async fn replicas_get_streamed_writes() {
+        let dir = TempDir::new().unwrap();
+        let store = Store::<Numbers>::open_streamed(dir.path().join("numbers"), Vec::new).unwrap();
+        let mirror = crate::simple_store::MemoryBackend::new();
+        store.add_replica_backend(mirror.clone());
+        store.write_streamed(vec![8, 9]).unwrap();
+        while store.replica_status()[0].lag > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(mirror.bytes().unwrap(), b"8\n9\n");
+    }
+}
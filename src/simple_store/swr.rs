@@ -0,0 +1,101 @@
+use super::Store;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::warn;
+
+impl<T> Store<T> {
+    /// Makes `get` start a background refresh, through the fetcher given to
+    /// `with_fetcher`, once the data is older than `soft_ttl`.
+    pub fn with_soft_ttl(self, soft_ttl: Duration) -> Self {
+        self.set_soft_ttl(soft_ttl);
+        self
+    }
+
+    /// `with_soft_ttl` for a store that's already shared.
+    pub fn set_soft_ttl(&self, soft_ttl: Duration) {
+        *self.inner.soft_ttl.lock() = Some(soft_ttl);
+    }
+}
+
+impl<T> Store<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Stale-while-revalidate: returns the current data straight away, and
+    /// if it is older than the store's soft TTL also starts a refresh in the
+    /// background for later callers to see. At most one such refresh runs
+    /// at a time; a failed one is logged and the next stale `get` tries
+    /// again. Without a soft TTL this is just `read_owned`.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn get(&self) -> Arc<T> {
+        let data = self.read_owned();
+        let stale = self
+            .inner
+            .soft_ttl
+            .lock()
+            .is_some_and(|soft_ttl| self.age() > soft_ttl);
+        if stale
+            && self
+                .inner
+                .revalidating
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            let mvstore = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = mvstore.refresh_now().await {
+                    warn!(
+                        "Failed to revalidate stale store {}: {:#}",
+                        mvstore.inner.loc.display(),
+                        e
+                    );
+                }
+                mvstore.inner.revalidating.store(false, Ordering::Release);
+            });
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Fetcher;
+    use crate::simple_store::testing::{Count, TempStore};
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicU8;
+
+    struct Counting(Arc<AtomicU8>);
+
+    #[async_trait]
+    impl Fetcher<Count> for Counting {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            Ok(Count(self.0.fetch_add(1, Ordering::SeqCst) + 1))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn serves_stale_data_while_revalidating() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let calls = Arc::new(AtomicU8::new(0));
+        let store = tmp
+            .store()
+            .clone()
+            .with_fetcher(Counting(calls.clone()))
+            .with_soft_ttl(Duration::from_secs(60));
+        store.write(Count(0)).unwrap();
+        assert_eq!(*store.get(), Count(0));
+        tokio::task::yield_now().await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let mut changes = store.subscribe();
+        assert_eq!(*store.get(), Count(0));
+        assert_eq!(*store.get(), Count(0));
+        changes.changed().await.unwrap();
+        assert_eq!(*store.get(), Count(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
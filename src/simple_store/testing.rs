@@ -0,0 +1,642 @@
+//! Helpers for testing code built on `Store` without hand-rolling temp files.
+//!
+//! Enable the `testing` feature from a dev-dependency to use these downstream,
+//! and `proptest` for `check_codec`.
+
+#[cfg(any(test, feature = "proptest"))]
+use super::LoadFailure;
+use super::Store;
+#[cfg(any(test, feature = "proptest"))]
+use super::codec::{Codec, SharedCodec};
+use crate::rng::SeededRng;
+use anyhow::{Context, anyhow, bail};
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{self, Instant};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A uniquely named directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let name = format!(
+            "kitchen-sink-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+pub(crate) use fixtures::{Climb, Count, Increment, Text};
+
+/// Data and fetchers shared by the crate's own tests.
+#[cfg(test)]
+mod fixtures {
+    use crate::simple_store::{Fetcher, Store};
+    use anyhow::bail;
+    use async_trait::async_trait;
+
+    /// UTF-8 text as a store's data, for tests that only need something to
+    /// write. Invalid UTF-8 fails to load.
+    #[derive(Debug, Default, Clone, PartialEq, Eq)]
+    pub(crate) struct Text(pub String);
+
+    impl TryFrom<Vec<u8>> for Text {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Text(String::from_utf8(value)?))
+        }
+    }
+
+    impl TryFrom<&[u8]> for Text {
+        type Error = anyhow::Error;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            Ok(Text(std::str::from_utf8(value)?.to_owned()))
+        }
+    }
+
+    impl<'a> From<&'a Text> for Vec<u8> {
+        fn from(value: &'a Text) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    /// A single byte as a store's data, for tests that count fetches or
+    /// writes. Anything but exactly one byte fails to load.
+    #[derive(Debug, Default, Clone, PartialEq, Eq)]
+    pub(crate) struct Count(pub u8);
+
+    impl TryFrom<Vec<u8>> for Count {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            match value.as_slice() {
+                [n] => Ok(Count(*n)),
+                _ => bail!("expected one byte, got {}", value.len()),
+            }
+        }
+    }
+
+    impl<'a> From<&'a Count> for Vec<u8> {
+        fn from(value: &'a Count) -> Self {
+            vec![value.0]
+        }
+    }
+
+    /// Counts up from the store's value, starting at 1.
+    pub(crate) struct Increment;
+
+    #[async_trait]
+    impl Fetcher<Count> for Increment {
+        async fn fetch(&self, store: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            Ok(Count(store.map(|s| s.read().0).unwrap_or_default() + 1))
+        }
+    }
+
+    /// Counts up to its cap and then stays there.
+    pub(crate) struct Climb(pub u8);
+
+    #[async_trait]
+    impl Fetcher<Count> for Climb {
+        async fn fetch(&self, store: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            let current = store.map(|s| s.read().0).unwrap_or_default();
+            Ok(Count((current + 1).min(self.0)))
+        }
+    }
+}
+
+/// A `Store` persisted inside its own `TempDir`. Derefs to the `Store`, and
+/// the directory is cleaned up when this is dropped.
+///
+/// # Example
+/// ```ignore
+/// let tmp: TempStore<MyData> = TempStore::new()?;
+/// tmp.write(MyData::default())?;
+/// tmp.corrupt_truncate(3)?;
+/// assert!(tmp.reopen().is_err());
+/// ```
+pub struct TempStore<T> {
+    store: Store<T>,
+    path: PathBuf,
+    dir: TempDir,
+}
+
+impl<T> Deref for TempStore<T> {
+    type Target = Store<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<T: Default + TryFrom<Vec<u8>, Error = anyhow::Error>> TempStore<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// A store initialized with `T::default()`.
+    pub fn new() -> Result<Self, anyhow::Error> {
+        Self::from_value(T::default())
+    }
+}
+
+impl<T: TryFrom<Vec<u8>, Error = anyhow::Error>> TempStore<T>
+where
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    /// A store whose file initially holds the serialized `value`.
+    pub fn from_value(value: T) -> Result<Self, anyhow::Error> {
+        let bytes: Vec<u8> = (&value).into();
+        Self::from_bytes(bytes)
+    }
+
+    /// A store loaded from raw bytes, as if they had been persisted earlier.
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, anyhow::Error> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("store");
+        std::fs::write(&path, bytes)?;
+        let store = Store::new_or_get(path.clone(), || Err(anyhow!("fixture missing")))?;
+        Ok(Self { store, path, dir })
+    }
+
+    /// A store loaded from a fixture file, which is copied so the original is
+    /// never modified.
+    pub fn from_fixture(fixture: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let fixture = fixture.as_ref();
+        let bytes = std::fs::read(fixture)
+            .with_context(|| format!("Failed to read fixture {}", fixture.display()))?;
+        Self::from_bytes(bytes)
+    }
+
+    /// Loads a fresh `Store` from the current file contents, which is how to
+    /// observe the effect of the corruption helpers.
+    pub fn reopen(&self) -> Result<Store<T>, anyhow::Error> {
+        Store::new_or_get(self.path.clone(), || Err(anyhow!("store file missing")))
+    }
+}
+
+impl<T> TempStore<T> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn store(&self) -> &Store<T> {
+        &self.store
+    }
+
+    /// The bytes currently on disk.
+    pub fn bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(std::fs::read(&self.path)?)
+    }
+
+    /// Cuts the persisted file down to `len` bytes, like a write interrupted
+    /// by a crash.
+    pub fn corrupt_truncate(&self, len: usize) -> Result<(), anyhow::Error> {
+        let mut bytes = self.bytes()?;
+        bytes.truncate(len);
+        Ok(std::fs::write(&self.path, bytes)?)
+    }
+
+    /// Inverts the bits of the byte at `idx`.
+    pub fn corrupt_flip_byte(&self, idx: usize) -> Result<(), anyhow::Error> {
+        let mut bytes = self.bytes()?;
+        let byte = bytes
+            .get_mut(idx)
+            .ok_or_else(|| anyhow!("offset {} is past the end of the file", idx))?;
+        *byte = !*byte;
+        Ok(std::fs::write(&self.path, bytes)?)
+    }
+
+    /// Replaces the file contents with arbitrary bytes.
+    pub fn corrupt_with(&self, bytes: impl AsRef<[u8]>) -> Result<(), anyhow::Error> {
+        Ok(std::fs::write(&self.path, bytes)?)
+    }
+
+    /// Files in the temp dir that a failed load moved aside as corrupt.
+    pub fn quarantined(&self) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(self.dir())? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().contains(".corrupt-"))
+            {
+                found.push(path);
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Deletes the persisted file.
+    pub fn remove_file(&self) -> Result<(), anyhow::Error> {
+        Ok(std::fs::remove_file(&self.path)?)
+    }
+}
+
+/// Persists `value` through a fresh `TempStore`, reloads it from disk, and
+/// checks the result is equal. Call it from a property test to cover
+/// arbitrary inputs, or use `check_codec`, which generates them:
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn codec_round_trips(v in any::<MyData>(), seed in any::<u64>()) {
+///         assert_round_trip(v).unwrap();
+///         check_mutations(&v, seed, 64).unwrap();
+///     }
+/// }
+/// ```
+pub fn assert_round_trip<T>(value: T) -> Result<(), anyhow::Error>
+where
+    T: PartialEq + Debug + TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    let tmp = TempStore::from_value(value)?;
+    let written: Vec<u8> = (&*tmp.read()).into();
+    let reloaded = tmp.reopen()?;
+    if *reloaded.read() != *tmp.read() {
+        bail!(
+            "round trip changed the value: wrote {:?}, read back {:?}",
+            *tmp.read(),
+            *reloaded.read()
+        );
+    }
+    if tmp.bytes()? != written {
+        bail!("persisted bytes differ from the serialized value");
+    }
+    Ok(())
+}
+
+/// Loads `rounds` randomly damaged copies of the serialized `value` (flipped,
+/// dropped, and inserted bytes, plus truncation), failing if any load panics.
+/// Loads may succeed or return errors; the point is that bad bytes never take
+/// the process down.
+pub fn check_mutations<T>(value: &T, seed: u64, rounds: usize) -> Result<(), anyhow::Error>
+where
+    T: TryFrom<Vec<u8>, Error = anyhow::Error>,
+    for<'a> Vec<u8>: From<&'a T>,
+{
+    let original: Vec<u8> = value.into();
+    let mut rng = SeededRng::new(seed);
+    for round in 0..rounds {
+        let mut bytes = original.clone();
+        let at = rng.below(bytes.len() as u64 + 1) as usize;
+        damage(&mut bytes, at, rng.below(4) as u8, rng.below(256) as u8);
+        if catch_unwind(AssertUnwindSafe(|| TempStore::<T>::from_bytes(&bytes))).is_err() {
+            bail!(
+                "loading mutated bytes panicked (seed {}, round {}): {:?}",
+                seed,
+                round,
+                bytes
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Flips a bit of, drops, or inserts `byte` at, the byte at `at`, or
+/// truncates there, by `kind`. `at` may be the length.
+fn damage(bytes: &mut Vec<u8>, at: usize, kind: u8, byte: u8) {
+    match kind % 4 {
+        0 if at < bytes.len() => bytes[at] ^= 1 << (byte % 8),
+        1 if at < bytes.len() => {
+            bytes.remove(at);
+        }
+        2 => bytes.insert(at, byte),
+        _ => bytes.truncate(at),
+    }
+}
+
+/// Round-trips 64 values drawn from `strategy` through a store persisting
+/// with `codec`, failing unless each reloads equal, then loads damaged
+/// copies of each file, failing if a load panics. `codec` may stack
+/// compression, encryption and checksums. A failure is shrunk to the
+/// smallest value and damage that reproduce it. Behind the `proptest`
+/// feature.
+///
+/// ```ignore
+/// let codec = Encrypted::new(Compressed::new(JsonCodec::default(), Gzip::default()), cipher);
+/// check_codec(any::<Catalog>(), codec)?;
+/// ```
+#[cfg(any(test, feature = "proptest"))]
+pub fn check_codec<T, C>(
+    strategy: impl proptest::strategy::Strategy<Value = T>,
+    codec: C,
+) -> Result<(), anyhow::Error>
+where
+    T: PartialEq + Debug + Send + Sync + 'static,
+    C: Codec<T> + Send + Sync + 'static,
+{
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use proptest::test_runner::{Config, TestCaseError, TestRunner};
+
+    let codec: SharedCodec<T> = std::sync::Arc::new(codec);
+    let damages = prop::collection::vec((any::<Index>(), any::<u8>(), any::<u8>()), 1..8);
+    let mut runner = TestRunner::new(Config {
+        cases: 64,
+        ..Config::default()
+    });
+    let fail = |e: anyhow::Error| TestCaseError::fail(format!("{:#}", e));
+    runner
+        .run(&(strategy, damages), |(value, damages)| {
+            let dir = TempDir::new().map_err(fail)?;
+            let path = dir.path().join("store");
+            let bytes = round_trip(&path, value, &codec).map_err(fail)?;
+            for (at, kind, byte) in damages {
+                let mut damaged = bytes.clone();
+                damage(&mut damaged, at.index(bytes.len() + 1), kind, byte);
+                std::fs::write(&path, &damaged).map_err(|e| fail(e.into()))?;
+                let load = || {
+                    Store::load_or_get(
+                        path.clone(),
+                        codec.clone(),
+                        LoadFailure::Fail.into(),
+                        || Err(anyhow!("store file missing")),
+                    )
+                };
+                if catch_unwind(AssertUnwindSafe(load)).is_err() {
+                    return Err(TestCaseError::fail(format!(
+                        "loading damaged bytes panicked: {:?}",
+                        damaged
+                    )));
+                }
+            }
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))
+}
+
+/// Persists `value` at `path` through `codec`, reopens it, and checks the
+/// reloaded value is equal. Returns the bytes on disk.
+#[cfg(any(test, feature = "proptest"))]
+fn round_trip<T>(path: &Path, value: T, codec: &SharedCodec<T>) -> Result<Vec<u8>, anyhow::Error>
+where
+    T: PartialEq + Debug,
+{
+    let open = |value: Option<T>| {
+        Store::load_or_get(
+            path.to_path_buf(),
+            codec.clone(),
+            LoadFailure::Fail.into(),
+            || value.ok_or_else(|| anyhow!("store file missing")),
+        )
+    };
+    let store = open(Some(value))?;
+    let reloaded = open(None)?;
+    if *reloaded.read() != *store.read() {
+        bail!(
+            "round trip changed the value: wrote {:?}, read back {:?}",
+            *store.read(),
+            *reloaded.read()
+        );
+    }
+    Ok(std::fs::read(path)?)
+}
+
+/// Drives tokio's paused clock so interval- and age-based logic can be tested
+/// deterministically without real sleeps. Time in this crate is measured with
+/// `tokio::time`, so everything built on it follows the paused clock.
+///
+/// Must be created inside a current-thread runtime such as a plain
+/// `#[tokio::test]`.
+///
+/// # Example
+/// ```ignore
+/// let time = TimeHarness::pause();
+/// store.scheduled_updates(fetcher, Duration::from_secs(180));
+/// time.advance_until_refresh(&store).await?;
+/// ```
+pub struct TimeHarness {
+    started: Instant,
+}
+
+impl TimeHarness {
+    /// Yields this many times after each step so woken tasks can finish.
+    const SETTLE_YIELDS: usize = 64;
+
+    /// Pauses the clock, panicking if it is already paused.
+    pub fn pause() -> Self {
+        time::pause();
+        Self {
+            started: Instant::now(),
+        }
+    }
+
+    /// Virtual time elapsed since the harness was created.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Moves the clock forward and lets any tasks whose timers fired run.
+    pub async fn advance(&self, by: Duration) {
+        time::advance(by).await;
+        settle(|| false).await;
+    }
+
+    /// Advances 100ms at a time until `store` has been refreshed, failing after
+    /// a day of virtual time.
+    pub async fn advance_until_refresh<T>(&self, store: &Store<T>) -> Result<(), anyhow::Error> {
+        self.advance_until_refresh_by(
+            store,
+            Duration::from_millis(100),
+            Duration::from_secs(86400),
+        )
+        .await
+    }
+
+    /// Advances by `step` until `store` has been refreshed, failing once `limit`
+    /// of virtual time has passed.
+    pub async fn advance_until_refresh_by<T>(
+        &self,
+        store: &Store<T>,
+        step: Duration,
+        limit: Duration,
+    ) -> Result<(), anyhow::Error> {
+        // Let freshly spawned refresh loops register their timers first
+        settle(|| false).await;
+        let generation = store.generation();
+        let updated_at = *store.inner.updated_at.lock();
+        // A refresh that fetched identical data marks the store fresh
+        // without a new generation
+        let refreshed =
+            || store.generation() != generation || *store.inner.updated_at.lock() != updated_at;
+        let deadline = Instant::now() + limit;
+        while Instant::now() < deadline {
+            time::advance(step).await;
+            if settle(refreshed).await {
+                return Ok(());
+            }
+        }
+        Err(anyhow!("store was not refreshed within {:?}", limit))
+    }
+}
+
+async fn settle(mut done: impl FnMut() -> bool) -> bool {
+    for _ in 0..TimeHarness::SETTLE_YIELDS {
+        if done() {
+            return true;
+        }
+        tokio::task::yield_now().await;
+    }
+    done()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Counter(u32);
+
+    impl TryFrom<Vec<u8>> for Counter {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            match <[u8; 4]>::try_from(value) {
+                Ok(bytes) => Ok(Counter(u32::from_le_bytes(bytes))),
+                Err(v) => bail!("expected 4 bytes, got {}", v.len()),
+            }
+        }
+    }
+
+    impl<'a> From<&'a Counter> for Vec<u8> {
+        fn from(value: &'a Counter) -> Self {
+            value.0.to_le_bytes().to_vec()
+        }
+    }
+
+    #[derive(Clone)]
+    struct Increment;
+
+    #[async_trait::async_trait]
+    impl super::super::Fetcher<Counter> for Increment {
+        async fn fetch(&self, store: Option<Store<Counter>>) -> Result<Counter, anyhow::Error> {
+            Ok(Counter(store.map(|s| s.read().0 + 1).unwrap_or_default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn advances_virtual_time_to_each_refresh() {
+        let time = TimeHarness::pause();
+        let tmp: TempStore<Counter> = TempStore::new().unwrap();
+        tmp.scheduled_updates(Increment, Duration::from_secs(180));
+
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(*tmp.read(), Counter(1));
+        assert!(time.elapsed() > Duration::from_secs(180));
+        assert!(time.elapsed() < Duration::from_secs(181));
+        time.advance_until_refresh(&tmp).await.unwrap();
+        assert_eq!(*tmp.read(), Counter(2));
+        assert!(time.elapsed() < Duration::from_secs(361));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn property_helpers_accept_well_behaved_codecs(n: u32, seed: u64) {
+            assert_round_trip(Counter(n)).unwrap();
+            check_mutations(&Counter(n), seed, 8).unwrap();
+        }
+    }
+
+    /// Decodes through `TryFrom` but encodes lossily, as a broken codec
+    /// might.
+    struct Lossy;
+
+    impl Codec<Counter> for Lossy {
+        fn encode(&self, value: &Counter) -> Result<Vec<u8>, anyhow::Error> {
+            Ok((value.0 & !1).to_le_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: Vec<u8>) -> Result<Counter, anyhow::Error> {
+            Counter::try_from(bytes)
+        }
+    }
+
+    #[test]
+    fn check_codec_catches_lossy_codecs() {
+        use proptest::prelude::*;
+
+        check_codec(any::<u32>().prop_map(Counter), super::super::Raw).unwrap();
+        let err = check_codec(any::<u32>().prop_map(Counter), Lossy).unwrap_err();
+        assert!(err.to_string().contains("round trip changed the value"));
+    }
+
+    #[test]
+    fn damaged_or_empty_files_fail_to_load() {
+        assert!(TempStore::<Count>::from_bytes([]).is_err());
+        assert!(TempStore::<Count>::from_bytes([1, 2]).is_err());
+        assert!(TempStore::<Text>::from_bytes([0xff, 0xfe]).is_err());
+
+        let tmp = TempStore::from_value(Count(4)).unwrap();
+        tmp.corrupt_truncate(0).unwrap();
+        assert!(tmp.reopen().is_err());
+        assert_eq!(tmp.quarantined().unwrap().len(), 1);
+        // The open store keeps what it loaded
+        assert_eq!(*tmp.read(), Count(4));
+    }
+
+    #[test]
+    fn round_trips_and_detects_corruption() {
+        let tmp: TempStore<Counter> = TempStore::from_value(Counter(7)).unwrap();
+        assert_eq!(*tmp.read(), Counter(7));
+        tmp.write(Counter(9)).unwrap();
+        assert_eq!(*tmp.reopen().unwrap().read(), Counter(9));
+
+        tmp.corrupt_flip_byte(0).unwrap();
+        assert_eq!(*tmp.reopen().unwrap().read(), Counter(!9u8 as u32));
+        tmp.remove_file().unwrap();
+        assert!(tmp.reopen().is_err());
+
+        tmp.write(Counter(3)).unwrap();
+        tmp.corrupt_truncate(2).unwrap();
+        assert!(tmp.reopen().is_err());
+        let quarantined = tmp.quarantined().unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(std::fs::read(&quarantined[0]).unwrap(), vec![3, 0]);
+        assert!(!tmp.path().exists());
+
+        tmp.corrupt_with([1]).unwrap();
+        let healed = Store::new_or_get(tmp.path().to_path_buf(), || Ok(Counter(5))).unwrap();
+        assert_eq!(*healed.read(), Counter(5));
+        assert_eq!(tmp.bytes().unwrap(), vec![5, 0, 0, 0]);
+        assert_eq!(tmp.quarantined().unwrap().len(), 2);
+
+        let dir = tmp.dir().to_path_buf();
+        drop(tmp);
+        assert!(!dir.exists());
+    }
+}
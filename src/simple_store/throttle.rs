@@ -0,0 +1,89 @@
+use super::Store;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Returned by a refresh that was refused, without fetching, because the
+/// store's refresh limit was used up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshThrottled {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for RefreshThrottled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refresh of store {} refused by its rate limit",
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for RefreshThrottled {}
+
+impl<T> Store<T> {
+    /// Caps how often the store's fetcher runs, however refreshes are
+    /// started: schedules, backoff retries and `refresh` calls all draw
+    /// from the same bucket. A refresh over the limit fails with
+    /// `RefreshThrottled`; one that joins a refresh in flight takes nothing.
+    ///
+    /// ```ignore
+    /// // At most one fetch a minute
+    /// let store = store.with_refresh_limit(RateLimit::every(Duration::from_secs(60)));
+    /// ```
+    pub fn with_refresh_limit(self, limit: RateLimit) -> Self {
+        self.set_refresh_limit(limit);
+        self
+    }
+
+    /// `with_refresh_limit` for a store that's already shared.
+    pub fn set_refresh_limit(&self, limit: RateLimit) {
+        *self.inner.refresh_limit.lock() = Some(RateLimiter::new(limit));
+    }
+
+    /// Takes a token for a fetch, if the store is limited.
+    pub(super) fn check_refresh_limit(&self) -> Result<(), RefreshThrottled> {
+        match &*self.inner.refresh_limit.lock() {
+            Some(limiter) if !limiter.check() => Err(RefreshThrottled {
+                path: self.inner.loc.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::Fetcher;
+    use crate::simple_store::testing::{Count, TempStore};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Counting(AtomicU32);
+
+    #[async_trait]
+    impl Fetcher<Count> for Counting {
+        async fn fetch(&self, _store: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            Ok(Count(self.0.fetch_add(1, Ordering::Relaxed) as u8 + 1))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_fetches_however_started() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        tmp.set_refresh_limit(RateLimit::every(Duration::from_secs(60)));
+        let fetcher = Counting::default();
+        assert!(tmp.refresh(&fetcher).await.unwrap());
+        let err = tmp.refresh(&fetcher).await.unwrap_err();
+        assert!(err.downcast_ref::<RefreshThrottled>().is_some());
+        assert_eq!(fetcher.0.load(Ordering::Relaxed), 1);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(tmp.refresh(&fetcher).await.unwrap());
+        assert_eq!(*tmp.read(), Count(2));
+    }
+}
@@ -0,0 +1,118 @@
+use super::{FetchResult, Fetcher, Store};
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Wraps a `Fetcher` so a hung upstream fails the fetch with `FetchTimeout`
+/// instead of stalling the initial load or the refresh loop forever. A
+/// timed out scheduled update counts as a failure like any other, so the
+/// `ErrorPolicy` and any `Backoff` apply.
+///
+/// ```ignore
+/// let fetcher = TimeoutFetcher::new(UsersFetcher::new(client), Duration::from_secs(10));
+/// let store = Store::new_with_fetcher(loc, fetcher.clone()).await?;
+/// store.scheduled_updates(fetcher, Duration::from_secs(60));
+/// ```
+#[derive(Clone)]
+pub struct TimeoutFetcher<F> {
+    inner: F,
+    limit: Duration,
+}
+
+impl<F> TimeoutFetcher<F> {
+    pub fn new(inner: F, limit: Duration) -> Self {
+        Self { inner, limit }
+    }
+}
+
+/// A fetch took longer than its `TimeoutFetcher` allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchTimeout {
+    pub after: Duration,
+}
+
+impl fmt::Display for FetchTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fetch timed out after {:?}", self.after)
+    }
+}
+
+impl std::error::Error for FetchTimeout {}
+
+#[async_trait]
+impl<T, F> Fetcher<T> for TimeoutFetcher<F>
+where
+    T: Send + Sync + 'static,
+    F: Fetcher<T> + Send + Sync,
+{
+    async fn fetch(&self, store: Option<Store<T>>) -> Result<T, anyhow::Error> {
+        timeout(self.limit, self.inner.fetch(store))
+            .await
+            .map_err(|_| FetchTimeout { after: self.limit })?
+    }
+
+    async fn fetch_update(&self, store: Store<T>) -> Result<FetchResult<T>, anyhow::Error> {
+        timeout(self.limit, self.inner.fetch_update(store))
+            .await
+            .map_err(|_| FetchTimeout { after: self.limit })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::ErrorPolicy;
+    use crate::simple_store::testing::{Count, TempDir, TimeHarness};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Answers the first fetch, then hangs.
+    struct HangsLater(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl Fetcher<Count> for HangsLater {
+        async fn fetch(&self, _: Option<Store<Count>>) -> Result<Count, anyhow::Error> {
+            if self.0.fetch_add(1, Ordering::SeqCst) > 0 {
+                futures::future::pending::<()>().await;
+            }
+            Ok(Count(1))
+        }
+    }
+
+    #[tokio::test]
+    async fn hung_fetches_fail() {
+        let dir = TempDir::new().unwrap();
+        let calls = Arc::new(AtomicU32::new(0));
+        let fetcher = Arc::new(TimeoutFetcher::new(
+            HangsLater(calls.clone()),
+            Duration::from_secs(5),
+        ));
+        let store = Store::new_with_fetcher(dir.path().join("count"), fetcher.clone())
+            .await
+            .unwrap();
+        assert_eq!(*store.read(), Count(1));
+
+        let time = TimeHarness::pause();
+        let err = store.refresh(&fetcher).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FetchTimeout>(),
+            Some(&FetchTimeout {
+                after: Duration::from_secs(5)
+            })
+        );
+        assert_eq!(store.metrics().consecutive_failures, 1);
+
+        store.scheduled_updates_with_policy(
+            fetcher,
+            Duration::from_secs(10),
+            ErrorPolicy::default(),
+        );
+        // One 10s wait, then a fetch that hangs past its 5s limit
+        for _ in 0..20 {
+            time.advance(Duration::from_secs(1)).await;
+        }
+        assert_eq!(store.metrics().consecutive_failures, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,419 @@
+use super::{Durability, StorageBackend, Store, TMP_COUNTER, lock, read_only};
+use anyhow::{Context, bail};
+use parking_lot::{MutexGuard, RwLockWriteGuard};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tracing::error;
+
+/// New data for several stores, written so that the files on disk never
+/// disagree: every store's data is written to a temp file first, then all
+/// are renamed into place together. If any step fails, the files already
+/// replaced are put back and no store changes, in memory or on disk. The
+/// new data is swapped into every store at once, so readers never see some
+/// stores changed and others not.
+///
+/// ```ignore
+/// Transaction::new()
+///     .stage(&index, new_index)
+///     .stage(&data, new_data)
+///     .commit_all()?;
+/// ```
+///
+/// File stores without write-behind can take part, as can stores kept in
+/// one SQLite database through `SqliteBackend::sibling` (behind the
+/// `sqlite` feature), which commit in a single SQLite transaction. The two
+/// kinds can't be mixed. Stores are locked in the order of their
+/// canonical paths, so transactions over the same stores can't deadlock
+/// whatever order they were staged in.
+///
+/// For file stores, a crash partway through the renames can still leave the
+/// stores out of step; copies of the replaced files are kept beside them as
+/// `.<file>.txn-prev-*` until the commit finishes. Backups aren't rotated
+/// for transactional writes.
+#[derive(Default)]
+pub struct Transaction<'a> {
+    stages: Vec<Box<dyn Staged<'a> + 'a>>,
+}
+
+/// One store's part of a `Transaction`, with the type of its data erased.
+trait Staged<'a> {
+    fn check(&mut self) -> Result<(), anyhow::Error>;
+    /// The store's writer lock, held from before anything is written until
+    /// the data is installed.
+    fn writer(&self) -> MutexGuard<'a, ()>;
+    /// Identifies the store, as file stores can share an empty `loc`.
+    fn id(&self) -> *const ();
+    fn loc(&self) -> &Path;
+    fn backend(&self) -> Option<&dyn StorageBackend>;
+    fn bytes(&self) -> &[u8];
+    fn durability(&self) -> Durability;
+    fn lock_policy(&self) -> Option<lock::LockPolicy>;
+    /// Takes the store's data lock, for `install_all` to swap every
+    /// store's data in while holding them all.
+    fn lock_data(&mut self) -> Box<dyn Swap + 'a>;
+    /// Finishes installing once the data is swapped in as `generation`.
+    fn installed(&mut self, generation: u64);
+    fn run_write_hooks(&self, generation: u64);
+}
+
+/// A store's data lock, held with its staged data ready to swap in.
+trait Swap {
+    /// Swaps the data in, returning its generation.
+    fn swap(&mut self) -> u64;
+}
+
+struct Locked<'a, T> {
+    store: &'a Store<T>,
+    guard: RwLockWriteGuard<'a, Arc<T>>,
+    data: Option<T>,
+}
+
+impl<T> Swap for Locked<'_, T> {
+    fn swap(&mut self) -> u64 {
+        *self.guard = Arc::new(self.data.take().expect("swapped once"));
+        self.store.inner.mark_updated()
+    }
+}
+
+struct Stage<'a, T> {
+    store: &'a Store<T>,
+    // Taken by `lock_data`
+    data: Option<T>,
+    // Fails when over the store's size limit
+    bytes: Result<Vec<u8>, Option<anyhow::Error>>,
+}
+
+impl<'a, T> Staged<'a> for Stage<'a, T> {
+    fn check(&mut self) -> Result<(), anyhow::Error> {
+        if let Err(e) = &mut self.bytes {
+            return Err(e.take().expect("checked once"));
+        }
+        self.store.check_writable()?;
+        if self.store.inner.write_behind.lock().is_some() {
+            bail!(
+                "Store {} defers its writes and can't join a transaction",
+                self.loc().display()
+            );
+        }
+        Ok(())
+    }
+
+    fn writer(&self) -> MutexGuard<'a, ()> {
+        self.store.inner.writer.lock()
+    }
+
+    fn id(&self) -> *const () {
+        Arc::as_ptr(&self.store.inner).cast()
+    }
+
+    fn loc(&self) -> &Path {
+        &self.store.inner.loc
+    }
+
+    fn backend(&self) -> Option<&dyn StorageBackend> {
+        self.store.inner.backend.as_deref()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.bytes.as_deref().expect("checked before use")
+    }
+
+    fn durability(&self) -> Durability {
+        self.store.durability()
+    }
+
+    fn lock_policy(&self) -> Option<lock::LockPolicy> {
+        self.store.lock_policy()
+    }
+
+    fn lock_data(&mut self) -> Box<dyn Swap + 'a> {
+        let store = self.store;
+        let bytes = self.bytes();
+        store.record_written(bytes.len());
+        store.record_history(bytes);
+        *store.inner.written.lock() = read_only::file_stamp(&store.inner.loc);
+        Box::new(Locked {
+            store,
+            guard: store.inner.data.write(),
+            data: self.data.take(),
+        })
+    }
+
+    fn installed(&mut self, generation: u64) {
+        let bytes = std::mem::replace(&mut self.bytes, Err(None));
+        self.store
+            .written(generation, bytes.expect("checked before use"));
+    }
+
+    fn run_write_hooks(&self, generation: u64) {
+        self.store.run_write_hooks(generation);
+    }
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `data` as the new contents of `store`, serializing it now.
+    /// Data over the store's `SizeLimit` fails the commit.
+    pub fn stage<T>(mut self, store: &'a Store<T>, mut data: T) -> Self
+where {
+        let bytes = store.serialize_within_limit(&mut data).map_err(Some);
+        self.stages.push(Box::new(Stage {
+            store,
+            data: Some(data),
+            bytes,
+        }));
+        self
+    }
+
+    /// Writes every staged store, or none of them.
+    pub fn commit_all(self) -> Result<(), anyhow::Error> {
+        let mut stages = self.stages;
+        for idx in 0..stages.len() {
+            stages[idx].check()?;
+            if stages[..idx].iter().any(|s| s.id() == stages[idx].id()) {
+                bail!("Store {} is staged twice", stages[idx].loc().display());
+            }
+        }
+        // The order every lock is taken in
+        stages.sort_by_cached_key(|s| (canonical(s.loc()), s.id()));
+        let writers: Vec<_> = stages.iter().map(|s| s.writer()).collect();
+        match stages.iter().filter(|s| s.backend().is_some()).count() {
+            0 => {}
+            n if n == stages.len() => return commit_backed(stages, writers),
+            _ => bail!("A transaction can't mix file stores with other backends"),
+        }
+        let _locks = stages
+            .iter()
+            .filter_map(|s| s.lock_policy().map(|policy| lock::acquire(s.loc(), policy)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut temps = Vec::with_capacity(stages.len());
+        for stage in &stages {
+            match write_temp(stage.loc(), stage.bytes(), stage.durability()) {
+                Ok(tmp) => temps.push(tmp),
+                Err(e) => {
+                    remove_all(&temps);
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut swapped: Vec<(&Path, Option<PathBuf>)> = Vec::with_capacity(temps.len());
+        for (stage, tmp) in stages.iter().zip(&temps) {
+            if let Err(e) = swap_in(stage.loc(), tmp, &mut swapped) {
+                roll_back(&swapped);
+                remove_all(&temps);
+                return Err(e).context("Transaction rolled back");
+            }
+        }
+        for (_, prev) in &swapped {
+            if let Some(prev) = prev {
+                let _ = std::fs::remove_file(prev);
+            }
+        }
+        let mut synced: Vec<&Path> = Vec::new();
+        for stage in &stages {
+            let dir = stage.loc().parent();
+            if stage.durability().sync_dir && !dir.is_some_and(|d| synced.contains(&d)) {
+                stage.durability().sync_parent(stage.loc())?;
+                synced.extend(dir);
+            }
+        }
+        install_all(stages, writers);
+        Ok(())
+    }
+}
+
+/// Swaps every store's data in with all their data locks held, then
+/// releases the writer locks and runs each store's write hooks.
+fn install_all<'a>(mut stages: Vec<Box<dyn Staged<'a> + 'a>>, writers: Vec<MutexGuard<'a, ()>>) {
+    let mut locked: Vec<_> = stages.iter_mut().map(|s| s.lock_data()).collect();
+    let generations: Vec<u64> = locked.iter_mut().map(|l| l.swap()).collect();
+    drop(locked);
+    for (stage, generation) in stages.iter_mut().zip(&generations) {
+        stage.installed(*generation);
+    }
+    drop(writers);
+    for (stage, generation) in stages.iter().zip(generations) {
+        stage.run_write_hooks(generation);
+    }
+}
+
+/// `commit_all` for stores kept in backends rather than files.
+#[cfg(feature = "sqlite")]
+fn commit_backed<'a>(
+    stages: Vec<Box<dyn Staged<'a> + 'a>>,
+    writers: Vec<MutexGuard<'a, ()>>,
+) -> Result<(), anyhow::Error> {
+    let writes: Vec<_> = stages
+        .iter()
+        .map(|s| (s.backend().expect("checked by commit_all"), s.bytes()))
+        .collect();
+    super::sqlite::persist_all(&writes)?;
+    install_all(stages, writers);
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn commit_backed<'a>(
+    _: Vec<Box<dyn Staged<'a> + 'a>>,
+    _: Vec<MutexGuard<'a, ()>>,
+) -> Result<(), anyhow::Error> {
+    bail!("Transactions only support file stores")
+}
+
+/// `loc` with its directory resolved, as `loc` itself may not exist yet.
+fn canonical(loc: &Path) -> PathBuf {
+    let dir = loc.parent().and_then(|dir| dir.canonicalize().ok());
+    match (dir, loc.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => loc.to_path_buf(),
+    }
+}
+
+/// `.<file>.txn-<kind>-<pid>-<n>`, beside `loc`.
+fn txn_path(loc: &Path, kind: &str) -> PathBuf {
+    let base = loc.file_name().unwrap_or_default().to_string_lossy();
+    loc.with_file_name(format!(
+        ".{}.txn-{}-{}-{}",
+        base,
+        kind,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+fn write_temp(loc: &Path, bytes: &[u8], durability: Durability) -> Result<PathBuf, anyhow::Error> {
+    let tmp = txn_path(loc, "new");
+    let written = (|| {
+        let mut file = std::fs::File::create(&tmp)?;
+        std::io::Write::write_all(&mut file, bytes)?;
+        durability.sync_file(&file)?;
+        Ok::<_, std::io::Error>(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to stage store {}", loc.display()));
+    }
+    Ok(tmp)
+}
+
+/// Renames `tmp` over the file at `loc`, so `loc` is never missing, after
+/// keeping the current file as a hard link (or, failing that, a copy) for
+/// rolling back. Notes in `swapped` whatever needs undoing.
+fn swap_in<'p>(
+    loc: &'p Path,
+    tmp: &Path,
+    swapped: &mut Vec<(&'p Path, Option<PathBuf>)>,
+) -> Result<(), anyhow::Error> {
+    let prev = match loc.exists() {
+        true => {
+            let prev = txn_path(loc, "prev");
+            std::fs::hard_link(loc, &prev)
+                .or_else(|_| std::fs::copy(loc, &prev).map(|_| ()))
+                .with_context(|| format!("Failed to keep a copy of {}", loc.display()))?;
+            Some(prev)
+        }
+        false => None,
+    };
+    let renamed = std::fs::rename(tmp, loc);
+    swapped.push((loc, prev));
+    renamed.with_context(|| format!("Failed to replace {}", loc.display()))
+}
+
+/// Puts back the files `swap_in` replaced, newest first.
+fn roll_back(swapped: &[(&Path, Option<PathBuf>)]) {
+    for (loc, prev) in swapped.iter().rev() {
+        let restored = match prev {
+            Some(prev) => std::fs::rename(prev, loc),
+            None => std::fs::remove_file(loc).or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }),
+        };
+        if let Err(e) = restored {
+            error!("Failed to roll back {}: {}", loc.display(), e);
+        }
+    }
+}
+
+fn remove_all(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::codec;
+    use crate::simple_store::testing::{Count, TempDir};
+
+    #[test]
+    fn commits_all_or_nothing() {
+        let dir = TempDir::new().unwrap();
+        let index = Store::new_or_get(dir.path().join("index"), || Ok(Count(1))).unwrap();
+        let data = Store::new_or_get(dir.path().join("data"), || Ok(Count(1))).unwrap();
+        Transaction::new()
+            .stage(&index, Count(2))
+            .stage(&data, Count(2))
+            .commit_all()
+            .unwrap();
+        assert_eq!(*index.read(), Count(2));
+        assert_eq!(std::fs::read(dir.path().join("data")).unwrap(), vec![2]);
+
+        // The second store can't be written, so neither is
+        let blocked = dir.path().join("blocked");
+        std::fs::write(&blocked, b"not a directory").unwrap();
+        let stuck = Store::from_parts(Count(0), blocked.join("count"), codec::raw());
+        let err = Transaction::new()
+            .stage(&index, Count(3))
+            .stage(&stuck, Count(3))
+            .commit_all();
+        assert!(err.is_err());
+        assert_eq!(*index.read(), Count(2));
+        assert_eq!(std::fs::read(dir.path().join("index")).unwrap(), vec![2]);
+        let leftovers = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".txn-")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn opposite_orders_dont_deadlock() {
+        let dir = TempDir::new().unwrap();
+        let a = Store::new_or_get(dir.path().join("a"), || Ok(Count(0))).unwrap();
+        let b = Store::new_or_get(dir.path().join("b"), || Ok(Count(0))).unwrap();
+        std::thread::scope(|scope| {
+            for n in 1..=2 {
+                let (a, b) = (&a, &b);
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        let staged = Transaction::new();
+                        let staged = match n {
+                            1 => staged.stage(a, Count(n)).stage(b, Count(n)),
+                            _ => staged.stage(b, Count(n)).stage(a, Count(n)),
+                        };
+                        staged.commit_all().unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(*a.read(), *b.read());
+        assert_eq!(
+            std::fs::read(dir.path().join("a")).unwrap(),
+            vec![a.read().0]
+        );
+    }
+}
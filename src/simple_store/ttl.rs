@@ -0,0 +1,103 @@
+use super::Store;
+use parking_lot::{MappedRwLockReadGuard, RwLockReadGuard};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Returned by `read_fresh` when the data is older than the store's
+/// `max_age`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expired {
+    pub path: PathBuf,
+    pub age: Duration,
+    pub max_age: Duration,
+}
+
+impl fmt::Display for Expired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Store {} expired: data is {:?} old, at most {:?} allowed",
+            self.path.display(),
+            self.age,
+            self.max_age
+        )
+    }
+}
+
+impl std::error::Error for Expired {}
+
+impl<T> Store<T> {
+    /// Makes `read_fresh` refuse data older than `max_age`, for data that
+    /// must never be served stale, like auth tokens. Age counts as `age`
+    /// does, so a refresh reporting `Unchanged` renews the data too. `read`
+    /// is unaffected.
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        self.set_max_age(max_age);
+        self
+    }
+
+    /// `with_max_age` for a store that's already shared.
+    pub fn set_max_age(&self, max_age: Duration) {
+        *self.inner.max_age.lock() = Some(max_age);
+    }
+
+    /// `read`, unless the data is older than the store's `max_age`, in which
+    /// case the error is `Expired`. Without a `max_age` it always reads.
+    pub fn read_fresh(&self) -> Result<MappedRwLockReadGuard<'_, T>, anyhow::Error> {
+        let data = self.inner.data.read();
+        if let Some(max_age) = *self.inner.max_age.lock() {
+            // Checked under the read lock, so a refresh can't land in between
+            let age = self.age();
+            if age > max_age {
+                return Err(Expired {
+                    path: self.inner.loc.clone(),
+                    age,
+                    max_age,
+                }
+                .into());
+            }
+        }
+        Ok(RwLockReadGuard::map(data, |data| &**data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempStore;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Token(String);
+
+    impl TryFrom<Vec<u8>> for Token {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Token(String::from_utf8(value)?))
+        }
+    }
+
+    impl<'a> From<&'a Token> for Vec<u8> {
+        fn from(value: &'a Token) -> Self {
+            value.0.as_bytes().to_vec()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refuses_expired_data() {
+        let tmp: TempStore<Token> = TempStore::new().unwrap();
+        tmp.write(Token("abc".into())).unwrap();
+        tokio::time::advance(Duration::from_secs(600)).await;
+        assert_eq!(tmp.read_fresh().unwrap().0, "abc");
+
+        tmp.set_max_age(Duration::from_secs(300));
+        let err = tmp.read_fresh().unwrap_err();
+        let expired = err.downcast_ref::<Expired>().unwrap();
+        assert_eq!(expired.max_age, Duration::from_secs(300));
+        assert_eq!(tmp.read().0, "abc");
+
+        tmp.write(Token("def".into())).unwrap();
+        assert_eq!(tmp.read_fresh().unwrap().0, "def");
+    }
+}
@@ -0,0 +1,93 @@
+use super::Store;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A hash of the serialized data as of a generation, so a write can tell
+/// it changes the data without reserializing what the store already holds.
+/// Only a differing hash is trusted: a match is confirmed against the bytes.
+#[derive(Clone, Copy)]
+pub(super) struct ContentHash {
+    generation: u64,
+    hash: u64,
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<T> Store<T> {
+    /// Whether writing `serialized` over `data`, the store's data as held
+    /// under the writer lock, would change nothing, in which case the write
+    /// is skipped: no file is rewritten and no change is signalled. The data
+    /// still counts as fresh. A placeholder store always writes, so it
+    /// becomes ready.
+    pub(super) fn unchanged(&self, data: &T, serialized: &[u8]) -> bool {
+        if !self.is_ready() {
+            return false;
+        }
+        let generation = self.version();
+        let known = match *self.inner.content_hash.lock() {
+            Some(cached) if cached.generation == generation => Some(cached.hash),
+            // Changed by a path that doesn't keep the hash, or not yet known
+            _ => None,
+        };
+        if known.is_some_and(|known| known != hash(serialized)) {
+            return false;
+        }
+        let Ok(current) = self.encode(data) else {
+            return false;
+        };
+        if known.is_none() {
+            self.remember_content(generation, &current);
+        }
+        let unchanged = current == serialized;
+        if unchanged {
+            self.inner.mark_fresh();
+        }
+        unchanged
+    }
+
+    /// Notes that `generation` was written as `serialized`.
+    pub(super) fn remember_content(&self, generation: u64, serialized: &[u8]) {
+        *self.inner.content_hash.lock() = Some(ContentHash {
+            generation,
+            hash: hash(serialized),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{TempStore, Text};
+
+    #[tokio::test]
+    async fn identical_writes_are_skipped() {
+        let tmp = TempStore::from_value(Text("a".into())).unwrap();
+        let version = tmp.version();
+        let changes = tmp.subscribe();
+        tmp.write(Text("a".into())).unwrap();
+        tmp.write_async(Text("a".into())).await.unwrap();
+        assert_eq!(tmp.version(), version);
+        assert!(!changes.has_changed().unwrap());
+        assert_eq!(tmp.metrics().bytes_written, 0);
+
+        tmp.write(Text("b".into())).unwrap();
+        tmp.write(Text("b".into())).unwrap();
+        assert_eq!(tmp.version(), version + 1);
+        assert_eq!(tmp.metrics().bytes_written, 1);
+    }
+
+    #[test]
+    fn matching_hashes_are_checked_against_the_bytes() {
+        let tmp = TempStore::from_value(Text("a".into())).unwrap();
+        // As if "b" collided with "a"
+        *tmp.inner.content_hash.lock() = Some(ContentHash {
+            generation: tmp.version(),
+            hash: hash(b"b"),
+        });
+        tmp.write(Text("b".into())).unwrap();
+        assert_eq!(tmp.bytes().unwrap(), b"b");
+    }
+}
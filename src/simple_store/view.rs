@@ -0,0 +1,113 @@
+use super::Store;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+type Generation = Box<dyn Fn() -> u64 + Send + Sync>;
+type Merge<V> = Box<dyn Fn() -> V + Send + Sync>;
+
+/// A read-only value derived from several Stores. The merge function reads
+/// the stores it captured; the view re-runs it lazily on the first `read`
+/// after any store registered with `with` has been written.
+///
+/// ```ignore
+/// let (users, teams) = (users.clone(), teams.clone());
+/// let directory = StoreView::new(move || Directory::join(&users.read(), &teams.read()))
+///     .with(&users_store)
+///     .with(&teams_store);
+/// let snapshot: Arc<Directory> = directory.read();
+/// ```
+pub struct StoreView<V> {
+    sources: Vec<Generation>,
+    merge: Merge<V>,
+    cache: Mutex<Option<(Vec<u64>, Arc<V>)>>,
+}
+
+impl<V> StoreView<V> {
+    pub fn new(merge: impl Fn() -> V + Send + Sync + 'static) -> Self {
+        Self {
+            sources: Vec::new(),
+            merge: Box::new(merge),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Recompute the view whenever `store` changes.
+    pub fn with<T: Send + Sync + 'static>(mut self, store: &Store<T>) -> Self {
+        let store = store.clone();
+        self.sources.push(Box::new(move || store.generation()));
+        *self.cache.get_mut() = None;
+        self
+    }
+
+    /// The merged value, recomputed first if any source changed since the
+    /// last read.
+    pub fn read(&self) -> Arc<V> {
+        let generations: Vec<u64> = self.sources.iter().map(|g| g()).collect();
+        let mut cache = self.cache.lock();
+        if let Some((seen, value)) = cache.as_ref()
+            && *seen == generations
+        {
+            return value.clone();
+        }
+        let value = Arc::new((self.merge)());
+        *cache = Some((generations, value.clone()));
+        value
+    }
+
+    /// Whether the next `read` will recompute.
+    pub fn is_stale(&self) -> bool {
+        match self.cache.lock().as_ref() {
+            Some((seen, _)) => self.sources.iter().map(|g| g()).ne(seen.iter().copied()),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Num(u8);
+
+    impl TryFrom<Vec<u8>> for Num {
+        type Error = anyhow::Error;
+
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Num(value.first().copied().unwrap_or_default()))
+        }
+    }
+
+    impl<'a> From<&'a Num> for Vec<u8> {
+        fn from(value: &'a Num) -> Self {
+            vec![value.0]
+        }
+    }
+
+    #[test]
+    fn recomputes_only_after_a_source_changes() {
+        let a: TempStore<Num> = TempStore::from_value(Num(1)).unwrap();
+        let b: TempStore<Num> = TempStore::from_value(Num(2)).unwrap();
+        let merges = Arc::new(AtomicUsize::new(0));
+
+        let (sa, sb, count) = (a.store().clone(), b.store().clone(), merges.clone());
+        let view = StoreView::new(move || {
+            count.fetch_add(1, Ordering::Relaxed);
+            sa.read().0 + sb.read().0
+        })
+        .with(&a)
+        .with(&b);
+
+        assert!(view.is_stale());
+        assert_eq!(*view.read(), 3);
+        assert_eq!(*view.read(), 3);
+        assert_eq!(merges.load(Ordering::Relaxed), 1);
+
+        b.write(Num(10)).unwrap();
+        assert!(view.is_stale());
+        assert_eq!(*view.read(), 11);
+        assert_eq!(merges.load(Ordering::Relaxed), 2);
+    }
+}
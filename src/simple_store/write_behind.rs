@@ -0,0 +1,193 @@
+use super::Store;
+use crate::shutdown::ShutdownCoordinator;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tracing::error;
+
+/// When a write-behind store flushes: every `interval`, or sooner once
+/// `max_pending` writes have piled up since the last flush.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteBehind {
+    pub interval: Duration,
+    pub max_pending: usize,
+}
+
+impl WriteBehind {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_pending: usize::MAX,
+        }
+    }
+
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+}
+
+impl From<Duration> for WriteBehind {
+    fn from(interval: Duration) -> Self {
+        Self::every(interval)
+    }
+}
+
+/// Bookkeeping for writes that haven't reached disk yet.
+pub(super) struct Deferred {
+    pending: AtomicUsize,
+    max_pending: usize,
+    due: Notify,
+    // The generation last flushed; also serializes flushes
+    flushed: Mutex<u64>,
+}
+
+impl Deferred {
+    pub(super) fn defer(&self) {
+        if self.pending.fetch_add(1, Ordering::AcqRel) + 1 >= self.max_pending {
+            self.due.notify_one();
+        }
+    }
+}
+
+impl<T> Store<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Applies writes in memory right away but coalesces their trips to disk,
+    /// for stores written many times a second. Pending writes are flushed on
+    /// `policy`'s schedule, by `flush`, and once more when `shutdown` runs,
+    /// so only a crash loses them.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn with_write_behind(
+        self,
+        policy: impl Into<WriteBehind>,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Self {
+        let policy = policy.into();
+        let deferred = Arc::new(Deferred {
+            pending: AtomicUsize::new(0),
+            max_pending: policy.max_pending,
+            due: Notify::new(),
+            flushed: Mutex::new(self.version()),
+        });
+        *self.inner.write_behind.lock() = Some(deferred.clone());
+        let token = shutdown.token();
+        let mvstore = self.clone();
+        shutdown.register_task(tokio::spawn(async move {
+            loop {
+                let stopping = tokio::select! {
+                    _ = token.cancelled() => true,
+                    _ = sleep(policy.interval) => false,
+                    _ = deferred.due.notified() => false,
+                };
+                if let Err(e) = mvstore.flush() {
+                    error!("Failed to flush write-behind store: {:#}", e);
+                }
+                if stopping {
+                    break;
+                }
+            }
+        }));
+        self
+    }
+}
+
+impl<T> Store<T> {
+    /// Writes any changes not yet on disk. A no-op unless the store is in
+    /// write-behind mode.
+    pub fn flush(&self) -> Result<(), anyhow::Error> {
+        let Some(deferred) = self.inner.write_behind.lock().clone() else {
+            return Ok(());
+        };
+        let mut flushed = deferred.flushed.lock();
+        deferred.pending.store(0, Ordering::Release);
+        let (generation, data) = {
+            let data = self.inner.data.read();
+            (self.version(), data.clone())
+        };
+        if generation == *flushed {
+            return Ok(());
+        }
+        self.save_now(&self.serialize(&data)?)?;
+        *flushed = generation;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::{Count, TempStore};
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_writes_until_flushed() {
+        let tmp: TempStore<Count> = TempStore::new().unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let store = tmp
+            .store()
+            .clone()
+            .with_write_behind(Duration::from_secs(5), &mut shutdown);
+
+        for n in 1..=3 {
+            store.write(Count(n)).unwrap();
+        }
+        assert_eq!(*store.read(), Count(3));
+        assert_eq!(tmp.bytes().unwrap(), vec![0]);
+        store.flush().unwrap();
+        assert_eq!(tmp.bytes().unwrap(), vec![3]);
+
+        store.update(|c| c.0 = 4).unwrap();
+        for _ in 0..10 {
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+        assert_eq!(tmp.bytes().unwrap(), vec![4]);
+
+        store.write(Count(5)).unwrap();
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        assert_eq!(tmp.bytes().unwrap(), vec![5]);
+    }
+
+    /// Counts its encodes, for the one test using it.
+    #[derive(Default, Clone)]
+    struct Tally;
+
+    static ENCODED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    impl TryFrom<Vec<u8>> for Tally {
+        type Error = anyhow::Error;
+
+        fn try_from(_: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Tally)
+        }
+    }
+
+    impl<'a> From<&'a Tally> for Vec<u8> {
+        fn from(_: &'a Tally) -> Self {
+            ENCODED.fetch_add(1, Ordering::Relaxed);
+            Vec::new()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_flushes_skip_serializing() {
+        let tmp: TempStore<Tally> = TempStore::new().unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let store = tmp
+            .store()
+            .clone()
+            .with_write_behind(Duration::from_secs(5), &mut shutdown);
+        store.update(|_| ()).unwrap();
+        store.flush().unwrap();
+        let encoded = ENCODED.load(Ordering::Relaxed);
+        for _ in 0..3 {
+            store.flush().unwrap();
+        }
+        assert_eq!(ENCODED.load(Ordering::Relaxed), encoded);
+    }
+}
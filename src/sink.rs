@@ -0,0 +1,182 @@
+use crate::shutdown::ShutdownCoordinator;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::{sync::Notify, time::sleep};
+use tracing::{error, warn};
+
+/// Destination for batches drained from a `BufferedSink`.
+#[async_trait]
+pub trait SinkWriter<T>: Send + Sync {
+    async fn write_batch(&self, batch: Vec<T>) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    /// Flush as soon as this many items are buffered.
+    pub batch_size: usize,
+    /// Flush whatever is buffered at least this often.
+    pub flush_interval: Duration,
+    /// Items pushed beyond this are dropped rather than growing without bound.
+    pub max_buffered: usize,
+    /// How long the final flush may take once shutdown begins.
+    pub shutdown_grace: Duration,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 512,
+            flush_interval: Duration::from_secs(5),
+            max_buffered: 65536,
+            shutdown_grace: Duration::from_secs(10),
+        }
+    }
+}
+
+struct Shared<T> {
+    buffer: Mutex<Vec<T>>,
+    notify: Notify,
+    dropped: AtomicU64,
+    config: SinkConfig,
+}
+
+/// Accepts items without blocking and hands them to a `SinkWriter` in batches
+/// from a background task. On shutdown whatever is still buffered is flushed,
+/// bounded by `SinkConfig::shutdown_grace`.
+pub struct BufferedSink<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for BufferedSink<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> BufferedSink<T> {
+    pub fn spawn<W>(writer: W, config: SinkConfig, shutdown: &mut ShutdownCoordinator) -> Self
+    where
+        W: SinkWriter<T> + 'static,
+    {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Vec::with_capacity(config.batch_size)),
+            notify: Notify::new(),
+            dropped: AtomicU64::new(0),
+            config,
+        });
+        let mvshared = shared.clone();
+        let completion = shutdown.token();
+        let jhandle = tokio::spawn(async move {
+            let config = &mvshared.config;
+            loop {
+                tokio::select! {
+                  _ = sleep(config.flush_interval) => {}
+                  _ = mvshared.notify.notified() => {}
+                  _ = completion.cancelled() => break,
+                }
+                flush(&mvshared, &writer).await;
+            }
+            if tokio::time::timeout(config.shutdown_grace, flush(&mvshared, &writer))
+                .await
+                .is_err()
+            {
+                error!(
+                    "Final sink flush exceeded {:?}, {} items lost",
+                    config.shutdown_grace,
+                    mvshared.buffer.lock().len()
+                );
+            }
+        });
+        shutdown.register_task(jhandle);
+        Self { shared }
+    }
+
+    /// Buffers an item, returning `false` if it was dropped because the
+    /// buffer is full.
+    pub fn push(&self, item: T) -> bool {
+        let len = {
+            let mut buffer = self.shared.buffer.lock();
+            if buffer.len() >= self.shared.config.max_buffered {
+                drop(buffer);
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            buffer.push(item);
+            buffer.len()
+        };
+        if len >= self.shared.config.batch_size {
+            self.shared.notify.notify_one();
+        }
+        true
+    }
+
+    /// Number of items dropped so far because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+async fn flush<T, W: SinkWriter<T>>(shared: &Shared<T>, writer: &W) {
+    loop {
+        let batch: Vec<T> = {
+            let mut buffer = shared.buffer.lock();
+            let take = buffer.len().min(shared.config.batch_size);
+            buffer.drain(..take).collect()
+        };
+        if batch.is_empty() {
+            return;
+        }
+        let len = batch.len();
+        if let Err(e) = writer.write_batch(batch).await {
+            warn!("Failed to write batch of {} items: {}", len, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<Vec<u32>>>>);
+
+    #[async_trait]
+    impl SinkWriter<u32> for Collect {
+        async fn write_batch(&self, batch: Vec<u32>) -> Result<(), anyhow::Error> {
+            self.0.lock().push(batch);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn flushes_full_batches_and_remainder_on_shutdown() {
+        let mut shutdown = ShutdownCoordinator::new();
+        let writer = Collect::default();
+        let config = SinkConfig {
+            batch_size: 2,
+            flush_interval: Duration::from_secs(3600),
+            max_buffered: 4,
+            ..Default::default()
+        };
+        let sink = BufferedSink::spawn(writer.clone(), config, &mut shutdown);
+        assert!(sink.push(1));
+        assert!(sink.push(2));
+        tokio::task::yield_now().await;
+        assert_eq!(*writer.0.lock(), vec![vec![1, 2]]);
+
+        for i in 3..8 {
+            sink.push(i);
+        }
+        assert_eq!(sink.dropped(), 1);
+
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+        let flushed: Vec<u32> = writer.0.lock().concat();
+        assert_eq!(flushed, vec![1, 2, 3, 4, 5, 6]);
+    }
+}
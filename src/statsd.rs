@@ -0,0 +1,201 @@
+//! Push metrics to a StatsD or DogStatsD agent over UDP, for environments
+//! that can't be scraped.
+//!
+//! Metrics are formatted on the caller's side and handed to a `BufferedSink`,
+//! which packs them into datagrams in the background and flushes what's left
+//! on shutdown.
+//!
+//! ```ignore
+//! let statsd = Statsd::spawn(
+//!     StatsdConfig::new("127.0.0.1:8125").with_prefix("myapp").with_tag("env", "prod"),
+//!     &mut shutdown,
+//! ).await?;
+//! statsd.count("requests", 1, &[("route", "/health")]);
+//! statsd.timing("fetch", elapsed, &[]);
+//! ```
+
+use crate::shutdown::ShutdownCoordinator;
+use crate::sink::{BufferedSink, SinkConfig, SinkWriter};
+use anyhow::Context;
+use async_trait::async_trait;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+    /// Agent address, e.g. `127.0.0.1:8125`.
+    pub addr: String,
+    /// Prepended to every metric name with a `.`.
+    pub prefix: Option<String>,
+    /// DogStatsD tags added to every metric. Leave empty for plain StatsD.
+    pub tags: Vec<(String, String)>,
+    /// Largest datagram to send; the default stays under a typical MTU.
+    pub max_packet: usize,
+    pub sink: SinkConfig,
+}
+
+impl StatsdConfig {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            prefix: None,
+            tags: Vec::new(),
+            max_packet: 1432,
+            sink: SinkConfig {
+                flush_interval: Duration::from_secs(1),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+}
+
+struct UdpWriter {
+    socket: UdpSocket,
+    max_packet: usize,
+}
+
+#[async_trait]
+impl SinkWriter<String> for UdpWriter {
+    async fn write_batch(&self, batch: Vec<String>) -> Result<(), anyhow::Error> {
+        for packet in pack(&batch, self.max_packet) {
+            self.socket.send(packet.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Joins lines with `\n` into packets no longer than `max_packet`. A line
+/// that is too long on its own is sent by itself.
+fn pack(lines: &[String], max_packet: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > max_packet {
+            packets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        packets.push(current);
+    }
+    packets
+}
+
+/// A cheap-to-clone StatsD client. Recording never blocks; metrics recorded
+/// while the buffer is full are dropped and counted in `dropped()`.
+#[derive(Clone)]
+pub struct Statsd {
+    sink: BufferedSink<String>,
+    prefix: Option<String>,
+    tags: Vec<(String, String)>,
+}
+
+impl Statsd {
+    pub async fn spawn(
+        config: StatsdConfig,
+        shutdown: &mut ShutdownCoordinator,
+    ) -> Result<Self, anyhow::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket
+            .connect(&config.addr)
+            .await
+            .with_context(|| format!("Failed to resolve StatsD agent {}", config.addr))?;
+        let writer = UdpWriter {
+            socket,
+            max_packet: config.max_packet,
+        };
+        Ok(Self {
+            sink: BufferedSink::spawn(writer, config.sink, shutdown),
+            prefix: config.prefix,
+            tags: config.tags,
+        })
+    }
+
+    pub fn count(&self, name: &str, value: i64, tags: &[(&str, &str)]) {
+        self.record(name, &value.to_string(), "c", tags)
+    }
+
+    pub fn gauge(&self, name: &str, value: f64, tags: &[(&str, &str)]) {
+        self.record(name, &value.to_string(), "g", tags)
+    }
+
+    /// Recorded in milliseconds.
+    pub fn timing(&self, name: &str, value: Duration, tags: &[(&str, &str)]) {
+        self.record(name, &value.as_millis().to_string(), "ms", tags)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.sink.dropped()
+    }
+
+    fn record(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+        let mut line = String::new();
+        if let Some(prefix) = &self.prefix {
+            let _ = write!(line, "{}.", prefix);
+        }
+        let _ = write!(line, "{}:{}|{}", name, value, kind);
+        let all_tags = self
+            .tags
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .chain(tags.iter().copied());
+        for (idx, (key, value)) in all_tags.enumerate() {
+            line.push_str(if idx == 0 { "|#" } else { "," });
+            let _ = write!(line, "{}:{}", key, value);
+        }
+        self.sink.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_lines_under_the_limit() {
+        let lines: Vec<String> = ["a:1|c", "b:2|c", "c:3|c", "toolong:12345|c"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            pack(&lines, 11),
+            vec!["a:1|c\nb:2|c", "c:3|c", "toolong:12345|c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_tagged_metrics_and_flushes_on_shutdown() {
+        let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut shutdown = ShutdownCoordinator::new();
+        let config = StatsdConfig::new(agent.local_addr().unwrap().to_string())
+            .with_prefix("svc")
+            .with_tag("env", "test");
+        let statsd = Statsd::spawn(config, &mut shutdown).await.unwrap();
+
+        statsd.count("requests", 3, &[("route", "/")]);
+        statsd.gauge("queue", 1.5, &[]);
+        statsd.timing("fetch", Duration::from_millis(42), &[]);
+        shutdown.token().cancel();
+        shutdown.wait_for_shutdown().await;
+
+        let mut buf = [0; 1500];
+        let n = agent.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "svc.requests:3|c|#env:test,route:/\nsvc.queue:1.5|g|#env:test\nsvc.fetch:42|ms|#env:test"
+        );
+    }
+}
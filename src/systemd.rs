@@ -0,0 +1,127 @@
+//! Lifecycle notifications for services run under systemd with `Type=notify`
+//! (see `sd_notify(3)`). Every call is a no-op returning `Ok(false)` when the
+//! process wasn't started by systemd.
+//!
+//! With the `systemd` feature enabled, `ShutdownCoordinator` sends
+//! `STOPPING=1` as soon as shutdown begins.
+//!
+//! ```ignore
+//! let mut shutdown = ShutdownCoordinator::new();
+//! start_everything(&mut shutdown).await?;
+//! systemd::spawn_watchdog(&mut shutdown);
+//! systemd::ready()?;
+//! shutdown.wait_for_shutdown().await;
+//! ```
+
+use crate::shutdown::ShutdownCoordinator;
+use anyhow::Context;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tracing::warn;
+
+/// Sends `state` (newline separated `KEY=VALUE` pairs) to the socket named
+/// by `NOTIFY_SOCKET`, returning `false` if there is none.
+pub fn notify(state: &str) -> Result<bool, anyhow::Error> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(socket) if !socket.is_empty() => notify_to(&socket, state).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+/// Startup finished; systemd considers the unit active from here on.
+pub fn ready() -> Result<bool, anyhow::Error> {
+    notify("READY=1")
+}
+
+/// Shutdown has begun.
+pub fn stopping() -> Result<bool, anyhow::Error> {
+    notify("STOPPING=1")
+}
+
+/// Tells the watchdog the service is still alive.
+pub fn watchdog_ping() -> Result<bool, anyhow::Error> {
+    notify("WATCHDOG=1")
+}
+
+/// How often systemd expects a watchdog ping (`WatchdogSec=`), if the
+/// watchdog is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pings the watchdog at half its interval until shutdown, returning
+/// `false` without spawning anything if the watchdog isn't enabled.
+pub fn spawn_watchdog(shutdown: &mut ShutdownCoordinator) -> bool {
+    let Some(interval) = watchdog_interval() else {
+        return false;
+    };
+    let token = shutdown.token();
+    let task = tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval / 2);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {
+                    if let Err(e) = watchdog_ping() {
+                        warn!("Failed to ping systemd watchdog: {:#}", e);
+                    }
+                }
+                _ = token.cancelled() => break,
+            }
+        }
+    });
+    shutdown.register_task(task);
+    true
+}
+
+fn notify_to(socket: &str, state: &str) -> Result<(), anyhow::Error> {
+    let sock = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        Some(name) => send_abstract(&sock, name, state),
+        None => sock.send_to(state.as_bytes(), socket).map(|_| ()),
+    }
+    .with_context(|| format!("Failed to notify systemd via {}", socket))
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(sock: &UnixDatagram, name: &str, state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    sock.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_sock: &UnixDatagram, name: &str, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("abstract socket @{} is only supported on Linux", name),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_store::testing::TempDir;
+
+    #[test]
+    fn sends_state_to_socket_path() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notify.sock");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        drop(listener);
+        assert!(notify_to(path.to_str().unwrap(), "STOPPING=1").is_err());
+    }
+}
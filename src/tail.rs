@@ -0,0 +1,200 @@
+use futures::Stream;
+use std::io::{self, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    time::sleep,
+};
+use tracing::debug;
+
+#[derive(Debug, Clone)]
+pub struct TailOptions {
+    /// How long to wait before checking the file again after reaching its end.
+    pub poll_interval: Duration,
+    /// Yield the existing contents first instead of only new lines.
+    pub from_start: bool,
+}
+
+impl Default for TailOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(250),
+            from_start: false,
+        }
+    }
+}
+
+/// Follows `path` as it grows, yielding each complete line (without the
+/// trailing newline). Only lines appended after the call are yielded.
+///
+/// # Example
+/// ```no_run
+/// use futures::StreamExt;
+/// use kitchen_sink::tail::tail;
+///
+/// # async fn run() {
+/// let mut lines = Box::pin(tail("/var/log/legacy.log"));
+/// while let Some(Ok(line)) = lines.next().await {
+///     println!("{}", line);
+/// }
+/// # }
+/// ```
+pub fn tail(path: impl Into<PathBuf>) -> impl Stream<Item = io::Result<String>> {
+    tail_with(path, TailOptions::default())
+}
+
+/// Like `tail`, with explicit options.
+///
+/// The stream keeps going across truncation (reads resume from the start of
+/// the file) and rotation (the new file at `path` is opened once the old one
+/// is drained). A missing file is waited for rather than treated as an error.
+pub fn tail_with(
+    path: impl Into<PathBuf>,
+    opts: TailOptions,
+) -> impl Stream<Item = io::Result<String>> {
+    let tailer = Tailer {
+        path: path.into(),
+        skip_existing: !opts.from_start,
+        opts,
+        reader: None,
+        ino: 0,
+        pos: 0,
+        partial: String::new(),
+    };
+    futures::stream::unfold(tailer, |mut tailer| async move {
+        let line = tailer.next_line().await;
+        Some((line, tailer))
+    })
+}
+
+struct Tailer {
+    path: PathBuf,
+    opts: TailOptions,
+    skip_existing: bool,
+    reader: Option<BufReader<File>>,
+    ino: u64,
+    pos: u64,
+    partial: String,
+}
+
+impl Tailer {
+    async fn next_line(&mut self) -> io::Result<String> {
+        loop {
+            let Some(reader) = self.reader.as_mut() else {
+                if !self.open().await? {
+                    sleep(self.opts.poll_interval).await;
+                }
+                continue;
+            };
+
+            let read = reader.read_line(&mut self.partial).await?;
+            self.pos += read as u64;
+            if self.partial.ends_with('\n') {
+                let mut line = std::mem::take(&mut self.partial);
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            if read == 0 {
+                self.check_replaced().await?;
+                sleep(self.opts.poll_interval).await;
+            }
+        }
+    }
+
+    /// Opens the file at `path`, returning `false` if it doesn't exist yet.
+    async fn open(&mut self) -> io::Result<bool> {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        self.ino = file.metadata().await?.ino();
+        self.pos = if std::mem::take(&mut self.skip_existing) {
+            file.seek(SeekFrom::End(0)).await?
+        } else {
+            0
+        };
+        self.reader = Some(BufReader::new(file));
+        Ok(true)
+    }
+
+    /// Detects rotation (a different file now lives at `path`) and truncation
+    /// (the file shrank below our read position).
+    async fn check_replaced(&mut self) -> io::Result<()> {
+        let meta = match tokio::fs::metadata(&self.path).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if meta.ino() != self.ino {
+            debug!("{} was rotated, reopening", self.path.display());
+            self.reader = None;
+            self.partial.clear();
+        } else if meta.len() < self.pos {
+            debug!("{} was truncated, reading from start", self.path.display());
+            if let Some(reader) = self.reader.as_mut() {
+                reader.seek(SeekFrom::Start(0)).await?;
+            }
+            self.pos = 0;
+            self.partial.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn follows_appends_truncation_and_rotation() {
+        let dir = std::env::temp_dir().join(format!("kitchen-sink-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        std::fs::write(&path, "old\n").unwrap();
+
+        let opts = TailOptions {
+            poll_interval: Duration::from_millis(5),
+            from_start: false,
+        };
+        let mut lines = Box::pin(tail_with(path.clone(), opts));
+        let append = |s: &str| {
+            let mut f = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            f.write_all(s.as_bytes()).unwrap();
+        };
+
+        // Prime the stream so it opens the file before anything is appended.
+        let next = tokio::spawn(async move {
+            let first = lines.next().await.unwrap().unwrap();
+            (first, lines)
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        append("one\ntw");
+        append("o\n");
+        let (first, mut lines) = next.await.unwrap();
+        assert_eq!(first, "one");
+        assert_eq!(lines.next().await.unwrap().unwrap(), "two");
+
+        std::fs::write(&path, "").unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        append("three\n");
+        assert_eq!(lines.next().await.unwrap().unwrap(), "three");
+
+        std::fs::rename(&path, dir.join("app.log.1")).unwrap();
+        std::fs::write(&path, "four\n").unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "four");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}